@@ -5,25 +5,35 @@ use dolly::prelude::YawPitch;
 use glam::{Mat4, Vec2, Vec3, Vec4, vec3};
 use gpu_allocator::MemoryLocation;
 use myndgera::{
-    App, AppState, Camera, ComputeHandle, Device, FIXED_TIME_STEP, Framework, GpuBuffer,
-    KeyboardMap, RenderContext, bytes_of, dispatch_optimal,
+    App, AppState, BLUE_IMAGE_IDX, Camera, ComputeHandle, FIXED_TIME_STEP, Framework, GpuBuffer,
+    KeyboardMap, QueueType, RenderContext, SyncPoint, bytes_of, dispatch_optimal,
     math::{cos, erot, hash13, look_at, sin, smooth_floor},
     passes::{
-        bloom::{Bloom, BloomParams},
-        taa::{Taa, TaaParams},
+        auto_exposure::{AutoExposure, AutoExposureSettings},
+        bloom::{Bloom, BloomSettings},
+        dof::{Dof, DofSettings},
+        finish::{Finish, FinishParams, FinishSettings},
+        fxaa::{Fxaa, FxaaSettings},
+        motion_blur::{MotionBlur, MotionBlurSettings},
+        smaa::{Smaa, SmaaSettings},
+        ssao::{Ssao, SsaoParams, SsaoSettings},
+        stack::{PostProcessInputs, PostProcessPass, PostProcessStack},
+        taa::{Taa, TaaSettings},
+        tonemap::{Tonemap, TonemapParams, TonemapSettings},
     },
     vulkan::{
-        Buffer, FragmentOutputDesc, FragmentShaderDesc, FrameGuard, ImageHandle, RenderHandle,
-        ScreenRelation, VertexInputDesc, VertexShaderDesc, ViewTarget,
+        Buffer, BufferUsage, FrameGuard, ImageUsage, PassContext, RenderGraph, ScreenRelation,
+        TransientImageDesc, ViewTarget,
     },
 };
-use std::{error::Error, f32::consts::PI, sync::Arc};
-use winit::event_loop::EventLoop;
+use std::{error::Error, f32::consts::PI};
+use winit::{event_loop::EventLoop, keyboard::KeyCode};
 
 const NUM_LIGHTS: usize = 4;
 const NUM_RAYS: usize = 12500 * NUM_LIGHTS;
 const NUM_BOUNCES: usize = 8;
 const NUM_LINES: usize = NUM_RAYS * NUM_BOUNCES;
+const RASTER_WORKGROUP_SIZE: u32 = 64;
 
 #[repr(C)]
 #[derive(Default, Clone, Copy, Debug, Pod, Zeroable)]
@@ -71,31 +81,57 @@ struct ResolvePC {
     green_image: u32,
     blue_image: u32,
     depth_image: u32,
+    ao_image: u32,
     camera_buffer: u64,
 }
 
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-struct PostProcessPC {
-    current_image: u32,
-    hdr_sampled: u32,
-    hdr_storage: u32,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AntiAliasing {
+    Taa,
+    Fxaa,
+    Smaa,
+}
+
+impl AntiAliasing {
+    const ALL: [Self; 3] = [Self::Taa, Self::Fxaa, Self::Smaa];
+
+    // Name of the pass in the post processing stack
+    fn pass_name(self) -> &'static str {
+        match self {
+            Self::Taa => Taa::NAME,
+            Self::Fxaa => Fxaa::NAME,
+            Self::Smaa => Smaa::NAME,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Self::Taa => Self::Fxaa,
+            Self::Fxaa => Self::Smaa,
+            Self::Smaa => Self::Taa,
+        }
+    }
 }
 
 struct Trig {
     lines_buffer: Buffer,
-    lights_buffer: Buffer,
+    // Written by the host every frame and only read by the spawn pass, one per frame in flight
+    lights_buffers: Vec<Buffer>,
+    lights: [Light; NUM_LIGHTS],
     spawn_pass: ComputeHandle,
     clear_pass: ComputeHandle,
     raster_pass: ComputeHandle,
     resolve_pass: ComputeHandle,
-    postprocess_pass: RenderHandle,
-    accumulate_images: Vec<ImageHandle>,
+    render_target: ViewTarget,
     view_target: ViewTarget,
-    depth_image: ImageHandle,
-    bloom: Bloom,
-    taa: Taa,
-    device: Arc<Device>,
+    ssao: Ssao,
+    anti_aliasing: AntiAliasing,
+    // Opt-in, it only applies while Taa upscales
+    dynamic_resolution: bool,
+    post_process: PostProcessStack,
+    tonemap: Tonemap,
+    finish: Finish,
+    raster_dispatch_checked: bool,
 }
 
 impl Framework for Trig {
@@ -111,11 +147,28 @@ impl Framework for Trig {
             vk::BufferUsageFlags::STORAGE_BUFFER,
             MemoryLocation::GpuOnly,
         )?;
-        let lights_buffer = ctx.device.create_buffer(
-            GpuBuffer::<Light, NUM_LIGHTS>::SIZE as u64,
-            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
-            MemoryLocation::GpuOnly,
-        )?;
+        let lights_buffers = (0..ctx.swapchain.frames_in_flight())
+            .map(|_| {
+                ctx.device.create_buffer(
+                    GpuBuffer::<Light, NUM_LIGHTS>::SIZE as u64,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    MemoryLocation::CpuToGpu,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Blue noise starts every frame on the async compute queue, graphics hands it back
+        // at the end of the frame
+        let blue_noise = state.texture_arena.default_image(BLUE_IMAGE_IDX);
+        ctx.device.one_time_submit(|_, cbuff| {
+            state.texture_arena.release_ownership(
+                &cbuff,
+                blue_noise,
+                ImageUsage::ComputeSampled,
+                QueueType::Graphics,
+                QueueType::AsyncCompute,
+            );
+            Ok(())
+        })?;
         let push_constant_range = vk::PushConstantRange::default()
             .size(size_of::<SpawnPC>() as _)
             .stage_flags(vk::ShaderStageFlags::COMPUTE);
@@ -157,68 +210,34 @@ impl Framework for Trig {
             ],
         )?;
 
-        let vertex_shader_desc = VertexShaderDesc {
-            shader_path: "shaders/screen_trig.vert".into(),
-            ..Default::default()
-        };
-        let fragment_shader_desc = FragmentShaderDesc {
-            shader_path: "examples/line_raster/postprocess.frag.glsl".into(),
-            ..Default::default()
-        };
-        let fragment_output_desc = FragmentOutputDesc {
-            surface_format: ctx.swapchain.format(),
-            ..Default::default()
-        };
-        let push_constant_range = vk::PushConstantRange::default()
-            .size(size_of::<PostProcessPC>() as _)
-            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
-        let postprocess_pass = state.pipeline_arena.create_render_pipeline(
-            VertexInputDesc::default(),
-            vertex_shader_desc,
-            fragment_shader_desc,
-            fragment_output_desc,
-            &[push_constant_range],
-            &[
-                state.texture_arena.sampled_set_layout,
-                state.texture_arena.storage_set_layout,
-            ],
+        let render_target = ViewTarget::with_relation(
+            ctx,
+            state,
+            vk::Format::B10G11R11_UFLOAT_PACK32,
+            ScreenRelation::Render,
         )?;
-
-        let mut accumulate_images = vec![];
-        let image_info = vk::ImageCreateInfo::default()
-            .extent(vk::Extent3D {
-                width: ctx.swapchain.extent.width,
-                height: ctx.swapchain.extent.height,
-                depth: 1,
-            })
-            .image_type(vk::ImageType::TYPE_2D)
-            .format(vk::Format::R32_UINT)
-            .usage(vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .mip_levels(1)
-            .array_layers(1)
-            .tiling(vk::ImageTiling::OPTIMAL);
-        for i in 0..3 {
-            let image = state.texture_arena.push_image(
-                image_info,
-                ScreenRelation::Identity,
-                &[],
-                Some(&format!("Ray Image {i}")),
-            )?;
-            accumulate_images.push(image);
-        }
         let view_target = ViewTarget::new(ctx, state, vk::Format::B10G11R11_UFLOAT_PACK32)?;
 
-        let depth_image = state.texture_arena.push_image(
-            image_info.format(vk::Format::R16_SFLOAT),
-            ScreenRelation::Identity,
-            &[],
-            Some("Depth Image"),
+        let ssao = Ssao::new(ctx, state, SsaoSettings::default())?;
+        // Anti-aliasing resolves the render target into the view target, one of them is enabled
+        let mut post_process = PostProcessStack::new();
+        post_process.push::<Taa>(ctx, state, TaaSettings::default())?;
+        post_process.push::<Fxaa>(ctx, state, FxaaSettings::default())?;
+        post_process.push::<Smaa>(ctx, state, SmaaSettings::default())?;
+        post_process.set_enabled(Fxaa::NAME, false);
+        post_process.set_enabled(Smaa::NAME, false);
+        post_process.push::<MotionBlur>(ctx, state, MotionBlurSettings::default())?;
+        post_process.push::<Dof>(ctx, state, DofSettings::default())?;
+        post_process.push::<Bloom>(ctx, state, BloomSettings::default())?;
+        post_process.push::<AutoExposure>(ctx, state, AutoExposureSettings::default())?;
+        let tonemap = Tonemap::new(state, Finish::INPUT_FORMAT, TonemapSettings::default())?;
+        let finish = Finish::new(
+            ctx,
+            state,
+            ctx.swapchain.format(),
+            FinishSettings::default(),
         )?;
 
-        let bloom = Bloom::new(ctx, state)?;
-        let taa = Taa::new(ctx, state)?;
-
         state.key_map = {
             use winit::keyboard::KeyCode::*;
             KeyboardMap::new()
@@ -233,19 +252,22 @@ impl Framework for Trig {
         };
 
         Ok(Self {
-            lights_buffer,
+            lights_buffers,
+            lights: [Light::default(); NUM_LIGHTS],
             lines_buffer,
             spawn_pass,
             clear_pass,
             raster_pass,
             resolve_pass,
-            postprocess_pass,
-            accumulate_images,
+            render_target,
             view_target,
-            depth_image,
-            bloom,
-            taa,
-            device: ctx.device.clone(),
+            ssao,
+            anti_aliasing: AntiAliasing::Taa,
+            dynamic_resolution: false,
+            post_process,
+            tonemap,
+            finish,
+            raster_dispatch_checked: false,
         })
     }
 
@@ -255,182 +277,251 @@ impl Framework for Trig {
         state: &mut AppState,
         frame: &mut FrameGuard,
     ) -> VkResult<()> {
-        let idx = frame.image_idx;
-        let texture_arena = &mut state.texture_arena;
-
-        let global_barrier = |src, dst| {
-            let mem_barrier = vk::MemoryBarrier2::default()
-                .src_stage_mask(src)
-                .dst_stage_mask(dst);
-            self.device.pipeline_barrier(
-                frame.command_buffer(),
-                &vk::DependencyInfo::default().memory_barriers(&[mem_barrier]),
-            )
-        };
-
-        let raster_push_constant = RasterPC {
-            red_image: texture_arena.get_storage_idx(self.accumulate_images[0], 0),
-            green_image: texture_arena.get_storage_idx(self.accumulate_images[1], 0),
-            blue_image: texture_arena.get_storage_idx(self.accumulate_images[2], 0),
-            depth_image: texture_arena.get_storage_idx(self.depth_image, 0),
-            noise_offset: rand::random::<Vec2>(),
-            camera_buffer: state.camera_uniform_gpu.address,
-            line_buffer: self.lines_buffer.address,
-        };
-
-        {
-            let pipeline = state.pipeline_arena.get_pipeline(self.clear_pass);
-            frame.bind_push_constants(
-                pipeline.layout,
-                vk::ShaderStageFlags::COMPUTE,
-                &[raster_push_constant],
-            );
-            frame.bind_descriptor_sets(
-                vk::PipelineBindPoint::COMPUTE,
-                pipeline.layout,
-                &[texture_arena.storage_set],
-            );
-            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
-            const SUBGROUP_SIZE: u32 = 16;
-            let extent = ctx.swapchain.extent();
-            frame.dispatch(
-                dispatch_optimal(extent.width, SUBGROUP_SIZE),
-                dispatch_optimal(extent.height, SUBGROUP_SIZE),
-                1,
-            );
+        if !self.raster_dispatch_checked {
+            self.check_raster_dispatch(ctx);
         }
 
-        global_barrier(
-            vk::PipelineStageFlags2::COMPUTE_SHADER,
+        let spawn = self.spawn(ctx, state, frame)?;
+        frame.wait_for(spawn, vk::PipelineStageFlags2::COMPUTE_SHADER);
+        let blue_noise = state.texture_arena.default_image(BLUE_IMAGE_IDX);
+        ctx.device.acquire_buffer_ownership(
+            frame.command_buffer(),
+            self.lines_buffer.buffer,
             vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_STORAGE_READ,
+            QueueType::AsyncCompute,
+            QueueType::Graphics,
         );
-
-        {
-            let pipeline = state.pipeline_arena.get_pipeline(self.raster_pass);
-            frame.bind_push_constants(
-                pipeline.layout,
-                vk::ShaderStageFlags::COMPUTE,
-                &[raster_push_constant],
-            );
-            frame.bind_descriptor_sets(
-                vk::PipelineBindPoint::COMPUTE,
-                pipeline.layout,
-                &[texture_arena.sampled_set, texture_arena.storage_set],
-            );
-            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline);
-            frame.dispatch(dispatch_optimal(NUM_LINES as u32, 64), 1, 1);
-        }
-
-        global_barrier(
-            vk::PipelineStageFlags2::COMPUTE_SHADER,
-            vk::PipelineStageFlags2::COMPUTE_SHADER,
+        state.texture_arena.acquire_ownership(
+            frame.command_buffer(),
+            blue_noise,
+            ImageUsage::ComputeSampled,
+            QueueType::AsyncCompute,
+            QueueType::Graphics,
         );
 
-        {
-            self.device.image_transition(
-                frame.command_buffer(),
-                &texture_arena
-                    .get_image(*self.view_target.main_image())
-                    .inner,
-                vk::ImageLayout::UNDEFINED,
-                vk::ImageLayout::GENERAL,
-            );
-            let pipeline = state.pipeline_arena.get_pipeline(self.resolve_pass);
-            frame.bind_push_constants(
-                pipeline.layout,
-                vk::ShaderStageFlags::COMPUTE,
-                &[ResolvePC {
-                    target_image: texture_arena.get_storage_idx(*self.view_target.main_image(), 0),
-                    red_image: texture_arena.get_storage_idx(self.accumulate_images[0], 0),
-                    green_image: texture_arena.get_storage_idx(self.accumulate_images[1], 0),
-                    blue_image: texture_arena.get_storage_idx(self.accumulate_images[2], 0),
-                    depth_image: texture_arena.get_storage_idx(self.depth_image, 0),
-                    camera_buffer: state.camera_uniform_gpu.address,
-                }],
-            );
-            frame.bind_descriptor_sets(
-                vk::PipelineBindPoint::COMPUTE,
-                pipeline.layout,
-                &[texture_arena.sampled_set, texture_arena.storage_set],
-            );
-            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
-            const SUBGROUP_SIZE: u32 = 16;
-            let extent = ctx.swapchain.extent();
-            frame.dispatch(
-                dispatch_optimal(extent.width, SUBGROUP_SIZE),
-                dispatch_optimal(extent.height, SUBGROUP_SIZE),
-                1,
-            );
-        }
+        let extent = state.texture_arena.render_extent(ctx.swapchain.extent());
+        let mut graph = RenderGraph::new();
 
-        global_barrier(
-            vk::PipelineStageFlags2::COMPUTE_SHADER,
-            vk::PipelineStageFlags2::COMPUTE_SHADER,
+        let ray_image = TransientImageDesc::new(
+            vk::Format::R32_UINT,
+            extent,
+            vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
         );
-
-        self.taa.apply(
-            ctx,
-            state,
-            frame,
-            TaaParams {
-                view_target: &self.view_target,
-                depth_image: self.depth_image,
+        let accumulate_images = [
+            graph.create_image("Ray Image 0", ray_image),
+            graph.create_image("Ray Image 1", ray_image),
+            graph.create_image("Ray Image 2", ray_image),
+        ];
+        let depth_image = graph.create_image(
+            "Depth Image",
+            TransientImageDesc {
+                format: vk::Format::R16_SFLOAT,
+                ..ray_image
             },
         );
 
-        global_barrier(
-            vk::PipelineStageFlags2::COMPUTE_SHADER,
-            vk::PipelineStageFlags2::COMPUTE_SHADER,
-        );
-
-        self.bloom.apply(
-            ctx,
-            state,
-            frame,
-            BloomParams {
-                target_image: *self.view_target.main_image(),
-                target_current_layout: vk::ImageLayout::GENERAL,
-                strength: 4. / 16.,
-                width: 2.,
-            },
-        );
+        let noise_offset = rand::random::<Vec2>();
+        let line_buffer = self.lines_buffer.address;
+        let raster_push_constant = move |pass: &mut PassContext| {
+            let images = accumulate_images.map(|image| pass.image(image));
+            let depth_image = pass.image(depth_image);
+            let texture_arena = &mut pass.state.texture_arena;
+            RasterPC {
+                red_image: texture_arena.get_storage_idx(images[0], 0),
+                green_image: texture_arena.get_storage_idx(images[1], 0),
+                blue_image: texture_arena.get_storage_idx(images[2], 0),
+                depth_image: texture_arena.get_storage_idx(depth_image, 0),
+                noise_offset,
+                camera_buffer: pass.state.camera_uniform_gpu.address,
+                line_buffer,
+            }
+        };
 
-        global_barrier(
-            vk::PipelineStageFlags2::COMPUTE_SHADER,
-            vk::PipelineStageFlags2::ALL_GRAPHICS,
+        let clear_pass = self.clear_pass;
+        graph
+            .add_pass("Clear")
+            .write(accumulate_images[0], ImageUsage::ComputeStorage)
+            .write(accumulate_images[1], ImageUsage::ComputeStorage)
+            .write(accumulate_images[2], ImageUsage::ComputeStorage)
+            .write(depth_image, ImageUsage::ComputeStorage)
+            .record(move |pass| {
+                let push_constant = raster_push_constant(pass);
+                let frame = pass.frame;
+                let pipeline = pass.state.pipeline_arena.get_pipeline(clear_pass);
+                frame.bind_push_constants(
+                    pipeline.layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    &[push_constant],
+                );
+                frame.bind_descriptor_sets(
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline.layout,
+                    &[pass.state.texture_arena.storage_set],
+                );
+                frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+                const SUBGROUP_SIZE: u32 = 16;
+                frame.dispatch(
+                    dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                    dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                    1,
+                );
+            });
+
+        let raster_pass = self.raster_pass;
+        graph
+            .add_pass("Raster")
+            .read_buffer(self.lines_buffer.buffer, BufferUsage::ComputeRead)
+            .write(accumulate_images[0], ImageUsage::ComputeStorage)
+            .write(accumulate_images[1], ImageUsage::ComputeStorage)
+            .write(accumulate_images[2], ImageUsage::ComputeStorage)
+            .write(depth_image, ImageUsage::ComputeStorage)
+            .record(move |pass| {
+                let push_constant = raster_push_constant(pass);
+                let frame = pass.frame;
+                let texture_arena = &pass.state.texture_arena;
+                let pipeline = pass.state.pipeline_arena.get_pipeline(raster_pass);
+                frame.bind_push_constants(
+                    pipeline.layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    &[push_constant],
+                );
+                frame.bind_descriptor_sets(
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline.layout,
+                    &[texture_arena.sampled_set, texture_arena.storage_set],
+                );
+                frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, pipeline);
+                let _query = frame.begin_statistics_query("Raster");
+                frame.dispatch(
+                    dispatch_optimal(NUM_LINES as u32, RASTER_WORKGROUP_SIZE),
+                    1,
+                    1,
+                );
+            });
+
+        let ao_image = self.ssao.ao_image();
+        let ssao = &mut self.ssao;
+        graph
+            .add_pass("Ssao")
+            .read(depth_image, ImageUsage::ComputeSampled)
+            .write(ao_image, ImageUsage::ComputeStorage)
+            .record(move |pass| {
+                let params = SsaoParams {
+                    depth_image: pass.image(depth_image),
+                    light_direction: None,
+                };
+                ssao.apply(pass.ctx, pass.state, pass.frame, params);
+            });
+
+        let hdr_image = *self.render_target.main_image();
+        let resolve_pass = self.resolve_pass;
+        graph
+            .add_pass("Resolve")
+            .read(accumulate_images[0], ImageUsage::ComputeStorage)
+            .read(accumulate_images[1], ImageUsage::ComputeStorage)
+            .read(accumulate_images[2], ImageUsage::ComputeStorage)
+            .read(depth_image, ImageUsage::ComputeStorage)
+            .read(ao_image, ImageUsage::ComputeSampled)
+            .write(hdr_image, ImageUsage::ComputeStorage)
+            .record(move |pass| {
+                let frame = pass.frame;
+                let images = accumulate_images.map(|image| pass.image(image));
+                let depth_image = pass.image(depth_image);
+                let texture_arena = &mut pass.state.texture_arena;
+                let push_constant = ResolvePC {
+                    target_image: texture_arena.get_storage_idx(hdr_image, 0),
+                    red_image: texture_arena.get_storage_idx(images[0], 0),
+                    green_image: texture_arena.get_storage_idx(images[1], 0),
+                    blue_image: texture_arena.get_storage_idx(images[2], 0),
+                    depth_image: texture_arena.get_storage_idx(depth_image, 0),
+                    ao_image: texture_arena.get_sampled_idx(ao_image, 0),
+                    camera_buffer: pass.state.camera_uniform_gpu.address,
+                };
+                let pipeline = pass.state.pipeline_arena.get_pipeline(resolve_pass);
+                frame.bind_push_constants(
+                    pipeline.layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    &[push_constant],
+                );
+                frame.bind_descriptor_sets(
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipeline.layout,
+                    &[texture_arena.sampled_set, texture_arena.storage_set],
+                );
+                frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+                const SUBGROUP_SIZE: u32 = 16;
+                frame.dispatch(
+                    dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                    dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                    1,
+                );
+            });
+
+        // Motion vectors are only written by Taa, motion blur is skipped without them
+        let motion_image = (self.anti_aliasing == AntiAliasing::Taa).then(|| {
+            self.post_process
+                .get::<Taa>()
+                .unwrap()
+                .motion_image()
+                .into()
+        });
+        let view_target = &self.view_target;
+        let exposure_buffer = self.post_process.get::<AutoExposure>().unwrap();
+        let exposure_buffer = exposure_buffer.exposure_buffer();
+        let (exposure_buffer, exposure_address) = (exposure_buffer.buffer, exposure_buffer.address);
+        let inputs = PostProcessInputs {
+            main_image: hdr_image,
+            output_target: Some(view_target),
+            depth_image: Some(depth_image),
+            motion_image,
+            exposure_buffer: Some((exposure_buffer, exposure_address)),
+            ..PostProcessInputs::new(&self.render_target)
+        };
+        let output_image = self.post_process.record(&mut graph, ctx, state, inputs);
+
+        let display_image = self.finish.input_image();
+        let color_space = ctx.swapchain.color_space();
+        let tonemap = &self.tonemap;
+        graph
+            .add_pass("Tonemap")
+            .read(output_image, ImageUsage::FragmentSampled)
+            .read_buffer(exposure_buffer, BufferUsage::FragmentRead)
+            .write(display_image, ImageUsage::ColorAttachment)
+            .record(move |pass| {
+                let params = TonemapParams {
+                    view_target,
+                    target_image: display_image,
+                    color_space,
+                    exposure_buffer: Some(exposure_address),
+                    linear_output: true,
+                };
+                tonemap.apply(pass.ctx, pass.state, pass.frame, params);
+            });
+
+        let swapchain_image = state.swapchain_handles[frame.image_idx];
+        let finish = &self.finish;
+        graph
+            .add_pass("Finish")
+            .read(display_image, ImageUsage::FragmentSampled)
+            .write(swapchain_image, ImageUsage::ColorAttachment)
+            .record(move |pass| {
+                let params = FinishParams {
+                    target_image: swapchain_image,
+                    color_space,
+                };
+                finish.apply(pass.ctx, pass.state, pass.frame, params);
+            });
+
+        graph.execute(ctx, state, frame)?;
+
+        state.texture_arena.release_ownership(
+            frame.command_buffer(),
+            blue_noise,
+            ImageUsage::ComputeSampled,
+            QueueType::Graphics,
+            QueueType::AsyncCompute,
         );
-
-        {
-            let texture_arena = &mut state.texture_arena;
-            frame.begin_rendering(
-                &ctx.swapchain.images[frame.image_idx],
-                &ctx.swapchain.views[frame.image_idx],
-                vk::AttachmentLoadOp::DONT_CARE,
-                [0., 0.025, 0.025, 1.0],
-            );
-            let pipeline = state.pipeline_arena.get_pipeline(self.postprocess_pass);
-            frame.bind_push_constants(
-                pipeline.layout,
-                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
-                &[PostProcessPC {
-                    current_image: texture_arena.get_sampled_idx(state.swapchain_handles[idx], 0),
-                    hdr_sampled: texture_arena.get_sampled_idx(*self.view_target.main_image(), 0),
-                    hdr_storage: texture_arena.get_storage_idx(*self.view_target.main_image(), 0),
-                }],
-            );
-            frame.bind_descriptor_sets(
-                vk::PipelineBindPoint::GRAPHICS,
-                pipeline.layout,
-                &[
-                    state.texture_arena.sampled_set,
-                    state.texture_arena.storage_set,
-                ],
-            );
-            frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
-            frame.draw(3, 1, 0, 0);
-            frame.end_rendering();
-        }
-
         Ok(())
     }
 
@@ -438,10 +529,10 @@ impl Framework for Trig {
         &mut self,
         ctx: &RenderContext,
         state: &mut AppState,
-        cbuff: &vk::CommandBuffer,
+        _cbuff: &vk::CommandBuffer,
     ) -> Result<()> {
         let time = state.time;
-        let mut lights = [Light::default(); NUM_LIGHTS as usize];
+        let mut lights = [Light::default(); NUM_LIGHTS];
         let make_light = |tr: Mat4, col: Vec3| Light {
             transform: tr,
             color: col.extend(0.),
@@ -460,44 +551,31 @@ impl Framework for Trig {
         lights[2] = make_light(tr(vec3(1.5, -1., 1.), 2), vec3(1., 0., 0.));
         lights[3] = make_light(tr(vec3(1., -1., -1.5), 3), vec3(1., 1., 1.));
 
-        let buffer_data = GpuBuffer {
-            size: lights.len() as u32,
-            data: lights,
+        self.lights = lights;
+
+        let output_extent = ctx.swapchain.extent();
+        let render_extent = state.texture_arena.render_extent(output_extent);
+        state.camera.jitter = match self.anti_aliasing {
+            AntiAliasing::Taa => self.post_process.get_mut::<Taa>().unwrap().get_jitter(
+                state.frame,
+                render_extent,
+                output_extent,
+            ),
+            AntiAliasing::Fxaa | AntiAliasing::Smaa => Vec2::ZERO,
         };
-        state
-            .staging_write
-            .write_buffer(self.lights_buffer.buffer, bytes_of(&buffer_data));
-
-        let extent = ctx.swapchain.extent();
-        state.camera.jitter = self
-            .taa
-            .get_jitter(state.frame, extent.width, extent.height);
-
-        let pipeline = state.pipeline_arena.get_pipeline(self.spawn_pass);
-        let spawn_push_constant = SpawnPC {
-            num_rays: NUM_RAYS as u32,
-            num_bounces: NUM_BOUNCES as u32,
-            time: state.time,
-            noise_offset: rand::random::<Vec2>(),
-            lights_buffer: self.lights_buffer.address,
-            line_buffer: self.lines_buffer.address,
-        };
-        ctx.device.bind_descriptor_sets(
-            &cbuff,
-            vk::PipelineBindPoint::COMPUTE,
-            pipeline.layout,
-            &[state.texture_arena.sampled_set],
-        );
-        ctx.device.bind_push_constants(
-            &cbuff,
-            pipeline.layout,
-            vk::ShaderStageFlags::COMPUTE,
-            bytes_of(&spawn_push_constant),
-        );
-        ctx.device
-            .bind_pipeline(&cbuff, vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
-        ctx.device
-            .dispatch(&cbuff, dispatch_optimal(NUM_RAYS as u32, 256), 1, 1);
+        if state.input.keyboard_state.was_just_pressed(KeyCode::KeyT) {
+            self.set_anti_aliasing(state, self.anti_aliasing.next());
+        }
+        if state.input.keyboard_state.was_just_pressed(KeyCode::KeyR) {
+            self.dynamic_resolution = !self.dynamic_resolution;
+            self.set_anti_aliasing(state, self.anti_aliasing);
+            tracing::info!("Dynamic resolution: {}", self.dynamic_resolution);
+        }
+        if state.input.keyboard_state.was_just_pressed(KeyCode::KeyB)
+            && let Some(enabled) = self.post_process.toggle(Bloom::NAME)
+        {
+            tracing::info!("Bloom: {enabled}");
+        }
 
         if state.input.mouse_state.left_held() {
             let sensitivity = 0.5;
@@ -527,6 +605,126 @@ impl Framework for Trig {
     }
 }
 
+impl Trig {
+    // Rays are generated on the async compute queue and handed to the graphics queue for raster
+    fn spawn(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &mut FrameGuard,
+    ) -> VkResult<SyncPoint> {
+        let lights_buffer = &mut self.lights_buffers[frame.frame_idx];
+        let buffer_data = GpuBuffer {
+            size: self.lights.len() as u32,
+            data: self.lights,
+        };
+        let bytes = bytes_of(&buffer_data);
+        lights_buffer.map_memory().unwrap()[..bytes.len()].copy_from_slice(bytes);
+
+        let pipeline = state.pipeline_arena.get_pipeline(self.spawn_pass);
+        let spawn_push_constant = SpawnPC {
+            num_rays: NUM_RAYS as u32,
+            num_bounces: NUM_BOUNCES as u32,
+            time: state.time,
+            noise_offset: rand::random::<Vec2>(),
+            lights_buffer: lights_buffer.address,
+            line_buffer: self.lines_buffer.address,
+        };
+        let blue_noise = state.texture_arena.default_image(BLUE_IMAGE_IDX);
+        let texture_arena = &mut state.texture_arena;
+        let device = &ctx.device;
+        let lines_buffer = self.lines_buffer.buffer;
+        // The previous frame's raster has to be done with the rays before they are overwritten
+        let waits = [(
+            device.last_submitted(QueueType::Graphics),
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+        )];
+        frame.submit_async_compute(&waits, |cbuff| {
+            texture_arena.acquire_ownership(
+                cbuff,
+                blue_noise,
+                ImageUsage::ComputeSampled,
+                QueueType::Graphics,
+                QueueType::AsyncCompute,
+            );
+            device.bind_descriptor_sets(
+                cbuff,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set],
+            );
+            device.bind_push_constants(
+                cbuff,
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                bytes_of(&spawn_push_constant),
+            );
+            device.bind_pipeline(cbuff, vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            device.dispatch(cbuff, dispatch_optimal(NUM_RAYS as u32, 256), 1, 1);
+
+            device.release_buffer_ownership(
+                cbuff,
+                lines_buffer,
+                vk::PipelineStageFlags2::COMPUTE_SHADER,
+                vk::AccessFlags2::SHADER_STORAGE_WRITE,
+                QueueType::AsyncCompute,
+                QueueType::Graphics,
+            );
+            texture_arena.release_ownership(
+                cbuff,
+                blue_noise,
+                ImageUsage::ComputeSampled,
+                QueueType::AsyncCompute,
+                QueueType::Graphics,
+            );
+        })
+    }
+
+    // Only Taa upscales, the others run with the render target at full resolution
+    fn set_anti_aliasing(&mut self, state: &mut AppState, anti_aliasing: AntiAliasing) {
+        let taa = anti_aliasing == AntiAliasing::Taa;
+        state.resolution.settings.enabled = taa && self.dynamic_resolution;
+        if taa {
+            self.post_process.get_mut::<Taa>().unwrap().reset_history();
+        }
+        for aa in AntiAliasing::ALL {
+            self.post_process
+                .set_enabled(aa.pass_name(), aa == anti_aliasing);
+        }
+        if !state.resolution.settings.enabled {
+            state.resolution.set_scale(1.);
+        }
+        self.anti_aliasing = anti_aliasing;
+        tracing::info!("Anti-aliasing: {anti_aliasing:?}");
+    }
+
+    // Every line needs exactly one raster invocation, the rest of the last workgroup idles
+    fn check_raster_dispatch(&mut self, ctx: &RenderContext) {
+        let queries = ctx.device.queries.lock();
+        if !queries.statistics_supported() {
+            self.raster_dispatch_checked = true;
+            return;
+        }
+        let Some(stats) = queries
+            .last_results()
+            .and_then(|results| results.statistics("Raster"))
+        else {
+            return;
+        };
+        let invocations = stats.compute_shader_invocations;
+        let expected =
+            dispatch_optimal(NUM_LINES as u32, RASTER_WORKGROUP_SIZE) * RASTER_WORKGROUP_SIZE;
+        if invocations != expected as u64 {
+            tracing::warn!(
+                "Raster dispatch ran {invocations} invocations, expected {expected} for {NUM_LINES} lines"
+            );
+        } else {
+            tracing::info!("Raster dispatch ran {invocations} invocations for {NUM_LINES} lines");
+        }
+        self.raster_dispatch_checked = true;
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let event_loop = EventLoop::with_user_event().build()?;
 
@@ -7,6 +7,8 @@ layout(set = 0, binding = 1) uniform texture2D gtextures[];
 layout(set = 1, binding = 0) coherent restrict uniform uimage2D gstorage_read[];
 layout(set = 1, binding = 0) writeonly coherent
     restrict uniform image2D gstorage_write[];
+layout(set = 1, binding = 0, r16f) readonly restrict uniform image2D
+    gstorage_readf[];
 
 #include "shared.glsl"
 #include <camera.glsl>
@@ -17,6 +19,7 @@ layout(scalar, push_constant) uniform PushConstant {
     uint green_img;
     uint blue_img;
     uint depth_img;
+    uint ao_img;
     CameraBuf camera;
 }
 pc;
@@ -30,6 +33,9 @@ vec2 cs_to_uv(vec2 pix, vec2 dims) {
 
 vec2 RESOLUTION;
 
+// Stand-in for indirect light, the only term ambient occlusion darkens
+const vec3 AMBIENT = vec3(0.02);
+
 float sdf_model1(vec3 p) { return sd_box(p, vec3(4.)); }
 
 vec3 get_norm(vec3 p, float s) {
@@ -88,7 +94,12 @@ void main() {
     float red = imageLoad(gstorage_read[pc.red_img], ipix).x;
     float green = imageLoad(gstorage_read[pc.green_img], ipix).x;
     float blue = imageLoad(gstorage_read[pc.blue_img], ipix).x;
+    // The lines themselves are emissive and stay unoccluded
     col += vec3(red, green, blue) / RAY_COLOR_RANGE;
+    float depth = imageLoad(gstorage_readf[pc.depth_img], ipix).x;
+    if (depth > 0.) {
+        col += AMBIENT * texelFetch(gtextures[pc.ao_img], ipix, 0).r;
+    }
 
     // float d = imageLoad(gstorage_read[pc.depth_img], ipix).x;
     // d = smoothstep(d, 0., 1.);
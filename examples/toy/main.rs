@@ -80,13 +80,13 @@ impl Framework for Trig {
 
     fn draw(
         &mut self,
-        ctx: &RenderContext,
+        _ctx: &RenderContext,
         state: &mut AppState,
         frame: &mut FrameGuard,
     ) -> VkResult<()> {
         frame.begin_rendering(
-            &ctx.swapchain.images[frame.image_idx],
-            &ctx.swapchain.views[frame.image_idx],
+            &mut state.texture_arena,
+            state.swapchain_handles[frame.image_idx],
             vk::AttachmentLoadOp::CLEAR,
             [1., 1., 1., 1.],
         );
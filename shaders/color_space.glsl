@@ -0,0 +1,52 @@
+// Matches SwapchainColorSpace on the host
+const uint COLOR_SPACE_SRGB = 0;
+const uint COLOR_SPACE_SCRGB = 1;
+const uint COLOR_SPACE_HDR10 = 2;
+
+// Reference white for SDR content displayed on HDR outputs
+const float PAPER_WHITE_NITS = 203.;
+
+vec3 linear_to_nonlinear_srgb(vec3 linear_color) {
+    bvec3 cutoff = lessThan(linear_color, vec3(0.0031308));
+    vec3 higher =
+        vec3(1.055) * pow(linear_color, vec3(1.0 / 2.4)) - vec3(0.055);
+    vec3 lower = linear_color * vec3(12.92);
+
+    return mix(higher, lower, cutoff);
+}
+
+vec3 nonlinear_to_linear_srgb(vec3 color) {
+    bvec3 cutoff = lessThan(color, vec3(0.04045));
+    vec3 higher = pow((color + vec3(0.055)) / vec3(1.055), vec3(2.4));
+    vec3 lower = color / vec3(12.92);
+
+    return mix(higher, lower, cutoff);
+}
+
+vec3 rec709_to_rec2020(vec3 color) {
+    mat3 m = mat3(0.6274, 0.0691, 0.0164, 0.3293, 0.9195, 0.0880, 0.0433,
+                  0.0114, 0.8956);
+    return m * color;
+}
+
+vec3 pq_encode(vec3 nits) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+    vec3 y = pow(clamp(nits / 10000., 0., 1.), vec3(m1));
+    return pow((c1 + c2 * y) / (1. + c3 * y), vec3(m2));
+}
+
+// Takes linear Rec.709 color where 1.0 is paper white
+vec3 encode_output(vec3 color, uint color_space, float paper_white_nits) {
+    switch (color_space) {
+    case COLOR_SPACE_SCRGB:
+        return color * (paper_white_nits / 80.);
+    case COLOR_SPACE_HDR10:
+        return pq_encode(rec709_to_rec2020(color) * paper_white_nits);
+    default:
+        return linear_to_nonlinear_srgb(clamp(color, 0., 1.));
+    }
+}
@@ -0,0 +1,15 @@
+#extension GL_EXT_scalar_block_layout : require
+
+const uint EXPOSURE_HISTOGRAM_BINS = 256;
+
+// Written by passes::auto_exposure, color is multiplied by `exposure`
+struct Exposure {
+    float exposure;
+    float ev;
+    float average_luminance;
+    uint histogram[EXPOSURE_HISTOGRAM_BINS];
+};
+
+layout(scalar, buffer_reference, buffer_reference_align = 4) buffer ExposureBuf {
+    Exposure data;
+};
@@ -1,9 +1,16 @@
 const float PI = acos(-1.);
 const float TAU = 2. * PI;
 
+#ifdef DEBUG_PRINTF
+#extension GL_EXT_debug_printf : enable
+#endif
+
 vec4 ASSERT_COL = vec4(0.);
 void assert(bool cond, int v) {
     if (!(cond)) {
+#ifdef DEBUG_PRINTF
+        debugPrintfEXT("assert %d failed", v);
+#endif
         if (v == 0)
             ASSERT_COL.x = -1.0;
         else if (v == 1)
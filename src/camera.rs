@@ -15,11 +15,50 @@ pub struct CameraUniform {
     prev_jitter: Vec2,
 }
 
+// Physical parameters for the depth of field and motion blur passes, distances in scene units
+#[derive(Clone, Copy, Debug)]
+pub struct Lens {
+    // Degrees of the frame the shutter stays open, 360 blurs over the whole frame
+    pub shutter_angle: f32,
+    pub focus_distance: f32,
+    // f-number, lower opens the aperture and shallows the depth of field
+    pub aperture: f32,
+    // Together with the vertical fov sets the focal length
+    pub sensor_height: f32,
+}
+
+impl Default for Lens {
+    fn default() -> Self {
+        Self {
+            shutter_angle: 180.,
+            focus_distance: 10.,
+            aperture: 2.8,
+            sensor_height: 0.024,
+        }
+    }
+}
+
+impl Lens {
+    pub fn focal_length(&self) -> f32 {
+        0.5 * self.sensor_height / (0.5 * Camera::FOVY).tan()
+    }
+
+    // Circle of confusion diameter on the sensor is coc_scale * |depth - focus| / depth
+    pub fn coc_scale(&self) -> f32 {
+        let focal_length = self.focal_length();
+        let focus_distance = self.focus_distance.max(focal_length * 1.01);
+        focal_length * focal_length / (self.aperture.max(0.1) * (focus_distance - focal_length))
+    }
+}
+
 #[derive(Debug)]
 pub struct Camera {
     pub rig: CameraRig,
     pub aspect: f32,
+    // Subpixel offset in NDC, a pixel spans 2 / size of the image the scene is rendered at
     pub jitter: Vec2,
+    pub lens: Lens,
+    cut: bool,
 }
 
 impl Camera {
@@ -36,9 +75,20 @@ impl Camera {
             rig,
             aspect: 1.25,
             jitter: Vec2::ZERO,
+            lens: Lens::default(),
+            cut: false,
         }
     }
 
+    // The next frame doesn't continue from this one, temporal history gets dropped
+    pub fn cut(&mut self) {
+        self.cut = true;
+    }
+
+    pub(crate) fn take_cut(&mut self) -> bool {
+        std::mem::take(&mut self.cut)
+    }
+
     pub fn set_position(&mut self, pos: Vec3) {
         self.rig.driver_mut::<dolly::drivers::Position>().position = pos.into();
     }
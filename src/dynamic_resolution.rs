@@ -0,0 +1,113 @@
+use crate::GpuFrameTimings;
+
+// Weight of the newest frame in the smoothed GPU time
+const SMOOTHING: f32 = 0.1;
+// Only step up when the predicted frame time leaves this much of the budget free
+const HEADROOM: f32 = 0.9;
+const MIN_SCALE: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DynamicResolutionSettings {
+    // Disabled keeps whatever was set with DynamicResolution::set_scale
+    pub enabled: bool,
+    pub target_ms: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    // Changes recreate the render sized images, so the scale moves in steps
+    pub step: f32,
+    // Frames measured after a change before the next one
+    pub cooldown: u32,
+}
+
+impl Default for DynamicResolutionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_ms: 1000. / 60.,
+            min_scale: 0.5,
+            max_scale: 1.,
+            step: 0.05,
+            cooldown: 30,
+        }
+    }
+}
+
+// Picks the render scale from the GPU profiler's frame time, the app applies it to
+// ScreenRelation::Render images between frames
+#[derive(Debug)]
+pub struct DynamicResolution {
+    pub settings: DynamicResolutionSettings,
+    scale: f32,
+    average_ms: Option<f32>,
+    last_frame: Option<u32>,
+    samples: u32,
+}
+
+impl DynamicResolution {
+    pub fn new(settings: DynamicResolutionSettings) -> Self {
+        Self {
+            settings,
+            scale: 1.,
+            average_ms: None,
+            last_frame: None,
+            samples: 0,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.clamp(MIN_SCALE, 1.);
+        self.average_ms = None;
+        self.samples = 0;
+    }
+
+    pub fn average_ms(&self) -> Option<f32> {
+        self.average_ms
+    }
+
+    pub(crate) fn update(&mut self, timings: Option<&GpuFrameTimings>) {
+        let Some(timings) = timings.filter(|_| self.settings.enabled) else {
+            return;
+        };
+        if self.last_frame == Some(timings.frame) {
+            return;
+        }
+        self.last_frame = Some(timings.frame);
+
+        let ms = timings.duration_ms() as f32;
+        let average = self
+            .average_ms
+            .map_or(ms, |average| average + (ms - average) * SMOOTHING);
+        self.average_ms = Some(average);
+        self.samples += 1;
+        if self.samples < self.settings.cooldown.max(1) {
+            return;
+        }
+
+        let settings = &self.settings;
+        let step = settings.step.max(0.01);
+        let min_scale = settings.min_scale.max(MIN_SCALE);
+        let max_scale = settings.max_scale.clamp(min_scale, 1.);
+        // Small bias so sums like 0.95 + 0.05 don't floor to the step below
+        let quantize =
+            |scale: f32| ((scale / step + 1e-3).floor() * step).clamp(min_scale, max_scale);
+
+        // GPU time is roughly proportional to the pixel count
+        let predicted_ms = |scale: f32| average * (scale / self.scale).powi(2);
+        let scale = if average > settings.target_ms {
+            let ideal = self.scale * (settings.target_ms / average).sqrt();
+            quantize(ideal).min(quantize(self.scale - step))
+        } else if predicted_ms(self.scale + step) < settings.target_ms * HEADROOM {
+            quantize(self.scale + step).max(self.scale.min(max_scale))
+        } else {
+            self.scale.clamp(min_scale, max_scale)
+        };
+
+        if scale != self.scale {
+            self.set_scale(scale);
+        }
+    }
+}
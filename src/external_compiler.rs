@@ -0,0 +1,236 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use ahash::AHashSet;
+use anyhow::{Context, Result, bail, ensure};
+
+use crate::{SHADER_FOLDER, ShaderBackend, ShaderKind};
+
+pub struct ExternalInvocation<'a> {
+    pub input: &'a Path,
+    pub output: &'a Path,
+    pub depfile: &'a Path,
+    pub kind: ShaderKind,
+}
+
+type ArgsBuilder = dyn Fn(&ExternalInvocation) -> Vec<OsString> + Send + Sync;
+
+pub struct ExternalCompiler {
+    program: PathBuf,
+    extensions: Vec<&'static str>,
+    args: Box<ArgsBuilder>,
+}
+
+impl ExternalCompiler {
+    pub fn new(
+        program: impl Into<PathBuf>,
+        extensions: &[&'static str],
+        args: impl Fn(&ExternalInvocation) -> Vec<OsString> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            program: program.into(),
+            extensions: extensions.to_vec(),
+            args: Box::new(args),
+        }
+    }
+
+    pub fn slangc() -> Self {
+        Self::new("slangc", &["slang"], |inv| {
+            let stage = match inv.kind {
+                ShaderKind::Vertex => "vertex",
+                ShaderKind::Fragment => "fragment",
+                ShaderKind::Compute => "compute",
+            };
+            [
+                inv.input.as_os_str(),
+                "-target".as_ref(),
+                "spirv".as_ref(),
+                "-profile".as_ref(),
+                "spirv_1_6".as_ref(),
+                "-entry".as_ref(),
+                "main".as_ref(),
+                "-stage".as_ref(),
+                stage.as_ref(),
+                "-I".as_ref(),
+                SHADER_FOLDER.as_ref(),
+                "-depfile".as_ref(),
+                inv.depfile.as_os_str(),
+                "-o".as_ref(),
+                inv.output.as_os_str(),
+            ]
+            .map(OsString::from)
+            .to_vec()
+        })
+    }
+
+    pub fn dxc() -> Self {
+        Self::new("dxc", &["hlsl"], |inv| {
+            let profile = match inv.kind {
+                ShaderKind::Vertex => "vs_6_6",
+                ShaderKind::Fragment => "ps_6_6",
+                ShaderKind::Compute => "cs_6_6",
+            };
+            [
+                "-spirv".as_ref(),
+                "-fspv-target-env=vulkan1.3".as_ref(),
+                "-T".as_ref(),
+                profile.as_ref(),
+                "-E".as_ref(),
+                "main".as_ref(),
+                "-I".as_ref(),
+                SHADER_FOLDER.as_ref(),
+                "-MD".as_ref(),
+                "-MF".as_ref(),
+                inv.depfile.as_os_str(),
+                "-Fo".as_ref(),
+                inv.output.as_os_str(),
+                inv.input.as_os_str(),
+            ]
+            .map(OsString::from)
+            .to_vec()
+        })
+    }
+}
+
+impl ShaderBackend for ExternalCompiler {
+    fn extensions(&self) -> &[&str] {
+        &self.extensions
+    }
+
+    fn compile(
+        &self,
+        path: &Path,
+        kind: ShaderKind,
+        includes: &mut AHashSet<PathBuf>,
+    ) -> Result<Vec<u32>> {
+        static INVOCATION: AtomicU64 = AtomicU64::new(0);
+        let stem = format!(
+            "myndgera-{}-{}",
+            std::process::id(),
+            INVOCATION.fetch_add(1, Ordering::Relaxed)
+        );
+        let output = std::env::temp_dir().join(format!("{stem}.spv"));
+        let depfile = std::env::temp_dir().join(format!("{stem}.d"));
+
+        let invocation = ExternalInvocation {
+            input: path,
+            output: &output,
+            depfile: &depfile,
+            kind,
+        };
+        let result = Command::new(&self.program)
+            .args((self.args)(&invocation))
+            .output()
+            .with_context(|| format!("Failed to run {}", self.program.display()));
+
+        if let Ok(deps) = std::fs::read_to_string(&depfile) {
+            includes.extend(
+                parse_depfile(&deps)
+                    .into_iter()
+                    .filter_map(|dep| dep.canonicalize().ok())
+                    .filter(|dep| dep != path),
+            );
+        }
+        let bytes = std::fs::read(&output);
+        let _ = std::fs::remove_file(&output);
+        let _ = std::fs::remove_file(&depfile);
+
+        let result = result?;
+        if !result.status.success() {
+            bail!(
+                "{} failed to compile {}:\n{}{}",
+                self.program.display(),
+                path.display(),
+                String::from_utf8_lossy(&result.stdout),
+                String::from_utf8_lossy(&result.stderr)
+            );
+        }
+        let bytes = bytes.context("External compiler produced no output")?;
+        ensure!(bytes.len() % 4 == 0, "SPIR-V size is not a multiple of 4");
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect())
+    }
+}
+
+// Make style: `target: dep dep`, lines continued with `\` and spaces in paths escaped as `\ `
+fn parse_depfile(deps: &str) -> Vec<PathBuf> {
+    let Some((_, deps)) = deps.split_once(": ") else {
+        return vec![];
+    };
+    let mut paths = vec![];
+    let mut path = String::new();
+    let mut chars = deps.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&' ') => path.push(chars.next().unwrap()),
+            '\\' if matches!(chars.peek(), Some('\r' | '\n')) => {}
+            c if c.is_whitespace() => {
+                if !path.is_empty() {
+                    paths.push(PathBuf::from(std::mem::take(&mut path)));
+                }
+            }
+            c => path.push(c),
+        }
+    }
+    if !path.is_empty() {
+        paths.push(PathBuf::from(path));
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_line() {
+        assert_eq!(
+            parse_depfile("out.spv: shaders/a.slang shaders/b.slang\n"),
+            [
+                PathBuf::from("shaders/a.slang"),
+                PathBuf::from("shaders/b.slang")
+            ]
+        );
+    }
+
+    #[test]
+    fn line_continuations() {
+        let expected = [
+            PathBuf::from("a.hlsl"),
+            PathBuf::from("b.hlsli"),
+            PathBuf::from("c.hlsli"),
+        ];
+        assert_eq!(
+            parse_depfile("out.spv: a.hlsl \\\n  b.hlsli \\\n  c.hlsli\n"),
+            expected
+        );
+        assert_eq!(
+            parse_depfile("out.spv: a.hlsl \\\r\n b.hlsli\\\r\n c.hlsli\r\n"),
+            expected
+        );
+    }
+
+    #[test]
+    fn escaped_spaces() {
+        assert_eq!(
+            parse_depfile("out.spv: my\\ shaders/a.slang other\\ dir/b\\ c.slang"),
+            [
+                PathBuf::from("my shaders/a.slang"),
+                PathBuf::from("other dir/b c.slang")
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_target() {
+        assert!(parse_depfile("").is_empty());
+        assert!(parse_depfile("a.slang b.slang").is_empty());
+        assert!(parse_depfile("out.spv: ").is_empty());
+    }
+}
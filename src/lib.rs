@@ -1,15 +1,15 @@
-use anyhow::{Result, bail};
+use anyhow::Result;
 use ash::{prelude::VkResult, vk};
-use either::Either;
 use glam::{vec2, vec3};
 use gpu_allocator::MemoryLocation;
+#[cfg(feature = "hot-reload")]
+use std::{io::Write, path::PathBuf};
 use std::{
-    io::Write,
-    path::{Path, PathBuf},
+    path::Path,
     sync::Arc,
     time::{Duration, Instant},
 };
-use tracing::{error, level_filters::LevelFilter, warn};
+use tracing::{debug, error, info, level_filters::LevelFilter, warn};
 use tracing_subscriber::{filter::EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
 use winit::{
     application::ApplicationHandler,
@@ -21,22 +21,37 @@ use winit::{
 };
 
 mod camera;
+mod dynamic_resolution;
+#[cfg(feature = "hot-reload")]
+mod external_compiler;
 mod input;
 pub mod math;
 pub mod passes;
 mod recorder;
 mod render_context;
+mod shader_bundle;
+#[cfg(feature = "hot-reload")]
 mod shader_compiler;
 pub mod utils;
 pub mod vulkan;
+#[cfg(feature = "hot-reload")]
 mod watcher;
 
+#[cfg(feature = "hot-reload")]
+pub use external_compiler::{ExternalCompiler, ExternalInvocation};
+pub use shader_bundle::*;
+#[cfg(feature = "hot-reload")]
 pub use shader_compiler::*;
+#[cfg(feature = "hot-reload")]
 pub use watcher::Watcher;
 
-use self::recorder::Recorder;
+#[cfg(not(any(feature = "hot-reload", feature = "embed-shaders")))]
+compile_error!("Without the hot-reload feature shaders can only come from embed-shaders");
+
+use self::recorder::{CapturedFrame, RecordEvent, Recorder};
 pub use self::{
-    camera::{Camera, CameraUniform},
+    camera::{Camera, CameraUniform, Lens},
+    dynamic_resolution::{DynamicResolution, DynamicResolutionSettings},
     input::{Input, KeyboardMap},
     render_context::RenderContext,
     utils::*,
@@ -58,16 +73,24 @@ pub const COLOR_SUBRESOURCE_MASK: vk::ImageSubresourceRange = vk::ImageSubresour
 pub const SHADER_FOLDER: &str = "shaders";
 pub const VIDEO_FOLDER: &str = "recordings";
 pub const SCREENSHOT_FOLDER: &str = "screenshots";
+pub const PROFILE_FOLDER: &str = "profiles";
 
 #[derive(Debug)]
 pub enum UserEvent {
-    Glsl { path: std::path::PathBuf },
+    Shader { path: std::path::PathBuf },
 }
 
 pub trait Framework: Sized {
     fn name() -> &'static str {
         "Myndgera"
     }
+    fn swapchain_config() -> SwapchainConfig {
+        SwapchainConfig::default()
+    }
+    // Folders searched by --precompile-shaders
+    fn shader_roots() -> &'static [&'static str] {
+        &[SHADER_FOLDER, "src", "examples"]
+    }
     fn init(app: &RenderContext, _ctx: &mut AppState) -> Result<Self>;
     fn resize(&mut self, _ctx: &mut RenderContext) -> Result<()> {
         Ok(())
@@ -94,10 +117,12 @@ pub struct AppState {
     pub frame: u32,
 
     pub staging_write: StagingWrite,
+    pub readback: ReadbackQueue,
 
     pub pipeline_arena: PipelineArena,
     pub texture_arena: TextureArena,
     pub swapchain_handles: Vec<ImageHandle>,
+    pub transient_images: TransientImagePool,
 
     pub input: Input,
     pub key_map: KeyboardMap,
@@ -106,8 +131,15 @@ pub struct AppState {
     pub camera_uniform: CameraUniform,
     pub camera_uniform_gpu: BufferTyped<CameraUniform>,
 
+    // Set for the frame after a resize, time reset or camera cut
+    pub history_reset: bool,
+
+    // Scale of ScreenRelation::Render images, applied before the next frame
+    pub resolution: DynamicResolution,
+
     recorder: Recorder,
     recording_time: Option<Duration>,
+    screenshot_requested: bool,
 
     pub pause: bool,
     pub time: f32,
@@ -135,8 +167,18 @@ impl AppState {
 
         let staging_write = StagingWrite::new(&ctx.device)?;
 
-        let file_watcher = Watcher::new(proxy)?;
-        let pipeline_arena = PipelineArena::new(&ctx.device, file_watcher)?;
+        let precompiled_shaders = EMBEDDED_SHADER_BUNDLE
+            .map(ShaderBundle::from_bytes)
+            .transpose()?;
+        #[cfg(feature = "hot-reload")]
+        let pipeline_arena =
+            PipelineArena::new(&ctx.device, Watcher::new(proxy)?, precompiled_shaders)?;
+        // Nothing sends shader events without the file watcher
+        #[cfg(not(feature = "hot-reload"))]
+        let pipeline_arena = {
+            drop(proxy);
+            PipelineArena::new(&ctx.device, precompiled_shaders)?
+        };
 
         let mut texture_arena = TextureArena::new(&ctx.device)?;
         let mut swapchain_handles = vec![];
@@ -151,14 +193,21 @@ impl AppState {
             camera_uniform,
             camera_uniform_gpu,
 
+            history_reset: true,
+
+            resolution: DynamicResolution::new(DynamicResolutionSettings::default()),
+
             recorder,
             recording_time,
+            screenshot_requested: false,
 
             pipeline_arena,
             texture_arena,
             swapchain_handles,
+            transient_images: TransientImagePool::new(&ctx.device),
 
             staging_write,
+            readback: ReadbackQueue::new(&ctx.device),
 
             input: Input::new(),
             key_map: KeyboardMap::new(),
@@ -181,42 +230,37 @@ pub struct AppInit<F> {
 }
 
 impl<F> AppInit<F> {
+    #[cfg(feature = "hot-reload")]
     pub fn reload_shaders(&mut self, path: PathBuf) -> Result<()> {
-        for frame in self.ctx.swapchain.frames.iter().filter_map(Option::as_ref) {
-            let fences = std::slice::from_ref(&frame.present_finished);
-            self.device.wait_for_fences(fences, true, u64::MAX)?;
+        let arena = &mut self.state.pipeline_arena;
+        for source in arena.file_watcher.dependent_sources(&path) {
+            arena.schedule_reload(&source)?;
         }
+        Ok(())
+    }
 
-        let state = &mut self.state;
-        let resolved = {
-            let mapping = state.pipeline_arena.file_watcher.include_mapping.lock();
-            mapping[Path::new(path.file_name().unwrap())].clone()
-        };
-
-        for ShaderSource { path, kind } in resolved {
-            let handles = &state.pipeline_arena.path_mapping[&path];
-            for handle in handles {
-                let compiler = &state.pipeline_arena.shader_compiler;
-                match handle {
-                    Either::Left(handle) => {
-                        let pipeline = &mut state.pipeline_arena.render_arena[*handle];
-                        match kind {
-                            ShaderKind::Vertex => pipeline.reload_vertex_lib(compiler, &path),
-                            ShaderKind::Fragment => pipeline.reload_fragment_lib(compiler, &path),
-                            ShaderKind::Compute => {
-                                bail!("Supplied compute shader into the render pipeline!")
-                            }
-                        }?;
-                        pipeline.link()?;
-                    }
-                    Either::Right(handle) => {
-                        let pipeline = &mut state.pipeline_arena.compute_arena[*handle];
-                        pipeline.reload(compiler)?;
-                    }
-                }
+    #[cfg(feature = "hot-reload")]
+    fn swap_reloaded_pipelines(&mut self) {
+        let frames_in_flight = self.ctx.swapchain.frames.len();
+        let outcomes = self.state.pipeline_arena.process_reloads(frames_in_flight);
+        let mut reloaded = false;
+        for outcome in outcomes {
+            match outcome {
+                Ok(()) => reloaded = true,
+                Err(err) => eprintln!("{err}"),
             }
         }
-        Ok(())
+        if reloaded {
+            const ESC: &str = "\x1B[";
+            const RESET: &str = "\x1B[0m";
+            eprint!("\r{ESC}42m{ESC}K{RESET}\r");
+            std::io::stdout().flush().unwrap();
+            std::thread::spawn(|| {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                eprint!("\r{ESC}40m{ESC}K{RESET}\r");
+                std::io::stdout().flush().unwrap();
+            });
+        }
     }
 }
 
@@ -226,7 +270,7 @@ impl<F: Framework> AppInit<F> {
         proxy: EventLoopProxy<UserEvent>,
         recording_time: Option<Duration>,
     ) -> Result<Self> {
-        let mut ctx = RenderContext::new(window)?;
+        let mut ctx = RenderContext::new(window, F::swapchain_config())?;
         let mut state = AppState::new(&mut ctx, proxy, recording_time)?;
         let framework = F::init(&ctx, &mut state)?;
         let device = ctx.device.clone();
@@ -239,7 +283,7 @@ impl<F: Framework> AppInit<F> {
         })
     }
 
-    fn update(&mut self) -> Result<()> {
+    fn update(&mut self, cbuff: vk::CommandBuffer) -> Result<()> {
         let state = &mut self.state;
         let new_instant = Instant::now();
         let frame_time = new_instant
@@ -250,14 +294,17 @@ impl<F: Framework> AppInit<F> {
 
         state.frame_accumulated_time += frame_time;
         while state.frame_accumulated_time >= FIXED_TIME_STEP {
-            self.device.one_time_submit(|device, cbuff| {
-                let _marker = device.create_scoped_marker(&cbuff, "State Update");
+            {
+                let _marker = self.device.create_scoped_marker(&cbuff, "State Update");
 
                 state.input.tick();
 
                 state.camera.rig.update(FIXED_TIME_STEP as f32);
 
-                state.camera_uniform = state.camera.get_uniform(Some(&state.camera_uniform));
+                let cut = state.camera.take_cut();
+                let previous = (!cut).then_some(&state.camera_uniform);
+                state.camera_uniform = state.camera.get_uniform(previous);
+                state.history_reset |= cut;
                 state.staging_write.write_buffer(
                     state.camera_uniform_gpu.buffer,
                     bytemuck::bytes_of(&state.camera_uniform),
@@ -266,9 +313,7 @@ impl<F: Framework> AppInit<F> {
                 self.framework.update(&self.ctx, state, &cbuff)?;
 
                 state.staging_write.consume_pending_writes(&cbuff)?;
-
-                Ok(())
-            })?;
+            }
 
             state.input.mouse_state.refresh();
 
@@ -277,12 +322,21 @@ impl<F: Framework> AppInit<F> {
         Ok(())
     }
 
+    fn wait_for_frames(&self) -> VkResult<()> {
+        let fences: Vec<_> = self
+            .ctx
+            .swapchain
+            .frames
+            .iter()
+            .flatten()
+            .map(|frame| frame.present_finished)
+            .collect();
+        let one_second = Duration::from_secs(1).as_nanos() as u64;
+        self.device.wait_for_fences(&fences, true, one_second)
+    }
+
     pub fn recreate_swapchain(&mut self) -> Result<()> {
-        if let Some(frame) = self.ctx.swapchain.get_current_frame() {
-            let fences = std::slice::from_ref(&frame.present_finished);
-            let one_second = Duration::from_secs(1).as_nanos() as u64;
-            self.device.wait_for_fences(fences, true, one_second)?;
-        }
+        self.wait_for_frames()?;
 
         let PhysicalSize { width, height } = self.ctx.window.inner_size();
         self.ctx
@@ -292,43 +346,116 @@ impl<F: Framework> AppInit<F> {
         self.state.camera.aspect = width as f32 / height as f32;
 
         self.state.texture_arena.resize(width, height)?;
-
-        let handles = &self.state.swapchain_handles;
-        for ((&handle, &image), &view) in handles
-            .iter()
-            .zip(&self.ctx.swapchain.images)
-            .zip(&self.ctx.swapchain.views)
+        self.state.history_reset = true;
+
+        // Image count can change along with the present mode
+        let swapchain = &self.ctx.swapchain;
+        let texture_arena = &mut self.state.texture_arena;
+        let handles = &mut self.state.swapchain_handles;
+        for handle in handles.drain(swapchain.images.len().min(handles.len())..) {
+            texture_arena.remove_image(handle);
+        }
+        for ((&handle, &image), &view) in
+            handles.iter().zip(&swapchain.images).zip(&swapchain.views)
         {
-            self.state
-                .texture_arena
-                .update_external_image(handle, image, view);
+            texture_arena.update_external_image(handle, image, view);
+        }
+        let new_images = swapchain.images.iter().zip(&swapchain.views);
+        for (&image, &view) in new_images.skip(handles.len()) {
+            handles.push(texture_arena.push_external_image(image, view)?);
         }
 
         self.framework.resize(&mut self.ctx)?;
         Ok(())
     }
 
+    fn update_render_scale(&mut self) -> Result<()> {
+        let timings = self.device.profiler.lock().last_frame().cloned();
+        self.state.resolution.update(timings.as_ref());
+
+        let scale = self.state.resolution.scale();
+        if scale == self.state.texture_arena.render_scale() {
+            return Ok(());
+        }
+        self.wait_for_frames()?;
+        let vk::Extent2D { width, height } = self.ctx.swapchain.extent();
+        self.state
+            .texture_arena
+            .set_render_scale(scale, width, height)?;
+        // Render sized images were recreated with undefined contents
+        self.state.history_reset = true;
+        debug!("Render scale: {scale:.2}");
+        Ok(())
+    }
+
     fn draw(&mut self) -> VkResult<()> {
+        #[cfg(feature = "hot-reload")]
+        self.swap_reloaded_pipelines();
+
         let mut frame = self.ctx.swapchain.acquire_next_image()?;
+        self.device.begin_frame_queries(
+            frame.command_buffer(),
+            frame.frame_idx,
+            self.state.frame,
+        )?;
 
-        self.framework
-            .draw(&self.ctx, &mut self.state, &mut frame)?;
+        self.state.readback.begin_frame(frame.frame_idx);
+        self.state.texture_arena.begin_frame(frame.frame_idx);
 
-        self.ctx.window.pre_present_notify();
+        let update = self
+            .state
+            .staging_write
+            .begin_frame(frame.frame_idx)
+            .and_then(|()| self.update(frame.command_buffer));
+        if let Err(err) = update {
+            error!("{err}");
+        }
 
-        if self.state.recorder.is_active() && self.state.recorder.ffmpeg_installed() {
-            let res = self.device.capture_image_data(
-                &self.ctx.swapchain.images[frame.image_idx],
+        self.framework
+            .draw(&self.ctx, &mut self.state, &mut frame)?;
+        self.state.history_reset = false;
+
+        let recording = self.state.recorder.is_active() && self.state.recorder.ffmpeg_installed();
+        if recording || self.state.screenshot_requested {
+            let sender = self.state.recorder.sender.clone();
+            let screenshot = std::mem::take(&mut self.state.screenshot_requested);
+            let res = self.state.readback.read_image_converted(
+                frame.command_buffer(),
+                &mut self.state.texture_arena,
+                self.state.swapchain_handles[frame.image_idx],
+                self.ctx.swapchain.format(),
                 self.ctx.swapchain.extent(),
-                |tex| self.state.recorder.record(tex),
+                vk::Format::R8G8B8A8_UNORM,
+                move |data| {
+                    let frame = CapturedFrame::from_readback(data);
+                    let event = if screenshot {
+                        RecordEvent::Screenshot(frame)
+                    } else {
+                        RecordEvent::Record(frame)
+                    };
+                    let _ = sender.send(event);
+                },
             );
             if let Err(err) = res {
                 error!("{err}");
             }
         }
 
+        self.state.texture_arena.transition(
+            frame.command_buffer(),
+            self.state.swapchain_handles[frame.image_idx],
+            ImageUsage::Present,
+        );
+        self.device.end_frame_queries();
+
+        self.ctx.window.pre_present_notify();
+
         self.ctx.swapchain.submit_image(frame)?;
 
+        if debug_printf_enabled() {
+            flush_debug_printf();
+        }
+
         self.state.frame = self.state.frame.wrapping_add(1);
         Ok(())
     }
@@ -342,13 +469,12 @@ impl<F: Framework> ApplicationHandler<UserEvent> for AppInit<F> {
             self.state.backup_time.as_secs_f32()
         };
 
-        let _ = self.update().map_err(|err| error!("{err}"));
-
-        if let Some(limit) = self.state.recording_time {
-            if self.state.timeline.elapsed() >= limit && self.state.recorder.is_active() {
-                self.state.recorder.finish();
-                event_loop.exit();
-            }
+        if let Some(limit) = self.state.recording_time
+            && self.state.timeline.elapsed() >= limit
+            && self.state.recorder.is_active()
+        {
+            self.state.recorder.finish();
+            event_loop.exit();
         }
     }
 
@@ -413,17 +539,25 @@ impl<F: Framework> ApplicationHandler<UserEvent> for AppInit<F> {
                         state.frame = 0;
                         state.timeline = Instant::now();
                         state.backup_time = state.timeline.elapsed();
+                        state.history_reset = true;
                     }
-                    NamedKey::F7 => {
-                        let _ = self
-                            .device
-                            .capture_image_data(
-                                self.ctx.swapchain.get_current_image(),
-                                self.ctx.swapchain.extent(),
-                                |tex| state.recorder.screenshot(tex),
-                            )
-                            .map_err(|err| error!("{err}"));
+                    NamedKey::F6 => {
+                        let path = Path::new(PROFILE_FOLDER).join(format!(
+                            "gpu-trace-{}.json",
+                            chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+                        ));
+                        let res = create_folder(PROFILE_FOLDER)
+                            .and_then(|()| self.device.profiler.lock().write_chrome_trace(&path));
+                        match res {
+                            Ok(()) => info!("Saved GPU trace to {}", path.display()),
+                            Err(err) => error!("Failed to save GPU trace: {err}"),
+                        }
+                    }
+                    // Captures are converted to 8-bit sRGB, HDR output would come out washed out
+                    NamedKey::F7 | NamedKey::F8 if self.ctx.swapchain.color_space().is_hdr() => {
+                        warn!("Screenshots and recordings need an SDR swapchain");
                     }
+                    NamedKey::F7 => state.screenshot_requested = true,
                     NamedKey::F8 => {
                         if !state.recorder.is_active() {
                             let mut image_dimensions = self.ctx.swapchain.image_dimensions();
@@ -434,6 +568,11 @@ impl<F: Framework> ApplicationHandler<UserEvent> for AppInit<F> {
                             state.recorder.finish();
                         }
                     }
+                    NamedKey::F9 => {
+                        let vsync = !self.ctx.swapchain.vsync();
+                        self.ctx.set_vsync(vsync);
+                        info!("Vsync: {vsync}");
+                    }
                     _ => {}
                 }
             }
@@ -461,6 +600,9 @@ impl<F: Framework> ApplicationHandler<UserEvent> for AppInit<F> {
                         Err(err) => warn!("{err}"),
                     }
                 }
+                if let Err(err) = self.update_render_scale() {
+                    warn!("{err}");
+                }
 
                 match self.draw() {
                     Ok(()) => {}
@@ -499,22 +641,14 @@ impl<F: Framework> ApplicationHandler<UserEvent> for AppInit<F> {
 
     fn user_event(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop, event: UserEvent) {
         match event {
-            UserEvent::Glsl { path } => {
-                match self.reload_shaders(path) {
-                    Err(err) => eprintln!("{err}"),
-                    Ok(()) => {
-                        const ESC: &str = "\x1B[";
-                        const RESET: &str = "\x1B[0m";
-                        eprint!("\r{ESC}42m{ESC}K{RESET}\r");
-                        std::io::stdout().flush().unwrap();
-                        std::thread::spawn(|| {
-                            std::thread::sleep(std::time::Duration::from_millis(50));
-                            eprint!("\r{ESC}40m{ESC}K{RESET}\r");
-                            std::io::stdout().flush().unwrap();
-                        });
-                    }
-                };
+            #[cfg(feature = "hot-reload")]
+            UserEvent::Shader { path } => {
+                if let Err(err) = self.reload_shaders(path) {
+                    eprintln!("{err}");
+                }
             }
+            #[cfg(not(feature = "hot-reload"))]
+            UserEvent::Shader { .. } => {}
         }
     }
 
@@ -549,8 +683,25 @@ impl<F: Framework> ApplicationHandler<UserEvent> for App<F> {
         let Args {
             recording_time,
             inner_size,
+            precompile_shaders,
         } = parse_args().unwrap();
 
+        // Compiled straight from the source tree, no window or device involved
+        if let Some(path) = precompile_shaders {
+            #[cfg(feature = "hot-reload")]
+            {
+                let bundle = ShaderBundle::compile_tree(F::shader_roots());
+                match bundle.and_then(|bundle| bundle.save(&path).map(|()| bundle.len())) {
+                    Ok(count) => info!("Saved {count} shaders to {path:?}"),
+                    Err(err) => error!("{err:#}"),
+                }
+            }
+            #[cfg(not(feature = "hot-reload"))]
+            error!("Can't precompile shaders into {path:?} without the hot-reload feature");
+            event_loop.exit();
+            return;
+        }
+
         let mut window_attributes = WindowAttributes::default().with_title(F::name());
         if let Some(size) = inner_size {
             window_attributes = window_attributes
@@ -567,10 +718,9 @@ impl<F: Framework> ApplicationHandler<UserEvent> for App<F> {
             let window = event_loop
                 .create_window(window_attributes)
                 .expect("Failed to create window");
-            *self = Self::Init(
-                AppInit::new(window, proxy.clone(), recording_time)
-                    .expect("Failed to initialize application"),
-            );
+            let app = AppInit::new(window, proxy.clone(), recording_time)
+                .expect("Failed to initialize application");
+            *self = Self::Init(app);
         }
     }
 
@@ -0,0 +1,85 @@
+#version 460
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+
+#include <exposure.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    ExposureBuf exposure_ptr;
+    float min_ev;
+    float ev_range;
+    float low_percent;
+    float high_percent;
+    float speed_brighten;
+    float speed_darken;
+    float compensation;
+    float delta_time;
+    uint reset;
+}
+pc;
+
+layout(local_size_x = 256, local_size_y = 1, local_size_z = 1) in;
+
+shared uint sh_histogram[EXPOSURE_HISTOGRAM_BINS];
+
+const float MID_GREY = 0.18;
+
+float bin_ev(uint bin) {
+    float t = (float(bin) - 0.5) / float(EXPOSURE_HISTOGRAM_BINS - 2);
+    return pc.min_ev + clamp(t, 0., 1.) * pc.ev_range;
+}
+
+void main() {
+    const uint lidx = gl_LocalInvocationIndex;
+
+    sh_histogram[lidx] = pc.exposure_ptr.data.histogram[lidx];
+    // Cleared for the next frame's histogram pass
+    pc.exposure_ptr.data.histogram[lidx] = 0;
+    barrier();
+
+    if (lidx != 0) {
+        return;
+    }
+
+    // Bin 0 holds the pixels too dark to meter, they stay out of the average
+    float total = 0.;
+    for (uint i = 1; i < EXPOSURE_HISTOGRAM_BINS; i++) {
+        total += float(sh_histogram[i]);
+    }
+
+    // Ignore the darkest and brightest parts of the frame so small highlights
+    // and shadows don't swing the exposure
+    float low = total * pc.low_percent;
+    float high = total * pc.high_percent;
+    float ev_sum = 0.;
+    float weight_sum = 0.;
+    for (uint i = 1; i < EXPOSURE_HISTOGRAM_BINS; i++) {
+        float count = float(sh_histogram[i]);
+        float offset = min(low, count);
+        count -= offset;
+        low -= offset;
+        high -= offset;
+        count = min(high, count);
+        high -= count;
+
+        ev_sum += count * bin_ev(i);
+        weight_sum += count;
+    }
+
+    float previous_ev = pc.exposure_ptr.data.ev;
+    float target_ev = weight_sum > 0. ? ev_sum / weight_sum : previous_ev;
+    target_ev = clamp(target_ev, pc.min_ev, pc.min_ev + pc.ev_range);
+
+    float ev = target_ev;
+    if (pc.reset == 0) {
+        // A darker scene needs a higher exposure, so it brightens
+        float speed =
+            target_ev < previous_ev ? pc.speed_brighten : pc.speed_darken;
+        ev = previous_ev +
+             (target_ev - previous_ev) * (1. - exp(-pc.delta_time * speed));
+    }
+
+    pc.exposure_ptr.data.ev = ev;
+    pc.exposure_ptr.data.average_luminance = exp2(ev);
+    pc.exposure_ptr.data.exposure = MID_GREY / exp2(ev - pc.compensation);
+}
@@ -0,0 +1,79 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+
+#include <exposure.glsl>
+#include <textures.glsl>
+
+// Matches MeteringMode on the host
+const uint METERING_AVERAGE = 0;
+const uint METERING_CENTER_WEIGHTED = 1;
+const uint METERING_MASK = 2;
+
+// Fixed point scale for the per pixel metering weight
+const float WEIGHT_SCALE = 64.;
+
+layout(scalar, push_constant) uniform PushConstant {
+    ExposureBuf exposure_ptr;
+    uint source_img;
+    uint mask_img;
+    uint metering;
+    float min_ev;
+    float inv_ev_range;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+shared uint sh_histogram[EXPOSURE_HISTOGRAM_BINS];
+
+float luminance(vec3 c) { return dot(c, vec3(0.2126, 0.7152, 0.0722)); }
+
+// Bin 0 holds pixels that are too dark to carry any information
+uint luminance_bin(float lum) {
+    if (lum < 1e-6) {
+        return 0;
+    }
+    float t = clamp((log2(lum) - pc.min_ev) * pc.inv_ev_range, 0., 1.);
+    return uint(t * float(EXPOSURE_HISTOGRAM_BINS - 2) + 1.);
+}
+
+float metering_weight(vec2 uv) {
+    switch (pc.metering) {
+    case METERING_CENTER_WEIGHTED:
+        return 1. - smoothstep(0., 0.75, length(uv * 2. - 1.));
+    case METERING_MASK:
+        return TexLinear(pc.mask_img, uv).r;
+    default:
+        return 1.;
+    }
+}
+
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    const uint lidx = gl_LocalInvocationIndex;
+
+    sh_histogram[lidx] = 0;
+    barrier();
+
+    ivec2 dims = textureSize(gtextures[pc.source_img], 0);
+    if (all(lessThan(gid, dims))) {
+        vec3 color = texelFetch(gtextures[pc.source_img], gid, 0).rgb;
+        vec2 uv = (vec2(gid) + 0.5) / vec2(dims);
+        uint weight = uint(metering_weight(uv) * WEIGHT_SCALE + 0.5);
+        if (weight > 0) {
+            atomicAdd(sh_histogram[luminance_bin(luminance(color))], weight);
+        }
+    }
+    barrier();
+
+    if (sh_histogram[lidx] > 0) {
+        atomicAdd(pc.exposure_ptr.data.histogram[lidx], sh_histogram[lidx]);
+    }
+}
@@ -0,0 +1,342 @@
+use std::{mem, sync::Arc};
+
+use anyhow::Result;
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+
+use crate::{
+    AppState, Buffer, BufferUsage, ComputeHandle, Device, FrameGuard, ImageHandle, ImageTransition,
+    ImageUsage, PassBuilder, RenderContext, ViewTarget, dispatch_optimal,
+    passes::stack::{PostProcessInputs, PostProcessParams, PostProcessPass, TargetAccess},
+};
+
+const HISTOGRAM_BINS: usize = 256;
+// Values past this are a hitch, adapting over them would jump the exposure
+const MAX_DELTA_TIME: f32 = 0.25;
+
+// Layout of the Exposure struct in shaders/exposure.glsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ExposureData {
+    pub exposure: f32,
+    pub ev: f32,
+    pub average_luminance: f32,
+    pub histogram: [u32; HISTOGRAM_BINS],
+}
+
+// Values match the METERING_* constants in histogram.comp.glsl
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MeteringMode {
+    #[default]
+    Average,
+    CenterWeighted,
+    // The red channel weights each pixel, stretched over the whole frame
+    Mask(ImageHandle),
+}
+
+impl MeteringMode {
+    fn as_raw(self) -> u32 {
+        match self {
+            Self::Average => 0,
+            Self::CenterWeighted => 1,
+            Self::Mask(_) => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AutoExposureSettings {
+    // Range of log2 average luminance the exposure adapts to
+    pub min_ev: f32,
+    pub max_ev: f32,
+    // Rates per second, brightening happens when going into a darker scene
+    pub speed_brighten: f32,
+    pub speed_darken: f32,
+    // Fractions of the histogram ignored from the dark and from the bright end
+    pub low_percent: f32,
+    pub high_percent: f32,
+    // In stops
+    pub compensation: f32,
+    pub metering: MeteringMode,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            min_ev: -8.,
+            max_ev: 8.,
+            speed_brighten: 3.,
+            speed_darken: 1.,
+            low_percent: 0.1,
+            high_percent: 0.9,
+            compensation: 0.,
+            metering: MeteringMode::default(),
+        }
+    }
+}
+
+pub struct AutoExposureParams<'a> {
+    pub view_target: &'a ViewTarget,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct HistogramPC {
+    exposure_buffer: u64,
+    source_image: u32,
+    mask_image: u32,
+    metering: u32,
+    min_ev: f32,
+    inv_ev_range: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct AveragePC {
+    exposure_buffer: u64,
+    min_ev: f32,
+    ev_range: f32,
+    low_percent: f32,
+    high_percent: f32,
+    speed_brighten: f32,
+    speed_darken: f32,
+    compensation: f32,
+    delta_time: f32,
+    reset: u32,
+}
+
+pub struct AutoExposure {
+    histogram_pipeline: ComputeHandle,
+    average_pipeline: ComputeHandle,
+    exposure_buffer: Buffer,
+    last_time: Option<f32>,
+    device: Arc<Device>,
+
+    pub settings: AutoExposureSettings,
+}
+
+impl AutoExposure {
+    pub fn new(
+        ctx: &RenderContext,
+        state: &mut AppState,
+        settings: AutoExposureSettings,
+    ) -> Result<Self> {
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<HistogramPC>() as u32);
+        let histogram_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/auto_exposure/histogram.comp.glsl",
+            &[push_constant_range],
+            &[state.texture_arena.sampled_set_layout],
+        )?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<AveragePC>() as u32);
+        let average_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/auto_exposure/average.comp.glsl",
+            &[push_constant_range],
+            &[],
+        )?;
+
+        let exposure_buffer = ctx.device.create_buffer(
+            mem::size_of::<ExposureData>() as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuOnly,
+        )?;
+        ctx.device.one_time_submit(|device, cbuff| unsafe {
+            device.cmd_fill_buffer(cbuff, exposure_buffer.buffer, 0, vk::WHOLE_SIZE, 0);
+            Ok(())
+        })?;
+
+        Ok(Self {
+            histogram_pipeline,
+            average_pipeline,
+            exposure_buffer,
+            last_time: None,
+            device: ctx.device.clone(),
+            settings,
+        })
+    }
+
+    // Holds ExposureData, its address goes into TonemapParams and BloomParams
+    pub fn exposure_buffer(&self) -> &Buffer {
+        &self.exposure_buffer
+    }
+
+    // Snaps to the current scene on the next frame instead of adapting
+    pub fn reset(&mut self) {
+        self.last_time = None;
+    }
+
+    pub fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: AutoExposureParams,
+    ) {
+        let _marker = ctx
+            .device
+            .create_scoped_marker(frame.command_buffer(), "Auto Exposure Pass");
+        let texture_arena = &mut state.texture_arena;
+        let settings = self.settings;
+        let source_image = *params.view_target.main_image();
+        let ev_range = (settings.max_ev - settings.min_ev).max(f32::EPSILON);
+
+        let delta_time = self
+            .last_time
+            .map_or(0., |last| (state.time - last).clamp(0., MAX_DELTA_TIME));
+        let reset = self.last_time.is_none() || state.history_reset;
+        self.last_time = Some(state.time);
+
+        let mut transitions = vec![ImageTransition::new(
+            source_image,
+            ImageUsage::ComputeSampled,
+        )];
+        let mask_image = match settings.metering {
+            MeteringMode::Mask(mask) => {
+                transitions.push(ImageTransition::new(mask, ImageUsage::ComputeSampled));
+                Some(mask)
+            }
+            _ => None,
+        };
+        texture_arena.transitions(frame.command_buffer(), &transitions);
+
+        // Last frame's readers have to finish before the histogram accumulates again
+        self.buffer_barrier(
+            frame.command_buffer(),
+            vk::PipelineStageFlags2::COMPUTE_SHADER | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE,
+        );
+
+        {
+            let push_constant = HistogramPC {
+                exposure_buffer: self.exposure_buffer.address,
+                source_image: texture_arena.get_sampled_idx(source_image, 0),
+                mask_image: mask_image.map_or(0, |mask| texture_arena.get_sampled_idx(mask, 0)),
+                metering: settings.metering.as_raw(),
+                min_ev: settings.min_ev,
+                inv_ev_range: 1. / ev_range,
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.histogram_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            let extent = texture_arena.get_image(source_image).info.unwrap().extent;
+            const SUBGROUP_SIZE: u32 = 16;
+            frame.dispatch(
+                dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+
+        self.buffer_barrier(
+            frame.command_buffer(),
+            vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_WRITE,
+        );
+
+        {
+            let push_constant = AveragePC {
+                exposure_buffer: self.exposure_buffer.address,
+                min_ev: settings.min_ev,
+                ev_range,
+                low_percent: settings.low_percent,
+                high_percent: settings.high_percent,
+                speed_brighten: settings.speed_brighten,
+                speed_darken: settings.speed_darken,
+                compensation: settings.compensation,
+                delta_time,
+                reset: reset as u32,
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.average_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(1, 1, 1);
+        }
+    }
+
+    fn buffer_barrier(
+        &self,
+        cbuff: &vk::CommandBuffer,
+        src_stages: vk::PipelineStageFlags2,
+        src_access: vk::AccessFlags2,
+    ) {
+        let barrier = vk::BufferMemoryBarrier2::default()
+            .buffer(self.exposure_buffer.buffer)
+            .size(vk::WHOLE_SIZE)
+            .src_stage_mask(src_stages)
+            .src_access_mask(src_access)
+            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE);
+        self.device.pipeline_barrier(
+            cbuff,
+            &vk::DependencyInfo::default().buffer_memory_barriers(std::slice::from_ref(&barrier)),
+        );
+    }
+}
+
+impl PostProcessPass for AutoExposure {
+    type Settings = AutoExposureSettings;
+    const NAME: &'static str = "Auto Exposure";
+
+    fn new(
+        ctx: &RenderContext,
+        state: &mut AppState,
+        settings: AutoExposureSettings,
+    ) -> Result<Self> {
+        Self::new(ctx, state, settings)
+    }
+
+    fn settings(&self) -> &AutoExposureSettings {
+        &self.settings
+    }
+
+    fn set_settings(
+        &mut self,
+        _ctx: &RenderContext,
+        _state: &mut AppState,
+        settings: AutoExposureSettings,
+    ) -> Result<()> {
+        self.settings = settings;
+        Ok(())
+    }
+
+    fn access(&self) -> TargetAccess {
+        TargetAccess::Read
+    }
+
+    fn declare<'g, 'a>(
+        &self,
+        pass: PassBuilder<'g, 'a>,
+        _inputs: &PostProcessInputs,
+    ) -> PassBuilder<'g, 'a> {
+        pass.write_buffer(self.exposure_buffer.buffer, BufferUsage::ComputeWrite)
+    }
+
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    ) {
+        let params = AutoExposureParams {
+            view_target: params.view_target,
+        };
+        AutoExposure::apply(self, ctx, state, frame, params);
+    }
+}
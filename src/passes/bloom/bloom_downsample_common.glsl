@@ -3,13 +3,18 @@ layout(set = 0, binding = 1) uniform texture2D gtextures[];
 layout(set = 1, binding = 0) writeonly coherent
     restrict uniform image2D gstorage[];
 
+#include <exposure.glsl>
 #include <textures.glsl>
 
 layout(scalar, push_constant) uniform PushConstant {
+    ExposureBuf exposure_ptr;
     uint source_img;
     uint target_img;
     uvec2 source_dim;
     uvec2 target_dim;
+    bool use_exposure_buffer;
+    float threshold;
+    float knee;
 }
 pc;
 
@@ -15,16 +15,32 @@ layout(set = 1, binding = 0) uniform readonly image2D gstorage[];
 
 float luminance(vec3 c) { return dot(c, vec3(0.2126, 0.7152, 0.0722)); }
 
-// Reduce the dynamic range of the input samples
+// Reduce the dynamic range of the input samples, weighted by what the
+// tonemapper will see so fireflies are handled alike in dark and bright scenes
 vec3 karis_average(vec3 c1, vec3 c2, vec3 c3, vec3 c4) {
-    float w1 = 1.0 / (luminance(c1.rgb) + 1.0);
-    float w2 = 1.0 / (luminance(c2.rgb) + 1.0);
-    float w3 = 1.0 / (luminance(c3.rgb) + 1.0);
-    float w4 = 1.0 / (luminance(c4.rgb) + 1.0);
+    float exposure = pc.use_exposure_buffer ? pc.exposure_ptr.data.exposure : 1.;
+    float w1 = 1.0 / (luminance(c1.rgb) * exposure + 1.0);
+    float w2 = 1.0 / (luminance(c2.rgb) * exposure + 1.0);
+    float w3 = 1.0 / (luminance(c3.rgb) * exposure + 1.0);
+    float w4 = 1.0 / (luminance(c4.rgb) * exposure + 1.0);
 
     return (c1 * w1 + c2 * w2 + c3 * w3 + c4 * w4) / (w1 + w2 + w3 + w4);
 }
 
+// Quadratic curve below the threshold, knee sets how far below it starts
+vec3 soft_threshold(vec3 color) {
+    if (pc.threshold <= 0.) {
+        return color;
+    }
+    float exposure = pc.use_exposure_buffer ? pc.exposure_ptr.data.exposure : 1.;
+    float brightness = max(color.r, max(color.g, color.b)) * exposure;
+    float knee = pc.threshold * pc.knee;
+    float soft = clamp(brightness - pc.threshold + knee, 0., 2. * knee);
+    soft = soft * soft / (4. * knee + 1e-5);
+    float contribution = max(soft, brightness - pc.threshold);
+    return color * contribution / max(brightness, 1e-5);
+}
+
 void main() {
     const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
     const ivec2 lid = ivec2(gl_LocalInvocationID.xy);
@@ -65,5 +81,5 @@ void main() {
     filterSum +=
         karis_average(samples[6], samples[7], samples[11], samples[12]) * 0.125;
 
-    imageStore(gstorage[pc.target_img], gid, vec4(filterSum, 1.0));
+    imageStore(gstorage[pc.target_img], gid, vec4(soft_threshold(filterSum), 1.0));
 }
@@ -15,9 +15,20 @@ layout(scalar, push_constant) uniform PushConstant {
     float strength;
     uint num_passes;
     bool is_final_pass;
+    vec3 source_tint;
+    vec3 target_tint;
+    uint composite;
+    uint lens_dirt_img;
+    float lens_dirt_intensity;
 }
 pc;
 
+// Matches BloomComposite on the host
+const uint COMPOSITE_ENERGY_CONSERVING = 0;
+const uint COMPOSITE_ADDITIVE = 1;
+
+const uint NO_TEXTURE = 0xFFFFFFFF;
+
 layout(set = 0, binding = 0) uniform sampler gsamplers[];
 layout(set = 0, binding = 1) uniform texture2D gtextures[];
 layout(set = 1, binding = 0) writeonly coherent
@@ -62,11 +73,23 @@ void main() {
     blur_sum += tex_lod(source_img, uv + vec2(0, 1) * width) * 2.0 / 16.0;
     blur_sum += tex_lod(source_img, uv + vec2(1, 1) * width) * 1.0 / 16.0;
 
+    blur_sum.rgb *= pc.source_tint;
+
     if (pc.is_final_pass) {
-        // Conserve energy
-        rgba = mix(rgba, blur_sum / pc.num_passes, pc.strength);
+        vec4 bloom = blur_sum / pc.num_passes;
+        if (pc.lens_dirt_img != NO_TEXTURE) {
+            vec3 dirt = tex_lod(pc.lens_dirt_img, uv).rgb;
+            bloom.rgb *= 1. + dirt * pc.lens_dirt_intensity;
+        }
+        if (pc.composite == COMPOSITE_ADDITIVE) {
+            rgba += bloom * pc.strength;
+        } else {
+            // Conserve energy
+            rgba = mix(rgba, bloom, pc.strength);
+        }
     } else {
         // Accumulate
+        rgba.rgb *= pc.target_tint;
         rgba += blur_sum;
     }
 
@@ -1,31 +1,105 @@
 // https://github.com/JuanDiegoMontoya/Frogfood/blob/main/src/techniques/Bloom.cpp
 
-use std::{mem, sync::Arc};
+use std::mem;
 
 use anyhow::Result;
 use ash::vk;
-use glam::{uvec2, UVec2};
+use glam::{uvec2, UVec2, Vec3};
 
 use crate::{
-    dispatch_optimal, AppState, ComputeHandle, Device, FrameGuard, ImageHandle, RenderContext,
-    ScreenRelation, COLOR_SUBRESOURCE_MASK,
+    dispatch_optimal,
+    passes::stack::{PostProcessInputs, PostProcessParams, PostProcessPass, TargetAccess},
+    AppState, BufferUsage, ComputeHandle, FrameGuard, ImageHandle, ImageTransition, ImageUsage,
+    PassBuilder, RenderContext, ScreenRelation, MAX_MIPCOUNT,
 };
 
+const NO_TEXTURE: u32 = u32::MAX;
+
+// Values match the COMPOSITE_* constants in bloom_upsample.comp.glsl
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BloomComposite {
+    // Blends towards the blurred image, pair with a zero threshold
+    #[default]
+    EnergyConserving,
+    // Adds the blurred image on top, pair with a threshold
+    Additive,
+}
+
+impl BloomComposite {
+    fn as_raw(self) -> u32 {
+        match self {
+            Self::EnergyConserving => 0,
+            Self::Additive => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BloomSettings {
+    // Changing any of these three rebuilds the accumulation texture
+    pub mip_count: u32,
+    pub format: vk::Format,
+    pub resolution: ScreenRelation,
+
+    pub strength: f32,
+    // Upsample filter radius in source texels
+    pub width: f32,
+    // Exposed brightness where bloom starts, 0 disables the prefilter
+    pub threshold: f32,
+    // Fraction of the threshold blended in softly below it
+    pub knee: f32,
+    // Per mip color weights starting at the largest mip, missing entries are white
+    pub tints: Vec<Vec3>,
+    pub lens_dirt: Option<ImageHandle>,
+    pub lens_dirt_intensity: f32,
+    pub composite: BloomComposite,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            mip_count: 6,
+            format: vk::Format::B10G11R11_UFLOAT_PACK32,
+            resolution: ScreenRelation::Half,
+            strength: 4. / 16.,
+            width: 2.,
+            threshold: 0.,
+            knee: 0.5,
+            tints: vec![],
+            lens_dirt: None,
+            lens_dirt_intensity: 1.,
+            composite: BloomComposite::default(),
+        }
+    }
+}
+
+impl BloomSettings {
+    fn tint(&self, mip_level: u32) -> Vec3 {
+        self.tints
+            .get(mip_level as usize)
+            .copied()
+            .unwrap_or(Vec3::ONE)
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct BloomParams {
     pub target_image: ImageHandle,
-    pub target_current_layout: vk::ImageLayout,
-    pub strength: f32,
-    pub width: f32,
+    // Address of the AutoExposure buffer, the prefilter weights exposed luminance
+    pub exposure_buffer: Option<u64>,
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 struct DownsamplePC {
+    exposure_buffer: u64,
     source_sampled_img_idx: u32,
     target_storage_img_idx: u32,
     source_dims: UVec2,
     target_dims: UVec2,
+    use_exposure_buffer: u32,
+    threshold: f32,
+    knee: f32,
 }
 
 #[repr(C)]
@@ -40,20 +114,23 @@ struct UpsamplePC {
     strength: f32,
     num_passes: u32,
     is_final_pass: u32,
+    source_tint: Vec3,
+    target_tint: Vec3,
+    composite: u32,
+    lens_dirt_img_idx: u32,
+    lens_dirt_intensity: f32,
 }
 
 pub struct Bloom {
     downsample_pass: ComputeHandle,
     downsample_low_pass: ComputeHandle,
     upsample_pass: ComputeHandle,
-    miplevel_count: u32,
     accum_texture: ImageHandle,
-    device: Arc<Device>,
+    settings: BloomSettings,
 }
 
 impl Bloom {
-    pub fn new(ctx: &RenderContext, state: &mut AppState) -> Result<Self> {
-        let miplevel_count = 6;
+    pub fn new(ctx: &RenderContext, state: &mut AppState, settings: BloomSettings) -> Result<Self> {
         let push_constant_range = vk::PushConstantRange::default()
             .stage_flags(vk::ShaderStageFlags::COMPUTE)
             .size(mem::size_of::<DownsamplePC>() as u32);
@@ -79,37 +156,47 @@ impl Bloom {
             &[push_constant_range],
             &desc_layouts,
         )?;
-        let texture_info = vk::ImageCreateInfo::default()
-            .extent(vk::Extent3D {
-                width: ctx.swapchain.extent.width,
-                height: ctx.swapchain.extent.height,
-                depth: 1,
-            })
-            .image_type(vk::ImageType::TYPE_2D)
-            .format(vk::Format::B10G11R11_UFLOAT_PACK32)
-            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .mip_levels(miplevel_count)
-            .array_layers(1)
-            .tiling(vk::ImageTiling::OPTIMAL)
-            .initial_layout(vk::ImageLayout::UNDEFINED);
-        let accum_texture = state.texture_arena.push_image(
-            texture_info,
-            ScreenRelation::Half,
-            &[],
-            Some("Accumulation Texture"),
-        )?;
+        let settings = BloomSettings {
+            mip_count: settings.mip_count.clamp(1, MAX_MIPCOUNT as u32),
+            ..settings
+        };
+        let accum_texture = create_accum_texture(ctx, state, &settings)?;
 
         Ok(Self {
             downsample_pass,
             downsample_low_pass,
             upsample_pass,
-            miplevel_count,
             accum_texture,
-            device: ctx.device.clone(),
+            settings,
         })
     }
 
+    pub fn settings(&self) -> &BloomSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        settings: BloomSettings,
+    ) -> Result<()> {
+        let settings = BloomSettings {
+            mip_count: settings.mip_count.clamp(1, MAX_MIPCOUNT as u32),
+            ..settings
+        };
+        let rebuild = settings.mip_count != self.settings.mip_count
+            || settings.format != self.settings.format
+            || settings.resolution != self.settings.resolution;
+        if rebuild {
+            // Frames in flight may still sample the old texture
+            state.texture_arena.retire_image(self.accum_texture);
+            self.accum_texture = create_accum_texture(ctx, state, &settings)?;
+        }
+        self.settings = settings;
+        Ok(())
+    }
+
     pub fn apply(
         &self,
         ctx: &RenderContext,
@@ -117,61 +204,43 @@ impl Bloom {
         frame: &FrameGuard,
         params: BloomParams,
     ) {
-        let _marker = self
+        let _marker = ctx
             .device
             .create_scoped_marker(frame.command_buffer(), "Bloom Pass");
-        let screen_extent = ctx.swapchain.extent;
-        let source_image = params.target_image;
+        let settings = &self.settings;
         let texture_arena = &mut state.texture_arena;
-
-        ctx.device.image_transition(
-            frame.command_buffer(),
-            &texture_arena.get_image(source_image).inner,
-            params.target_current_layout,
-            vk::ImageLayout::GENERAL,
-        );
-        ctx.device.image_transition(
-            frame.command_buffer(),
-            &texture_arena.get_image(self.accum_texture).inner,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::GENERAL,
-        );
-
-        let image_barriers = {
-            let image_barrier = vk::ImageMemoryBarrier2::default()
-                .subresource_range(COLOR_SUBRESOURCE_MASK)
-                .src_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
-                .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
-                .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
-                .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
-            [
-                image_barrier.image(texture_arena.get_image(params.target_image).inner),
-                image_barrier.image(texture_arena.get_image(self.accum_texture).inner),
-            ]
+        let extent_of = |image| {
+            let extent = texture_arena.get_image(image).info.unwrap().extent;
+            uvec2(extent.width, extent.height)
         };
+        let screen_dims = extent_of(params.target_image);
+        let accum_dims = extent_of(self.accum_texture);
+        let mip_dims = |mip_level: u32| (accum_dims >> mip_level).max(UVec2::ONE);
 
-        for i in 0..self.miplevel_count {
-            ctx.device.pipeline_barrier(
-                frame.command_buffer(),
-                &vk::DependencyInfo::default().image_memory_barriers(&image_barriers),
-            );
-
-            let target_dims = uvec2(screen_extent.width, screen_extent.height) >> (i + 1);
+        for i in 0..settings.mip_count {
+            let target_dims = mip_dims(i);
 
             let (pipeline, source_lod, source_texture, source_dims, workgroup_size);
             if i == 0 {
                 pipeline = self.downsample_low_pass;
                 source_lod = 0;
                 source_texture = params.target_image;
-                source_dims = uvec2(screen_extent.width, screen_extent.height);
+                source_dims = screen_dims;
                 workgroup_size = 16;
             } else {
                 pipeline = self.downsample_pass;
                 source_lod = i - 1;
                 source_texture = self.accum_texture;
-                source_dims = uvec2(screen_extent.width, screen_extent.height) >> i;
+                source_dims = mip_dims(i - 1);
                 workgroup_size = 8;
             }
+            texture_arena.transitions(
+                frame.command_buffer(),
+                &[
+                    ImageTransition::mip(source_texture, source_lod, ImageUsage::ComputeSampled),
+                    ImageTransition::mip(self.accum_texture, i, ImageUsage::ComputeStorage),
+                ],
+            );
 
             let downsample_pipeline = state.pipeline_arena.get_pipeline(pipeline);
             frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, downsample_pipeline);
@@ -179,11 +248,15 @@ impl Bloom {
                 downsample_pipeline.layout,
                 vk::ShaderStageFlags::COMPUTE,
                 &[DownsamplePC {
+                    exposure_buffer: params.exposure_buffer.unwrap_or_default(),
                     source_sampled_img_idx: texture_arena
                         .get_sampled_idx(source_texture, source_lod),
                     target_storage_img_idx: texture_arena.get_storage_idx(self.accum_texture, i),
                     source_dims,
                     target_dims,
+                    use_exposure_buffer: params.exposure_buffer.is_some() as u32,
+                    threshold: settings.threshold,
+                    knee: settings.knee,
                 }],
             );
             frame.bind_descriptor_sets(
@@ -207,23 +280,44 @@ impl Bloom {
             &[texture_arena.sampled_set, texture_arena.storage_set],
         );
 
-        for i in (0..self.miplevel_count).rev() {
-            ctx.device.pipeline_barrier(
+        let lens_dirt = settings
+            .lens_dirt
+            .filter(|_| settings.lens_dirt_intensity > 0.);
+        if let Some(lens_dirt) = lens_dirt {
+            texture_arena.transition(
                 frame.command_buffer(),
-                &vk::DependencyInfo::default().image_memory_barriers(&image_barriers),
+                lens_dirt,
+                ImageUsage::ComputeSampled,
             );
+        }
 
-            let source_dims = uvec2(screen_extent.width, screen_extent.height) >> (i + 1);
-            let (target_lod, target_texture, target_dims);
+        for i in (0..settings.mip_count).rev() {
+            let source_dims = mip_dims(i);
+            let (target_lod, target_texture, target_dims, target_tint);
             if i == 0 {
                 target_lod = 0;
                 target_texture = params.target_image;
-                target_dims = uvec2(screen_extent.width, screen_extent.height);
+                target_dims = screen_dims;
+                target_tint = Vec3::ONE;
             } else {
                 target_lod = i - 1;
                 target_texture = self.accum_texture;
-                target_dims = uvec2(screen_extent.width, screen_extent.height) >> i;
+                target_dims = mip_dims(i - 1);
+                target_tint = settings.tint(i - 1);
             }
+            // Deeper mips already carry their tint in the accumulated source
+            let source_tint = match i == settings.mip_count - 1 {
+                true => settings.tint(i),
+                false => Vec3::ONE,
+            };
+            // The target is both sampled and written, so it stays in GENERAL
+            texture_arena.transitions(
+                frame.command_buffer(),
+                &[
+                    ImageTransition::mip(self.accum_texture, i, ImageUsage::ComputeSampled),
+                    ImageTransition::mip(target_texture, target_lod, ImageUsage::ComputeStorage),
+                ],
+            );
 
             frame.bind_push_constants(
                 upsample_pipeline.layout,
@@ -236,10 +330,16 @@ impl Bloom {
                         .get_storage_idx(target_texture, target_lod),
                     source_dims,
                     target_dims,
-                    width: params.width,
-                    strength: params.strength,
-                    num_passes: self.miplevel_count,
+                    width: settings.width,
+                    strength: settings.strength,
+                    num_passes: settings.mip_count,
                     is_final_pass: (i == 0) as u32,
+                    source_tint,
+                    target_tint,
+                    composite: settings.composite.as_raw(),
+                    lens_dirt_img_idx: lens_dirt
+                        .map_or(NO_TEXTURE, |dirt| texture_arena.get_sampled_idx(dirt, 0)),
+                    lens_dirt_intensity: settings.lens_dirt_intensity,
                 }],
             );
             frame.dispatch(
@@ -248,11 +348,83 @@ impl Bloom {
                 1,
             );
         }
-        ctx.device.image_transition(
-            frame.command_buffer(),
-            &texture_arena.get_image(source_image).inner,
-            vk::ImageLayout::GENERAL,
-            params.target_current_layout,
-        );
     }
 }
+
+impl PostProcessPass for Bloom {
+    type Settings = BloomSettings;
+    const NAME: &'static str = "Bloom";
+
+    fn new(ctx: &RenderContext, state: &mut AppState, settings: BloomSettings) -> Result<Self> {
+        Self::new(ctx, state, settings)
+    }
+
+    fn settings(&self) -> &BloomSettings {
+        &self.settings
+    }
+
+    fn set_settings(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        settings: BloomSettings,
+    ) -> Result<()> {
+        Bloom::set_settings(self, ctx, state, settings)
+    }
+
+    fn access(&self) -> TargetAccess {
+        TargetAccess::InPlace
+    }
+
+    fn declare<'g, 'a>(
+        &self,
+        pass: PassBuilder<'g, 'a>,
+        inputs: &PostProcessInputs,
+    ) -> PassBuilder<'g, 'a> {
+        match inputs.exposure_buffer {
+            Some((buffer, _)) => pass.read_buffer(buffer, BufferUsage::ComputeRead),
+            None => pass,
+        }
+    }
+
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    ) {
+        let params = BloomParams {
+            target_image: *params.view_target.main_image(),
+            exposure_buffer: params.exposure_buffer,
+        };
+        Bloom::apply(self, ctx, state, frame, params);
+    }
+}
+
+fn create_accum_texture(
+    ctx: &RenderContext,
+    state: &mut AppState,
+    settings: &BloomSettings,
+) -> Result<ImageHandle> {
+    let texture_info = vk::ImageCreateInfo::default()
+        .extent(vk::Extent3D {
+            width: ctx.swapchain.extent.width,
+            height: ctx.swapchain.extent.height,
+            depth: 1,
+        })
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(settings.format)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .mip_levels(settings.mip_count)
+        .array_layers(1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .initial_layout(vk::ImageLayout::UNDEFINED);
+    state.texture_arena.push_image(
+        texture_info,
+        settings.resolution,
+        &[],
+        Some("Accumulation Texture"),
+    )
+}
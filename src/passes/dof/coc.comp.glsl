@@ -0,0 +1,42 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint depth_img;
+    uint coc_img;
+    float coc_scale;
+    float focus_distance;
+    float max_coc_radius;
+    float znear;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+// Thin lens circle of confusion, the depth may be at a lower resolution
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.coc_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+    vec2 uv = (vec2(gid) + 0.5) / vec2(dims);
+
+    // Reverse-Z with an infinite far plane
+    float raw_depth = TexNear(pc.depth_img, uv).x;
+    float depth = pc.znear / max(raw_depth, 1e-7);
+
+    float coc = pc.coc_scale * abs(depth - pc.focus_distance) / depth;
+    coc = min(coc, pc.max_coc_radius);
+    imageStore(gstorage[pc.coc_img], gid, vec4(coc, depth, 0., 0.));
+}
@@ -0,0 +1,62 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint src_img;
+    uint dst_img;
+    uint coc_img;
+    float max_coc_radius;
+    float radius_scale;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+const float GOLDEN_ANGLE = 2.39996323;
+
+// Single pass bokeh gather along a golden angle spiral
+// https://blog.voxagon.se/2018/05/04/bokeh-depth-of-field-in-single-pass.html
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.dst_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+    vec2 inv_dims = 1. / vec2(dims);
+    vec2 uv = (vec2(gid) + 0.5) * inv_dims;
+
+    vec4 center = texelFetch(gtextures[pc.src_img], gid, 0);
+    vec2 center_coc = TexNear(pc.coc_img, uv).xy;
+
+    vec3 color = center.rgb;
+    float total = 1.;
+    float radius = pc.radius_scale;
+    for (float angle = 0.; radius < pc.max_coc_radius; angle += GOLDEN_ANGLE) {
+        vec2 sample_uv = uv + vec2(cos(angle), sin(angle)) * radius * inv_dims;
+        vec3 sample_color = TexLinear(pc.src_img, sample_uv).rgb;
+        vec2 sample_coc = TexNear(pc.coc_img, sample_uv).xy;
+
+        // A blurry background can't spread over a sharper foreground
+        float sample_size = sample_coc.x;
+        if (sample_coc.y > center_coc.y) {
+            sample_size = min(sample_size, center_coc.x * 2.);
+        }
+
+        float m = smoothstep(radius - 0.5, radius + 0.5, sample_size);
+        color += mix(color / total, sample_color, m);
+        total += 1.;
+        radius += pc.radius_scale / radius;
+    }
+
+    imageStore(gstorage[pc.dst_img], gid, vec4(color / total, center.a));
+}
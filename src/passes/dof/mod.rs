@@ -0,0 +1,262 @@
+use std::mem;
+
+use anyhow::Result;
+use ash::vk;
+
+use crate::{
+    AppState, Camera, ComputeHandle, FrameGuard, ImageHandle, ImageTransition, ImageUsage,
+    PassBuilder, RenderContext, ScreenRelation, ViewTarget, dispatch_optimal,
+    passes::stack::{PostProcessInputs, PostProcessParams, PostProcessPass},
+};
+
+// Focus distance and aperture come from Camera::lens
+#[derive(Clone, Copy, Debug)]
+pub struct DofSettings {
+    // Largest circle of confusion radius in output pixels, also bounds the gather
+    pub max_coc_radius: f32,
+    // Spacing of the spiral samples, lower takes more of them
+    pub radius_scale: f32,
+}
+
+impl Default for DofSettings {
+    fn default() -> Self {
+        Self {
+            max_coc_radius: 16.,
+            radius_scale: 0.75,
+        }
+    }
+}
+
+pub struct DofParams<'a> {
+    pub view_target: &'a ViewTarget,
+    pub depth_image: ImageHandle,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CocPC {
+    depth_image: u32,
+    coc_image: u32,
+    coc_scale: f32,
+    focus_distance: f32,
+    max_coc_radius: f32,
+    znear: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GatherPC {
+    src_image: u32,
+    dst_image: u32,
+    coc_image: u32,
+    max_coc_radius: f32,
+    radius_scale: f32,
+}
+
+pub struct Dof {
+    coc_pipeline: ComputeHandle,
+    gather_pipeline: ComputeHandle,
+    // Radius in pixels and linear depth
+    coc_image: ImageHandle,
+
+    pub settings: DofSettings,
+}
+
+impl Dof {
+    pub fn new(ctx: &RenderContext, state: &mut AppState, settings: DofSettings) -> Result<Self> {
+        let desc_layouts = [
+            state.texture_arena.sampled_set_layout,
+            state.texture_arena.storage_set_layout,
+        ];
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<CocPC>() as u32);
+        let coc_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/dof/coc.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<GatherPC>() as u32);
+        let gather_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/dof/gather.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+
+        let coc_info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: ctx.swapchain.extent.width,
+                height: ctx.swapchain.extent.height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R16G16_SFLOAT)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let coc_image = state.texture_arena.push_image(
+            coc_info,
+            ScreenRelation::Identity,
+            &[],
+            Some("Circle Of Confusion"),
+        )?;
+
+        Ok(Self {
+            coc_pipeline,
+            gather_pipeline,
+            coc_image,
+            settings,
+        })
+    }
+
+    pub fn apply(
+        &self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: DofParams,
+    ) {
+        let _marker = ctx
+            .device
+            .create_scoped_marker(frame.command_buffer(), "Depth Of Field Pass");
+        let settings = self.settings;
+        let lens = state.camera.lens;
+        let texture_arena = &mut state.texture_arena;
+        let postprocess_write = params.view_target.post_process_write();
+        let output_extent = texture_arena
+            .get_image(*postprocess_write.destination)
+            .info
+            .unwrap()
+            .extent;
+        const SUBGROUP_SIZE: u32 = 16;
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(params.depth_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.coc_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        {
+            // Sensor diameter to output pixel radius
+            let coc_scale = lens.coc_scale() / lens.sensor_height * output_extent.height as f32;
+            let push_constant = CocPC {
+                depth_image: texture_arena.get_sampled_idx(params.depth_image, 0),
+                coc_image: texture_arena.get_storage_idx(self.coc_image, 0),
+                coc_scale: 0.5 * coc_scale,
+                focus_distance: lens.focus_distance,
+                max_coc_radius: settings.max_coc_radius,
+                znear: Camera::ZNEAR,
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.coc_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            let extent = texture_arena.get_image(self.coc_image).info.unwrap().extent;
+            frame.dispatch(
+                dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(*postprocess_write.source, ImageUsage::ComputeSampled),
+                ImageTransition::new(*postprocess_write.destination, ImageUsage::ComputeStorage),
+                ImageTransition::new(self.coc_image, ImageUsage::ComputeSampled),
+            ],
+        );
+
+        {
+            let push_constant = GatherPC {
+                src_image: texture_arena.get_sampled_idx(*postprocess_write.source, 0),
+                dst_image: texture_arena.get_storage_idx(*postprocess_write.destination, 0),
+                coc_image: texture_arena.get_sampled_idx(self.coc_image, 0),
+                max_coc_radius: settings.max_coc_radius,
+                radius_scale: settings.radius_scale.max(0.1),
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.gather_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(
+                dispatch_optimal(output_extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(output_extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
+
+impl PostProcessPass for Dof {
+    type Settings = DofSettings;
+    const NAME: &'static str = "Depth Of Field";
+
+    fn new(ctx: &RenderContext, state: &mut AppState, settings: DofSettings) -> Result<Self> {
+        Self::new(ctx, state, settings)
+    }
+
+    fn settings(&self) -> &DofSettings {
+        &self.settings
+    }
+
+    fn set_settings(
+        &mut self,
+        _ctx: &RenderContext,
+        _state: &mut AppState,
+        settings: DofSettings,
+    ) -> Result<()> {
+        self.settings = settings;
+        Ok(())
+    }
+
+    fn is_active(&self, inputs: &PostProcessInputs) -> bool {
+        inputs.depth_image.is_some()
+    }
+
+    fn declare<'g, 'a>(
+        &self,
+        pass: PassBuilder<'g, 'a>,
+        inputs: &PostProcessInputs,
+    ) -> PassBuilder<'g, 'a> {
+        pass.read(inputs.depth_image.unwrap(), ImageUsage::ComputeSampled)
+    }
+
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    ) {
+        let params = DofParams {
+            view_target: params.view_target,
+            depth_image: params.depth_image.unwrap(),
+        };
+        Dof::apply(self, ctx, state, frame, params);
+    }
+}
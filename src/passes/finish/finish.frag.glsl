@@ -0,0 +1,116 @@
+#version 460
+
+#include <extensions.glsl>
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+
+#include <color_space.glsl>
+#include <textures.glsl>
+
+// Matches Dither on the host
+const uint DITHER_OFF = 0;
+const uint DITHER_BLUE_NOISE = 1;
+const uint DITHER_ORDERED = 2;
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint source_image;
+    uint frame;
+    float grain_intensity;
+    float grain_size;
+    float grain_response;
+    float vignette_intensity;
+    float vignette_smoothness;
+    float chromatic_aberration;
+    float quantization_step;
+    uint dither;
+    uint color_space;
+    bool srgb_target;
+}
+pc;
+
+// R2 sequence, moves the noise every frame without repeating patterns
+const vec2 R2 = vec2(0.7548776662, 0.5698402910);
+
+vec4 blue_noise(ivec2 pix) {
+    ivec2 size = textureSize(gtextures[BLUE_TEX], 0);
+    ivec2 offset = ivec2(fract(float(pc.frame % 256u) * R2) * vec2(size));
+    return texelFetch(gtextures[BLUE_TEX], (pix + offset) % size, 0);
+}
+
+// Bilinear between wrapped texels, the samplers clamp
+float grain_noise(vec2 pix) {
+    vec2 pos = pix / pc.grain_size - 0.5;
+    ivec2 base = ivec2(floor(pos));
+    vec2 f = fract(pos);
+    float a = blue_noise(base).r;
+    float b = blue_noise(base + ivec2(1, 0)).r;
+    float c = blue_noise(base + ivec2(0, 1)).r;
+    float d = blue_noise(base + ivec2(1, 1)).r;
+    return mix(mix(a, b, f.x), mix(c, d, f.x), f.y);
+}
+
+float luminance(vec3 color) { return dot(color, vec3(0.2126, 0.7152, 0.0722)); }
+
+// Zero centered noise in quantization steps
+vec3 dither_noise(ivec2 pix) {
+    switch (pc.dither) {
+    case DITHER_BLUE_NOISE: {
+        // Triangular noise spanning two steps hides the banding best
+        vec2 noise = blue_noise(pix).gb;
+        return vec3(noise.x + noise.y - 1.);
+    }
+    case DITHER_ORDERED:
+        return vec3(texelFetch(gtextures[DITHER_TEX], pix % 8, 0).r - 0.5);
+    default:
+        return vec3(0.);
+    }
+}
+
+// Works on the linear output of Tonemap and encodes it for the display
+void main() {
+    vec2 uv = vec2(in_uv.x, 1. - in_uv.y);
+    vec2 dims = vec2(textureSize(gtextures[pc.source_image], 0));
+    // 0 at the center and 1 at the corners, round on any aspect ratio
+    vec2 centered = (uv - 0.5) * vec2(dims.x / dims.y, 1.);
+    float radius = length(centered) / length(vec2(0.5 * dims.x / dims.y, 0.5));
+
+    // Lateral aberration grows toward the edges of the lens
+    vec3 col;
+    if (pc.chromatic_aberration != 0.) {
+        vec2 dir = length(centered) > 0. ? normalize(centered) : vec2(0.);
+        dir.x *= dims.y / dims.x;
+        vec2 shift = dir * radius * radius * pc.chromatic_aberration * 0.5 /
+                     dims.y;
+        col.r = TexLinear(pc.source_image, uv + shift).r;
+        col.g = TexLinear(pc.source_image, uv).g;
+        col.b = TexLinear(pc.source_image, uv - shift).b;
+    } else {
+        col = TexLinear(pc.source_image, uv).rgb;
+    }
+
+    float falloff = smoothstep(1. - pc.vignette_smoothness, 1., radius);
+    col *= 1. - pc.vignette_intensity * falloff;
+
+    // Scales with the color like film grain, so the shadows don't turn grey
+    if (pc.grain_intensity > 0.) {
+        float response =
+            mix(1., 1. - clamp(luminance(col), 0., 1.), pc.grain_response);
+        float grain = grain_noise(gl_FragCoord.xy) - 0.5;
+        col *= max(1. + 2. * grain * pc.grain_intensity * response, 0.);
+    }
+
+    // The quantization happens on the encoded values, so that's where the dither goes
+    col = encode_output(col, pc.color_space, PAPER_WHITE_NITS);
+    if (pc.quantization_step > 0.) {
+        col += dither_noise(ivec2(gl_FragCoord.xy)) * pc.quantization_step;
+    }
+    if (pc.srgb_target) {
+        col = nonlinear_to_linear_srgb(clamp(col, 0., 1.));
+    }
+
+    out_color = vec4(col, 1.);
+}
@@ -0,0 +1,244 @@
+use std::mem;
+
+use anyhow::Result;
+use ash::vk;
+
+use crate::{
+    AppState, FragmentOutputDesc, FragmentShaderDesc, FrameGuard, ImageHandle, ImageTransition,
+    ImageUsage, RenderContext, RenderHandle, ScreenRelation, SwapchainColorSpace, VertexInputDesc,
+    VertexShaderDesc,
+};
+
+// Values match the DITHER_* constants in finish.frag.glsl
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    Off,
+    // Triangular blue noise, animated with the grain
+    #[default]
+    BlueNoise,
+    // Static 8x8 Bayer pattern from the arena's dither image
+    Ordered,
+}
+
+impl Dither {
+    fn as_raw(self) -> u32 {
+        match self {
+            Self::Off => 0,
+            Self::BlueNoise => 1,
+            Self::Ordered => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FinishSettings {
+    // Amplitude of the grain relative to the linear color, 0 disables it
+    pub grain_intensity: f32,
+    // In output pixels
+    pub grain_size: f32,
+    // How much the grain fades out toward the highlights
+    pub grain_response: f32,
+    // Darkening at the corners, 0 disables it
+    pub vignette_intensity: f32,
+    // Fraction of the distance to the corners the falloff spans
+    pub vignette_smoothness: f32,
+    // Red and blue shift apart by this many output pixels at the corners
+    pub chromatic_aberration: f32,
+    // Spreads the quantization of the encoded output to the precision of the target format
+    pub dither: Dither,
+}
+
+impl Default for FinishSettings {
+    fn default() -> Self {
+        Self {
+            grain_intensity: 0.08,
+            grain_size: 1.5,
+            grain_response: 0.8,
+            vignette_intensity: 0.25,
+            vignette_smoothness: 0.6,
+            chromatic_aberration: 1.,
+            dither: Dither::default(),
+        }
+    }
+}
+
+pub struct FinishParams {
+    pub target_image: ImageHandle,
+    pub color_space: SwapchainColorSpace,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct FinishPC {
+    source_image: u32,
+    frame: u32,
+    grain_intensity: f32,
+    grain_size: f32,
+    grain_response: f32,
+    vignette_intensity: f32,
+    vignette_smoothness: f32,
+    chromatic_aberration: f32,
+    quantization_step: f32,
+    dither: u32,
+    color_space: u32,
+    // The hardware encodes to sRGB on write, the shader output stays linear
+    srgb_target: u32,
+}
+
+// Smallest encoded step the target format can represent, what the dithering spreads
+fn quantization_step(format: vk::Format) -> f32 {
+    match format {
+        vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::A8B8G8R8_UNORM_PACK32
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::A8B8G8R8_SRGB_PACK32 => 1. / 255.,
+        vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 => 1. / 1023.,
+        _ => 0.,
+    }
+}
+
+fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
+// Last pass of the frame, takes over writing the target from Tonemap which renders into
+// Finish::input_image instead
+pub struct Finish {
+    pipeline: RenderHandle,
+    input_image: ImageHandle,
+    target_format: vk::Format,
+
+    pub settings: FinishSettings,
+}
+
+impl Finish {
+    // Holds the linear output of Tonemap, so values above 1 survive for HDR
+    pub const INPUT_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+    pub fn new(
+        ctx: &RenderContext,
+        state: &mut AppState,
+        target_format: vk::Format,
+        settings: FinishSettings,
+    ) -> Result<Self> {
+        let vertex_shader_desc = VertexShaderDesc {
+            shader_path: "shaders/screen_trig.vert".into(),
+            ..Default::default()
+        };
+        let fragment_shader_desc = FragmentShaderDesc {
+            shader_path: "src/passes/finish/finish.frag.glsl".into(),
+            ..Default::default()
+        };
+        let fragment_output_desc = FragmentOutputDesc {
+            surface_format: target_format,
+            ..Default::default()
+        };
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .size(mem::size_of::<FinishPC>() as u32);
+        let pipeline = state.pipeline_arena.create_render_pipeline(
+            VertexInputDesc::default(),
+            vertex_shader_desc,
+            fragment_shader_desc,
+            fragment_output_desc,
+            &[push_constant_range],
+            &[state.texture_arena.sampled_set_layout],
+        )?;
+
+        let info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: ctx.swapchain.extent.width,
+                height: ctx.swapchain.extent.height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(Self::INPUT_FORMAT)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::COLOR_ATTACHMENT)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let input_image = state.texture_arena.push_image(
+            info,
+            ScreenRelation::Identity,
+            &[],
+            Some("Finish Input"),
+        )?;
+
+        Ok(Self {
+            pipeline,
+            input_image,
+            target_format,
+            settings,
+        })
+    }
+
+    // Target for Tonemap, created with INPUT_FORMAT
+    pub fn input_image(&self) -> ImageHandle {
+        self.input_image
+    }
+
+    pub fn apply(
+        &self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: FinishParams,
+    ) {
+        let _marker = ctx
+            .device
+            .create_scoped_marker(frame.command_buffer(), "Finish Pass");
+        let settings = self.settings;
+        let frame_idx = state.frame;
+        let texture_arena = &mut state.texture_arena;
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[ImageTransition::new(
+                self.input_image,
+                ImageUsage::FragmentSampled,
+            )],
+        );
+
+        frame.begin_rendering(
+            texture_arena,
+            params.target_image,
+            vk::AttachmentLoadOp::DONT_CARE,
+            [0., 0., 0., 1.],
+        );
+        let push_constant = FinishPC {
+            source_image: texture_arena.get_sampled_idx(self.input_image, 0),
+            frame: frame_idx,
+            grain_intensity: settings.grain_intensity.max(0.),
+            grain_size: settings.grain_size.max(1.),
+            grain_response: settings.grain_response.clamp(0., 1.),
+            vignette_intensity: settings.vignette_intensity.clamp(0., 1.),
+            vignette_smoothness: settings.vignette_smoothness.clamp(1e-3, 1.),
+            chromatic_aberration: settings.chromatic_aberration,
+            quantization_step: quantization_step(self.target_format),
+            dither: settings.dither.as_raw(),
+            color_space: params.color_space.as_raw(),
+            srgb_target: is_srgb(self.target_format) as u32,
+        };
+        let pipeline = state.pipeline_arena.get_pipeline(self.pipeline);
+        frame.bind_push_constants(
+            pipeline.layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            &[push_constant],
+        );
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            &[texture_arena.sampled_set],
+        );
+        frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+        frame.draw(3, 1, 0, 0);
+        frame.end_rendering();
+    }
+}
@@ -0,0 +1,189 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint src_img;
+    uint dst_img;
+    float edge_threshold;
+    float edge_threshold_min;
+    float subpixel;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+const int ITERATIONS = 12;
+
+vec2 UV;
+vec2 INV_DIMS;
+
+float max3(vec3 x) { return max(x.r, max(x.g, x.b)); }
+
+// The input is linear HDR, edges are found on a compressed perceptual luma
+float luma(vec3 color) {
+    color = color / (1. + max3(color));
+    return sqrt(dot(color, vec3(0.2126, 0.7152, 0.0722)));
+}
+
+float luma_at(vec2 uv) { return luma(TexLinear(pc.src_img, uv).rgb); }
+float luma_offset(int x, int y) { return luma_at(UV + vec2(x, y) * INV_DIMS); }
+
+float quality(int i) {
+    if (i < 5) {
+        return 1.;
+    } else if (i == 5) {
+        return 1.5;
+    } else if (i < 10) {
+        return 2.;
+    } else if (i == 10) {
+        return 4.;
+    }
+    return 8.;
+}
+
+// FXAA 3.11 quality preset, restructured
+// https://developer.download.nvidia.com/assets/gamedev/files/sdk/11/FXAA_WhitePaper.pdf
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.dst_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+    // Edge steps are taken in source pixels
+    INV_DIMS = 1. / vec2(textureSize(gtextures[pc.src_img], 0));
+    UV = (vec2(gid) + 0.5) / vec2(dims);
+
+    vec4 center = TexLinear(pc.src_img, UV);
+    float luma_center = luma(center.rgb);
+    float luma_down = luma_offset(0, 1);
+    float luma_up = luma_offset(0, -1);
+    float luma_left = luma_offset(-1, 0);
+    float luma_right = luma_offset(1, 0);
+
+    float luma_min = min(min(luma_center, min(luma_down, luma_up)),
+                         min(luma_left, luma_right));
+    float luma_max = max(max(luma_center, max(luma_down, luma_up)),
+                         max(luma_left, luma_right));
+    float luma_range = luma_max - luma_min;
+    if (luma_range < max(pc.edge_threshold_min, luma_max * pc.edge_threshold)) {
+        imageStore(gstorage[pc.dst_img], gid, center);
+        return;
+    }
+
+    float luma_down_left = luma_offset(-1, 1);
+    float luma_up_right = luma_offset(1, -1);
+    float luma_up_left = luma_offset(-1, -1);
+    float luma_down_right = luma_offset(1, 1);
+
+    float luma_down_up = luma_down + luma_up;
+    float luma_left_right = luma_left + luma_right;
+    float luma_left_corners = luma_down_left + luma_up_left;
+    float luma_down_corners = luma_down_left + luma_down_right;
+    float luma_right_corners = luma_down_right + luma_up_right;
+    float luma_up_corners = luma_up_right + luma_up_left;
+
+    float edge_horizontal = abs(-2. * luma_left + luma_left_corners) +
+                            abs(-2. * luma_center + luma_down_up) * 2. +
+                            abs(-2. * luma_right + luma_right_corners);
+    float edge_vertical = abs(-2. * luma_up + luma_up_corners) +
+                          abs(-2. * luma_center + luma_left_right) * 2. +
+                          abs(-2. * luma_down + luma_down_corners);
+    bool is_horizontal = edge_horizontal >= edge_vertical;
+
+    // Pick the side of the edge with the steepest gradient
+    float luma1 = is_horizontal ? luma_up : luma_left;
+    float luma2 = is_horizontal ? luma_down : luma_right;
+    float gradient1 = luma1 - luma_center;
+    float gradient2 = luma2 - luma_center;
+    bool is1_steepest = abs(gradient1) >= abs(gradient2);
+    float gradient_scaled = 0.25 * max(abs(gradient1), abs(gradient2));
+
+    float step_length = is_horizontal ? INV_DIMS.y : INV_DIMS.x;
+    float luma_local_average;
+    if (is1_steepest) {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma1 + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma2 + luma_center);
+    }
+
+    // Walk along the edge in both directions until its end
+    vec2 current_uv = UV;
+    if (is_horizontal) {
+        current_uv.y += step_length * 0.5;
+    } else {
+        current_uv.x += step_length * 0.5;
+    }
+    vec2 offset = is_horizontal ? vec2(INV_DIMS.x, 0.) : vec2(0., INV_DIMS.y);
+    vec2 uv1 = current_uv - offset;
+    vec2 uv2 = current_uv + offset;
+
+    float luma_end1 = luma_at(uv1) - luma_local_average;
+    float luma_end2 = luma_at(uv2) - luma_local_average;
+    bool reached1 = abs(luma_end1) >= gradient_scaled;
+    bool reached2 = abs(luma_end2) >= gradient_scaled;
+    if (!reached1) {
+        uv1 -= offset;
+    }
+    if (!reached2) {
+        uv2 += offset;
+    }
+
+    for (int i = 2; i < ITERATIONS && !(reached1 && reached2); i++) {
+        if (!reached1) {
+            luma_end1 = luma_at(uv1) - luma_local_average;
+            reached1 = abs(luma_end1) >= gradient_scaled;
+        }
+        if (!reached2) {
+            luma_end2 = luma_at(uv2) - luma_local_average;
+            reached2 = abs(luma_end2) >= gradient_scaled;
+        }
+        if (!reached1) {
+            uv1 -= offset * quality(i);
+        }
+        if (!reached2) {
+            uv2 += offset * quality(i);
+        }
+    }
+
+    float distance1 = is_horizontal ? UV.x - uv1.x : UV.y - uv1.y;
+    float distance2 = is_horizontal ? uv2.x - UV.x : uv2.y - UV.y;
+    bool is_direction1 = distance1 < distance2;
+    float distance_final = min(distance1, distance2);
+    float edge_thickness = distance1 + distance2;
+    float pixel_offset = -distance_final / edge_thickness + 0.5;
+
+    // Only blend when the end of the edge varies the same way as the center
+    bool is_luma_center_smaller = luma_center < luma_local_average;
+    float luma_end = is_direction1 ? luma_end1 : luma_end2;
+    bool correct_variation = (luma_end < 0.) != is_luma_center_smaller;
+    float final_offset = correct_variation ? pixel_offset : 0.;
+
+    float luma_average = (1. / 12.) * (2. * (luma_down_up + luma_left_right) +
+                                       luma_left_corners + luma_right_corners);
+    float subpixel_offset1 =
+        clamp(abs(luma_average - luma_center) / luma_range, 0., 1.);
+    float subpixel_offset2 =
+        (-2. * subpixel_offset1 + 3.) * subpixel_offset1 * subpixel_offset1;
+    float subpixel_offset = subpixel_offset2 * subpixel_offset2 * pc.subpixel;
+    final_offset = max(final_offset, subpixel_offset);
+
+    vec2 final_uv = UV;
+    if (is_horizontal) {
+        final_uv.y += final_offset * step_length;
+    } else {
+        final_uv.x += final_offset * step_length;
+    }
+    vec3 color = TexLinear(pc.src_img, final_uv).rgb;
+    imageStore(gstorage[pc.dst_img], gid, vec4(color, center.a));
+}
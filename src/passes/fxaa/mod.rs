@@ -0,0 +1,173 @@
+use std::mem;
+
+use anyhow::Result;
+use ash::vk;
+
+use crate::{
+    AppState, ComputeHandle, FrameGuard, ImageTransition, ImageUsage, RenderContext, ViewTarget,
+    dispatch_optimal,
+    passes::stack::{PostProcessParams, PostProcessPass, TargetAccess},
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct FxaaSettings {
+    // Local contrast needed to count as an edge, relative to the brightest neighbour
+    pub edge_threshold: f32,
+    // Absolute floor of the threshold, keeps noise in dark areas untouched
+    pub edge_threshold_min: f32,
+    // Amount of subpixel aliasing removal, 0 keeps it sharp
+    pub subpixel: f32,
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        Self {
+            edge_threshold: 0.125,
+            edge_threshold_min: 0.0312,
+            subpixel: 0.75,
+        }
+    }
+}
+
+pub struct FxaaParams<'a> {
+    pub view_target: &'a ViewTarget,
+    // Writes into this target instead of resolving in place
+    pub output_target: Option<&'a ViewTarget>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct FxaaPC {
+    src_image: u32,
+    dst_image: u32,
+    edge_threshold: f32,
+    edge_threshold_min: f32,
+    subpixel: f32,
+}
+
+pub struct Fxaa {
+    pipeline: ComputeHandle,
+
+    pub settings: FxaaSettings,
+}
+
+impl Fxaa {
+    pub fn new(state: &mut AppState, settings: FxaaSettings) -> Result<Self> {
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<FxaaPC>() as u32);
+        let pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/fxaa/fxaa.comp.glsl",
+            &[push_constant_range],
+            &[
+                state.texture_arena.sampled_set_layout,
+                state.texture_arena.storage_set_layout,
+            ],
+        )?;
+
+        Ok(Self { pipeline, settings })
+    }
+
+    pub fn apply(
+        &self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: FxaaParams,
+    ) {
+        let _marker = ctx
+            .device
+            .create_scoped_marker(frame.command_buffer(), "Fxaa Pass");
+        let texture_arena = &mut state.texture_arena;
+        let (source_image, destination_image) = match params.output_target {
+            Some(output_target) => (
+                *params.view_target.main_image(),
+                *output_target.post_process_write().destination,
+            ),
+            None => {
+                let write = params.view_target.post_process_write();
+                (*write.source, *write.destination)
+            }
+        };
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(source_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(destination_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        let push_constant = FxaaPC {
+            src_image: texture_arena.get_sampled_idx(source_image, 0),
+            dst_image: texture_arena.get_storage_idx(destination_image, 0),
+            edge_threshold: self.settings.edge_threshold,
+            edge_threshold_min: self.settings.edge_threshold_min,
+            subpixel: self.settings.subpixel,
+        };
+        let pipeline = state.pipeline_arena.get_pipeline(self.pipeline);
+        frame.bind_push_constants(
+            pipeline.layout,
+            vk::ShaderStageFlags::COMPUTE,
+            &[push_constant],
+        );
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::COMPUTE,
+            pipeline.layout,
+            &[texture_arena.sampled_set, texture_arena.storage_set],
+        );
+        frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+        const SUBGROUP_SIZE: u32 = 16;
+        let extent = texture_arena
+            .get_image(destination_image)
+            .info
+            .unwrap()
+            .extent;
+        frame.dispatch(
+            dispatch_optimal(extent.width, SUBGROUP_SIZE),
+            dispatch_optimal(extent.height, SUBGROUP_SIZE),
+            1,
+        );
+    }
+}
+
+impl PostProcessPass for Fxaa {
+    type Settings = FxaaSettings;
+    const NAME: &'static str = "Fxaa";
+
+    fn new(_ctx: &RenderContext, state: &mut AppState, settings: FxaaSettings) -> Result<Self> {
+        Self::new(state, settings)
+    }
+
+    fn settings(&self) -> &FxaaSettings {
+        &self.settings
+    }
+
+    fn set_settings(
+        &mut self,
+        _ctx: &RenderContext,
+        _state: &mut AppState,
+        settings: FxaaSettings,
+    ) -> Result<()> {
+        self.settings = settings;
+        Ok(())
+    }
+
+    fn access(&self) -> TargetAccess {
+        TargetAccess::Resolve
+    }
+
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    ) {
+        let params = FxaaParams {
+            view_target: params.view_target,
+            output_target: params.output_target,
+        };
+        Fxaa::apply(self, ctx, state, frame, params);
+    }
+}
@@ -1,2 +1,11 @@
+pub mod auto_exposure;
 pub mod bloom;
+pub mod dof;
+pub mod finish;
+pub mod fxaa;
+pub mod motion_blur;
+pub mod smaa;
+pub mod ssao;
+pub mod stack;
 pub mod taa;
+pub mod tonemap;
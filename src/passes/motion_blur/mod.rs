@@ -0,0 +1,353 @@
+use std::mem;
+
+use anyhow::Result;
+use ash::vk;
+use glam::{Vec2, vec2};
+
+use crate::{
+    AppState, Camera, ComputeHandle, FrameGuard, ImageHandle, ImageTransition, ImageUsage,
+    PassBuilder, RenderContext, ScreenRelation, ViewTarget, dispatch_optimal,
+    passes::stack::{PostProcessInputs, PostProcessParams, PostProcessPass},
+};
+
+// Matches TILE_SIZE in the shaders, also the longest blur in output pixels
+const TILE_SIZE: u32 = 32;
+
+#[derive(Clone, Copy, Debug)]
+pub struct MotionBlurSettings {
+    // Taken along the dominant velocity of the neighbourhood
+    pub samples: u32,
+    // In output pixels, clamped to the tile size
+    pub max_radius: f32,
+    // Depth range in scene units over which samples count as the same surface
+    pub depth_softness: f32,
+}
+
+impl Default for MotionBlurSettings {
+    fn default() -> Self {
+        Self {
+            samples: 12,
+            max_radius: TILE_SIZE as f32,
+            depth_softness: 0.5,
+        }
+    }
+}
+
+pub struct MotionBlurParams<'a> {
+    pub view_target: &'a ViewTarget,
+    // Motion vectors in uv units, Taa::motion_image
+    pub motion_image: ImageHandle,
+    pub depth_image: ImageHandle,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct TileMaxPC {
+    output_size: Vec2,
+    motion_image: u32,
+    tile_image: u32,
+    velocity_scale: f32,
+    max_radius: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct NeighborMaxPC {
+    tile_image: u32,
+    neighbor_image: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct MotionBlurPC {
+    src_image: u32,
+    dst_image: u32,
+    motion_image: u32,
+    depth_image: u32,
+    neighbor_image: u32,
+    samples: u32,
+    velocity_scale: f32,
+    max_radius: f32,
+    depth_softness: f32,
+    znear: f32,
+    frame: u32,
+}
+
+pub struct MotionBlur {
+    tile_max_pipeline: ComputeHandle,
+    neighbor_max_pipeline: ComputeHandle,
+    blur_pipeline: ComputeHandle,
+    tile_image: ImageHandle,
+    neighbor_image: ImageHandle,
+
+    pub settings: MotionBlurSettings,
+}
+
+impl MotionBlur {
+    pub fn new(
+        ctx: &RenderContext,
+        state: &mut AppState,
+        settings: MotionBlurSettings,
+    ) -> Result<Self> {
+        let desc_layouts = [
+            state.texture_arena.sampled_set_layout,
+            state.texture_arena.storage_set_layout,
+        ];
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<TileMaxPC>() as u32);
+        let tile_max_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/motion_blur/tile_max.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<NeighborMaxPC>() as u32);
+        let neighbor_max_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/motion_blur/neighbor_max.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<MotionBlurPC>() as u32);
+        let blur_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/motion_blur/motion_blur.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+
+        let tile_info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: ctx.swapchain.extent.width,
+                height: ctx.swapchain.extent.height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R16G16_SFLOAT)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        // Rounded down, the last tile in each row and column also covers the remainder
+        let tile_relation = ScreenRelation::Scale(1. / TILE_SIZE as f32);
+        let tile_image = state.texture_arena.push_image(
+            tile_info,
+            tile_relation,
+            &[],
+            Some("Motion Blur Tile Max"),
+        )?;
+        let neighbor_image = state.texture_arena.push_image(
+            tile_info,
+            tile_relation,
+            &[],
+            Some("Motion Blur Neighbor Max"),
+        )?;
+
+        Ok(Self {
+            tile_max_pipeline,
+            neighbor_max_pipeline,
+            blur_pipeline,
+            tile_image,
+            neighbor_image,
+            settings,
+        })
+    }
+
+    pub fn apply(
+        &self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: MotionBlurParams,
+    ) {
+        let _marker = ctx
+            .device
+            .create_scoped_marker(frame.command_buffer(), "Motion Blur Pass");
+        let settings = self.settings;
+        let velocity_scale = state.camera.lens.shutter_angle.clamp(0., 360.) / 360.;
+        let max_radius = settings.max_radius.clamp(0., TILE_SIZE as f32);
+        let frame_idx = state.frame;
+        let texture_arena = &mut state.texture_arena;
+        let postprocess_write = params.view_target.post_process_write();
+        let output_extent = texture_arena
+            .get_image(*postprocess_write.destination)
+            .info
+            .unwrap()
+            .extent;
+        let tile_extent = texture_arena
+            .get_image(self.tile_image)
+            .info
+            .unwrap()
+            .extent;
+        const SUBGROUP_SIZE: u32 = 16;
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(params.motion_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.tile_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        // One workgroup per tile
+        {
+            let push_constant = TileMaxPC {
+                output_size: vec2(output_extent.width as f32, output_extent.height as f32),
+                motion_image: texture_arena.get_sampled_idx(params.motion_image, 0),
+                tile_image: texture_arena.get_storage_idx(self.tile_image, 0),
+                velocity_scale,
+                max_radius,
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.tile_max_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(tile_extent.width, tile_extent.height, 1);
+        }
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(self.tile_image, ImageUsage::ComputeStorage),
+                ImageTransition::new(self.neighbor_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        {
+            let push_constant = NeighborMaxPC {
+                tile_image: texture_arena.get_storage_idx(self.tile_image, 0),
+                neighbor_image: texture_arena.get_storage_idx(self.neighbor_image, 0),
+            };
+            let pipeline = state
+                .pipeline_arena
+                .get_pipeline(self.neighbor_max_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(
+                dispatch_optimal(tile_extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(tile_extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(*postprocess_write.source, ImageUsage::ComputeSampled),
+                ImageTransition::new(*postprocess_write.destination, ImageUsage::ComputeStorage),
+                ImageTransition::new(params.depth_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.neighbor_image, ImageUsage::ComputeSampled),
+            ],
+        );
+
+        {
+            let push_constant = MotionBlurPC {
+                src_image: texture_arena.get_sampled_idx(*postprocess_write.source, 0),
+                dst_image: texture_arena.get_storage_idx(*postprocess_write.destination, 0),
+                motion_image: texture_arena.get_sampled_idx(params.motion_image, 0),
+                depth_image: texture_arena.get_sampled_idx(params.depth_image, 0),
+                neighbor_image: texture_arena.get_sampled_idx(self.neighbor_image, 0),
+                samples: settings.samples.max(1),
+                velocity_scale,
+                max_radius,
+                depth_softness: settings.depth_softness.max(1e-4),
+                znear: Camera::ZNEAR,
+                frame: frame_idx,
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.blur_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(
+                dispatch_optimal(output_extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(output_extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
+
+impl PostProcessPass for MotionBlur {
+    type Settings = MotionBlurSettings;
+    const NAME: &'static str = "Motion Blur";
+
+    fn new(
+        ctx: &RenderContext,
+        state: &mut AppState,
+        settings: MotionBlurSettings,
+    ) -> Result<Self> {
+        Self::new(ctx, state, settings)
+    }
+
+    fn settings(&self) -> &MotionBlurSettings {
+        &self.settings
+    }
+
+    fn set_settings(
+        &mut self,
+        _ctx: &RenderContext,
+        _state: &mut AppState,
+        settings: MotionBlurSettings,
+    ) -> Result<()> {
+        self.settings = settings;
+        Ok(())
+    }
+
+    // Motion vectors only exist while Taa runs
+    fn is_active(&self, inputs: &PostProcessInputs) -> bool {
+        inputs.depth_image.is_some() && inputs.motion_image.is_some()
+    }
+
+    fn declare<'g, 'a>(
+        &self,
+        pass: PassBuilder<'g, 'a>,
+        inputs: &PostProcessInputs,
+    ) -> PassBuilder<'g, 'a> {
+        pass.read(inputs.depth_image.unwrap(), ImageUsage::ComputeSampled)
+            .read(inputs.motion_image.unwrap(), ImageUsage::ComputeSampled)
+    }
+
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    ) {
+        let params = MotionBlurParams {
+            view_target: params.view_target,
+            motion_image: params.motion_image.unwrap(),
+            depth_image: params.depth_image.unwrap(),
+        };
+        MotionBlur::apply(self, ctx, state, frame, params);
+    }
+}
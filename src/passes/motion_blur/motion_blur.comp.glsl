@@ -0,0 +1,123 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint src_img;
+    uint dst_img;
+    uint motion_img;
+    uint depth_img;
+    uint neighbor_img;
+    uint samples;
+    float velocity_scale;
+    float max_radius;
+    float depth_softness;
+    float znear;
+    uint frame;
+}
+pc;
+
+const int TILE_SIZE = 32;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+// Reconstruction filter from "A Reconstruction Filter for Plausible Motion
+// Blur", McGuire et al. 2012
+
+vec2 DIMS;
+
+vec2 pixel_velocity(vec2 uv) {
+    vec2 velocity = TexNear(pc.motion_img, uv).xy * DIMS * pc.velocity_scale;
+    float len = length(velocity);
+    return len > pc.max_radius ? velocity * (pc.max_radius / len) : velocity;
+}
+
+// Reverse-Z with an infinite far plane
+float linear_depth(vec2 uv) {
+    float raw_depth = TexNear(pc.depth_img, uv).x;
+    return pc.znear / max(raw_depth, 1e-7);
+}
+
+// 1 when a is in front of b
+float soft_depth_compare(float a, float b) {
+    return clamp(1. - (a - b) / pc.depth_softness, 0., 1.);
+}
+
+float cone(float dist, float velocity_len) {
+    return clamp(1. - dist / max(velocity_len, 1e-4), 0., 1.);
+}
+
+float cylinder(float dist, float velocity_len) {
+    return 1. - smoothstep(0.95 * velocity_len, 1.05 * velocity_len, dist);
+}
+
+// Breaks up the banding of the fixed sample count
+float interleaved_gradient_noise(vec2 pixel) {
+    pixel += float(pc.frame % 64) * 5.588238;
+    return fract(52.9829189 * fract(dot(pixel, vec2(0.06711056, 0.00583715))));
+}
+
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.dst_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+    DIMS = vec2(dims);
+    vec2 uv = (vec2(gid) + 0.5) / DIMS;
+    vec4 color = texelFetch(gtextures[pc.src_img], gid, 0);
+
+    ivec2 tiles = textureSize(gtextures[pc.neighbor_img], 0);
+    ivec2 tile = min(gid / TILE_SIZE, tiles - 1);
+    vec2 neighbor_velocity = texelFetch(gtextures[pc.neighbor_img], tile, 0).xy;
+    float neighbor_len = length(neighbor_velocity);
+    if (neighbor_len <= 0.5) {
+        imageStore(gstorage[pc.dst_img], gid, color);
+        return;
+    }
+
+    vec2 center_velocity = pixel_velocity(uv);
+    float center_len = max(length(center_velocity), 0.5);
+    float center_depth = linear_depth(uv);
+
+    float weight = 1. / center_len;
+    vec3 sum = color.rgb * weight;
+    float jitter = interleaved_gradient_noise(vec2(gid)) - 0.5;
+    for (uint i = 0; i < pc.samples; i++) {
+        float t = (float(i) + jitter + 1.) / (float(pc.samples) + 1.);
+        t = t * 2. - 1.;
+        vec2 offset = neighbor_velocity * t;
+        vec2 sample_uv = (floor(vec2(gid) + offset) + 0.5) / DIMS;
+        if (any(notEqual(clamp(sample_uv, 0., 1.), sample_uv))) {
+            continue;
+        }
+        float dist = length(offset);
+
+        vec2 sample_velocity = pixel_velocity(sample_uv);
+        float sample_len = max(length(sample_velocity), 0.5);
+        float sample_depth = linear_depth(sample_uv);
+
+        // Foreground sample blurred over the center, center blurred over the
+        // background, and both moving across each other
+        float foreground = soft_depth_compare(sample_depth, center_depth);
+        float background = soft_depth_compare(center_depth, sample_depth);
+        float alpha = foreground * cone(dist, sample_len) +
+                      background * cone(dist, center_len) +
+                      cylinder(dist, sample_len) *
+                          cylinder(dist, center_len) * 2.;
+
+        weight += alpha;
+        sum += alpha * TexNear(pc.src_img, sample_uv).rgb;
+    }
+
+    imageStore(gstorage[pc.dst_img], gid, vec4(sum / weight, color.a));
+}
@@ -0,0 +1,38 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 1, binding = 0) uniform image2D gstorage[];
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint tile_img;
+    uint neighbor_img;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+// Dominant velocity of the 3x3 tiles around, a pixel can only be blurred by
+// something that moves at most one tile away
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.tile_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+
+    vec2 max_velocity = vec2(0.);
+    for (int y = -1; y <= 1; ++y) {
+        for (int x = -1; x <= 1; ++x) {
+            ivec2 tile = clamp(gid + ivec2(x, y), ivec2(0), dims - 1);
+            vec2 velocity = imageLoad(gstorage[pc.tile_img], tile).xy;
+            if (dot(velocity, velocity) > dot(max_velocity, max_velocity)) {
+                max_velocity = velocity;
+            }
+        }
+    }
+    imageStore(gstorage[pc.neighbor_img], gid, vec4(max_velocity, 0., 0.));
+}
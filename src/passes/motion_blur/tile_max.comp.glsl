@@ -0,0 +1,80 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    vec2 output_size;
+    uint motion_img;
+    uint tile_img;
+    float velocity_scale;
+    float max_radius;
+}
+pc;
+
+const int TILE_SIZE = 32;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+shared vec2 sh_velocity[256];
+
+// Velocity in output pixels over the time the shutter is open
+vec2 pixel_velocity(ivec2 pixel) {
+    vec2 uv = (vec2(pixel) + 0.5) / pc.output_size;
+    vec2 velocity = TexNear(pc.motion_img, uv).xy * pc.output_size;
+    velocity *= pc.velocity_scale;
+    float len = length(velocity);
+    return len > pc.max_radius ? velocity * (pc.max_radius / len) : velocity;
+}
+
+// One workgroup per tile, the last row and column take the remainder of the
+// screen as well
+void main() {
+    const ivec2 tile = ivec2(gl_WorkGroupID.xy);
+    const ivec2 tiles = imageSize(gstorage[pc.tile_img]);
+    const uint lidx = gl_LocalInvocationIndex;
+
+    ivec2 start = tile * TILE_SIZE;
+    ivec2 end = min(start + TILE_SIZE, ivec2(pc.output_size));
+    if (tile.x == tiles.x - 1) {
+        end.x = int(pc.output_size.x);
+    }
+    if (tile.y == tiles.y - 1) {
+        end.y = int(pc.output_size.y);
+    }
+
+    vec2 max_velocity = vec2(0.);
+    for (int y = start.y + int(gl_LocalInvocationID.y); y < end.y; y += 16) {
+        for (int x = start.x + int(gl_LocalInvocationID.x); x < end.x;
+             x += 16) {
+            vec2 velocity = pixel_velocity(ivec2(x, y));
+            if (dot(velocity, velocity) > dot(max_velocity, max_velocity)) {
+                max_velocity = velocity;
+            }
+        }
+    }
+    sh_velocity[lidx] = max_velocity;
+    barrier();
+
+    for (uint stride = 128; stride > 0; stride >>= 1) {
+        if (lidx < stride) {
+            vec2 other = sh_velocity[lidx + stride];
+            if (dot(other, other) > dot(sh_velocity[lidx], sh_velocity[lidx])) {
+                sh_velocity[lidx] = other;
+            }
+        }
+        barrier();
+    }
+
+    if (lidx == 0) {
+        imageStore(gstorage[pc.tile_img], tile, vec4(sh_velocity[0], 0., 0.));
+    }
+}
@@ -0,0 +1,57 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint src_img;
+    uint dst_img;
+    uint blend_img;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+// Neighborhood blending, mixes each pixel with the neighbour across the
+// strongest edge touching it
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.dst_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+    vec2 inv_dims = 1. / vec2(dims);
+    vec2 uv = (vec2(gid) + 0.5) * inv_dims;
+
+    vec4 a;
+    a.x = TexLinear(pc.blend_img, uv + vec2(inv_dims.x, 0.)).a; // Right
+    a.y = TexLinear(pc.blend_img, uv + vec2(0., inv_dims.y)).g; // Bottom
+    a.wz = TexLinear(pc.blend_img, uv).xz;                      // Top, left
+
+    vec4 center = TexLinear(pc.src_img, uv);
+    if (dot(a, vec4(1.)) < 1e-5) {
+        imageStore(gstorage[pc.dst_img], gid, center);
+        return;
+    }
+
+    bool horizontal = max(a.x, a.z) > max(a.y, a.w);
+    vec4 blending_offset =
+        horizontal ? vec4(a.x, 0., a.z, 0.) : vec4(0., a.y, 0., a.w);
+    vec2 blending_weight = horizontal ? a.xz : a.yw;
+    blending_weight /= dot(blending_weight, vec2(1.));
+    vec4 blending_uv =
+        blending_offset * vec4(inv_dims, -inv_dims) + uv.xyxy;
+
+    vec3 color =
+        blending_weight.x * TexLinear(pc.src_img, blending_uv.xy).rgb +
+        blending_weight.y * TexLinear(pc.src_img, blending_uv.zw).rgb;
+    imageStore(gstorage[pc.dst_img], gid, vec4(color, center.a));
+}
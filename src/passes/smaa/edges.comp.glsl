@@ -0,0 +1,74 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint src_img;
+    uint edges_img;
+    float threshold;
+    float local_contrast;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+vec2 UV;
+vec2 INV_DIMS;
+
+float max3(vec3 x) { return max(x.r, max(x.g, x.b)); }
+
+// The input is linear HDR, edges are found on a compressed perceptual luma
+float luma(vec3 color) {
+    color = color / (1. + max3(color));
+    return sqrt(dot(color, vec3(0.2126, 0.7152, 0.0722)));
+}
+
+float luma_offset(int x, int y) {
+    return luma(TexLinear(pc.src_img, UV + vec2(x, y) * INV_DIMS).rgb);
+}
+
+// Luma edge detection, red marks an edge on the left and green one on the top
+// https://www.iryoku.com/smaa/
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.edges_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+    INV_DIMS = 1. / vec2(dims);
+    UV = (vec2(gid) + 0.5) * INV_DIMS;
+
+    float l = luma_offset(0, 0);
+    float l_left = luma_offset(-1, 0);
+    float l_top = luma_offset(0, -1);
+    vec4 delta;
+    delta.xy = abs(l - vec2(l_left, l_top));
+    vec2 edges = step(vec2(pc.threshold), delta.xy);
+    if (dot(edges, vec2(1.)) == 0.) {
+        imageStore(gstorage[pc.edges_img], gid, vec4(0.));
+        return;
+    }
+
+    // Drop edges much weaker than the strongest one around them
+    float l_right = luma_offset(1, 0);
+    float l_bottom = luma_offset(0, 1);
+    delta.zw = abs(l - vec2(l_right, l_bottom));
+    vec2 max_delta = max(delta.xy, delta.zw);
+    float l_left_left = luma_offset(-2, 0);
+    float l_top_top = luma_offset(0, -2);
+    delta.zw = abs(vec2(l_left, l_top) - vec2(l_left_left, l_top_top));
+    max_delta = max(max_delta.xy, delta.zw);
+    float final_delta = max(max_delta.x, max_delta.y);
+    edges *= step(final_delta, pc.local_contrast * delta.xy);
+
+    imageStore(gstorage[pc.edges_img], gid, vec4(edges, 0., 0.));
+}
@@ -0,0 +1,349 @@
+use std::mem;
+
+use anyhow::Result;
+use ash::vk;
+
+use crate::{
+    AppState, ComputeHandle, FrameGuard, ImageHandle, ImageTransition, ImageUsage, RenderContext,
+    ScreenRelation, ViewTarget, dispatch_optimal,
+    passes::stack::{PostProcessParams, PostProcessPass, TargetAccess},
+};
+
+mod textures;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SmaaSettings {
+    // Luma difference needed to count as an edge
+    pub threshold: f32,
+    // Edges weaker than the strongest neighbouring one by this factor are dropped
+    pub local_contrast: f32,
+    // Each step covers two pixels along the edge
+    pub max_search_steps: u32,
+    // In percent, 0 keeps corners sharp
+    pub corner_rounding: f32,
+}
+
+impl Default for SmaaSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 0.1,
+            local_contrast: 2.,
+            max_search_steps: 16,
+            corner_rounding: 25.,
+        }
+    }
+}
+
+pub struct SmaaParams<'a> {
+    pub view_target: &'a ViewTarget,
+    // Writes into this target instead of resolving in place
+    pub output_target: Option<&'a ViewTarget>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct EdgesPC {
+    src_image: u32,
+    edges_image: u32,
+    threshold: f32,
+    local_contrast: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct WeightsPC {
+    edges_image: u32,
+    area_image: u32,
+    search_image: u32,
+    blend_image: u32,
+    max_search_steps: u32,
+    corner_rounding: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct BlendPC {
+    src_image: u32,
+    dst_image: u32,
+    blend_image: u32,
+}
+
+pub struct Smaa {
+    edges_pipeline: ComputeHandle,
+    weights_pipeline: ComputeHandle,
+    blend_pipeline: ComputeHandle,
+    edges_image: ImageHandle,
+    blend_image: ImageHandle,
+    area_image: ImageHandle,
+    search_image: ImageHandle,
+
+    pub settings: SmaaSettings,
+}
+
+impl Smaa {
+    pub fn new(ctx: &RenderContext, state: &mut AppState, settings: SmaaSettings) -> Result<Self> {
+        let desc_layouts = [
+            state.texture_arena.sampled_set_layout,
+            state.texture_arena.storage_set_layout,
+        ];
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<EdgesPC>() as u32);
+        let edges_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/smaa/edges.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<WeightsPC>() as u32);
+        let weights_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/smaa/weights.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<BlendPC>() as u32);
+        let blend_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/smaa/blend.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+
+        let mut info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: ctx.swapchain.extent.width,
+                height: ctx.swapchain.extent.height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R8G8_UNORM)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let edges_image = state.texture_arena.push_image(
+            info,
+            ScreenRelation::Identity,
+            &[],
+            Some("Smaa Edges"),
+        )?;
+        info.format = vk::Format::R8G8B8A8_UNORM;
+        let blend_image = state.texture_arena.push_image(
+            info,
+            ScreenRelation::Identity,
+            &[],
+            Some("Smaa Blend Weights"),
+        )?;
+
+        info.usage = vk::ImageUsageFlags::SAMPLED;
+        info.format = vk::Format::R8G8_UNORM;
+        info.extent.width = textures::AREA_TEX_WIDTH;
+        info.extent.height = textures::AREA_TEX_HEIGHT;
+        let area_image = state.texture_arena.push_image(
+            info,
+            ScreenRelation::None,
+            &textures::area_texture(),
+            Some("Smaa Area Texture"),
+        )?;
+        info.format = vk::Format::R8_UNORM;
+        info.extent.width = textures::SEARCH_TEX_WIDTH;
+        info.extent.height = textures::SEARCH_TEX_HEIGHT;
+        let search_image = state.texture_arena.push_image(
+            info,
+            ScreenRelation::None,
+            &textures::search_texture(),
+            Some("Smaa Search Texture"),
+        )?;
+
+        Ok(Self {
+            edges_pipeline,
+            weights_pipeline,
+            blend_pipeline,
+            edges_image,
+            blend_image,
+            area_image,
+            search_image,
+            settings,
+        })
+    }
+
+    pub fn apply(
+        &self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: SmaaParams,
+    ) {
+        let _marker = ctx
+            .device
+            .create_scoped_marker(frame.command_buffer(), "Smaa Pass");
+        let settings = self.settings;
+        let texture_arena = &mut state.texture_arena;
+        let (source_image, destination_image) = match params.output_target {
+            Some(output_target) => (
+                *params.view_target.main_image(),
+                *output_target.post_process_write().destination,
+            ),
+            None => {
+                let write = params.view_target.post_process_write();
+                (*write.source, *write.destination)
+            }
+        };
+        let extent = texture_arena
+            .get_image(self.edges_image)
+            .info
+            .unwrap()
+            .extent;
+        const SUBGROUP_SIZE: u32 = 16;
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(source_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.edges_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        {
+            let push_constant = EdgesPC {
+                src_image: texture_arena.get_sampled_idx(source_image, 0),
+                edges_image: texture_arena.get_storage_idx(self.edges_image, 0),
+                threshold: settings.threshold,
+                local_contrast: settings.local_contrast.max(1.),
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.edges_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(
+                dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(self.edges_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.area_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.search_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.blend_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        {
+            let push_constant = WeightsPC {
+                edges_image: texture_arena.get_sampled_idx(self.edges_image, 0),
+                area_image: texture_arena.get_sampled_idx(self.area_image, 0),
+                search_image: texture_arena.get_sampled_idx(self.search_image, 0),
+                blend_image: texture_arena.get_storage_idx(self.blend_image, 0),
+                max_search_steps: settings.max_search_steps.clamp(1, 112),
+                corner_rounding: settings.corner_rounding.clamp(0., 100.) / 100.,
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.weights_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(
+                dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(self.blend_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(destination_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        {
+            let push_constant = BlendPC {
+                src_image: texture_arena.get_sampled_idx(source_image, 0),
+                dst_image: texture_arena.get_storage_idx(destination_image, 0),
+                blend_image: texture_arena.get_sampled_idx(self.blend_image, 0),
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.blend_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(
+                dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
+
+impl PostProcessPass for Smaa {
+    type Settings = SmaaSettings;
+    const NAME: &'static str = "Smaa";
+
+    fn new(ctx: &RenderContext, state: &mut AppState, settings: SmaaSettings) -> Result<Self> {
+        Self::new(ctx, state, settings)
+    }
+
+    fn settings(&self) -> &SmaaSettings {
+        &self.settings
+    }
+
+    fn set_settings(
+        &mut self,
+        _ctx: &RenderContext,
+        _state: &mut AppState,
+        settings: SmaaSettings,
+    ) -> Result<()> {
+        self.settings = settings;
+        Ok(())
+    }
+
+    fn access(&self) -> TargetAccess {
+        TargetAccess::Resolve
+    }
+
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    ) {
+        let params = SmaaParams {
+            view_target: params.view_target,
+            output_target: params.output_target,
+        };
+        Smaa::apply(self, ctx, state, frame, params);
+    }
+}
@@ -0,0 +1,241 @@
+// CPU port of the orthogonal half of AreaTex.py and of SearchTex.py from the
+// reference implementation, https://github.com/iryoku/smaa/tree/master/Scripts
+
+pub const AREA_TEX_WIDTH: u32 = 160;
+pub const AREA_TEX_HEIGHT: u32 = 560;
+pub const SEARCH_TEX_WIDTH: u32 = 64;
+pub const SEARCH_TEX_HEIGHT: u32 = 16;
+
+// In texels of one pattern cell, distances are stored squared
+const MAX_DISTANCE: usize = 16;
+const SMOOTH_MAX_DISTANCE: f32 = 32.;
+// One slab of 80 rows per subsample offset, only the first is used without SMAA T2x
+const SUBSAMPLE_OFFSETS: [f32; 7] = [0., -0.25, 0.25, -0.125, 0.125, -0.375, 0.375];
+// Cell of each crossing edge pattern in the 5x5 grid indexed by the rounded edge values
+const EDGES_ORTHO: [(usize, usize); 16] = [
+    (0, 0),
+    (3, 0),
+    (0, 3),
+    (3, 3),
+    (1, 0),
+    (4, 0),
+    (1, 3),
+    (4, 3),
+    (0, 1),
+    (3, 1),
+    (0, 4),
+    (3, 4),
+    (1, 1),
+    (4, 1),
+    (1, 4),
+    (4, 4),
+];
+
+type Area = (f32, f32);
+
+fn add(a: Area, b: Area) -> Area {
+    (a.0 + b.0, a.1 + b.1)
+}
+
+// Area under the line p1 -> p2 inside the pixel x..x+1, split by the side of
+// the edge it covers
+fn area(p1: (f32, f32), p2: (f32, f32), x: f32) -> Area {
+    let d = (p2.0 - p1.0, p2.1 - p1.1);
+    let x1 = x;
+    let x2 = x + 1.;
+    let y1 = p1.1 + d.1 * (x1 - p1.0) / d.0;
+    let y2 = p1.1 + d.1 * (x2 - p1.0) / d.0;
+
+    let inside = (x1 >= p1.0 && x1 < p2.0) || (x2 > p1.0 && x2 <= p2.0);
+    if !inside {
+        return (0., 0.);
+    }
+
+    let is_trapezoid = y1.signum() == y2.signum() || y1.abs() < 1e-4 || y2.abs() < 1e-4;
+    if is_trapezoid {
+        let a = (y1 + y2) / 2.;
+        if a < 0. { (a.abs(), 0.) } else { (0., a.abs()) }
+    } else {
+        // Two triangles on each side of the crossing point
+        let x = -p1.1 * d.0 / d.1 + p1.0;
+        let a1 = if x > p1.0 { y1 * x.fract() / 2. } else { 0. };
+        let a2 = if x < p2.0 {
+            y2 * (1. - x.fract()) / 2.
+        } else {
+            0.
+        };
+        let a = if a1.abs() > a2.abs() { a1 } else { -a2 };
+        if a < 0. {
+            (a1.abs(), a2.abs())
+        } else {
+            (a2.abs(), a1.abs())
+        }
+    }
+}
+
+// Rounds the U shapes so short ones don't blend as much as long ones
+fn smooth_area(d: f32, a1: Area, a2: Area) -> Area {
+    let b1 = ((a1.0 * 2.).sqrt() * 0.5, (a1.1 * 2.).sqrt() * 0.5);
+    let b2 = ((a2.0 * 2.).sqrt() * 0.5, (a2.1 * 2.).sqrt() * 0.5);
+    let p = (d / SMOOTH_MAX_DISTANCE).clamp(0., 1.);
+    let lerp = |b: f32, a: f32| b + (a - b) * p;
+    (
+        lerp(b1.0, a1.0) + lerp(b2.0, a2.0),
+        lerp(b1.1, a1.1) + lerp(b2.1, a2.1),
+    )
+}
+
+fn area_ortho(pattern: usize, left: f32, right: f32, offset: f32) -> Area {
+    let d = left + right + 1.;
+    let o1 = 0.5 + offset;
+    let o2 = 0.5 + offset - 1.;
+    let half = d / 2.;
+
+    match pattern {
+        1 if left <= right => area((0., o2), (half, 0.), left),
+        2 if left >= right => area((half, 0.), (d, o2), left),
+        3 => {
+            let a1 = area((0., o2), (half, 0.), left);
+            let a2 = area((half, 0.), (d, o2), left);
+            smooth_area(d, a1, a2)
+        }
+        4 if left <= right => area((0., o1), (half, 0.), left),
+        // Z shapes, the offset makes them converge with pattern 0
+        6 if offset.abs() > 0. => {
+            let a1 = area((0., o1), (d, o2), left);
+            let a2 = add(
+                area((0., o1), (half, 0.), left),
+                area((half, 0.), (d, o2), left),
+            );
+            ((a1.0 + a2.0) / 2., (a1.1 + a2.1) / 2.)
+        }
+        6 => area((0., o1), (d, o2), left),
+        7 | 11 => area((0., o2), (d, o2), left),
+        8 if left >= right => area((half, 0.), (d, o1), left),
+        9 if offset.abs() > 0. => {
+            let a1 = area((0., o2), (d, o1), left);
+            let a2 = add(
+                area((0., o2), (half, 0.), left),
+                area((half, 0.), (d, o1), left),
+            );
+            ((a1.0 + a2.0) / 2., (a1.1 + a2.1) / 2.)
+        }
+        9 => area((0., o2), (d, o1), left),
+        12 => {
+            let a1 = area((0., o1), (half, 0.), left);
+            let a2 = area((half, 0.), (d, o1), left);
+            smooth_area(d, a1, a2)
+        }
+        13 => area((0., o2), (d, o1), left),
+        14 => area((0., o1), (d, o2), left),
+        _ => (0., 0.),
+    }
+}
+
+// RG8 texels, the diagonal half on the right is left empty
+pub fn area_texture() -> Vec<u8> {
+    let width = AREA_TEX_WIDTH as usize;
+    let mut data = vec![0u8; width * AREA_TEX_HEIGHT as usize * 2];
+    let cell_size = MAX_DISTANCE * 5;
+    for (slab, &offset) in SUBSAMPLE_OFFSETS.iter().enumerate() {
+        for (pattern, &(cell_x, cell_y)) in EDGES_ORTHO.iter().enumerate() {
+            for y in 0..MAX_DISTANCE {
+                for x in 0..MAX_DISTANCE {
+                    let (left, right) = ((x * x) as f32, (y * y) as f32);
+                    let (a1, a2) = area_ortho(pattern, left, right, offset);
+                    let row = slab * cell_size + cell_y * MAX_DISTANCE + y;
+                    let column = cell_x * MAX_DISTANCE + x;
+                    let idx = (row * width + column) * 2;
+                    data[idx] = (a1 * 255.).round().clamp(0., 255.) as u8;
+                    data[idx + 1] = (a2 * 255.).round().clamp(0., 255.) as u8;
+                }
+            }
+        }
+    }
+    data
+}
+
+// Edges active in a bilinear fetch at (-0.25, -0.125) from the current pixel,
+// the fetched value is (e0 + 3 e1 + 7 e2 + 21 e3) / 32
+fn decode_fetch(code: usize) -> Option<[bool; 4]> {
+    (0..16).find_map(|bits: usize| {
+        let e = [bits & 1 != 0, bits & 2 != 0, bits & 4 != 0, bits & 8 != 0];
+        let value = e[0] as usize + 3 * e[1] as usize + 7 * e[2] as usize + 21 * e[3] as usize;
+        (value == code).then_some(e)
+    })
+}
+
+fn delta_left(left: [bool; 4], top: [bool; 4]) -> u8 {
+    let mut d = 0;
+    if top[3] {
+        d += 1;
+    }
+    if d == 1 && top[2] && !left[1] && !left[3] {
+        d += 1;
+    }
+    d
+}
+
+fn delta_right(left: [bool; 4], top: [bool; 4]) -> u8 {
+    let mut d = 0;
+    if top[3] && !left[1] && !left[3] {
+        d += 1;
+    }
+    if d == 1 && top[2] && !left[0] && !left[2] {
+        d += 1;
+    }
+    d
+}
+
+// R8 texels, the 66x33 table cropped to its lower half and flipped
+pub fn search_texture() -> Vec<u8> {
+    let width = SEARCH_TEX_WIDTH as usize;
+    let mut data = vec![0u8; width * SEARCH_TEX_HEIGHT as usize];
+    for row in 0..SEARCH_TEX_HEIGHT as usize {
+        let Some(top) = decode_fetch(32 - row) else {
+            continue;
+        };
+        for x in 0..width {
+            let (code, delta): (_, fn(_, _) -> u8) = if x < 33 {
+                (x, delta_left)
+            } else {
+                (x - 33, delta_right)
+            };
+            if let Some(left) = decode_fetch(code) {
+                data[row * width + x] = 127 * delta(left, top);
+            }
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Texels from AreaTex.h and SearchTex.h in the reference implementation
+    #[test]
+    fn matches_reference_textures() {
+        let area = area_texture();
+        let texel = |x: usize, y: usize| {
+            let idx = (y * AREA_TEX_WIDTH as usize + x) * 2;
+            (area[idx], area[idx + 1])
+        };
+        assert_eq!(texel(0, 0), (0, 0));
+        assert_eq!(texel(48, 0), (32, 0));
+        assert_eq!(texel(16, 0), (0, 32));
+        assert_eq!(texel(0, 16), (0, 32));
+        assert_eq!(texel(16, 16), (0, 126));
+        assert_eq!(texel(3, 48), (115, 0));
+
+        let search = search_texture();
+        #[rustfmt::skip]
+        let first_row = [
+            0xfe, 0xfe, 0x00, 0x7f, 0x7f, 0x00, 0x00, 0xfe, 0xfe, 0x00, 0x7f, 0x7f, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7f, 0x7f, 0x00, 0x7f, 0x7f, 0x00, 0x00,
+            0x7f, 0x7f, 0x00, 0x7f, 0x7f, 0xfe, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7f, 0x7f,
+            0x00,
+        ];
+        assert_eq!(search[..first_row.len()], first_row);
+    }
+}
@@ -0,0 +1,171 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint edges_img;
+    uint area_img;
+    uint search_img;
+    uint blend_img;
+    uint max_search_steps;
+    float corner_rounding;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+// Layout of the textures generated in textures.rs
+const float AREATEX_MAX_DISTANCE = 16.;
+const vec2 AREATEX_PIXEL_SIZE = 1. / vec2(160., 560.);
+const vec2 SEARCHTEX_SIZE = vec2(66., 33.);
+const vec2 SEARCHTEX_PACKED_SIZE = vec2(64., 16.);
+
+vec2 INV_DIMS;
+
+// Bilinear fetches are what tells the searches which edges are set
+vec2 edges_at(vec2 uv) { return TexLinear(pc.edges_img, uv).rg; }
+vec2 edges_offset(vec2 uv, ivec2 offset) {
+    return edges_at(uv + vec2(offset) * INV_DIMS);
+}
+
+// Distance to the end of the edge in the last two pixels of a search
+float search_length(vec2 e, float offset) {
+    vec2 scale = SEARCHTEX_SIZE * vec2(0.5, -1.) + vec2(-1., 1.);
+    vec2 bias = SEARCHTEX_SIZE * vec2(offset, 1.) + vec2(0.5, -0.5);
+    scale /= SEARCHTEX_PACKED_SIZE;
+    bias /= SEARCHTEX_PACKED_SIZE;
+    return TexNear(pc.search_img, scale * e + bias).r;
+}
+
+float search_x_left(vec2 uv, float end) {
+    vec2 e = vec2(0., 1.);
+    while (uv.x > end && e.g > 0.8281 && e.r == 0.) {
+        e = edges_at(uv);
+        uv.x -= 2. * INV_DIMS.x;
+    }
+    float offset = -(255. / 127.) * search_length(e, 0.) + 3.25;
+    return INV_DIMS.x * offset + uv.x;
+}
+
+float search_x_right(vec2 uv, float end) {
+    vec2 e = vec2(0., 1.);
+    while (uv.x < end && e.g > 0.8281 && e.r == 0.) {
+        e = edges_at(uv);
+        uv.x += 2. * INV_DIMS.x;
+    }
+    float offset = -(255. / 127.) * search_length(e, 0.5) + 3.25;
+    return -INV_DIMS.x * offset + uv.x;
+}
+
+float search_y_up(vec2 uv, float end) {
+    vec2 e = vec2(1., 0.);
+    while (uv.y > end && e.r > 0.8281 && e.g == 0.) {
+        e = edges_at(uv);
+        uv.y -= 2. * INV_DIMS.y;
+    }
+    float offset = -(255. / 127.) * search_length(e.gr, 0.) + 3.25;
+    return INV_DIMS.y * offset + uv.y;
+}
+
+float search_y_down(vec2 uv, float end) {
+    vec2 e = vec2(1., 0.);
+    while (uv.y < end && e.r > 0.8281 && e.g == 0.) {
+        e = edges_at(uv);
+        uv.y += 2. * INV_DIMS.y;
+    }
+    float offset = -(255. / 127.) * search_length(e.gr, 0.5) + 3.25;
+    return -INV_DIMS.y * offset + uv.y;
+}
+
+// Coverage for the distances to both ends and the crossing edges there
+vec2 area(vec2 dist, float e1, float e2) {
+    vec2 uv = AREATEX_MAX_DISTANCE * round(4. * vec2(e1, e2)) + dist;
+    // Always the first subsample slab, the others are only used by SMAA T2x
+    uv = AREATEX_PIXEL_SIZE * uv + 0.5 * AREATEX_PIXEL_SIZE;
+    return TexLinear(pc.area_img, uv).rg;
+}
+
+void detect_horizontal_corner(inout vec2 weights, vec4 uv, vec2 d) {
+    vec2 left_right = step(d.xy, d.yx);
+    vec2 rounding = (1. - pc.corner_rounding) * left_right;
+    // Less blending for pixels in the middle of a line
+    rounding /= left_right.x + left_right.y;
+    vec2 factor = vec2(1.);
+    factor.x -= rounding.x * edges_offset(uv.xy, ivec2(0, 1)).r;
+    factor.x -= rounding.y * edges_offset(uv.zw, ivec2(1, 1)).r;
+    factor.y -= rounding.x * edges_offset(uv.xy, ivec2(0, -2)).r;
+    factor.y -= rounding.y * edges_offset(uv.zw, ivec2(1, -2)).r;
+    weights *= clamp(factor, 0., 1.);
+}
+
+void detect_vertical_corner(inout vec2 weights, vec4 uv, vec2 d) {
+    vec2 left_right = step(d.xy, d.yx);
+    vec2 rounding = (1. - pc.corner_rounding) * left_right;
+    rounding /= left_right.x + left_right.y;
+    vec2 factor = vec2(1.);
+    factor.x -= rounding.x * edges_offset(uv.xy, ivec2(1, 0)).g;
+    factor.x -= rounding.y * edges_offset(uv.zw, ivec2(1, 1)).g;
+    factor.y -= rounding.x * edges_offset(uv.xy, ivec2(-2, 0)).g;
+    factor.y -= rounding.y * edges_offset(uv.zw, ivec2(-2, 1)).g;
+    weights *= clamp(factor, 0., 1.);
+}
+
+// Blending weight calculation without diagonal patterns
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.blend_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+    INV_DIMS = 1. / vec2(dims);
+    vec2 pixel = vec2(gid) + 0.5;
+    vec2 uv = pixel * INV_DIMS;
+
+    vec4 offset0 = uv.xyxy + INV_DIMS.xyxy * vec4(-0.25, -0.125, 1.25, -0.125);
+    vec4 offset1 = uv.xyxy + INV_DIMS.xyxy * vec4(-0.125, -0.25, -0.125, 1.25);
+    vec4 offset2 = vec4(offset0.xz, offset1.yw) +
+                   INV_DIMS.xxyy * vec4(-2., 2., -2., 2.) *
+                       float(pc.max_search_steps);
+
+    vec4 weights = vec4(0.);
+    vec2 e = edges_at(uv);
+
+    // Edge at the top
+    if (e.g > 0.) {
+        vec3 coords;
+        coords.x = search_x_left(offset0.xy, offset2.x);
+        coords.y = offset1.y;
+        float e1 = edges_at(coords.xy).r;
+        coords.z = search_x_right(offset0.zw, offset2.y);
+        vec2 d = abs(round(vec2(dims.x) * vec2(coords.x, coords.z) - pixel.x));
+        float e2 = edges_offset(coords.zy, ivec2(1, 0)).r;
+        weights.rg = area(sqrt(d), e1, e2);
+        coords.y = uv.y;
+        detect_horizontal_corner(weights.rg, coords.xyzy, d);
+    }
+
+    // Edge on the left
+    if (e.r > 0.) {
+        vec3 coords;
+        coords.y = search_y_up(offset1.xy, offset2.z);
+        coords.x = offset0.x;
+        float e1 = edges_at(coords.xy).g;
+        coords.z = search_y_down(offset1.zw, offset2.w);
+        vec2 d = abs(round(vec2(dims.y) * vec2(coords.y, coords.z) - pixel.y));
+        float e2 = edges_offset(coords.xz, ivec2(0, 1)).g;
+        weights.ba = area(sqrt(d), e1, e2);
+        coords.x = uv.x;
+        detect_vertical_corner(weights.ba, coords.xyxz, d);
+    }
+
+    imageStore(gstorage[pc.blend_img], gid, weights);
+}
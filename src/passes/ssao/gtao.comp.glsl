@@ -0,0 +1,196 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <camera.glsl>
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    CameraBuf camera_ptr;
+    uint depth_img;
+    uint raw_img;
+    uint slices;
+    uint steps;
+    float radius;
+    float intensity;
+    float proj_scale;
+    float znear;
+    vec3 light_dir;
+    float shadow_length;
+    float shadow_thickness;
+    uint frame;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+const float PI = acos(-1.);
+const float HALF_PI = 0.5 * PI;
+// Fits in half floats, also marks the sky
+const float MAX_DEPTH = 65000.;
+const uint SHADOW_STEPS = 12;
+
+Camera CAM;
+ivec2 DIMS;
+
+float raw_depth_at(ivec2 pix) {
+    return texelFetch(gtextures[pc.depth_img], clamp(pix, ivec2(0), DIMS - 1),
+                      0)
+        .x;
+}
+
+vec3 world_from_depth(vec2 uv, float raw_depth) {
+    vec4 clip = vec4(uv.x * 2. - 1., (1. - uv.y) * 2. - 1., raw_depth, 1.);
+    vec4 world = CAM.clip_to_world * clip;
+    return world.xyz / world.w;
+}
+
+// The sky is pushed far away instead of to infinity
+vec3 world_at(ivec2 pix) {
+    vec2 uv = (vec2(pix) + 0.5) / vec2(DIMS);
+    return world_from_depth(uv, max(raw_depth_at(pix), 1e-7));
+}
+
+// Differences toward the neighbour closest on each axis, the other one may be
+// across a depth discontinuity
+vec3 normal_from_depth(ivec2 gid, vec3 pos, vec3 view) {
+    vec3 left = pos - world_at(gid - ivec2(1, 0));
+    vec3 right = world_at(gid + ivec2(1, 0)) - pos;
+    vec3 up = pos - world_at(gid - ivec2(0, 1));
+    vec3 down = world_at(gid + ivec2(0, 1)) - pos;
+    vec3 dx = dot(left, left) < dot(right, right) ? left : right;
+    vec3 dy = dot(up, up) < dot(down, down) ? up : down;
+    vec3 normal = normalize(cross(dx, dy));
+    return dot(normal, view) < 0. ? -normal : normal;
+}
+
+// Ground truth ambient occlusion, slices are set up in world space so only
+// the inverse view projection is needed
+// https://www.activision.com/cdn/research/Practical_Real_Time_Strategies_for_Accurate_Indirect_Occlusion_NEW%20VERSION_COLOR.pdf
+// https://github.com/GameTechDev/XeGTAO
+float gtao(ivec2 gid, vec2 uv, float raw_depth, vec3 pos, vec3 view,
+           vec3 normal, float screen_radius, vec2 noise) {
+    float falloff_range = 0.615 * pc.radius;
+    float falloff_mul = -1. / falloff_range;
+    float falloff_add = (pc.radius - falloff_range) / falloff_range + 1.;
+    // Keeps the first sample off the center pixel
+    float min_offset = 1.3 / screen_radius;
+
+    float visibility = 0.;
+    for (uint slice = 0; slice < pc.slices; slice++) {
+        float phi = (float(slice) + noise.x) * PI / float(pc.slices);
+        vec2 dir = vec2(cos(phi), sin(phi));
+
+        vec3 side =
+            world_from_depth(uv + dir / vec2(DIMS), raw_depth) - pos;
+        vec3 ortho = normalize(side - dot(side, view) * view);
+        vec3 axis = cross(ortho, view);
+        vec3 proj_normal = normal - axis * dot(normal, axis);
+        float proj_length = length(proj_normal);
+        float cos_n = clamp(dot(proj_normal, view) / proj_length, 0., 1.);
+        float n = sign(dot(ortho, proj_normal)) * acos(cos_n);
+
+        float low_cos0 = cos(n + HALF_PI);
+        float low_cos1 = cos(n - HALF_PI);
+        float horizon_cos0 = low_cos0;
+        float horizon_cos1 = low_cos1;
+        for (uint i = 0; i < pc.steps; i++) {
+            // Denser close to the center
+            float s = (float(i) + noise.y) / float(pc.steps);
+            s = s * s + min_offset;
+            ivec2 offset = ivec2(round(dir * s * screen_radius));
+
+            vec3 delta0 = world_at(gid + offset) - pos;
+            vec3 delta1 = world_at(gid - offset) - pos;
+            float length0 = length(delta0);
+            float length1 = length(delta1);
+            float weight0 = clamp(length0 * falloff_mul + falloff_add, 0., 1.);
+            float weight1 = clamp(length1 * falloff_mul + falloff_add, 0., 1.);
+            float cos0 = mix(low_cos0, dot(delta0 / length0, view), weight0);
+            float cos1 = mix(low_cos1, dot(delta1 / length1, view), weight1);
+            horizon_cos0 = max(horizon_cos0, cos0);
+            horizon_cos1 = max(horizon_cos1, cos1);
+        }
+
+        float h0 = -acos(clamp(horizon_cos1, -1., 1.));
+        float h1 = acos(clamp(horizon_cos0, -1., 1.));
+        h0 = n + clamp(h0 - n, -HALF_PI, HALF_PI);
+        h1 = n + clamp(h1 - n, -HALF_PI, HALF_PI);
+        float arc0 = cos_n + 2. * h0 * sin(n) - cos(2. * h0 - n);
+        float arc1 = cos_n + 2. * h1 * sin(n) - cos(2. * h1 - n);
+        visibility += proj_length * 0.25 * (arc0 + arc1);
+    }
+    return visibility / float(pc.slices);
+}
+
+// Marches the depth buffer toward the light for occluders the shadow maps miss
+float contact_shadow(vec3 pos, vec3 normal, float depth, float noise) {
+    if (pc.shadow_length <= 0. || dot(pc.light_dir, pc.light_dir) == 0.) {
+        return 1.;
+    }
+    // Clears the depth precision, which drops with the distance
+    vec3 origin = pos + normal * (0.002 * depth);
+    for (uint i = 0; i < SHADOW_STEPS; i++) {
+        float t = (float(i) + noise) / float(SHADOW_STEPS) * pc.shadow_length;
+        vec4 clip = CAM.world_to_clip * vec4(origin + pc.light_dir * t, 1.);
+        if (clip.w <= 0.) {
+            break;
+        }
+        vec2 uv = vec2(clip.x, -clip.y) / clip.w * 0.5 + 0.5;
+        if (any(lessThan(uv, vec2(0.))) || any(greaterThan(uv, vec2(1.)))) {
+            break;
+        }
+        float scene_raw = raw_depth_at(ivec2(uv * vec2(DIMS)));
+        float scene_depth = pc.znear / max(scene_raw, 1e-7);
+        float behind = clip.w - scene_depth;
+        if (behind > 0.01 * clip.w && behind < pc.shadow_thickness) {
+            return 0.;
+        }
+    }
+    return 1.;
+}
+
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    DIMS = imageSize(gstorage[pc.raw_img]);
+    if (any(greaterThanEqual(gid, DIMS))) {
+        return;
+    }
+    CAM = pc.camera_ptr.cam;
+
+    float raw_depth = raw_depth_at(gid);
+    float depth = pc.znear / max(raw_depth, 1e-7);
+    if (raw_depth <= 0. || depth >= MAX_DEPTH) {
+        imageStore(gstorage[pc.raw_img], gid, vec4(1., 1., MAX_DEPTH, 0.));
+        return;
+    }
+
+    vec2 uv = (vec2(gid) + 0.5) / vec2(DIMS);
+    vec3 pos = world_from_depth(uv, raw_depth);
+    vec3 view = normalize(CAM.pos.xyz - pos);
+    vec3 normal = normal_from_depth(gid, pos, view);
+
+    ivec2 noise_size = textureSize(gtextures[BLUE_TEX], 0);
+    vec3 noise = texelFetch(gtextures[BLUE_TEX], gid % noise_size, 0).rgb;
+    // R2 offsets decorrelate the frames for the temporal denoise
+    noise = fract(noise + float(pc.frame % 64u) *
+                              vec3(0.7548776662, 0.5698402910, 0.6180339887));
+
+    float visibility = 1.;
+    float screen_radius = pc.radius * pc.proj_scale / depth;
+    if (screen_radius >= 1.) {
+        visibility = gtao(gid, uv, raw_depth, pos, view, normal, screen_radius,
+                          noise.xy);
+        visibility = pow(clamp(visibility, 0., 1.), pc.intensity);
+    }
+    float shadow = contact_shadow(pos, normal, depth, noise.z);
+
+    imageStore(gstorage[pc.raw_img], gid, vec4(visibility, shadow, depth, 0.));
+}
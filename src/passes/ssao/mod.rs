@@ -0,0 +1,321 @@
+use std::mem;
+
+use anyhow::Result;
+use ash::vk;
+use glam::Vec3;
+
+use crate::{
+    AppState, Camera, ComputeHandle, FrameGuard, ImageHandle, ImageTransition, ImageUsage,
+    RenderContext, ScreenRelation, dispatch_optimal,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct SsaoSettings {
+    // World space distance within which geometry occludes a point
+    pub radius: f32,
+    // Exponent on the visibility, above 1 darkens
+    pub intensity: f32,
+    // Directions around each pixel, rotated every frame
+    pub slices: u32,
+    // Samples on each side of a slice
+    pub steps: u32,
+    // Relative depth difference the spatial denoise still blurs across
+    pub depth_tolerance: f32,
+    // History weight of the temporal denoise, 0 disables it
+    pub temporal_feedback: f32,
+    // World space length of the rays toward SsaoParams::light_direction, 0 disables them
+    pub contact_shadow_length: f32,
+    // How far behind the depth buffer a surface is assumed to extend
+    pub contact_shadow_thickness: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            radius: 1.,
+            intensity: 1.,
+            slices: 2,
+            steps: 4,
+            depth_tolerance: 0.05,
+            temporal_feedback: 0.9,
+            contact_shadow_length: 0.25,
+            contact_shadow_thickness: 0.1,
+        }
+    }
+}
+
+pub struct SsaoParams {
+    // Reversed infinite depth at render resolution, the one Taa consumes
+    pub depth_image: ImageHandle,
+    // World space direction toward the light casting contact shadows
+    pub light_direction: Option<Vec3>,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GtaoPC {
+    camera_buffer: u64,
+    depth_image: u32,
+    raw_image: u32,
+    slices: u32,
+    steps: u32,
+    radius: f32,
+    intensity: f32,
+    proj_scale: f32,
+    znear: f32,
+    light_direction: Vec3,
+    contact_shadow_length: f32,
+    contact_shadow_thickness: f32,
+    frame: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct TemporalPC {
+    camera_buffer: u64,
+    depth_image: u32,
+    raw_image: u32,
+    history_image: u32,
+    accum_image: u32,
+    feedback: f32,
+    reset: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct SpatialPC {
+    accum_image: u32,
+    ao_image: u32,
+    history_image: u32,
+    depth_tolerance: f32,
+}
+
+pub struct Ssao {
+    gtao_pipeline: ComputeHandle,
+    temporal_pipeline: ComputeHandle,
+    spatial_pipeline: ComputeHandle,
+    // All of them hold ambient visibility, contact shadow visibility and linear depth
+    raw_image: ImageHandle,
+    accum_image: ImageHandle,
+    history_image: ImageHandle,
+    ao_image: ImageHandle,
+    // Render sized images are recreated when the scale changes
+    history_extent: vk::Extent3D,
+
+    pub settings: SsaoSettings,
+}
+
+impl Ssao {
+    pub fn new(ctx: &RenderContext, state: &mut AppState, settings: SsaoSettings) -> Result<Self> {
+        let desc_layouts = [
+            state.texture_arena.sampled_set_layout,
+            state.texture_arena.storage_set_layout,
+        ];
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<GtaoPC>() as u32);
+        let gtao_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/ssao/gtao.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<TemporalPC>() as u32);
+        let temporal_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/ssao/temporal.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<SpatialPC>() as u32);
+        let spatial_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/ssao/spatial.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
+
+        let info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: ctx.swapchain.extent.width,
+                height: ctx.swapchain.extent.height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R16G16B16A16_SFLOAT)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::STORAGE)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let mut push_image = |name| {
+            state
+                .texture_arena
+                .push_image(info, ScreenRelation::Render, &[], Some(name))
+        };
+        let raw_image = push_image("Ssao Raw")?;
+        let accum_image = push_image("Ssao Accumulated")?;
+        let history_image = push_image("Ssao History")?;
+        let ao_image = push_image("Ssao")?;
+
+        Ok(Self {
+            gtao_pipeline,
+            temporal_pipeline,
+            spatial_pipeline,
+            raw_image,
+            accum_image,
+            history_image,
+            ao_image,
+            history_extent: vk::Extent3D::default(),
+            settings,
+        })
+    }
+
+    // Ambient visibility in red and contact shadow visibility in green, valid after apply
+    pub fn ao_image(&self) -> ImageHandle {
+        self.ao_image
+    }
+
+    pub fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: SsaoParams,
+    ) {
+        let _marker = ctx
+            .device
+            .create_scoped_marker(frame.command_buffer(), "Ssao Pass");
+        let settings = self.settings;
+        let frame_idx = state.frame;
+        let camera_buffer = state.camera_uniform_gpu.address;
+        let texture_arena = &mut state.texture_arena;
+        let extent = texture_arena.get_image(self.ao_image).info.unwrap().extent;
+        let reset = state.history_reset || extent != self.history_extent;
+        self.history_extent = extent;
+        const SUBGROUP_SIZE: u32 = 16;
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(params.depth_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.raw_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        {
+            // Pixels per world unit at a linear depth of 1
+            let proj_scale = 0.5 * extent.height as f32 / (0.5 * Camera::FOVY).tan();
+            let push_constant = GtaoPC {
+                camera_buffer,
+                depth_image: texture_arena.get_sampled_idx(params.depth_image, 0),
+                raw_image: texture_arena.get_storage_idx(self.raw_image, 0),
+                slices: settings.slices.max(1),
+                steps: settings.steps.max(1),
+                radius: settings.radius.max(1e-3),
+                intensity: settings.intensity.max(0.),
+                proj_scale,
+                znear: Camera::ZNEAR,
+                light_direction: params
+                    .light_direction
+                    .map_or(Vec3::ZERO, |direction| direction.normalize_or_zero()),
+                contact_shadow_length: settings.contact_shadow_length.max(0.),
+                contact_shadow_thickness: settings.contact_shadow_thickness.max(1e-3),
+                frame: frame_idx,
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.gtao_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(
+                dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(self.raw_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.history_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.accum_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        {
+            let push_constant = TemporalPC {
+                camera_buffer,
+                depth_image: texture_arena.get_sampled_idx(params.depth_image, 0),
+                raw_image: texture_arena.get_sampled_idx(self.raw_image, 0),
+                history_image: texture_arena.get_sampled_idx(self.history_image, 0),
+                accum_image: texture_arena.get_storage_idx(self.accum_image, 0),
+                feedback: settings.temporal_feedback.clamp(0., 0.98),
+                reset: reset as u32,
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.temporal_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(
+                dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+
+        texture_arena.transitions(
+            frame.command_buffer(),
+            &[
+                ImageTransition::new(self.accum_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.history_image, ImageUsage::ComputeStorage),
+                ImageTransition::new(self.ao_image, ImageUsage::ComputeStorage),
+            ],
+        );
+
+        // Also feeds the next frame, so the history gets blurred once more each frame
+        {
+            let push_constant = SpatialPC {
+                accum_image: texture_arena.get_sampled_idx(self.accum_image, 0),
+                ao_image: texture_arena.get_storage_idx(self.ao_image, 0),
+                history_image: texture_arena.get_storage_idx(self.history_image, 0),
+                depth_tolerance: settings.depth_tolerance.max(1e-4),
+            };
+            let pipeline = state.pipeline_arena.get_pipeline(self.spatial_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            frame.dispatch(
+                dispatch_optimal(extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
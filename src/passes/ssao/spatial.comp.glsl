@@ -0,0 +1,54 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint accum_img;
+    uint ao_img;
+    uint history_img;
+    float depth_tolerance;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+const int RADIUS = 2;
+
+// Tent filter weighted by how close the neighbours are in depth
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.ao_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+
+    vec4 center = texelFetch(gtextures[pc.accum_img], gid, 0);
+    float inv_tolerance = 1. / (center.z * pc.depth_tolerance);
+
+    vec2 sum = vec2(0.);
+    float weight_sum = 0.;
+    for (int y = -RADIUS; y <= RADIUS; y++) {
+        for (int x = -RADIUS; x <= RADIUS; x++) {
+            ivec2 pix = clamp(gid + ivec2(x, y), ivec2(0), dims - 1);
+            vec4 neighbour = texelFetch(gtextures[pc.accum_img], pix, 0);
+            float weight = (float(RADIUS + 1 - abs(x)) *
+                            float(RADIUS + 1 - abs(y)));
+            weight *= max(0., 1. - abs(neighbour.z - center.z) * inv_tolerance);
+            sum += neighbour.xy * weight;
+            weight_sum += weight;
+        }
+    }
+
+    vec4 result = vec4(sum / weight_sum, center.z, 0.);
+    imageStore(gstorage[pc.ao_img], gid, result);
+    imageStore(gstorage[pc.history_img], gid, result);
+}
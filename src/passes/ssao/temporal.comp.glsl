@@ -0,0 +1,64 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+layout(set = 1, binding = 0) uniform writeonly image2D gstorage[];
+
+#include <camera.glsl>
+#include <textures.glsl>
+
+layout(scalar, push_constant) uniform PushConstant {
+    CameraBuf camera_ptr;
+    uint depth_img;
+    uint raw_img;
+    uint history_img;
+    uint accum_img;
+    float feedback;
+    bool reset;
+}
+pc;
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+// Reprojects the history through the previous frame's camera, rejecting it
+// where the depth it was computed at doesn't match
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.accum_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+
+    vec4 current = texelFetch(gtextures[pc.raw_img], gid, 0);
+    float raw_depth = texelFetch(gtextures[pc.depth_img], gid, 0).x;
+    if (pc.reset || raw_depth <= 0.) {
+        imageStore(gstorage[pc.accum_img], gid, current);
+        return;
+    }
+
+    Camera camera = pc.camera_ptr.cam;
+    vec2 uv = (vec2(gid) + 0.5) / vec2(dims);
+    vec4 clip = vec4(uv.x * 2. - 1., (1. - uv.y) * 2. - 1., raw_depth, 1.);
+    vec4 world = camera.clip_to_world * clip;
+    vec4 prev_clip = camera.prev_world_to_clip * vec4(world.xyz / world.w, 1.);
+    vec2 prev_uv = vec2(prev_clip.x, -prev_clip.y) / prev_clip.w * 0.5 + 0.5;
+    if (prev_clip.w <= 0. || any(lessThan(prev_uv, vec2(0.))) ||
+        any(greaterThan(prev_uv, vec2(1.)))) {
+        imageStore(gstorage[pc.accum_img], gid, current);
+        return;
+    }
+
+    // Linear depth is the clip w of the reversed infinite projection
+    vec4 history = TexLinear(pc.history_img, prev_uv);
+    float depth_error = abs(history.z - prev_clip.w) / prev_clip.w;
+    float feedback = pc.feedback * (1. - smoothstep(0.02, 0.1, depth_error));
+
+    vec2 visibility = mix(current.xy, history.xy, feedback);
+    imageStore(gstorage[pc.accum_img], gid,
+               vec4(visibility, current.z, 0.));
+}
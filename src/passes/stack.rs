@@ -0,0 +1,387 @@
+use std::any::Any;
+
+use anyhow::{Result, bail, ensure};
+use ash::vk;
+
+use crate::{
+    AppState, FrameGuard, GraphImage, ImageHandle, ImageUsage, PassBuilder, RenderContext,
+    RenderGraph, ViewTarget,
+};
+
+// How a pass touches the view target, the stack declares these accesses to the graph
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TargetAccess {
+    // Reads the main image and writes the other one through a single post_process_write
+    PingPong,
+    // Writes the main image in place
+    InPlace,
+    // Only reads the main image
+    Read,
+    // Reads the main image and writes the next image of PostProcessInputs::output_target, later
+    // passes continue on the output target. Same as PingPong without an output target
+    Resolve,
+}
+
+// Resources the stack hands to its passes, gathered while building the graph
+#[derive(Clone, Copy)]
+pub struct PostProcessInputs<'a> {
+    pub view_target: &'a ViewTarget,
+    // Image of the view target holding the frame once the passes before the stack ran
+    pub main_image: ImageHandle,
+    // Written by the first Resolve pass, e.g. the display sized pair Taa upscales into
+    pub output_target: Option<&'a ViewTarget>,
+    pub depth_image: Option<GraphImage>,
+    // Motion vectors in uv units, Taa::motion_image
+    pub motion_image: Option<GraphImage>,
+    // Handle and address of AutoExposure::exposure_buffer
+    pub exposure_buffer: Option<(vk::Buffer, u64)>,
+}
+
+impl<'a> PostProcessInputs<'a> {
+    pub fn new(view_target: &'a ViewTarget) -> Self {
+        Self {
+            view_target,
+            main_image: *view_target.main_image(),
+            output_target: None,
+            depth_image: None,
+            motion_image: None,
+            exposure_buffer: None,
+        }
+    }
+}
+
+pub struct PostProcessParams<'a> {
+    pub view_target: &'a ViewTarget,
+    // Only set for the Resolve pass writing into it
+    pub output_target: Option<&'a ViewTarget>,
+    pub depth_image: Option<ImageHandle>,
+    pub motion_image: Option<ImageHandle>,
+    pub exposure_buffer: Option<u64>,
+}
+
+pub trait PostProcessPass: Any + Sized {
+    type Settings: Clone;
+    // Names the pass in the stack and in the render graph
+    const NAME: &'static str;
+
+    fn new(ctx: &RenderContext, state: &mut AppState, settings: Self::Settings) -> Result<Self>;
+    // Screen sized images are recreated by the texture arena, this is for state tied to them
+    fn resize(&mut self, _ctx: &RenderContext, _state: &mut AppState) -> Result<()> {
+        Ok(())
+    }
+    fn settings(&self) -> &Self::Settings;
+    fn set_settings(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        settings: Self::Settings,
+    ) -> Result<()>;
+    fn access(&self) -> TargetAccess {
+        TargetAccess::PingPong
+    }
+    // How the main image is read unless the access is InPlace
+    fn main_image_usage(&self) -> ImageUsage {
+        ImageUsage::ComputeSampled
+    }
+    // Skipped for the frame otherwise, e.g. when an input it needs is missing
+    fn is_active(&self, _inputs: &PostProcessInputs) -> bool {
+        true
+    }
+    // Declares resources besides the view target
+    fn declare<'g, 'a>(
+        &self,
+        pass: PassBuilder<'g, 'a>,
+        _inputs: &PostProcessInputs,
+    ) -> PassBuilder<'g, 'a> {
+        pass
+    }
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    );
+}
+
+// Object safe side of PostProcessPass, the settings type is only reachable by downcasting
+trait DynPass: Any {
+    fn name(&self) -> &'static str;
+    fn resize(&mut self, ctx: &RenderContext, state: &mut AppState) -> Result<()>;
+    fn access(&self) -> TargetAccess;
+    fn main_image_usage(&self) -> ImageUsage;
+    fn is_active(&self, inputs: &PostProcessInputs) -> bool;
+    fn declare<'g, 'a>(
+        &self,
+        pass: PassBuilder<'g, 'a>,
+        inputs: &PostProcessInputs,
+    ) -> PassBuilder<'g, 'a>;
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    );
+}
+
+impl<P: PostProcessPass> DynPass for P {
+    fn name(&self) -> &'static str {
+        P::NAME
+    }
+    fn resize(&mut self, ctx: &RenderContext, state: &mut AppState) -> Result<()> {
+        PostProcessPass::resize(self, ctx, state)
+    }
+    fn access(&self) -> TargetAccess {
+        PostProcessPass::access(self)
+    }
+    fn main_image_usage(&self) -> ImageUsage {
+        PostProcessPass::main_image_usage(self)
+    }
+    fn is_active(&self, inputs: &PostProcessInputs) -> bool {
+        PostProcessPass::is_active(self, inputs)
+    }
+    fn declare<'g, 'a>(
+        &self,
+        pass: PassBuilder<'g, 'a>,
+        inputs: &PostProcessInputs,
+    ) -> PassBuilder<'g, 'a> {
+        PostProcessPass::declare(self, pass, inputs)
+    }
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    ) {
+        PostProcessPass::apply(self, ctx, state, frame, params)
+    }
+}
+
+struct StackEntry {
+    pass: Box<dyn DynPass>,
+    enabled: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TargetWrite {
+    pub pass: &'static str,
+    pub image: ImageHandle,
+}
+
+// Ordered passes over a ViewTarget, recorded into the graph one pass each
+#[derive(Default)]
+pub struct PostProcessStack {
+    entries: Vec<StackEntry>,
+    // Passes are resized when the view target extent changes between frames
+    extent: Option<vk::Extent3D>,
+    writes: Vec<TargetWrite>,
+}
+
+impl PostProcessStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Creates the pass at the end of the stack, enabled
+    pub fn push<P: PostProcessPass>(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        settings: P::Settings,
+    ) -> Result<()> {
+        ensure!(
+            self.position(P::NAME).is_none(),
+            "Pass {} is already in the stack",
+            P::NAME
+        );
+        let pass = P::new(ctx, state, settings)?;
+        self.entries.push(StackEntry {
+            pass: Box::new(pass),
+            enabled: true,
+        });
+        Ok(())
+    }
+
+    pub fn get<P: PostProcessPass>(&self) -> Option<&P> {
+        self.entries
+            .iter()
+            .find_map(|entry| (&*entry.pass as &dyn Any).downcast_ref())
+    }
+
+    pub fn get_mut<P: PostProcessPass>(&mut self) -> Option<&mut P> {
+        self.entries
+            .iter_mut()
+            .find_map(|entry| (&mut *entry.pass as &mut dyn Any).downcast_mut())
+    }
+
+    pub fn settings<P: PostProcessPass>(&self) -> Option<&P::Settings> {
+        self.get::<P>().map(|pass| pass.settings())
+    }
+
+    pub fn set_settings<P: PostProcessPass>(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        settings: P::Settings,
+    ) -> Result<()> {
+        let Some(pass) = self.get_mut::<P>() else {
+            bail!("Pass {} is not in the stack", P::NAME);
+        };
+        pass.set_settings(ctx, state, settings)
+    }
+
+    // Names in recording order
+    pub fn order(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.entries.iter().map(|entry| entry.pass.name())
+    }
+
+    // Returns false if no pass has that name
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let Some(idx) = self.position(name) else {
+            return false;
+        };
+        self.entries[idx].enabled = enabled;
+        true
+    }
+
+    pub fn is_enabled(&self, name: &str) -> Option<bool> {
+        self.position(name).map(|idx| self.entries[idx].enabled)
+    }
+
+    // Returns the new state
+    pub fn toggle(&mut self, name: &str) -> Option<bool> {
+        let idx = self.position(name)?;
+        let entry = &mut self.entries[idx];
+        entry.enabled = !entry.enabled;
+        Some(entry.enabled)
+    }
+
+    // Index is clamped to the end of the stack, returns false if no pass has that name
+    pub fn move_to(&mut self, name: &str, index: usize) -> bool {
+        let Some(idx) = self.position(name) else {
+            return false;
+        };
+        let entry = self.entries.remove(idx);
+        let index = index.min(self.entries.len());
+        self.entries.insert(index, entry);
+        true
+    }
+
+    pub fn resize(&mut self, ctx: &RenderContext, state: &mut AppState) -> Result<()> {
+        for entry in &mut self.entries {
+            entry.pass.resize(ctx, state)?;
+        }
+        Ok(())
+    }
+
+    // Images of the view target each pass of the last recorded frame wrote, in order
+    pub fn writes(&self) -> &[TargetWrite] {
+        &self.writes
+    }
+
+    // Adds a graph pass for every enabled and active pass, returns the image holding
+    // the result once they ran
+    pub fn record<'a>(
+        &'a mut self,
+        graph: &mut RenderGraph<'a>,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        inputs: PostProcessInputs<'a>,
+    ) -> ImageHandle {
+        // Passes after a Resolve run at the output target's extent
+        let sized_image = inputs
+            .output_target
+            .map_or(inputs.main_image, |target| *target.main_image());
+        let extent = state
+            .texture_arena
+            .get_image(sized_image)
+            .info
+            .unwrap()
+            .extent;
+        if self.extent.is_some_and(|last| last != extent)
+            && let Err(err) = self.resize(ctx, state)
+        {
+            tracing::error!("Failed to resize post processing: {err}");
+        }
+        self.extent = Some(extent);
+
+        let mut target = inputs.view_target;
+        let mut output_target = inputs.output_target;
+        let mut main_image = inputs.main_image;
+        let other_of = |target: &ViewTarget, image| {
+            let [image_a, image_b] = target.images();
+            if image == image_a { image_b } else { image_a }
+        };
+        self.writes.clear();
+
+        for entry in &mut self.entries {
+            if !entry.enabled || !entry.pass.is_active(&inputs) {
+                continue;
+            }
+            let name = entry.pass.name();
+            let access = entry.pass.access();
+            let usage = entry.pass.main_image_usage();
+            let source_target = target;
+            let resolve_target = match access {
+                TargetAccess::Resolve => output_target.take(),
+                _ => None,
+            };
+            let mut builder = graph.add_pass(name);
+            let expected_image = match access {
+                TargetAccess::PingPong | TargetAccess::Resolve => {
+                    let destination = match resolve_target {
+                        Some(output) => *output.next_post_process_write().destination,
+                        None => other_of(target, main_image),
+                    };
+                    builder = builder
+                        .read(main_image, usage)
+                        .write(destination, ImageUsage::ComputeStorage);
+                    destination
+                }
+                TargetAccess::InPlace => {
+                    builder = builder.write(main_image, ImageUsage::ComputeStorage);
+                    main_image
+                }
+                TargetAccess::Read => {
+                    builder = builder.read(main_image, usage);
+                    main_image
+                }
+            };
+            target = resolve_target.unwrap_or(target);
+            if access != TargetAccess::Read {
+                self.writes.push(TargetWrite {
+                    pass: name,
+                    image: expected_image,
+                });
+            }
+            main_image = expected_image;
+
+            let stack_pass = &mut entry.pass;
+            stack_pass.declare(builder, &inputs).record(move |pass| {
+                let params = PostProcessParams {
+                    view_target: source_target,
+                    output_target: resolve_target,
+                    depth_image: inputs.depth_image.map(|image| pass.image(image)),
+                    motion_image: inputs.motion_image.map(|image| pass.image(image)),
+                    exposure_buffer: inputs.exposure_buffer.map(|(_, address)| address),
+                };
+                stack_pass.apply(pass.ctx, pass.state, pass.frame, params);
+                debug_assert_eq!(
+                    *resolve_target.unwrap_or(source_target).main_image(),
+                    expected_image,
+                    "{name} did not leave the view target as its access declares"
+                );
+            });
+        }
+
+        main_image
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.pass.name() == name)
+    }
+}
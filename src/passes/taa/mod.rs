@@ -3,12 +3,14 @@ use std::mem;
 use anyhow::Result;
 use ash::vk;
 use glam::{vec2, Vec2};
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 use rand::{rngs::StdRng, seq::SliceRandom};
 
 use crate::{
-    dispatch_optimal, AppState, ComputeHandle, FrameGuard, ImageHandle, RenderContext,
-    ScreenRelation, ViewTarget, COLOR_SUBRESOURCE_MASK,
+    dispatch_optimal,
+    passes::stack::{PostProcessInputs, PostProcessParams, PostProcessPass, TargetAccess},
+    AppState, ComputeHandle, FrameGuard, ImageHandle, ImageTransition, ImageUsage, PassBuilder,
+    RenderContext, ScreenRelation, ViewTarget,
 };
 
 #[repr(C)]
@@ -22,17 +24,81 @@ struct ReprojectPC {
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 struct TaaPC {
+    // Where the samples of the source image sit in its pixel grid
+    jitter: Vec2,
     src_image: u32,
     dst_image: u32,
     history_image: u32,
     motion_image: u32,
+    clamp_mode: u32,
+    feedback_min: f32,
+    feedback_max: f32,
+    motion_rejection: f32,
+    reset: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct SharpenPC {
+    history_image: u32,
+    dst_image: u32,
+    sharpness: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JitterSequence {
+    #[default]
+    Halton,
+    R2,
+    BlueNoise,
+}
+
+// Values match the CLAMP_* constants in taa.comp.glsl
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HistoryClamp {
+    #[default]
+    VarianceClip,
+    Aabb,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TaaSettings {
+    pub sequence: JitterSequence,
+    pub sequence_length: u32,
+    pub clamp: HistoryClamp,
+    // Weight of the history, picked between the two by how well it matches the current luma
+    pub feedback_min: f32,
+    pub feedback_max: f32,
+    // Feedback is divided by 1 + motion in pixels times this, 0 disables it
+    pub motion_rejection: f32,
+    // Contrast adaptive sharpening after the resolve, 0 disables it
+    pub sharpness: f32,
+}
+
+impl Default for TaaSettings {
+    fn default() -> Self {
+        Self {
+            sequence: JitterSequence::default(),
+            sequence_length: 16,
+            clamp: HistoryClamp::default(),
+            feedback_min: 0.88,
+            feedback_max: 0.97,
+            motion_rejection: 0.,
+            sharpness: 0.,
+        }
+    }
 }
 
 pub struct TaaParams<'a> {
     pub view_target: &'a ViewTarget,
     pub depth_image: ImageHandle,
+    // Upscales the render resolution view target into this one instead of resolving in place
+    pub output_target: Option<&'a ViewTarget>,
 }
 
+// Caps the stretched sequence, best candidate generation is cubic in the length
+const MAX_SEQUENCE_LENGTH: u32 = 128;
+
 #[inline]
 fn radical_inverse(mut n: u32, base: u32) -> f32 {
     let mut val = 0.0f32;
@@ -49,17 +115,70 @@ fn radical_inverse(mut n: u32, base: u32) -> f32 {
     val
 }
 
+// Offsets in [-1, 1] pixels
+fn jitter_samples(sequence: JitterSequence, n: u32) -> Vec<Vec2> {
+    let n = n.max(1);
+    match sequence {
+        JitterSequence::Halton => (0..n)
+            .map(|i| {
+                Vec2::new(
+                    radical_inverse(i % n + 1, 2) * 2. - 1.,
+                    radical_inverse(i % n + 1, 3) * 2. - 1.,
+                )
+            })
+            .collect(),
+        // https://extremelearning.com.au/unreasonable-effectiveness-of-quasirandom-sequences/
+        JitterSequence::R2 => {
+            let g = 1.324_717_9_f32;
+            let alpha = vec2(1. / g, 1. / (g * g));
+            (0..n)
+                .map(|i| (0.5 + alpha * (i + 1) as f32).fract() * 2. - 1.)
+                .collect()
+        }
+        // Mitchell's best candidate, every prefix stays evenly spread
+        JitterSequence::BlueNoise => {
+            const CANDIDATES_PER_SAMPLE: usize = 8;
+            let mut rng = StdRng::seed_from_u64(0x5eed);
+            let toroidal_distance = |a: Vec2, b: Vec2| {
+                let d = (a - b).abs();
+                d.min(2. - d).length_squared()
+            };
+            let mut samples: Vec<Vec2> = Vec::with_capacity(n as usize);
+            for i in 0..n as usize {
+                let candidates =
+                    (0..i * CANDIDATES_PER_SAMPLE + 1).map(|_| rng.random::<Vec2>() * 2. - 1.);
+                let best = candidates
+                    .map(|candidate| {
+                        let distance = samples
+                            .iter()
+                            .map(|&sample| toroidal_distance(candidate, sample))
+                            .fold(f32::INFINITY, f32::min);
+                        (candidate, distance)
+                    })
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map(|(candidate, _)| candidate)
+                    .unwrap();
+                samples.push(best);
+            }
+            samples
+        }
+    }
+}
+
 pub struct Taa {
     history_image: ImageHandle,
     motion_image: ImageHandle,
     reproject_pipeline: ComputeHandle,
     taa_pipeline: ComputeHandle,
+    sharpen_pipeline: ComputeHandle,
+    settings: TaaSettings,
+    history_valid: bool,
 
     pub jitter_samples: Vec<Vec2>,
 }
 
 impl Taa {
-    pub fn new(ctx: &RenderContext, state: &mut AppState) -> Result<Self> {
+    pub fn new(ctx: &RenderContext, state: &mut AppState, settings: TaaSettings) -> Result<Self> {
         let push_constant_range = vk::PushConstantRange::default()
             .stage_flags(vk::ShaderStageFlags::COMPUTE)
             .size(mem::size_of::<ReprojectPC>() as u32);
@@ -80,6 +199,14 @@ impl Taa {
             &[push_constant_range],
             &desc_layouts,
         )?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::COMPUTE)
+            .size(mem::size_of::<SharpenPC>() as u32);
+        let sharpen_pipeline = state.pipeline_arena.create_compute_pipeline(
+            "src/passes/taa/sharpen.comp.glsl",
+            &[push_constant_range],
+            &desc_layouts,
+        )?;
 
         let texture_info = vk::ImageCreateInfo::default()
             .extent(vk::Extent3D {
@@ -97,7 +224,7 @@ impl Taa {
             .initial_layout(vk::ImageLayout::UNDEFINED);
         let motion_image = state.texture_arena.push_image(
             texture_info,
-            ScreenRelation::Identity,
+            ScreenRelation::Render,
             &[],
             Some("Motion Image"),
         )?;
@@ -108,28 +235,62 @@ impl Taa {
             Some("History Image"),
         )?;
 
-        let n = 16;
-        let jitter_samples = (0..n)
-            .map(|i| {
-                Vec2::new(
-                    radical_inverse(i % n + 1, 2) * 2. - 1.,
-                    radical_inverse(i % n + 1, 3) * 2. - 1.,
-                )
-            })
-            .collect();
+        let jitter_samples = jitter_samples(settings.sequence, settings.sequence_length);
 
         Ok(Self {
             history_image,
             motion_image,
             reproject_pipeline,
             taa_pipeline,
+            sharpen_pipeline,
+            settings,
+            history_valid: false,
 
             jitter_samples,
         })
     }
 
-    pub fn get_jitter(&mut self, frame_idx: u32, width: u32, height: u32) -> Vec2 {
-        if 0 == frame_idx % self.jitter_samples.len() as u32 && frame_idx > 0 {
+    pub fn settings(&self) -> &TaaSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: TaaSettings) {
+        if settings.sequence != self.settings.sequence
+            || settings.sequence_length != self.settings.sequence_length
+        {
+            self.jitter_samples = jitter_samples(settings.sequence, settings.sequence_length);
+        }
+        self.settings = settings;
+    }
+
+    // Motion vectors in uv units at the render resolution, written by apply
+    pub fn motion_image(&self) -> ImageHandle {
+        self.motion_image
+    }
+
+    // Drops the accumulated history on the next apply, also done on AppState::history_reset
+    pub fn reset_history(&mut self) {
+        self.history_valid = false;
+    }
+
+    // NDC offset for Camera::jitter, within half a pixel of the render resolution.
+    // Upscaling stretches the sequence by the squared ratio so every output pixel gets covered
+    pub fn get_jitter(
+        &mut self,
+        frame_idx: u32,
+        render_extent: vk::Extent2D,
+        output_extent: vk::Extent2D,
+    ) -> Vec2 {
+        let ratio = output_extent.width as f32 / render_extent.width.max(1) as f32;
+        let length = (self.settings.sequence_length as f32 * ratio * ratio).ceil() as u32;
+        let length = length
+            .min(MAX_SEQUENCE_LENGTH)
+            .max(self.settings.sequence_length);
+        if length != self.jitter_samples.len() as u32 {
+            self.jitter_samples = jitter_samples(self.settings.sequence, length);
+        }
+
+        if frame_idx.is_multiple_of(self.jitter_samples.len() as u32) && frame_idx > 0 {
             let mut rng = StdRng::seed_from_u64(frame_idx as u64);
 
             let prev_sample = self.jitter_samples.last().copied();
@@ -142,11 +303,11 @@ impl Taa {
         }
 
         self.jitter_samples[frame_idx as usize % self.jitter_samples.len()]
-            / vec2(width as f32, height as f32)
+            / vec2(render_extent.width as f32, render_extent.height as f32)
     }
 
     pub fn apply(
-        &self,
+        &mut self,
         ctx: &RenderContext,
         state: &mut AppState,
         frame: &FrameGuard,
@@ -155,25 +316,44 @@ impl Taa {
         let _marker = ctx
             .device
             .create_scoped_marker(frame.command_buffer(), "Taa Pass");
+        let settings = self.settings;
+        let reset = !self.history_valid || state.history_reset;
+        self.history_valid = true;
+        let sharpen = settings.sharpness > 0.;
         let texture_arena = &mut state.texture_arena;
-        let postprocess_write = params.view_target.post_process_write();
-
-        let barrier = vk::ImageMemoryBarrier2::default()
-            .subresource_range(COLOR_SUBRESOURCE_MASK)
-            .image(texture_arena.get_image(*postprocess_write.source).inner)
-            .src_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
-            .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
-            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
-        ctx.device.pipeline_barrier(
+        let (source_image, destination_image) = match params.output_target {
+            Some(output_target) => (
+                *params.view_target.main_image(),
+                *output_target.post_process_write().destination,
+            ),
+            None => {
+                let write = params.view_target.post_process_write();
+                (*write.source, *write.destination)
+            }
+        };
+        let source_extent = texture_arena.get_image(source_image).info.unwrap().extent;
+        let output_extent = texture_arena
+            .get_image(destination_image)
+            .info
+            .unwrap()
+            .extent;
+        // Camera jitter is in NDC with y up, the samples move the other way on screen
+        let jitter = state.camera_uniform.jitter
+            * vec2(0.5, -0.5)
+            * vec2(source_extent.width as f32, source_extent.height as f32);
+
+        texture_arena.transitions(
             frame.command_buffer(),
-            &vk::DependencyInfo::default().image_memory_barriers(&[barrier]),
+            &[
+                ImageTransition::new(params.depth_image, ImageUsage::ComputeSampled),
+                ImageTransition::new(self.motion_image, ImageUsage::ComputeStorage),
+            ],
         );
 
         {
             let reproject_push_constant = ReprojectPC {
                 motion_image: texture_arena.get_storage_idx(self.motion_image, 0),
-                depth_image: texture_arena.get_storage_idx(params.depth_image, 0),
+                depth_image: texture_arena.get_sampled_idx(params.depth_image, 0),
                 camera_buffer: state.camera_uniform_gpu.address,
             };
 
@@ -190,7 +370,11 @@ impl Taa {
             );
             frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
             const SUBGROUP_SIZE: u32 = 16;
-            let extent = ctx.swapchain.extent();
+            let extent = texture_arena
+                .get_image(self.motion_image)
+                .info
+                .unwrap()
+                .extent;
             frame.dispatch(
                 dispatch_optimal(extent.width, SUBGROUP_SIZE),
                 dispatch_optimal(extent.height, SUBGROUP_SIZE),
@@ -198,24 +382,28 @@ impl Taa {
             );
         }
 
-        let barrier = vk::ImageMemoryBarrier2::default()
-            .subresource_range(COLOR_SUBRESOURCE_MASK)
-            .image(texture_arena.get_image(self.motion_image).inner)
-            .src_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
-            .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
-            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
-            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER);
-        ctx.device.pipeline_barrier(
+        texture_arena.transitions(
             frame.command_buffer(),
-            &vk::DependencyInfo::default().image_memory_barriers(&[barrier]),
+            &[
+                ImageTransition::new(source_image, ImageUsage::ComputeStorage),
+                ImageTransition::new(destination_image, ImageUsage::ComputeStorage),
+                ImageTransition::new(self.motion_image, ImageUsage::ComputeStorage),
+                ImageTransition::new(self.history_image, ImageUsage::ComputeStorage),
+            ],
         );
 
         {
             let taa_push_constant = TaaPC {
-                src_image: texture_arena.get_storage_idx(*postprocess_write.source, 0),
-                dst_image: texture_arena.get_storage_idx(*postprocess_write.destination, 0),
+                jitter,
+                src_image: texture_arena.get_storage_idx(source_image, 0),
+                dst_image: texture_arena.get_storage_idx(destination_image, 0),
                 motion_image: texture_arena.get_storage_idx(self.motion_image, 0),
                 history_image: texture_arena.get_storage_idx(self.history_image, 0),
+                clamp_mode: settings.clamp as u32,
+                feedback_min: settings.feedback_min,
+                feedback_max: settings.feedback_max,
+                motion_rejection: settings.motion_rejection,
+                reset: reset as u32,
             };
 
             let pipeline = state.pipeline_arena.get_pipeline(self.taa_pipeline);
@@ -231,20 +419,114 @@ impl Taa {
             );
             frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
             const SUBGROUP_SIZE: u32 = 16;
-            let extent = ctx.swapchain.extent();
             frame.dispatch(
-                dispatch_optimal(extent.width, SUBGROUP_SIZE),
-                dispatch_optimal(extent.height, SUBGROUP_SIZE),
+                dispatch_optimal(output_extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(output_extent.height, SUBGROUP_SIZE),
                 1,
             );
         }
 
-        let barrier = barrier
-            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
-            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS);
-        ctx.device.pipeline_barrier(
-            frame.command_buffer(),
-            &vk::DependencyInfo::default().image_memory_barriers(&[barrier]),
-        );
+        if sharpen {
+            texture_arena.transition(
+                frame.command_buffer(),
+                self.history_image,
+                ImageUsage::ComputeStorage,
+            );
+            texture_arena.transition(
+                frame.command_buffer(),
+                destination_image,
+                ImageUsage::ComputeStorage,
+            );
+            let sharpen_push_constant = SharpenPC {
+                history_image: texture_arena.get_storage_idx(self.history_image, 0),
+                dst_image: texture_arena.get_storage_idx(destination_image, 0),
+                sharpness: settings.sharpness,
+            };
+
+            let pipeline = state.pipeline_arena.get_pipeline(self.sharpen_pipeline);
+            frame.bind_push_constants(
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                &[sharpen_push_constant],
+            );
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                &[texture_arena.sampled_set, texture_arena.storage_set],
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+            const SUBGROUP_SIZE: u32 = 16;
+            frame.dispatch(
+                dispatch_optimal(output_extent.width, SUBGROUP_SIZE),
+                dispatch_optimal(output_extent.height, SUBGROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
+
+impl PostProcessPass for Taa {
+    type Settings = TaaSettings;
+    const NAME: &'static str = "Taa";
+
+    fn new(ctx: &RenderContext, state: &mut AppState, settings: TaaSettings) -> Result<Self> {
+        Self::new(ctx, state, settings)
+    }
+
+    // The history images came back empty
+    fn resize(&mut self, _ctx: &RenderContext, _state: &mut AppState) -> Result<()> {
+        self.reset_history();
+        Ok(())
+    }
+
+    fn settings(&self) -> &TaaSettings {
+        &self.settings
+    }
+
+    fn set_settings(
+        &mut self,
+        _ctx: &RenderContext,
+        _state: &mut AppState,
+        settings: TaaSettings,
+    ) -> Result<()> {
+        Taa::set_settings(self, settings);
+        Ok(())
+    }
+
+    fn access(&self) -> TargetAccess {
+        TargetAccess::Resolve
+    }
+
+    // The resolve shader loads the source as a storage image
+    fn main_image_usage(&self) -> ImageUsage {
+        ImageUsage::ComputeStorage
+    }
+
+    fn is_active(&self, inputs: &PostProcessInputs) -> bool {
+        inputs.depth_image.is_some()
+    }
+
+    fn declare<'g, 'a>(
+        &self,
+        pass: PassBuilder<'g, 'a>,
+        inputs: &PostProcessInputs,
+    ) -> PassBuilder<'g, 'a> {
+        pass.read(inputs.depth_image.unwrap(), ImageUsage::ComputeSampled)
+            .write(self.motion_image, ImageUsage::ComputeStorage)
+    }
+
+    fn apply(
+        &mut self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: PostProcessParams,
+    ) {
+        let params = TaaParams {
+            view_target: params.view_target,
+            depth_image: params.depth_image.unwrap(),
+            output_target: params.output_target,
+        };
+        Taa::apply(self, ctx, state, frame, params);
     }
 }
@@ -42,10 +42,13 @@ void main() {
 
     vec2 uv = (vec2(gid) + 0.5) / vec2(dims);
 
+    // Sampled like the other depth readers, so the graph keeps it in one layout
+    ivec2 depth_dims = textureSize(gtextures[pc.depth_img], 0);
     float depth = 0.;
     for (int y = -1; y <= 1; ++y) {
         for (int x = -1; x <= 1; ++x) {
-            float d = imageLoad(gstorage[pc.depth_img], gid + ivec2(x, y)).x;
+            ivec2 pix = clamp(gid + ivec2(x, y), ivec2(0), depth_dims - 1);
+            float d = texelFetch(gtextures[pc.depth_img], pix, 0).x;
             depth = max(depth, d);
         }
     }
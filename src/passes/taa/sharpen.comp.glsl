@@ -0,0 +1,61 @@
+#version 460
+#extension GL_EXT_nonuniform_qualifier : require
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_scalar_block_layout : require
+#extension GL_EXT_shader_image_load_formatted : require
+#extension GL_EXT_samplerless_texture_functions : require
+
+layout(scalar, push_constant) uniform PushConstant {
+    uint history_img;
+    uint dst_img;
+    float sharpness;
+}
+pc;
+
+layout(set = 1, binding = 0) coherent restrict uniform image2D gstorage[];
+
+float max3(vec3 x) { return max(x.r, max(x.g, x.b)); }
+vec3 reverse_tonemap(vec3 color) { return color * 1. / (1.0 - max3(color)); }
+
+vec3 load(ivec2 pos, ivec2 dims) {
+    pos = clamp(pos, ivec2(0), dims - 1);
+    return imageLoad(gstorage[pc.history_img], pos).rgb;
+}
+
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+
+// Contrast adaptive sharpening on the tonemapped history
+// https://github.com/GPUOpen-Effects/FidelityFX-CAS
+void main() {
+    const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
+    ivec2 dims = imageSize(gstorage[pc.dst_img]);
+    if (any(greaterThanEqual(gid, dims))) {
+        return;
+    }
+
+    //  b
+    // d e f
+    //  h
+    vec3 b = load(gid + ivec2(0, -1), dims);
+    vec3 d = load(gid + ivec2(-1, 0), dims);
+    vec3 e = load(gid, dims);
+    vec3 f = load(gid + ivec2(1, 0), dims);
+    vec3 h = load(gid + ivec2(0, 1), dims);
+
+    vec3 min_rgb = min(min(min(d, e), min(f, b)), h);
+    vec3 max_rgb = max(max(max(d, e), max(f, b)), h);
+
+    // Less sharpening where the neighbourhood is already close to clipping
+    vec3 amp = clamp(min(min_rgb, 1.0 - max_rgb) / max(max_rgb, 1e-5), 0., 1.);
+    amp = sqrt(amp);
+
+    float peak = -1.0 / mix(8.0, 5.0, clamp(pc.sharpness, 0., 1.));
+    vec3 w = amp * peak;
+
+    vec3 color = (b * w + d * w + f * w + h * w + e) / (1.0 + 4.0 * w);
+    color = clamp(color, 0., 0.999);
+
+    // The resolve already wrote the output, only its alpha is kept
+    float alpha = imageLoad(gstorage[pc.dst_img], gid).a;
+    imageStore(gstorage[pc.dst_img], gid, vec4(reverse_tonemap(color), alpha));
+}
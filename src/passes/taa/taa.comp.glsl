@@ -6,28 +6,29 @@
 #extension GL_EXT_samplerless_texture_functions : require
 
 layout(scalar, push_constant) uniform PushConstant {
+    vec2 jitter;
     uint src_img;
     uint dst_img;
     uint history_img;
     uint motion_img;
+    uint clamp_mode;
+    float feedback_min;
+    float feedback_max;
+    float motion_rejection;
+    bool reset;
 }
 pc;
 
+// Matches HistoryClamp on the host
+const uint CLAMP_VARIANCE_CLIP = 0;
+const uint CLAMP_AABB = 1;
+
 layout(set = 0, binding = 0) uniform sampler gsamplers[];
 layout(set = 0, binding = 1) uniform texture2D gtextures[];
 layout(set = 1, binding = 0) coherent restrict uniform image2D gstorage[];
 
 #include <textures.glsl>
 
-// Controls how much to blend between the current and past samples
-// Lower numbers = less of the current col and more of the past col = more
-// smoothing Values chosen empirically
-const float DEFAULT_HISTORY_BLEND_RATE =
-    0.1; // Default blend rate to use when no confidence in history
-const float MIN_HISTORY_BLEND_RATE =
-    0.015; // Minimum blend rate allowed, to ensure at least some of the current
-           // col is used
-
 // TAA is ideally applied after tonemapping, but before post processing
 // Post processing wants to go before tonemapping, which conflicts
 // Solution: Put TAA before tonemapping, tonemap TAA input, apply TAA,
@@ -84,17 +85,32 @@ layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
 
 void main() {
     const ivec2 gid = ivec2(gl_GlobalInvocationID.xy);
-    uvec2 dims = imageSize(gstorage[pc.src_img]);
+    uvec2 dims = imageSize(gstorage[pc.dst_img]);
     if (any(greaterThanEqual(gid, dims))) {
         return;
     }
     vec2 uv = (vec2(gid) + 0.5) / vec2(dims);
 
-    vec4 original_color = imageLoad(gstorage[pc.src_img], gid);
+    // Nearest source sample, the same pixel unless upscaling from a lower
+    // render resolution
+    ivec2 src_dims = imageSize(gstorage[pc.src_img]);
+    ivec2 src_pos = gid;
+    float sample_weight = 1.0;
+    if (any(notEqual(src_dims, ivec2(dims)))) {
+        vec2 src_position = uv * vec2(src_dims) - pc.jitter;
+        src_pos = clamp(ivec2(floor(src_position)), ivec2(0), src_dims - 1);
+        // Distance to the sample in output pixels, far ones mostly refine the
+        // history instead of replacing it
+        vec2 offset = (src_position - (vec2(src_pos) + 0.5)) * vec2(dims) /
+                      vec2(src_dims);
+        sample_weight = exp(-2.29 * dot(offset, offset));
+    }
+
+    vec4 original_color = imageLoad(gstorage[pc.src_img], src_pos);
     vec3 current_color = original_color.rgb;
     current_color = tonemap(current_color);
 
-    vec3 velocity = imageLoad(gstorage[pc.motion_img], gid).rgb;
+    vec3 velocity = imageLoad(gstorage[pc.motion_img], src_pos).rgb;
     vec2 motion_vector = velocity.xy;
 
     // Reproject to find the equivalent sample from the past
@@ -128,20 +144,20 @@ void main() {
     history_color +=
         sample_history(texel_position_12.x, texel_position_3.y) * w12.x * w3.y;
 
-    // Constrain past sample with 3x3 YCoCg variance clipping (reduces ghosting)
+    // Constrain past sample with the 3x3 YCoCg neighbourhood (reduces ghosting)
     // YCoCg:
     // https://advances.realtimerendering.com/s2014/index.html#_HIGH-QUALITY_TEMPORAL_SUPERSAMPLING,
     // slide 33 Variance clipping:
     // https://developer.download.nvidia.com/gameworks/events/GDC2016/msalvi_temporal_supersampling.pdf
-    vec3 s_tl = sample_view_target(gid + ivec2(-1, 1.));
-    vec3 s_tm = sample_view_target(gid + ivec2(0.0, 1.));
-    vec3 s_tr = sample_view_target(gid + ivec2(1., 1.));
-    vec3 s_ml = sample_view_target(gid + ivec2(-1., 0.0));
+    vec3 s_tl = sample_view_target(src_pos + ivec2(-1, 1.));
+    vec3 s_tm = sample_view_target(src_pos + ivec2(0.0, 1.));
+    vec3 s_tr = sample_view_target(src_pos + ivec2(1., 1.));
+    vec3 s_ml = sample_view_target(src_pos + ivec2(-1., 0.0));
     vec3 s_mm = RGB_to_YCoCg(current_color);
-    vec3 s_mr = sample_view_target(gid + ivec2(1, 0.0));
-    vec3 s_bl = sample_view_target(gid + ivec2(-1, -1));
-    vec3 s_bm = sample_view_target(gid + ivec2(0.0, -1));
-    vec3 s_br = sample_view_target(gid + ivec2(1, -1));
+    vec3 s_mr = sample_view_target(src_pos + ivec2(1, 0.0));
+    vec3 s_bl = sample_view_target(src_pos + ivec2(-1, -1));
+    vec3 s_bm = sample_view_target(src_pos + ivec2(0.0, -1));
+    vec3 s_br = sample_view_target(src_pos + ivec2(1, -1));
     vec3 moment_1 =
         s_tl + s_tm + s_tr + s_ml + s_mm + s_mr + s_bl + s_bm + s_br;
     vec3 moment_2 = (s_tl * s_tl) + (s_tm * s_tm) + (s_tr * s_tr) +
@@ -151,30 +167,45 @@ void main() {
     vec3 variance = (moment_2 / 9.0) - (mean * mean);
     vec3 std_deviation = sqrt(max(variance, vec3(0.0)));
     history_color = RGB_to_YCoCg(history_color);
-    history_color = clip_towards_aabb_center(
-        history_color, s_mm, mean - std_deviation, mean + std_deviation);
-    history_color = YCoCg_to_RGB(history_color);
+    if (pc.clamp_mode == CLAMP_AABB) {
+        vec3 aabb_min = min(min(min(s_tl, s_tm), min(s_tr, s_ml)),
+                            min(min(s_mm, s_mr), min(min(s_bl, s_bm), s_br)));
+        vec3 aabb_max = max(max(max(s_tl, s_tm), max(s_tr, s_ml)),
+                            max(max(s_mm, s_mr), max(max(s_bl, s_bm), s_br)));
+        history_color = clamp(history_color, aabb_min, aabb_max);
+    } else {
+        history_color = clip_towards_aabb_center(
+            history_color, s_mm, mean - std_deviation, mean + std_deviation);
+    }
 
-    float history_confidence = 0.12;
+    // Keep more history where it agrees with the current luma (reduces
+    // flicker) and less where it doesn't (reduces ghosting)
+    float luma_current = s_mm.x;
+    float luma_history = history_color.x;
+    float unbiased_diff = abs(luma_current - luma_history) /
+                          max(luma_current, max(luma_history, 0.2));
+    float unbiased_weight = 1.0 - unbiased_diff;
+    float feedback = mix(pc.feedback_min, pc.feedback_max,
+                         unbiased_weight * unbiased_weight);
+    history_color = YCoCg_to_RGB(history_color);
 
-    // Blend current and past sample
-    // Use more of the history if we're confident in it (reduces noise when
-    // there is no motion) https://hhoppe.com/supersample.pdf, section 4.1
-    float current_color_factor =
-        clamp(1.0 / history_confidence, MIN_HISTORY_BLEND_RATE,
-              DEFAULT_HISTORY_BLEND_RATE);
+    // Fast moving pixels trust their history less
+    float motion_pixels = length(motion_vector * vec2(dims));
+    feedback /= 1.0 + motion_pixels * pc.motion_rejection;
+    feedback = 1.0 - (1.0 - feedback) * sample_weight;
 
-    // Reject history when motion vectors point off screen
-    if (any(bvec2(clamp(history_uv, 0., 1.) != history_uv))) {
-        current_color_factor = 1.0;
-        history_confidence = 1.0;
+    // Reject history when motion vectors point off screen or it was reset
+    if (pc.reset || any(bvec2(clamp(history_uv, 0., 1.) != history_uv))) {
+        feedback = 0.0;
     }
 
-    current_color = mix(history_color, current_color, current_color_factor);
+    // Uninitialized history may hold NaNs, so it's skipped instead of weighted
+    if (feedback > 0.0) {
+        current_color = mix(current_color, history_color, feedback);
+    }
 
-    imageStore(gstorage[pc.history_img], gid,
-               vec4(current_color, history_confidence));
+    imageStore(gstorage[pc.history_img], gid, vec4(current_color, 1.0));
+    // Sharpening replaces the color from the history but keeps this alpha
     current_color = reverse_tonemap(current_color);
-    imageStore(gstorage[pc.dst_img], gid,
-               vec4(current_color, original_color.a));
+    imageStore(gstorage[pc.dst_img], gid, vec4(current_color, original_color.a));
 }
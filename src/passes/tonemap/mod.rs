@@ -0,0 +1,258 @@
+use std::mem;
+
+use anyhow::Result;
+use ash::vk;
+use glam::{Vec3, vec3};
+
+use crate::{
+    AppState, FragmentOutputDesc, FragmentShaderDesc, FrameGuard, ImageHandle, ImageTransition,
+    ImageUsage, RenderContext, RenderHandle, ScreenRelation, SwapchainColorSpace, VertexInputDesc,
+    VertexShaderDesc, ViewTarget,
+};
+
+const NO_LUT: u32 = u32::MAX;
+// Matches PAPER_WHITE_NITS in color_space.glsl
+const PAPER_WHITE_NITS: f32 = 203.;
+
+// Values match the TONEMAP_* constants in tonemap.frag.glsl
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TonemapOperator {
+    Aces,
+    AgX,
+    #[default]
+    PbrNeutral,
+    Reinhard,
+    // Tony McMapface style 3D LUT indexed by x / (x + 1) of the HDR color
+    Lut(ImageHandle),
+}
+
+impl TonemapOperator {
+    fn as_raw(self) -> u32 {
+        match self {
+            Self::Aces => 0,
+            Self::AgX => 1,
+            Self::PbrNeutral => 2,
+            Self::Reinhard => 3,
+            Self::Lut(_) => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TonemapSettings {
+    pub operator: TonemapOperator,
+    // In stops
+    pub exposure: f32,
+    // Both in [-1, 1], negative temperature is cooler and negative tint is greener
+    pub temperature: f32,
+    pub tint: f32,
+    pub contrast: f32,
+    pub saturation: f32,
+    // 3D LUT applied to the display referred sRGB output
+    pub grading_lut: Option<ImageHandle>,
+    // Brightest the display can show, only used by HDR color spaces
+    pub peak_nits: f32,
+}
+
+impl Default for TonemapSettings {
+    fn default() -> Self {
+        Self {
+            operator: TonemapOperator::default(),
+            exposure: 0.,
+            temperature: 0.,
+            tint: 0.,
+            contrast: 1.,
+            saturation: 1.,
+            grading_lut: None,
+            peak_nits: 1000.,
+        }
+    }
+}
+
+pub struct TonemapParams<'a> {
+    pub view_target: &'a ViewTarget,
+    pub target_image: ImageHandle,
+    pub color_space: SwapchainColorSpace,
+    // Address of the AutoExposure buffer, multiplied on top of the fixed exposure
+    pub exposure_buffer: Option<u64>,
+    // Skips the display encoding for a later pass to do it, like Finish
+    pub linear_output: bool,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct TonemapPC {
+    exposure_buffer: u64,
+    white_balance: Vec3,
+    source_image: u32,
+    tonemapper: u32,
+    tonemapper_lut: u32,
+    grading_lut: u32,
+    color_space: u32,
+    exposure: f32,
+    contrast: f32,
+    saturation: f32,
+    peak_white: f32,
+    use_exposure_buffer: u32,
+    linear_output: u32,
+}
+
+pub struct Tonemap {
+    pipeline: RenderHandle,
+    pub settings: TonemapSettings,
+}
+
+impl Tonemap {
+    pub fn new(
+        state: &mut AppState,
+        target_format: vk::Format,
+        settings: TonemapSettings,
+    ) -> Result<Self> {
+        let vertex_shader_desc = VertexShaderDesc {
+            shader_path: "shaders/screen_trig.vert".into(),
+            ..Default::default()
+        };
+        let fragment_shader_desc = FragmentShaderDesc {
+            shader_path: "src/passes/tonemap/tonemap.frag.glsl".into(),
+            ..Default::default()
+        };
+        let fragment_output_desc = FragmentOutputDesc {
+            surface_format: target_format,
+            ..Default::default()
+        };
+        let push_constant_range = vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .size(mem::size_of::<TonemapPC>() as u32);
+        let pipeline = state.pipeline_arena.create_render_pipeline(
+            VertexInputDesc::default(),
+            vertex_shader_desc,
+            fragment_shader_desc,
+            fragment_output_desc,
+            &[push_constant_range],
+            &[state.texture_arena.sampled_set_layout],
+        )?;
+
+        Ok(Self { pipeline, settings })
+    }
+
+    // Cube LUT with tightly packed texels, e.g. 48^3 RGB9E5 for Tony McMapface
+    pub fn create_lut(
+        state: &mut AppState,
+        size: u32,
+        format: vk::Format,
+        data: &[u8],
+        name: &str,
+    ) -> Result<ImageHandle> {
+        let info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: size,
+                height: size,
+                depth: size,
+            })
+            .image_type(vk::ImageType::TYPE_3D)
+            .format(format)
+            .usage(vk::ImageUsageFlags::SAMPLED)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        state
+            .texture_arena
+            .push_image(info, ScreenRelation::None, data, Some(name))
+    }
+
+    pub fn apply(
+        &self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+        params: TonemapParams,
+    ) {
+        let _marker = ctx
+            .device
+            .create_scoped_marker(frame.command_buffer(), "Tonemap Pass");
+        let texture_arena = &mut state.texture_arena;
+        let source_image = *params.view_target.main_image();
+        let settings = &self.settings;
+
+        let mut transitions = vec![ImageTransition::new(
+            source_image,
+            ImageUsage::FragmentSampled,
+        )];
+        let operator_lut = match settings.operator {
+            TonemapOperator::Lut(lut) => Some(lut),
+            _ => None,
+        };
+        for lut in operator_lut.iter().chain(settings.grading_lut.iter()) {
+            transitions.push(ImageTransition::new(*lut, ImageUsage::FragmentSampled));
+        }
+        texture_arena.transitions(frame.command_buffer(), &transitions);
+
+        frame.begin_rendering(
+            texture_arena,
+            params.target_image,
+            vk::AttachmentLoadOp::DONT_CARE,
+            [0., 0., 0., 1.],
+        );
+        let push_constant = TonemapPC {
+            exposure_buffer: params.exposure_buffer.unwrap_or_default(),
+            white_balance: white_balance_coeffs(settings.temperature, settings.tint),
+            source_image: texture_arena.get_sampled_idx(source_image, 0),
+            tonemapper: settings.operator.as_raw(),
+            tonemapper_lut: operator_lut
+                .map_or(NO_LUT, |lut| texture_arena.get_sampled_idx(lut, 0)),
+            grading_lut: settings
+                .grading_lut
+                .map_or(NO_LUT, |lut| texture_arena.get_sampled_idx(lut, 0)),
+            color_space: params.color_space.as_raw(),
+            exposure: settings.exposure.exp2(),
+            contrast: settings.contrast,
+            saturation: settings.saturation,
+            peak_white: if params.color_space.is_hdr() {
+                (settings.peak_nits / PAPER_WHITE_NITS).max(1.)
+            } else {
+                1.
+            },
+            use_exposure_buffer: params.exposure_buffer.is_some() as u32,
+            linear_output: params.linear_output as u32,
+        };
+        let pipeline = state.pipeline_arena.get_pipeline(self.pipeline);
+        frame.bind_push_constants(
+            pipeline.layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            &[push_constant],
+        );
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            &[texture_arena.sampled_set],
+        );
+        frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+        frame.draw(3, 1, 0, 0);
+        frame.end_rendering();
+    }
+}
+
+// Per channel LMS scale moving the D65 white point along the temperature and tint axes
+fn white_balance_coeffs(temperature: f32, tint: f32) -> Vec3 {
+    let t1 = temperature * 100. / 65.;
+    let t2 = tint * 100. / 65.;
+
+    let x = 0.31271 - t1 * if t1 < 0. { 0.1 } else { 0.05 };
+    let standard_illuminant_y = 2.87 * x - 3. * x * x - 0.275_095_07;
+    let y = standard_illuminant_y + t2 * 0.05;
+
+    let w1 = vec3(0.949_237, 1.035_42, 1.087_28);
+
+    let big_y = 1.;
+    let big_x = big_y * x / y;
+    let big_z = big_y * (1. - x - y) / y;
+    let w2 = vec3(
+        0.7328 * big_x + 0.4296 * big_y - 0.1624 * big_z,
+        -0.7036 * big_x + 1.6975 * big_y + 0.0061 * big_z,
+        0.0030 * big_x + 0.0136 * big_y + 0.9834 * big_z,
+    );
+
+    w1 / w2
+}
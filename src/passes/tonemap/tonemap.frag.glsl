@@ -0,0 +1,195 @@
+#version 460
+
+#include <extensions.glsl>
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+// Same binding, the LUTs are 3D images
+layout(set = 0, binding = 1) uniform texture3D gtextures3d[];
+
+#include <color_space.glsl>
+#include <exposure.glsl>
+#include <textures.glsl>
+
+// Matches TonemapOperator on the host
+const uint TONEMAP_ACES = 0;
+const uint TONEMAP_AGX = 1;
+const uint TONEMAP_PBR_NEUTRAL = 2;
+const uint TONEMAP_REINHARD = 3;
+const uint TONEMAP_LUT = 4;
+
+const uint NO_LUT = 0xFFFFFFFF;
+
+layout(scalar, push_constant) uniform PushConstant {
+    ExposureBuf exposure_ptr;
+    vec3 white_balance;
+    uint source_image;
+    uint tonemapper;
+    uint tonemapper_lut;
+    uint grading_lut;
+    uint color_space;
+    float exposure;
+    float contrast;
+    float saturation;
+    // Display peak over paper white, 1 for SDR outputs
+    float peak_white;
+    bool use_exposure_buffer;
+    bool linear_output;
+}
+pc;
+
+float luminance(vec3 color) { return dot(color, vec3(0.2126, 0.7152, 0.0722)); }
+
+vec3 aces_tonemap(vec3 color) {
+    mat3 m1 = mat3(0.59719, 0.07600, 0.02840, 0.35458, 0.90834, 0.13383,
+                   0.04823, 0.01566, 0.83777);
+    mat3 m2 = mat3(1.60475, -0.10208, -0.00327, -0.53108, 1.10813, -0.07276,
+                   -0.07367, -0.00605, 1.07602);
+    vec3 v = m1 * color;
+    vec3 a = v * (v + 0.0245786) - 0.000090537;
+    vec3 b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(m2 * (a / b), 0.0, 1.0);
+}
+
+// https://iolite-engine.com/blog_posts/minimal_agx_implementation
+vec3 agx_contrast_approx(vec3 x) {
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x +
+           0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+vec3 agx_tonemap(vec3 color) {
+    const mat3 agx_mat =
+        mat3(0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+             0.0784335999999992, 0.878468636469772, 0.0784336,
+             0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+    const mat3 agx_mat_inv =
+        mat3(1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+             -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+             -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+    const float min_ev = -12.47393;
+    const float max_ev = 4.026069;
+
+    color = agx_mat * color;
+    color = clamp(log2(max(color, 1e-10)), min_ev, max_ev);
+    color = (color - min_ev) / (max_ev - min_ev);
+    color = agx_contrast_approx(color);
+    color = agx_mat_inv * color;
+    return pow(clamp(color, 0., 1.), vec3(2.2));
+}
+
+// Compresses towards `white` instead of 1 so HDR outputs keep their headroom
+vec3 pbr_neutral_tonemap(vec3 color, float white) {
+    const float desaturation = 0.15;
+    float start_compression = (0.8 - 0.04) * white;
+
+    float x = min(color.r, min(color.g, color.b));
+    float offset = x < 0.08 ? x - 6.25 * x * x : 0.04;
+    color -= offset;
+
+    float peak = max(color.r, max(color.g, color.b));
+    if (peak < start_compression)
+        return color;
+
+    float d = white - start_compression;
+    float new_peak = white - d * d / (peak + d - start_compression);
+    color *= new_peak / peak;
+
+    float g = 1. - 1. / (desaturation * (peak - new_peak) + 1.);
+    return mix(color, new_peak * vec3(1, 1, 1), g);
+}
+
+vec3 reinhard_tonemap(vec3 color, float white) {
+    return color / (1. + luminance(color) / white);
+}
+
+// The fitted curves only cover SDR, this keeps everything below the knee and stretches the
+// shoulder from 1 up to `peak`
+vec3 expand_highlights(vec3 color, float peak) {
+    const float knee = 0.5;
+    vec3 t = max(color - knee, 0.) / (1. - knee);
+    float curve = 1. - (1. - knee) / (peak - knee);
+    vec3 expanded = knee + (1. - knee) * t / (1. - t * curve);
+    return mix(color, expanded, greaterThan(color, vec3(knee)));
+}
+
+vec3 sample_lut(uint lut, vec3 uvw) {
+    vec3 dims = vec3(textureSize(gtextures3d[nonuniformEXT(lut)], 0));
+    uvw = uvw * ((dims - 1.) / dims) + 0.5 / dims;
+    return textureLod(nonuniformEXT(sampler3D(gtextures3d[lut],
+                                              gsamplers[LINEAR_SAMPL])),
+                      uvw, 0.)
+        .rgb;
+}
+
+// Tony McMapface style, the LUT is indexed by x / (x + 1) of the stimulus
+vec3 lut_tonemap(vec3 color) {
+    vec3 encoded = color / (color + 1.);
+    return sample_lut(pc.tonemapper_lut, encoded);
+}
+
+// https://github.com/Unity-Technologies/Graphics/blob/master/Packages/com.unity.render-pipelines.core/ShaderLibrary/Color.hlsl
+vec3 white_balance(vec3 color, vec3 balance) {
+    const mat3 lin_to_lms =
+        mat3(3.90405e-1, 5.49941e-1, 8.92632e-3, 7.08416e-2, 9.63172e-1,
+             1.35775e-3, 2.31082e-2, 1.28021e-1, 9.36245e-1);
+    const mat3 lms_to_lin =
+        mat3(2.85847e+0, -1.62879e+0, -2.48910e-2, -2.10182e-1, 1.15820e+0,
+             3.24281e-4, -4.18120e-2, -1.18169e-1, 1.06867e+0);
+    vec3 lms = color * lin_to_lms;
+    lms *= balance;
+    return lms * lms_to_lin;
+}
+
+vec3 color_grade(vec3 color) {
+    const float mid_grey = 0.18;
+    color = white_balance(color, pc.white_balance);
+    color = mid_grey * pow(max(color, 0.) / mid_grey, vec3(pc.contrast));
+    float luma = luminance(color);
+    return max(luma + (color - luma) * pc.saturation, 0.);
+}
+
+// Returns display referred color in [0, pc.peak_white]
+vec3 tonemap(vec3 color) {
+    switch (pc.tonemapper) {
+    case TONEMAP_ACES:
+        return expand_highlights(aces_tonemap(color), pc.peak_white);
+    case TONEMAP_AGX:
+        return expand_highlights(agx_tonemap(color), pc.peak_white);
+    case TONEMAP_REINHARD:
+        return reinhard_tonemap(color, pc.peak_white);
+    case TONEMAP_LUT:
+        return expand_highlights(lut_tonemap(color), pc.peak_white);
+    default:
+        return pbr_neutral_tonemap(color, pc.peak_white);
+    }
+}
+
+void main() {
+    vec2 uv = vec2(in_uv.x, 1. - in_uv.y);
+    vec3 col = TexLinear(pc.source_image, uv).rgb;
+
+    col *= pc.exposure;
+    if (pc.use_exposure_buffer) {
+        col *= pc.exposure_ptr.data.exposure;
+    }
+    col = color_grade(col);
+    col = tonemap(col);
+
+    // Grading LUTs are authored for display referred sRGB, HDR highlights above paper white
+    // pass through ungraded
+    if (pc.grading_lut != NO_LUT) {
+        vec3 sdr = clamp(col, 0., 1.);
+        vec3 encoded = linear_to_nonlinear_srgb(sdr);
+        col += nonlinear_to_linear_srgb(sample_lut(pc.grading_lut, encoded)) - sdr;
+    }
+
+    if (!pc.linear_output) {
+        col = encode_output(col, pc.color_space, PAPER_WHITE_NITS);
+    }
+    out_color = vec4(col, 1.0);
+}
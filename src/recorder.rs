@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use std::{
     fs::File,
     io::{BufWriter, Write},
@@ -8,17 +8,42 @@ use std::{
     time::Instant,
 };
 
-use crate::{ImageDimensions, ManagedImage, SCREENSHOT_FOLDER, VIDEO_FOLDER, create_folder};
+use crate::{
+    ImageDimensions, ReadbackData, SCREENSHOT_FOLDER, VIDEO_FOLDER, align_to, create_folder,
+};
 use crossbeam_channel::{Receiver, Sender};
 
 pub enum RecordEvent {
     Start(ImageDimensions),
-    Record(ManagedImage),
+    Record(CapturedFrame),
     Finish,
-    Screenshot(ManagedImage),
+    Screenshot(CapturedFrame),
     CloseThread,
 }
 
+pub struct CapturedFrame {
+    pub bytes: Vec<u8>,
+    pub image_dimensions: ImageDimensions,
+}
+
+impl CapturedFrame {
+    // Expects tightly packed RGBA8 rows, the video encoder wants even dimensions so the padding
+    // is left black
+    pub fn from_readback(data: ReadbackData<'_>) -> Self {
+        let (width, height) = (data.extent.width as usize, data.extent.height as usize);
+        let image_dimensions = ImageDimensions::new(align_to(width, 2), align_to(height, 2), 1);
+        let row_size = image_dimensions.unpadded_bytes_per_row;
+        let mut bytes = vec![0; row_size * image_dimensions.height];
+        for (dst, src) in bytes.chunks_mut(row_size).zip(data.bytes.chunks(width * 4)) {
+            dst[..src.len()].copy_from_slice(src);
+        }
+        Self {
+            bytes,
+            image_dimensions,
+        }
+    }
+}
+
 pub struct Recorder {
     pub sender: Sender<RecordEvent>,
     ffmpeg_installed: bool,
@@ -65,22 +90,11 @@ impl Recorder {
         self.ffmpeg_installed
     }
 
-    pub fn screenshot(&self, image: ManagedImage) {
-        let _ = self
-            .sender
-            .send(RecordEvent::Screenshot(image))
-            .context("Failed to send screenshot");
-    }
-
     pub fn start(&mut self, dims: ImageDimensions) {
         self.is_active = true;
         self.send(RecordEvent::Start(dims));
     }
 
-    pub fn record(&self, image: ManagedImage) {
-        self.send(RecordEvent::Record(image));
-    }
-
     pub fn finish(&mut self) {
         self.is_active = false;
         self.send(RecordEvent::Finish);
@@ -166,22 +180,15 @@ fn record_thread(rx: Receiver<RecordEvent>) {
                 recorder =
                     Some(new_ffmpeg_command(image_dimensions, filename.to_str().unwrap()).unwrap());
             }
-            RecordEvent::Record(mut frame) => {
+            RecordEvent::Record(frame) => {
                 if let Some(ref mut recorder) = recorder {
                     let writer = recorder.process.stdin.as_mut().unwrap();
                     let mut writer = BufWriter::new(writer);
 
                     let padded_bytes = frame.image_dimensions.padded_bytes_per_row as _;
                     let unpadded_bytes = frame.image_dimensions.unpadded_bytes_per_row as _;
-                    let data = match frame.map_memory() {
-                        Some(data) => data,
-                        None => {
-                            tracing::error!("Failed to map memory");
-                            continue;
-                        }
-                    };
-
-                    for chunk in data
+                    for chunk in frame
+                        .bytes
                         .chunks(padded_bytes)
                         .map(|chunk| &chunk[..unpadded_bytes])
                     {
@@ -197,18 +204,9 @@ fn record_thread(rx: Receiver<RecordEvent>) {
                 recorder = None;
                 println!("Recording finished");
             }
-            RecordEvent::Screenshot(mut frame) => {
-                let image_dimensions = frame.image_dimensions;
-                let data = match frame.map_memory() {
-                    Some(data) => data,
-                    None => {
-                        tracing::error!("Failed to map memory");
-                        continue;
-                    }
-                };
-
-                let _ =
-                    save_screenshot(data, image_dimensions).map_err(|err| tracing::error!("{err}"));
+            RecordEvent::Screenshot(frame) => {
+                let _ = save_screenshot(&frame.bytes, frame.image_dimensions)
+                    .map_err(|err| tracing::error!("{err}"));
             }
             RecordEvent::CloseThread => {
                 return;
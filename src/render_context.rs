@@ -1,4 +1,4 @@
-use crate::vulkan::{self, Device, Instance, Surface, Swapchain};
+use crate::vulkan::{self, Device, Instance, Surface, Swapchain, SwapchainConfig};
 use anyhow::Result;
 use ash::khr::{self};
 use std::sync::Arc;
@@ -15,7 +15,7 @@ pub struct RenderContext {
 }
 
 impl RenderContext {
-    pub fn new(window: Window) -> Result<Self> {
+    pub fn new(window: Window, config: SwapchainConfig) -> Result<Self> {
         let instance = Instance::new(Some(&window))?;
 
         let surface = Surface::new(&instance, &window)?;
@@ -25,7 +25,8 @@ impl RenderContext {
 
         let PhysicalSize { width, height } = window.inner_size();
         let swapchain_loader = khr::swapchain::Device::new(&instance, &device);
-        let swapchain = vulkan::Swapchain::new(&device, &surface, swapchain_loader, width, height)?;
+        let swapchain =
+            vulkan::Swapchain::new(&device, &surface, swapchain_loader, width, height, config)?;
 
         Ok(Self {
             window,
@@ -36,4 +37,12 @@ impl RenderContext {
             is_swapchain_dirty: false,
         })
     }
+
+    // The swapchain is recreated with the new present mode before the next frame
+    pub fn set_vsync(&mut self, enabled: bool) {
+        if self.swapchain.vsync() != enabled {
+            self.swapchain.set_vsync(enabled);
+            self.is_swapchain_dirty = true;
+        }
+    }
 }
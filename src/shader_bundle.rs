@@ -0,0 +1,228 @@
+#[cfg(feature = "hot-reload")]
+use std::path::PathBuf;
+use std::path::{Component, Path};
+
+use ahash::AHashMap;
+use anyhow::{Context, Result, bail, ensure};
+
+use crate::ShaderKind;
+#[cfg(feature = "hot-reload")]
+use crate::{ShaderBackends, ShaderCompiler};
+
+#[cfg(feature = "embed-shaders")]
+pub const EMBEDDED_SHADER_BUNDLE: Option<&[u8]> =
+    Some(include_bytes!(env!("MYNDGERA_SHADER_BUNDLE")));
+#[cfg(not(feature = "embed-shaders"))]
+pub const EMBEDDED_SHADER_BUNDLE: Option<&[u8]> = None;
+
+const BUNDLE_MAGIC: &[u8; 4] = b"MSPV";
+const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Default)]
+pub struct ShaderBundle {
+    modules: AHashMap<(String, ShaderKind), Vec<u32>>,
+}
+
+impl ShaderBundle {
+    pub fn insert(&mut self, path: impl AsRef<Path>, kind: ShaderKind, code: &[u32]) {
+        self.modules
+            .insert((bundle_key(path.as_ref()), kind), code.to_vec());
+    }
+
+    pub fn get(&self, path: impl AsRef<Path>, kind: ShaderKind) -> Result<&[u32]> {
+        let key = bundle_key(path.as_ref());
+        self.modules
+            .get(&(key, kind))
+            .map(Vec::as_slice)
+            .with_context(|| {
+                format!(
+                    "Shader {} ({kind:?}) is missing from the shader bundle",
+                    path.as_ref().display()
+                )
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut entries: Vec<_> = self.modules.iter().collect();
+        entries.sort_by(|(a, _), (b, _)| (&a.0, a.1 as u8).cmp(&(&b.0, b.1 as u8)));
+
+        let mut bytes = vec![];
+        bytes.extend_from_slice(BUNDLE_MAGIC);
+        bytes.extend_from_slice(&BUNDLE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for ((path, kind), code) in entries {
+            bytes.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(path.as_bytes());
+            bytes.extend_from_slice(&(*kind as u32).to_le_bytes());
+            bytes.extend_from_slice(&(code.len() as u32).to_le_bytes());
+            for word in code {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = BundleReader { bytes };
+        ensure!(
+            reader.take(4)? == BUNDLE_MAGIC,
+            "Not a shader bundle: wrong magic"
+        );
+        let version = reader.read_u32()?;
+        ensure!(
+            version == BUNDLE_VERSION,
+            "Unsupported shader bundle version: {version}"
+        );
+
+        let mut modules = AHashMap::new();
+        for _ in 0..reader.read_u32()? {
+            let path_len = reader.read_u32()? as usize;
+            let path = std::str::from_utf8(reader.take(path_len)?)?.to_string();
+            let kind = match reader.read_u32()? {
+                0 => ShaderKind::Fragment,
+                1 => ShaderKind::Vertex,
+                2 => ShaderKind::Compute,
+                kind => bail!("Unknown shader kind in the shader bundle: {kind}"),
+            };
+            let code_len = reader.read_u32()? as usize;
+            let code = (0..code_len)
+                .map(|_| reader.read_u32())
+                .collect::<Result<_>>()?;
+            modules.insert((path, kind), code);
+        }
+
+        Ok(Self { modules })
+    }
+
+    // Every shader under the roots whose file name carries a stage, keyed by its path
+    #[cfg(feature = "hot-reload")]
+    pub fn compile_tree(roots: &[&str]) -> Result<Self> {
+        let compiler = ShaderCompiler::unwatched(&ShaderBackends::default())?;
+        let mut bundle = Self::default();
+        let mut pending: Vec<PathBuf> = roots.iter().map(PathBuf::from).collect();
+        while let Some(path) = pending.pop() {
+            if path.is_dir() {
+                let entries = std::fs::read_dir(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                for entry in entries {
+                    pending.push(entry?.path());
+                }
+                continue;
+            }
+            let Some(kind) = ShaderKind::from_path(&path) else {
+                continue;
+            };
+            if !compiler.handles(&path) {
+                continue;
+            }
+            let code = compiler
+                .compile(&path, kind)
+                .with_context(|| format!("Failed to precompile {}", path.display()))?;
+            bundle.insert(&path, kind, &code);
+        }
+        Ok(bundle)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path.as_ref(), self.to_bytes()).with_context(|| {
+            format!(
+                "Failed to write shader bundle to {}",
+                path.as_ref().display()
+            )
+        })
+    }
+}
+
+struct BundleReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> BundleReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(self.bytes.len() >= len, "Shader bundle is truncated");
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+fn bundle_key(path: &Path) -> String {
+    path.components()
+        .filter(|component| !matches!(component, Component::CurDir))
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_bundle() -> ShaderBundle {
+        let mut bundle = ShaderBundle::default();
+        bundle.insert(
+            "./shaders/a.comp.glsl",
+            ShaderKind::Compute,
+            &[0x07230203, 1, 2],
+        );
+        bundle.insert("shaders/b.vert", ShaderKind::Vertex, &[3]);
+        bundle.insert("shaders/b.vert", ShaderKind::Fragment, &[]);
+        bundle
+    }
+
+    #[test]
+    fn round_trip() {
+        let bundle = sample_bundle();
+        let bytes = bundle.to_bytes();
+        let decoded = ShaderBundle::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.modules, bundle.modules);
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert_eq!(
+            decoded
+                .get("shaders/a.comp.glsl", ShaderKind::Compute)
+                .unwrap(),
+            [0x07230203, 1, 2]
+        );
+        assert!(
+            decoded
+                .get("shaders/a.comp.glsl", ShaderKind::Vertex)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = sample_bundle().to_bytes();
+        bytes[0] = b'X';
+        let err = ShaderBundle::from_bytes(&bytes).err().unwrap();
+        assert!(err.to_string().contains("magic"), "{err}");
+    }
+
+    #[test]
+    fn rejects_unknown_version() {
+        let mut bytes = sample_bundle().to_bytes();
+        bytes[4..8].copy_from_slice(&(BUNDLE_VERSION + 1).to_le_bytes());
+        let err = ShaderBundle::from_bytes(&bytes).err().unwrap();
+        assert!(err.to_string().contains("version"), "{err}");
+    }
+
+    #[test]
+    fn rejects_truncated_bundle() {
+        let bytes = sample_bundle().to_bytes();
+        for len in [0, 3, 8, bytes.len() - 1] {
+            assert!(ShaderBundle::from_bytes(&bytes[..len]).is_err(), "{len}");
+        }
+    }
+}
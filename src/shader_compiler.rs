@@ -1,81 +1,250 @@
-use std::path::Path;
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use crate::{SHADER_FOLDER, Watcher};
-use anyhow::{Context, Result};
-use shaderc::{CompilationArtifact, IncludeType, ShaderKind};
+use crate::{SHADER_FOLDER, ShaderKind, Watcher, debug_printf_enabled};
+use ahash::AHashSet;
+use anyhow::{Context, Result, bail};
+use parking_lot::{Mutex, RwLock};
+use shaderc::{IncludeType, SourceLanguage};
 
-pub struct ShaderCompiler {
+// Everything the built-in shaderc backend compiles, see `source_language`
+pub const SHADERC_EXTENSIONS: &[&str] = &["glsl", "frag", "vert", "comp", "hlsl", "hlsli"];
+
+pub trait ShaderBackend {
+    fn extensions(&self) -> &[&str];
+    fn compile(
+        &self,
+        path: &Path,
+        kind: ShaderKind,
+        includes: &mut AHashSet<PathBuf>,
+    ) -> Result<Vec<u32>>;
+}
+
+pub type SharedShaderBackend = Arc<dyn ShaderBackend + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct ShaderBackends {
+    backends: Arc<RwLock<Vec<SharedShaderBackend>>>,
+}
+
+impl ShaderBackends {
+    pub fn register(&self, backend: SharedShaderBackend) {
+        self.backends.write().push(backend);
+    }
+
+    fn find(&self, extension: &OsStr) -> Option<SharedShaderBackend> {
+        let backends = self.backends.read();
+        backends
+            .iter()
+            .rev()
+            .find(|backend| handles_extension(backend.as_ref(), extension))
+            .cloned()
+    }
+}
+
+fn handles_extension(backend: &dyn ShaderBackend, extension: &OsStr) -> bool {
+    backend.extensions().iter().any(|ext| extension == *ext)
+}
+
+pub struct ShadercBackend {
     compiler: shaderc::Compiler,
-    options: shaderc::CompileOptions<'static>,
+    glsl_options: shaderc::CompileOptions<'static>,
+    hlsl_options: shaderc::CompileOptions<'static>,
+    includes: Arc<Mutex<AHashSet<PathBuf>>>,
 }
 
-impl ShaderCompiler {
-    pub fn new(watcher: &Watcher) -> Result<Self> {
-        let mut options =
-            shaderc::CompileOptions::new().context("Failed to create shader compiler options")?;
-        options.set_target_env(
-            shaderc::TargetEnv::Vulkan,
-            shaderc::EnvVersion::Vulkan1_3 as u32,
-        );
-        if !cfg!(debug_assertions) {
-            options.set_optimization_level(shaderc::OptimizationLevel::Performance);
-        }
-        options.set_target_spirv(shaderc::SpirvVersion::V1_6);
-        options.set_generate_debug_info();
-
-        let watcher_copy = watcher.clone();
-        options.set_include_callback(move |name, include_type, source_file, _depth| {
-            let path = match include_type {
-                IncludeType::Relative => {
-                    let mapping = watcher_copy.include_mapping.lock();
-                    let source_file = mapping.get(Path::new(source_file)).unwrap();
-                    let source_path = &source_file.iter().next().unwrap().path;
-                    source_path.parent().unwrap().join(name)
-                }
-                IncludeType::Standard => Path::new(SHADER_FOLDER).join(name),
-            };
-            // TODO: recreate dependencies in case someone removes includes
-            match std::fs::read_to_string(&path) {
-                Ok(glsl_code) => {
-                    let include_path = path.canonicalize().unwrap();
-                    {
-                        let mut watcher = watcher_copy.watcher.lock();
-                        let _ = watcher.watch(&include_path, notify::RecursiveMode::NonRecursive);
-                    }
-                    {
-                        let mut mapping = watcher_copy.include_mapping.lock();
-                        let sources: Vec<_> =
-                            mapping[Path::new(source_file)].iter().cloned().collect();
-                        for source in sources {
-                            mapping.entry(name.into()).or_default().insert(source);
-                        }
-                    }
-                    Ok(shaderc::ResolvedInclude {
-                        resolved_name: String::from(name),
-                        content: glsl_code,
-                    })
-                }
-                Err(err) => Err(format!(
-                    "Failed to resolve include to {name} in {source_file} (was looking for {path:?}): {err}"
-                )),
-            }
-        });
+impl ShadercBackend {
+    pub fn new() -> Result<Self> {
+        let includes = Arc::new(Mutex::new(AHashSet::new()));
+
+        let mut glsl_options = base_options()?;
+        glsl_options.set_include_callback(include_callback(includes.clone(), false));
+
+        let mut hlsl_options = base_options()?;
+        hlsl_options.set_source_language(SourceLanguage::HLSL);
+        hlsl_options.set_auto_bind_uniforms(true);
+        hlsl_options.set_hlsl_io_mapping(true);
+        hlsl_options.set_include_callback(include_callback(includes.clone(), true));
 
         Ok(Self {
-            compiler: shaderc::Compiler::new().unwrap(),
-            options,
+            compiler: shaderc::Compiler::new().context("Failed to create shaderc compiler")?,
+            glsl_options,
+            hlsl_options,
+            includes,
         })
     }
+}
+
+impl ShaderBackend for ShadercBackend {
+    fn extensions(&self) -> &[&str] {
+        SHADERC_EXTENSIONS
+    }
 
-    pub fn compile(&self, path: impl AsRef<Path>, kind: ShaderKind) -> Result<CompilationArtifact> {
-        let source = std::fs::read_to_string(path.as_ref())?;
-        Ok(self.compiler.compile_into_spirv(
+    fn compile(
+        &self,
+        path: &Path,
+        kind: ShaderKind,
+        includes: &mut AHashSet<PathBuf>,
+    ) -> Result<Vec<u32>> {
+        let source = std::fs::read_to_string(path)?;
+        let options = match source_language(path) {
+            Some(SourceLanguage::HLSL) => &self.hlsl_options,
+            Some(SourceLanguage::GLSL) => &self.glsl_options,
+            None => bail!("shaderc doesn't compile {}", path.display()),
+        };
+        self.includes.lock().clear();
+        let artifact = self.compiler.compile_into_spirv(
             &source,
-            kind,
-            path.as_ref().file_name().and_then(|s| s.to_str()).unwrap(),
+            kind.into(),
+            &path.to_string_lossy(),
             // TODO: Don't use fixed entry point in the future
             "main",
-            Some(&self.options),
-        )?)
+            Some(options),
+        );
+        includes.extend(self.includes.lock().drain());
+        Ok(artifact?.as_binary().to_vec())
+    }
+}
+
+fn source_language(path: &Path) -> Option<SourceLanguage> {
+    match path.extension()?.to_str()? {
+        "glsl" | "frag" | "vert" | "comp" => Some(SourceLanguage::GLSL),
+        "hlsl" | "hlsli" => Some(SourceLanguage::HLSL),
+        _ => None,
+    }
+}
+
+fn base_options() -> Result<shaderc::CompileOptions<'static>> {
+    let mut options =
+        shaderc::CompileOptions::new().context("Failed to create shader compiler options")?;
+    options.set_target_env(
+        shaderc::TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_3 as u32,
+    );
+    if !cfg!(debug_assertions) {
+        options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    }
+    options.set_target_spirv(shaderc::SpirvVersion::V1_6);
+    options.set_generate_debug_info();
+    if debug_printf_enabled() {
+        options.add_macro_definition("DEBUG_PRINTF", None);
+    }
+    Ok(options)
+}
+
+// HLSL quoted includes fall back to the shader folder when not found next to the includer
+fn include_callback(
+    includes: Arc<Mutex<AHashSet<PathBuf>>>,
+    relative_fallback: bool,
+) -> impl Fn(&str, IncludeType, &str, usize) -> shaderc::IncludeCallbackResult + 'static {
+    move |name, include_type, source_file, _depth| {
+        let path = match include_type {
+            IncludeType::Relative => {
+                let path = Path::new(source_file).parent().unwrap().join(name);
+                if relative_fallback && !path.exists() {
+                    Path::new(SHADER_FOLDER).join(name)
+                } else {
+                    path
+                }
+            }
+            IncludeType::Standard => Path::new(SHADER_FOLDER).join(name),
+        };
+        let code = std::fs::read_to_string(&path).and_then(|code| Ok((path.canonicalize()?, code)));
+        match code {
+            Ok((include_path, code)) => {
+                let resolved_name = include_path.to_string_lossy().into_owned();
+                includes.lock().insert(include_path);
+                Ok(shaderc::ResolvedInclude {
+                    resolved_name,
+                    content: code,
+                })
+            }
+            Err(err) => Err(format!(
+                "Failed to resolve include to {name} in {source_file} (was looking for {path:?}): {err}"
+            )),
+        }
+    }
+}
+
+pub struct ShaderCompiler {
+    shaderc: ShadercBackend,
+    backends: ShaderBackends,
+    watcher: Option<Watcher>,
+}
+
+impl ShaderCompiler {
+    pub fn new(watcher: &Watcher, backends: &ShaderBackends) -> Result<Self> {
+        Ok(Self {
+            shaderc: ShadercBackend::new()?,
+            backends: backends.clone(),
+            watcher: Some(watcher.clone()),
+        })
+    }
+
+    // Compiles without tracking includes, for offline builds
+    pub fn unwatched(backends: &ShaderBackends) -> Result<Self> {
+        Ok(Self {
+            shaderc: ShadercBackend::new()?,
+            backends: backends.clone(),
+            watcher: None,
+        })
+    }
+
+    pub fn handles(&self, path: &Path) -> bool {
+        let extension = path.extension().unwrap_or_default();
+        self.backends.find(extension).is_some() || handles_extension(&self.shaderc, extension)
+    }
+
+    pub fn compile(&self, path: impl AsRef<Path>, kind: ShaderKind) -> Result<Vec<u32>> {
+        let path = path.as_ref().canonicalize()?;
+        let extension = path.extension().unwrap_or_default();
+        let mut includes = AHashSet::new();
+        let code = match self.backends.find(extension) {
+            Some(backend) => backend.compile(&path, kind, &mut includes),
+            None if handles_extension(&self.shaderc, extension) => {
+                self.shaderc.compile(&path, kind, &mut includes)
+            }
+            None => bail!("No shader backend compiles {}", path.display()),
+        };
+        // Failed compiles only add edges so that fixing a broken header still triggers a reload
+        if let Some(watcher) = &self.watcher {
+            watcher.update_includes(&path, includes, code.is_ok());
+        }
+        code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_language_from_extension() {
+        let language = |path: &str| source_language(Path::new(path));
+        assert!(matches!(
+            language("a.comp.glsl"),
+            Some(SourceLanguage::GLSL)
+        ));
+        assert!(matches!(
+            language("shaders/screen_trig.vert"),
+            Some(SourceLanguage::GLSL)
+        ));
+        assert!(matches!(
+            language("a.frag.hlsl"),
+            Some(SourceLanguage::HLSL)
+        ));
+        assert!(matches!(
+            language("common.hlsli"),
+            Some(SourceLanguage::HLSL)
+        ));
+        for path in ["", "glsl", "a.gl", "a.slang", "é.hlsl_", "a.comp.txt"] {
+            assert!(language(path).is_none(), "{path}");
+        }
+        for ext in SHADERC_EXTENSIONS {
+            assert!(language(&format!("a.{ext}")).is_some(), "{ext}");
+        }
     }
 }
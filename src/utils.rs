@@ -1,7 +1,7 @@
 use std::{
     io,
     ops::{Add, BitAnd, Not, Sub},
-    path::Path,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
@@ -21,6 +21,23 @@ pub enum ShaderKind {
     Compute,
 }
 
+impl ShaderKind {
+    // Stage from the file name: `*.comp`, or a stage suffix before the language like `*.comp.glsl`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let stage = match path.extension()?.to_str()? {
+            stage @ ("comp" | "vert" | "frag") => stage,
+            _ => Path::new(path.file_stem()?).extension()?.to_str()?,
+        };
+        match stage {
+            "comp" => Some(Self::Compute),
+            "vert" => Some(Self::Vertex),
+            "frag" => Some(Self::Fragment),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "hot-reload")]
 impl From<ShaderKind> for shaderc::ShaderKind {
     fn from(value: ShaderKind) -> Self {
         match value {
@@ -45,11 +62,13 @@ pub fn create_folder<P: AsRef<Path>>(name: P) -> io::Result<()> {
 pub struct Args {
     pub inner_size: Option<(u32, u32)>,
     pub recording_time: Option<Duration>,
+    pub precompile_shaders: Option<PathBuf>,
 }
 
 pub fn parse_args() -> anyhow::Result<Args> {
     let mut inner_size = None;
     let mut record_time = None;
+    let mut precompile_shaders = None;
     let args = std::env::args().skip(1).step_by(2);
     for (flag, value) in args.zip(std::env::args().skip(2).step_by(2)) {
         match flag.trim() {
@@ -70,6 +89,7 @@ pub fn parse_args() -> anyhow::Result<Args> {
                     .context("Failed to parse window size: Missing 'x' delimiter")?;
                 inner_size = Some((w.parse()?, h.parse()?));
             }
+            "--precompile-shaders" => precompile_shaders = Some(PathBuf::from(value)),
             _ => {}
         }
     }
@@ -77,6 +97,7 @@ pub fn parse_args() -> anyhow::Result<Args> {
     Ok(Args {
         recording_time: record_time,
         inner_size,
+        precompile_shaders,
     })
 }
 
@@ -130,3 +151,24 @@ pub fn find_memory_type_index(
         })
         .map(|(index, _memory_type)| index as _)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shader_kind_from_stage_suffix() {
+        let kind = |path: &str| ShaderKind::from_path(Path::new(path));
+        assert_eq!(kind("shaders/screen_trig.vert"), Some(ShaderKind::Vertex));
+        assert_eq!(
+            kind("src/passes/finish/finish.frag.glsl"),
+            Some(ShaderKind::Fragment)
+        );
+        assert_eq!(kind("bloom.comp.hlsl"), Some(ShaderKind::Compute));
+        assert_eq!(kind("shaders/prelude.glsl"), None);
+        assert_eq!(kind("shader.vert.bak.glsl"), None);
+        assert_eq!(kind("comp"), None);
+        assert_eq!(kind(".comp"), None);
+        assert_eq!(kind("ü.frag"), Some(ShaderKind::Fragment));
+    }
+}
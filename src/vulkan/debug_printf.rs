@@ -0,0 +1,121 @@
+use std::{
+    ffi::CStr,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use ash::vk;
+use parking_lot::Mutex;
+
+pub const DEBUG_PRINTF_ENV: &str = "MYNDGERA_DEBUG_PRINTF";
+
+const MAX_LINES_PER_SECOND: usize = 32;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+pub fn debug_printf_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        std::env::var(DEBUG_PRINTF_ENV).is_ok_and(|value| !matches!(value.as_str(), "" | "0"))
+    })
+}
+
+struct PipelineLog {
+    name: String,
+    messages: Vec<String>,
+    window_start: Instant,
+    admitted: usize,
+    suppressed: usize,
+}
+
+// Keyed by pipeline handle, messages past the rate limit are only counted
+static PRINTF_LOG: Mutex<Option<AHashMap<u64, PipelineLog>>> = Mutex::new(None);
+
+pub(super) unsafe extern "system" fn debug_printf_callback(
+    _flag: vk::DebugUtilsMessageSeverityFlagsEXT,
+    _typ: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    _: *mut std::ffi::c_void,
+) -> vk::Bool32 {
+    let data = unsafe { &*p_callback_data };
+    let id_name = unsafe { data.message_id_name_as_c_str() }
+        .map(CStr::to_string_lossy)
+        .unwrap_or_default();
+    if !id_name.contains("DEBUG-PRINTF") {
+        return vk::FALSE;
+    }
+
+    let objects = match data.object_count {
+        0 => &[][..],
+        count => unsafe { std::slice::from_raw_parts(data.p_objects, count as usize) },
+    };
+    let pipeline = objects
+        .iter()
+        .find(|object| object.object_type == vk::ObjectType::PIPELINE);
+
+    let mut log = PRINTF_LOG.lock();
+    let log = log
+        .get_or_insert_default()
+        .entry(pipeline.map_or(0, |object| object.object_handle))
+        .or_insert_with(|| PipelineLog {
+            name: pipeline.map_or_else(
+                || "unknown pipeline".to_string(),
+                |object| match unsafe { object.object_name_as_c_str() } {
+                    Some(name) => name.to_string_lossy().into_owned(),
+                    None => format!("pipeline {:#x}", object.object_handle),
+                },
+            ),
+            messages: vec![],
+            window_start: Instant::now(),
+            admitted: 0,
+            suppressed: 0,
+        });
+    if log.admitted >= MAX_LINES_PER_SECOND {
+        log.suppressed += 1;
+        return vk::FALSE;
+    }
+    log.admitted += 1;
+
+    let message = unsafe { data.message_as_c_str() }
+        .map(CStr::to_string_lossy)
+        .unwrap_or_default();
+    let payload = message
+        .rsplit(['|', '\n'])
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+        .to_string();
+    log.messages.push(payload);
+    vk::FALSE
+}
+
+// Lines can't be matched to the frame that printed them, the validation layer reports them
+// whenever it processes the finished command buffer
+pub fn flush_debug_printf() {
+    let mut log = PRINTF_LOG.lock();
+    let Some(pipelines) = log.as_mut() else {
+        return;
+    };
+
+    let now = Instant::now();
+    for log in pipelines.values_mut() {
+        if !log.messages.is_empty() {
+            let lines = log.messages.join("\n\t");
+            tracing::info!(target: "shader_printf", "{}:\n\t{lines}", log.name);
+            log.messages.clear();
+        }
+        if now.duration_since(log.window_start) >= RATE_WINDOW {
+            if log.suppressed > 0 {
+                tracing::warn!(
+                    target: "shader_printf",
+                    "{}: suppressed {} printf messages",
+                    log.name,
+                    log.suppressed
+                );
+            }
+            log.window_start = now;
+            log.admitted = 0;
+            log.suppressed = 0;
+        }
+    }
+}
@@ -10,7 +10,7 @@ use std::{
     mem::ManuallyDrop,
     sync::Arc,
 };
-use tracing::warn;
+use tracing::{debug, warn};
 
 use ash::{
     ext, khr,
@@ -18,8 +18,10 @@ use ash::{
     vk::{self, Handle},
 };
 
-use super::{Buffer, BufferTyped, Instance, ManagedImage, Surface};
-use crate::align_to;
+use super::{
+    Buffer, BufferTyped, GpuProfiler, GpuQueries, GpuQueue, Instance, QueryKind, QueueType,
+    ScopedQuery, Submission, Surface, SyncPoint, debug_printf_enabled,
+};
 
 pub struct Device {
     pub physical_device: vk::PhysicalDevice,
@@ -28,9 +30,12 @@ pub struct Device {
     pub descriptor_indexing_props: vk::PhysicalDeviceDescriptorIndexingProperties<'static>,
     pub command_pool: vk::CommandPool,
     pub main_queue_family_idx: u32,
-    pub queue: vk::Queue,
     pub transfer_queue_family_idx: u32,
+    pub graphics_queue: Mutex<GpuQueue>,
+    pub async_compute_queue: Option<Mutex<GpuQueue>>,
     pub allocator: Mutex<Allocator>,
+    pub profiler: Mutex<GpuProfiler>,
+    pub queries: Mutex<GpuQueries>,
     pub device: ash::Device,
     pub dynamic_rendering: khr::dynamic_rendering::Device,
     pub(crate) dbg_utils: ext::debug_utils::Device,
@@ -59,6 +64,7 @@ impl Device {
             ext::extended_dynamic_state2::NAME,
             ext::extended_dynamic_state::NAME,
             khr::synchronization2::NAME,
+            khr::timeline_semaphore::NAME,
             khr::buffer_device_address::NAME,
             khr::create_renderpass2::NAME,
             ext::descriptor_indexing::NAME,
@@ -69,7 +75,7 @@ impl Device {
         let required_device_extensions_set = HashSet::from(required_device_extensions);
 
         let devices = unsafe { instance.enumerate_physical_devices() }?;
-        let (pdevice, main_queue_family_idx, transfer_queue_family_idx) =
+        let (pdevice, main_queue_family_idx, transfer_queue_family_idx, compute_queue) =
             devices
                 .into_iter()
                 .find_map(|device| {
@@ -118,18 +124,56 @@ impl Device {
                         },
                     )?;
 
-                    Some((device, main_queue_idx?, transfer_queue_idx))
+                    let compute_queue_idx = queue_properties
+                        .iter()
+                        .enumerate()
+                        .map(|(family_idx, properties)| (family_idx as u32, properties))
+                        // A family whose only queue goes to transfers leaves compute on graphics
+                        .filter(|&(family_idx, properties)| {
+                            properties.queue_flags.contains(QF::COMPUTE)
+                                && !properties.queue_flags.contains(QF::GRAPHICS)
+                                && (family_idx != transfer_queue_idx || properties.queue_count > 1)
+                        })
+                        // Prefer a family that doesn't have to share with transfers
+                        .max_by_key(|&(family_idx, _)| family_idx != transfer_queue_idx)
+                        .map(|(family_idx, _)| {
+                            let queue_idx = (family_idx == transfer_queue_idx) as u32;
+                            (family_idx, queue_idx)
+                        });
+
+                    Some((
+                        device,
+                        main_queue_idx?,
+                        transfer_queue_idx,
+                        compute_queue_idx,
+                    ))
                 })
                 .context("Failed to find suitable device.")?;
 
-        let queue_infos = [
+        let compute_queue_count = match compute_queue {
+            Some((family_idx, queue_idx)) if family_idx == transfer_queue_family_idx => {
+                queue_idx as usize + 1
+            }
+            _ => 1,
+        };
+        let transfer_priorities = [0.5, 0.75];
+        let mut queue_infos = vec![
             vk::DeviceQueueCreateInfo::default()
                 .queue_family_index(main_queue_family_idx)
                 .queue_priorities(&[1.0]),
             vk::DeviceQueueCreateInfo::default()
                 .queue_family_index(transfer_queue_family_idx)
-                .queue_priorities(&[0.5]),
+                .queue_priorities(&transfer_priorities[..compute_queue_count]),
         ];
+        if let Some((family_idx, _)) = compute_queue
+            && family_idx != transfer_queue_family_idx
+        {
+            queue_infos.push(
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(family_idx)
+                    .queue_priorities(&[0.75]),
+            );
+        }
 
         let required_device_extensions = required_device_extensions.map(|x| x.as_ptr());
 
@@ -158,6 +202,8 @@ impl Device {
             vk::PhysicalDeviceBufferDeviceAddressFeatures::default().buffer_device_address(true);
         let mut feature_synchronization2 =
             vk::PhysicalDeviceSynchronization2Features::default().synchronization2(true);
+        let mut feature_timeline_semaphore =
+            vk::PhysicalDeviceTimelineSemaphoreFeatures::default().timeline_semaphore(true);
         let mut feature_pipeline_library =
             vk::PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT::default()
                 .graphics_pipeline_library(true);
@@ -171,6 +217,14 @@ impl Device {
         if cfg!(debug_assertions) {
             features.robust_buffer_access = 1;
         }
+        let supported_features = unsafe { instance.get_physical_device_features(pdevice) };
+        features.pipeline_statistics_query = supported_features.pipeline_statistics_query;
+        features.occlusion_query_precise = supported_features.occlusion_query_precise;
+        if debug_printf_enabled() {
+            features.vertex_pipeline_stores_and_atomics =
+                supported_features.vertex_pipeline_stores_and_atomics;
+            features.fragment_stores_and_atomics = supported_features.fragment_stores_and_atomics;
+        }
 
         let mut default_features = vk::PhysicalDeviceFeatures2::default()
             .features(features)
@@ -178,6 +232,7 @@ impl Device {
             .push_next(&mut feature_descriptor_indexing)
             .push_next(&mut feature_buffer_device_address)
             .push_next(&mut feature_synchronization2)
+            .push_next(&mut feature_timeline_semaphore)
             .push_next(&mut feature_scalar_layout)
             .push_next(&mut feature_dynamic_state)
             .push_next(&mut feature_pipeline_library)
@@ -218,19 +273,47 @@ impl Device {
             )?
         };
 
-        {};
+        let timestamp_valid_bits = unsafe {
+            instance.get_physical_device_queue_family_properties(pdevice)
+                [main_queue_family_idx as usize]
+                .timestamp_valid_bits
+        };
+        let profiler = GpuProfiler::new(
+            timestamp_valid_bits,
+            device_properties.properties.limits.timestamp_period,
+        );
+
         let dbg_utils = ext::debug_utils::Device::new(&instance.inner, &device);
 
+        let queue = unsafe { device.get_device_queue(main_queue_family_idx, 0) };
+        let graphics_queue = GpuQueue::new(&device, queue, main_queue_family_idx)?;
+        let async_compute_queue = compute_queue
+            .map(|(family_idx, queue_idx)| {
+                let queue = unsafe { device.get_device_queue(family_idx, queue_idx) };
+                GpuQueue::new(&device, queue, family_idx).map(Mutex::new)
+            })
+            .transpose()?;
+        debug!(
+            "Async compute queue family: {:?}",
+            compute_queue.map(|(family_idx, _)| family_idx)
+        );
+
         let device = Device {
             physical_device: pdevice,
             device_properties: device_properties.properties,
             descriptor_indexing_props,
-            queue: unsafe { device.get_device_queue(main_queue_family_idx, 0) },
             main_queue_family_idx,
             transfer_queue_family_idx,
+            graphics_queue: Mutex::new(graphics_queue),
+            async_compute_queue,
             command_pool,
             memory_properties,
             allocator: Mutex::new(allocator),
+            profiler: Mutex::new(profiler),
+            queries: Mutex::new(GpuQueries::new(
+                supported_features.pipeline_statistics_query == vk::TRUE,
+                supported_features.occlusion_query_precise == vk::TRUE,
+            )),
             device,
             dynamic_rendering,
             dbg_utils,
@@ -240,6 +323,83 @@ impl Device {
         Ok((device, transfer_queue))
     }
 
+    pub fn queue_family_idx(&self, ty: QueueType) -> u32 {
+        match (ty, &self.async_compute_queue) {
+            (QueueType::AsyncCompute, Some(queue)) => queue.lock().family_idx,
+            _ => self.main_queue_family_idx,
+        }
+    }
+
+    pub fn submit(&self, ty: QueueType, submission: Submission) -> VkResult<SyncPoint> {
+        match (ty, &self.async_compute_queue) {
+            (QueueType::AsyncCompute, Some(queue)) => queue.lock().submit(&self.device, submission),
+            _ => self.graphics_queue.lock().submit(&self.device, submission),
+        }
+    }
+
+    pub fn last_submitted(&self, ty: QueueType) -> SyncPoint {
+        match (ty, &self.async_compute_queue) {
+            (QueueType::AsyncCompute, Some(queue)) => queue.lock().last_submitted(),
+            _ => self.graphics_queue.lock().last_submitted(),
+        }
+    }
+
+    // Exclusive buffers keep their contents across queue families only through a release on
+    // the source queue followed by a matching acquire on the destination queue
+    pub fn release_buffer_ownership(
+        &self,
+        &cbuff: &vk::CommandBuffer,
+        buffer: vk::Buffer,
+        src_stages: vk::PipelineStageFlags2,
+        src_access: vk::AccessFlags2,
+        from: QueueType,
+        to: QueueType,
+    ) {
+        let (src_family, dst_family) = (self.queue_family_idx(from), self.queue_family_idx(to));
+        let barrier = vk::BufferMemoryBarrier2::default()
+            .buffer(buffer)
+            .size(vk::WHOLE_SIZE)
+            .src_stage_mask(src_stages)
+            .src_access_mask(src_access);
+        let barrier = match src_family == dst_family {
+            true => barrier,
+            false => barrier
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family),
+        };
+        self.pipeline_barrier(
+            &cbuff,
+            &vk::DependencyInfo::default().buffer_memory_barriers(std::slice::from_ref(&barrier)),
+        );
+    }
+
+    pub fn acquire_buffer_ownership(
+        &self,
+        &cbuff: &vk::CommandBuffer,
+        buffer: vk::Buffer,
+        dst_stages: vk::PipelineStageFlags2,
+        dst_access: vk::AccessFlags2,
+        from: QueueType,
+        to: QueueType,
+    ) {
+        let (src_family, dst_family) = (self.queue_family_idx(from), self.queue_family_idx(to));
+        let barrier = vk::BufferMemoryBarrier2::default()
+            .buffer(buffer)
+            .size(vk::WHOLE_SIZE)
+            .dst_stage_mask(dst_stages)
+            .dst_access_mask(dst_access);
+        let barrier = match src_family == dst_family {
+            true => barrier,
+            false => barrier
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family),
+        };
+        self.pipeline_barrier(
+            &cbuff,
+            &vk::DependencyInfo::default().buffer_memory_barriers(std::slice::from_ref(&barrier)),
+        );
+    }
+
     pub fn wait_idle(&self) {
         let _ = unsafe { self.device.device_wait_idle() };
     }
@@ -279,6 +439,10 @@ impl Device {
         command_buffer: &'buff vk::CommandBuffer,
         label: &str,
     ) -> ScopedMarker<'buff> {
+        let timed = self
+            .profiler
+            .lock()
+            .begin_scope(&self.device, *command_buffer, label);
         let label = CString::new(label).unwrap_or_default();
         let label = vk::DebugUtilsLabelEXT::default().label_name(&label);
         unsafe {
@@ -289,9 +453,49 @@ impl Device {
         ScopedMarker {
             command_buffer,
             device: Arc::clone(self),
+            timed,
+        }
+    }
+
+    pub fn begin_query(
+        self: &Arc<Self>,
+        &cbuff: &vk::CommandBuffer,
+        kind: QueryKind,
+        name: &str,
+    ) -> ScopedQuery {
+        let query = self
+            .queries
+            .lock()
+            .begin_query(&self.device, cbuff, kind, name)
+            .map(|query| (kind, query));
+        ScopedQuery {
+            command_buffer: cbuff,
+            device: Arc::clone(self),
+            query,
         }
     }
 
+    // Queries of the slot are read back from its previous use, so the slot's fence must
+    // have signaled
+    pub fn begin_frame_queries(
+        &self,
+        &cbuff: &vk::CommandBuffer,
+        slot_idx: usize,
+        frame: u32,
+    ) -> VkResult<()> {
+        self.profiler
+            .lock()
+            .begin_frame(&self.device, cbuff, slot_idx, frame)?;
+        self.queries
+            .lock()
+            .begin_frame(&self.device, cbuff, slot_idx, frame)
+    }
+
+    pub fn end_frame_queries(&self) {
+        self.profiler.lock().end_frame(&self.device);
+        self.queries.lock().end_frame();
+    }
+
     pub fn create_image(
         &self,
         info: &vk::ImageCreateInfo,
@@ -395,25 +599,16 @@ impl Device {
         unsafe { self.device.create_fence(&fence_info, None) }
     }
 
-    pub fn start_command_buffer(&self) -> VkResult<vk::CommandBuffer> {
-        let command_buffer = unsafe {
-            self.allocate_command_buffers(
-                &vk::CommandBufferAllocateInfo::default()
-                    .command_pool(self.command_pool)
-                    .command_buffer_count(1)
-                    .level(vk::CommandBufferLevel::PRIMARY),
-            )?[0]
-        };
-
+    pub fn begin_secondary_command_buffer(&self, &cbuff: &vk::CommandBuffer) -> VkResult<()> {
+        let inheritance = vk::CommandBufferInheritanceInfo::default();
         unsafe {
-            self.begin_command_buffer(
-                command_buffer,
+            self.device.begin_command_buffer(
+                cbuff,
                 &vk::CommandBufferBeginInfo::default()
-                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
-            )?;
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                    .inheritance_info(&inheritance),
+            )
         }
-
-        Ok(command_buffer)
     }
 
     pub fn end_command_buffer(&self, &cbuff: &vk::CommandBuffer) -> VkResult<()> {
@@ -445,10 +640,10 @@ impl Device {
 
             self.end_command_buffer(&command_buffer)?;
 
-            let submit_info =
-                vk::SubmitInfo::default().command_buffers(std::slice::from_ref(&command_buffer));
-
-            self.queue_submit(self.queue, &[submit_info], fence)?;
+            let submission = Submission::new()
+                .command_buffer(command_buffer)
+                .fence(fence);
+            self.submit(QueueType::Graphics, submission)?;
             self.wait_for_fences(&[fence], true, u64::MAX)?;
 
             self.destroy_fence(fence, None);
@@ -562,48 +757,6 @@ impl Device {
         );
     }
 
-    pub fn capture_image_data(
-        self: &Arc<Self>,
-        src_image: &vk::Image,
-        extent: vk::Extent2D,
-        callback: impl FnOnce(ManagedImage),
-    ) -> Result<()> {
-        let dst_image = ManagedImage::new(
-            self,
-            &vk::ImageCreateInfo::default()
-                .extent(vk::Extent3D {
-                    width: align_to(extent.width, 2),
-                    height: align_to(extent.height, 2),
-                    depth: 1,
-                })
-                .image_type(vk::ImageType::TYPE_2D)
-                .format(vk::Format::R8G8B8A8_UNORM)
-                .usage(vk::ImageUsageFlags::TRANSFER_DST)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .mip_levels(1)
-                .array_layers(1)
-                .tiling(vk::ImageTiling::LINEAR),
-            MemoryLocation::GpuToCpu,
-        )?;
-
-        self.one_time_submit(|device, command_buffer| {
-            device.blit_image(
-                &command_buffer,
-                src_image,
-                extent,
-                vk::ImageLayout::PRESENT_SRC_KHR,
-                &dst_image.image,
-                extent,
-                vk::ImageLayout::UNDEFINED,
-            );
-            Ok(())
-        })?;
-
-        callback(dst_image);
-
-        Ok(())
-    }
-
     pub fn create_buffer(
         self: &Arc<Self>,
         size: u64,
@@ -835,6 +988,12 @@ impl Device {
 
 impl Drop for Device {
     fn drop(&mut self) {
+        self.profiler.get_mut().destroy(&self.device);
+        self.queries.get_mut().destroy(&self.device);
+        self.graphics_queue.get_mut().destroy(&self.device);
+        if let Some(queue) = &mut self.async_compute_queue {
+            queue.get_mut().destroy(&self.device);
+        }
         unsafe {
             self.device.destroy_command_pool(self.command_pool, None);
             self.device.destroy_device(None);
@@ -867,10 +1026,17 @@ impl std::fmt::Display for RendererInfo {
 pub struct ScopedMarker<'a> {
     command_buffer: &'a vk::CommandBuffer,
     device: Arc<Device>,
+    timed: bool,
 }
 
 impl Drop for ScopedMarker<'_> {
     fn drop(&mut self) {
+        if self.timed {
+            self.device
+                .profiler
+                .lock()
+                .end_scope(&self.device, *self.command_buffer);
+        }
         unsafe {
             self.device
                 .dbg_utils
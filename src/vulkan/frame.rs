@@ -2,41 +2,139 @@ use std::sync::Arc;
 
 use ash::{prelude::VkResult, vk};
 
-use super::Device;
+use super::{
+    Device, ImageHandle, ImageUsage, QueryKind, QueueType, ScopedQuery, Submission, SyncPoint,
+    TextureArena,
+};
 
 pub struct Frame {
     pub image_available_semaphore: vk::Semaphore,
-    pub render_finished_semaphore: vk::Semaphore,
     pub present_finished: vk::Fence,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    secondary_pools: Vec<(vk::CommandPool, vk::CommandBuffer)>,
+    secondary_in_use: usize,
+    compute_pools: Vec<(vk::CommandPool, vk::CommandBuffer)>,
+    compute_in_use: usize,
+    compute_submitted: Option<SyncPoint>,
 }
 
 impl Frame {
     pub fn new(device: &Device) -> VkResult<Self> {
         let image_available_semaphore = device.create_semaphore()?;
-        let render_finished_semaphore = device.create_semaphore()?;
         let present_finished = device.create_fence(vk::FenceCreateFlags::SIGNALED)?;
+        let command_pool = create_transient_pool(device, device.main_queue_family_idx)?;
+        let command_buffer =
+            allocate_command_buffer(device, command_pool, vk::CommandBufferLevel::PRIMARY)?;
         Ok(Self {
             image_available_semaphore,
-            render_finished_semaphore,
             present_finished,
+            command_pool,
+            command_buffer,
+            secondary_pools: vec![],
+            secondary_in_use: 0,
+            compute_pools: vec![],
+            compute_in_use: 0,
+            compute_submitted: None,
         })
     }
 
+    pub(super) fn begin_command_buffer(&mut self, device: &Device) -> VkResult<vk::CommandBuffer> {
+        // The frame fence only covers graphics work, async compute may not have been waited on
+        if let Some(point) = self.compute_submitted.take() {
+            point.wait(device, u64::MAX)?;
+        }
+        unsafe {
+            device.reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())?;
+            for &(pool, _) in &self.secondary_pools[..self.secondary_in_use] {
+                device.reset_command_pool(pool, vk::CommandPoolResetFlags::empty())?;
+            }
+            for &(pool, _) in &self.compute_pools[..self.compute_in_use] {
+                device.reset_command_pool(pool, vk::CommandPoolResetFlags::empty())?;
+            }
+            device.begin_command_buffer(
+                self.command_buffer,
+                &vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?;
+        }
+        self.secondary_in_use = 0;
+        self.compute_in_use = 0;
+        Ok(self.command_buffer)
+    }
+
+    fn next_secondary_command_buffer(&mut self, device: &Device) -> VkResult<vk::CommandBuffer> {
+        if self.secondary_in_use == self.secondary_pools.len() {
+            let pool = create_transient_pool(device, device.main_queue_family_idx)?;
+            let command_buffer =
+                allocate_command_buffer(device, pool, vk::CommandBufferLevel::SECONDARY)?;
+            self.secondary_pools.push((pool, command_buffer));
+        }
+        let (_, command_buffer) = self.secondary_pools[self.secondary_in_use];
+        self.secondary_in_use += 1;
+        Ok(command_buffer)
+    }
+
+    fn next_compute_command_buffer(&mut self, device: &Device) -> VkResult<vk::CommandBuffer> {
+        if self.compute_in_use == self.compute_pools.len() {
+            let family_idx = device.queue_family_idx(QueueType::AsyncCompute);
+            let pool = create_transient_pool(device, family_idx)?;
+            let command_buffer =
+                allocate_command_buffer(device, pool, vk::CommandBufferLevel::PRIMARY)?;
+            self.compute_pools.push((pool, command_buffer));
+        }
+        let (_, command_buffer) = self.compute_pools[self.compute_in_use];
+        self.compute_in_use += 1;
+        Ok(command_buffer)
+    }
+
     pub fn destroy(&mut self, device: &Device) {
         unsafe {
             device.destroy_fence(self.present_finished, None);
             device.destroy_semaphore(self.image_available_semaphore, None);
-            device.destroy_semaphore(self.render_finished_semaphore, None);
+            device.destroy_command_pool(self.command_pool, None);
+            for &(pool, _) in self.secondary_pools.iter().chain(&self.compute_pools) {
+                device.destroy_command_pool(pool, None);
+            }
         }
     }
 }
 
+fn create_transient_pool(device: &Device, family_idx: u32) -> VkResult<vk::CommandPool> {
+    unsafe {
+        device.create_command_pool(
+            &vk::CommandPoolCreateInfo::default()
+                .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                .queue_family_index(family_idx),
+            None,
+        )
+    }
+}
+
+fn allocate_command_buffer(
+    device: &Device,
+    pool: vk::CommandPool,
+    level: vk::CommandBufferLevel,
+) -> VkResult<vk::CommandBuffer> {
+    let command_buffers = unsafe {
+        device.allocate_command_buffers(
+            &vk::CommandBufferAllocateInfo::default()
+                .command_pool(pool)
+                .command_buffer_count(1)
+                .level(level),
+        )?
+    };
+    Ok(command_buffers[0])
+}
+
 pub struct FrameGuard {
     pub frame: Frame,
     pub command_buffer: vk::CommandBuffer,
     pub extent: vk::Extent2D,
+    pub frame_idx: usize,
     pub image_idx: usize,
     pub device: Arc<Device>,
+    pub(super) waits: Vec<(SyncPoint, vk::PipelineStageFlags2)>,
 }
 
 impl FrameGuard {
@@ -44,25 +142,79 @@ impl FrameGuard {
         &self.command_buffer
     }
 
+    pub fn secondary_command_buffers(&mut self, count: usize) -> VkResult<Vec<vk::CommandBuffer>> {
+        (0..count)
+            .map(|_| self.frame.next_secondary_command_buffer(&self.device))
+            .collect()
+    }
+
+    pub fn begin_statistics_query(&self, name: &str) -> ScopedQuery {
+        self.device
+            .begin_query(self.command_buffer(), QueryKind::PipelineStatistics, name)
+    }
+
+    // Must begin and end within the same rendering scope
+    pub fn begin_occlusion_query(&self, name: &str) -> ScopedQuery {
+        self.device
+            .begin_query(self.command_buffer(), QueryKind::Occlusion, name)
+    }
+
+    // Makes the frame's graphics submission wait for work on another queue
+    pub fn wait_for(&mut self, point: SyncPoint, stages: vk::PipelineStageFlags2) {
+        self.waits.push((point, stages));
+    }
+
+    // Records and submits right away, so the work can overlap with the rest of the frame.
+    // Pass the returned point to `wait_for` before the results are used on the graphics queue
+    pub fn submit_async_compute(
+        &mut self,
+        waits: &[(SyncPoint, vk::PipelineStageFlags2)],
+        record: impl FnOnce(&vk::CommandBuffer),
+    ) -> VkResult<SyncPoint> {
+        let command_buffer = self.frame.next_compute_command_buffer(&self.device)?;
+        unsafe {
+            self.device.begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )?
+        };
+        record(&command_buffer);
+        self.device.end_command_buffer(&command_buffer)?;
+
+        let submission = waits
+            .iter()
+            .fold(Submission::new(), |submission, &(point, stages)| {
+                submission.wait(point, stages)
+            })
+            .command_buffer(command_buffer);
+        let point = self.device.submit(QueueType::AsyncCompute, submission)?;
+        self.frame.compute_submitted = Some(point);
+        Ok(point)
+    }
+
+    pub fn execute_commands(&self, secondary_command_buffers: &[vk::CommandBuffer]) {
+        unsafe {
+            self.device
+                .cmd_execute_commands(self.command_buffer, secondary_command_buffers)
+        };
+    }
+
     pub fn begin_rendering(
         &self,
-        image: &vk::Image,
-        view: &vk::ImageView,
+        texture_arena: &mut TextureArena,
+        target: ImageHandle,
         load_op: vk::AttachmentLoadOp,
         color: [f32; 4],
     ) {
-        self.device.image_transition(
-            self.command_buffer(),
-            image,
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-        );
+        texture_arena.transition(self.command_buffer(), target, ImageUsage::ColorAttachment);
+        let view = texture_arena.get_image(target).views[0].unwrap();
 
         let clear_color = vk::ClearValue {
             color: vk::ClearColorValue { float32: color },
         };
         let color_attachment = vk::RenderingAttachmentInfo::default()
-            .image_view(*view)
+            .image_view(view)
             .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
             .resolve_image_layout(vk::ImageLayout::PRESENT_SRC_KHR)
             .load_op(load_op)
@@ -0,0 +1,204 @@
+use ash::vk;
+
+use super::ImageHandle;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageUsage {
+    ComputeSampled,
+    ComputeStorage,
+    FragmentSampled,
+    FragmentStorage,
+    ColorAttachment,
+    TransferSrc,
+    TransferDst,
+    Present,
+    General,
+}
+
+impl ImageUsage {
+    pub fn state(self) -> ImageState {
+        use vk::{AccessFlags2 as A, ImageLayout as L, PipelineStageFlags2 as S};
+        let (layout, stages, access) = match self {
+            Self::ComputeSampled => (
+                L::SHADER_READ_ONLY_OPTIMAL,
+                S::COMPUTE_SHADER,
+                A::SHADER_READ,
+            ),
+            Self::ComputeStorage => (
+                L::GENERAL,
+                S::COMPUTE_SHADER,
+                A::SHADER_READ | A::SHADER_WRITE,
+            ),
+            Self::FragmentSampled => (
+                L::SHADER_READ_ONLY_OPTIMAL,
+                S::FRAGMENT_SHADER,
+                A::SHADER_READ,
+            ),
+            Self::FragmentStorage => (
+                L::GENERAL,
+                S::FRAGMENT_SHADER,
+                A::SHADER_READ | A::SHADER_WRITE,
+            ),
+            Self::ColorAttachment => (
+                L::COLOR_ATTACHMENT_OPTIMAL,
+                S::COLOR_ATTACHMENT_OUTPUT,
+                A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE,
+            ),
+            Self::TransferSrc => (L::TRANSFER_SRC_OPTIMAL, S::TRANSFER, A::TRANSFER_READ),
+            Self::TransferDst => (L::TRANSFER_DST_OPTIMAL, S::TRANSFER, A::TRANSFER_WRITE),
+            // Acquire semaphores are waited on at this stage, so the next frame's
+            // first barrier chains from it
+            Self::Present => (L::PRESENT_SRC_KHR, S::COLOR_ATTACHMENT_OUTPUT, A::NONE),
+            Self::General => (
+                L::GENERAL,
+                S::ALL_COMMANDS,
+                A::MEMORY_READ | A::MEMORY_WRITE,
+            ),
+        };
+        ImageState {
+            layout,
+            stages,
+            access,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    pub stages: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+}
+
+impl ImageState {
+    pub const UNDEFINED: Self = Self {
+        layout: vk::ImageLayout::UNDEFINED,
+        stages: vk::PipelineStageFlags2::NONE,
+        access: vk::AccessFlags2::NONE,
+    };
+
+    // Swapchain images are acquired with a semaphore waited on at COLOR_ATTACHMENT_OUTPUT
+    pub const ACQUIRED: Self = Self {
+        layout: vk::ImageLayout::UNDEFINED,
+        stages: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        access: vk::AccessFlags2::NONE,
+    };
+
+    fn writes(&self) -> vk::AccessFlags2 {
+        self.access
+            & (vk::AccessFlags2::SHADER_WRITE
+                | vk::AccessFlags2::SHADER_STORAGE_WRITE
+                | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE
+                | vk::AccessFlags2::TRANSFER_WRITE
+                | vk::AccessFlags2::HOST_WRITE
+                | vk::AccessFlags2::MEMORY_WRITE)
+    }
+
+    // Returns the state to synchronize against, or merges read-only accesses into self
+    pub(super) fn transition_to(&mut self, next: ImageState) -> Option<ImageState> {
+        if self.layout == next.layout && self.writes().is_empty() && next.writes().is_empty() {
+            self.stages |= next.stages;
+            self.access |= next.access;
+            return None;
+        }
+        let prev = ImageState {
+            access: self.writes(),
+            ..*self
+        };
+        *self = next;
+        Some(prev)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ImageTransition {
+    pub handle: ImageHandle,
+    pub mip_level: Option<u32>,
+    pub usage: ImageUsage,
+}
+
+impl ImageTransition {
+    pub fn new(handle: ImageHandle, usage: ImageUsage) -> Self {
+        Self {
+            handle,
+            mip_level: None,
+            usage,
+        }
+    }
+
+    pub fn mip(handle: ImageHandle, mip_level: u32, usage: ImageUsage) -> Self {
+        Self {
+            handle,
+            mip_level: Some(mip_level),
+            usage,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_in_the_same_layout_merge() {
+        let mut state = ImageUsage::ComputeSampled.state();
+        assert_eq!(
+            state.transition_to(ImageUsage::FragmentSampled.state()),
+            None
+        );
+        assert_eq!(
+            state.stages,
+            vk::PipelineStageFlags2::COMPUTE_SHADER | vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(state.access, vk::AccessFlags2::SHADER_READ);
+    }
+
+    #[test]
+    fn layout_change_waits_without_flushing_reads() {
+        let mut state = ImageUsage::ComputeSampled.state();
+        let prev = state
+            .transition_to(ImageUsage::TransferDst.state())
+            .unwrap();
+        assert_eq!(prev.layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(prev.stages, vk::PipelineStageFlags2::COMPUTE_SHADER);
+        assert_eq!(prev.access, vk::AccessFlags2::NONE);
+        assert_eq!(state, ImageUsage::TransferDst.state());
+    }
+
+    #[test]
+    fn writes_are_flushed() {
+        let mut state = ImageUsage::ComputeStorage.state();
+        let prev = state
+            .transition_to(ImageUsage::ComputeStorage.state())
+            .unwrap();
+        assert_eq!(prev.layout, vk::ImageLayout::GENERAL);
+        assert_eq!(prev.access, vk::AccessFlags2::SHADER_WRITE);
+
+        let mut state = ImageUsage::ColorAttachment.state();
+        let prev = state
+            .transition_to(ImageUsage::FragmentSampled.state())
+            .unwrap();
+        assert_eq!(prev.access, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE);
+        assert_eq!(state, ImageUsage::FragmentSampled.state());
+    }
+
+    #[test]
+    fn undefined_transitions_from_nothing() {
+        let mut state = ImageState::UNDEFINED;
+        let prev = state
+            .transition_to(ImageUsage::ComputeStorage.state())
+            .unwrap();
+        assert_eq!(prev, ImageState::UNDEFINED);
+
+        let mut state = ImageState::ACQUIRED;
+        let prev = state
+            .transition_to(ImageUsage::ColorAttachment.state())
+            .unwrap();
+        assert_eq!(
+            prev.stages,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(prev.layout, vk::ImageLayout::UNDEFINED);
+    }
+}
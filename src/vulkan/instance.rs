@@ -5,7 +5,7 @@ use ash::{Entry, ext, khr, vk};
 use tracing::{debug, error, info, warn};
 use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
-use super::{Device, Surface};
+use super::{Device, Surface, debug_printf_callback, debug_printf_enabled};
 
 unsafe extern "system" fn vulkan_debug_callback(
     flag: vk::DebugUtilsMessageSeverityFlagsEXT,
@@ -30,6 +30,7 @@ pub struct Instance {
     pub inner: ash::Instance,
     dbg_loader: ext::debug_utils::Instance,
     dbg_callbk: vk::DebugUtilsMessengerEXT,
+    printf_callbk: Option<vk::DebugUtilsMessengerEXT>,
 }
 
 impl std::ops::Deref for Instance {
@@ -43,10 +44,11 @@ impl std::ops::Deref for Instance {
 impl Instance {
     pub fn new(display_handle: Option<&impl HasDisplayHandle>) -> Result<Self> {
         let entry = unsafe { Entry::load() }?;
-        let layers = [
-            #[cfg(debug_assertions)]
-            c"VK_LAYER_KHRONOS_validation".as_ptr(),
-        ];
+        let debug_printf = debug_printf_enabled();
+        let mut layers = vec![];
+        if cfg!(debug_assertions) || debug_printf {
+            layers.push(c"VK_LAYER_KHRONOS_validation".as_ptr());
+        }
         let mut extensions = vec![
             ext::debug_utils::NAME.as_ptr(),
             khr::get_physical_device_properties2::NAME.as_ptr(),
@@ -68,6 +70,10 @@ impl Instance {
             .iter()
             .filter_map(|ext| ext.extension_name_as_c_str().ok())
             .collect::<HashSet<_>>();
+        // Optional, only needed for HDR swapchain color spaces
+        if available_extensions.contains(ext::swapchain_colorspace::NAME) {
+            extensions.push(ext::swapchain_colorspace::NAME.as_ptr());
+        }
         let extension_names =
             HashSet::from_iter(extensions.iter().map(|&ext| unsafe { CStr::from_ptr(ext) }));
         let mut missing = extension_names.difference(&available_extensions).peekable();
@@ -85,11 +91,17 @@ impl Instance {
         let appinfo = vk::ApplicationInfo::default()
             .application_name(c"Modern Vulkan")
             .api_version(vk::API_VERSION_1_3);
-        let instance_info = vk::InstanceCreateInfo::default()
+        let enabled_validation_features = [vk::ValidationFeatureEnableEXT::DEBUG_PRINTF];
+        let mut validation_features = vk::ValidationFeaturesEXT::default()
+            .enabled_validation_features(&enabled_validation_features);
+        let mut instance_info = vk::InstanceCreateInfo::default()
             .application_info(&appinfo)
             .flags(create_flags)
             .enabled_layer_names(&layers)
             .enabled_extension_names(&extensions);
+        if debug_printf {
+            instance_info = instance_info.push_next(&mut validation_features);
+        }
         let inner = unsafe { entry.create_instance(&instance_info, None) }?;
 
         let dbg_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
@@ -108,9 +120,20 @@ impl Instance {
         let dbg_loader = ext::debug_utils::Instance::new(&entry, &inner);
         let dbg_callbk = unsafe { dbg_loader.create_debug_utils_messenger(&dbg_info, None)? };
 
+        let printf_callbk = if debug_printf {
+            let printf_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
+                .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::INFO)
+                .message_type(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION)
+                .pfn_user_callback(Some(debug_printf_callback));
+            Some(unsafe { dbg_loader.create_debug_utils_messenger(&printf_info, None)? })
+        } else {
+            None
+        };
+
         Ok(Self {
             dbg_loader,
             dbg_callbk,
+            printf_callbk,
             entry,
             inner,
         })
@@ -133,6 +156,10 @@ impl Drop for Instance {
         unsafe {
             self.dbg_loader
                 .destroy_debug_utils_messenger(self.dbg_callbk, None);
+            if let Some(printf_callbk) = self.printf_callbk {
+                self.dbg_loader
+                    .destroy_debug_utils_messenger(printf_callbk, None);
+            }
             self.inner.destroy_instance(None);
         }
     }
@@ -1,9 +1,18 @@
 mod buffers;
+mod debug_printf;
 mod device;
 mod frame;
+mod image_usage;
 mod instance;
 mod pipeline_arena;
+#[cfg(feature = "hot-reload")]
+mod pipeline_reloader;
+mod profiler;
+mod queries;
+mod readback;
+mod render_graph;
 mod staging;
+mod submission;
 mod surface;
 mod swapchain;
 mod texture_arena;
@@ -12,11 +21,21 @@ mod view_target;
 use ash::vk;
 
 pub use buffers::*;
+use debug_printf::debug_printf_callback;
+pub use debug_printf::{DEBUG_PRINTF_ENV, debug_printf_enabled, flush_debug_printf};
 pub use device::*;
 pub use frame::*;
+pub use image_usage::*;
 pub use instance::Instance;
 pub use pipeline_arena::*;
+#[cfg(feature = "hot-reload")]
+pub(crate) use pipeline_reloader::*;
+pub use profiler::*;
+pub use queries::*;
+pub use readback::*;
+pub use render_graph::*;
 pub use staging::*;
+pub use submission::*;
 pub use surface::Surface;
 pub use swapchain::*;
 pub use texture_arena::*;
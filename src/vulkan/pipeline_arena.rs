@@ -1,5 +1,5 @@
 use ahash::{AHashMap, AHashSet};
-use anyhow::Result;
+use anyhow::{Result, bail};
 use either::Either;
 use slotmap::SlotMap;
 use std::{
@@ -14,12 +14,18 @@ use ash::{
 };
 
 use super::Device;
-use crate::{ShaderCompiler, ShaderKind, ShaderSource, Watcher};
+#[cfg(feature = "hot-reload")]
+use super::{PipelineReloader, ReloadJob, ReloadResult};
+#[cfg(feature = "hot-reload")]
+use crate::{ShaderBackends, ShaderCompiler, ShaderSource, SharedShaderBackend, Watcher};
+use crate::{ShaderBundle, ShaderKind};
 
 pub struct ComputePipeline {
     pub layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
     shader_path: PathBuf,
+    #[cfg(feature = "hot-reload")]
+    generation: u64,
     device: Arc<Device>,
 }
 
@@ -42,13 +48,11 @@ impl Drop for ComputePipeline {
 impl ComputePipeline {
     fn new(
         device: &Arc<Device>,
-        shader_compiler: &ShaderCompiler,
         shader_path: impl AsRef<Path>,
+        cs_code: &[u32],
         push_constant_ranges: &[vk::PushConstantRange],
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
     ) -> Result<Self> {
-        let cs_bytes = shader_compiler.compile(&shader_path, shaderc::ShaderKind::Compute)?;
-
         let pipeline_layout = unsafe {
             device.create_pipeline_layout(
                 &vk::PipelineLayoutCreateInfo::default()
@@ -58,51 +62,23 @@ impl ComputePipeline {
             )?
         };
 
-        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(cs_bytes.as_binary());
-        let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::COMPUTE)
-            .name(c"main")
-            .push_next(&mut shader_module);
-
-        let create_info = vk::ComputePipelineCreateInfo::default()
-            .layout(pipeline_layout)
-            .stage(shader_stage);
-        let pipeline = unsafe {
-            device.create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
-        };
-        let pipeline = pipeline.map_err(|(_, err)| err)?[0];
+        let pipeline = create_compute_pipeline(device, pipeline_layout, cs_code)?;
 
-        Ok(Self {
+        let pipeline = Self {
             pipeline,
             shader_path: shader_path.as_ref().to_path_buf(),
             layout: pipeline_layout,
+            #[cfg(feature = "hot-reload")]
+            generation: 0,
             device: device.clone(),
-        })
-    }
-
-    pub fn reload(&mut self, shader_compiler: &ShaderCompiler) -> Result<()> {
-        let cs_bytes = shader_compiler.compile(&self.shader_path, shaderc::ShaderKind::Compute)?;
-
-        unsafe { self.device.destroy_pipeline(self.pipeline, None) }
-
-        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(cs_bytes.as_binary());
-        let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::COMPUTE)
-            .name(c"main")
-            .push_next(&mut shader_module);
-
-        let create_info = vk::ComputePipelineCreateInfo::default()
-            .layout(self.layout)
-            .stage(shader_stage);
-        let pipeline = unsafe {
-            self.device
-                .create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
         };
-        let pipeline = pipeline.map_err(|(_, err)| err)?[0];
-
-        self.pipeline = pipeline;
+        pipeline.name_pipeline();
+        Ok(pipeline)
+    }
 
-        Ok(())
+    fn name_pipeline(&self) {
+        self.device
+            .name_object(self.pipeline, &shader_name(&self.shader_path));
     }
 }
 
@@ -120,6 +96,7 @@ impl Default for VertexInputDesc {
     }
 }
 
+#[derive(Clone)]
 pub struct VertexShaderDesc {
     pub shader_path: PathBuf,
     pub dynamic_state: Vec<vk::DynamicState>,
@@ -148,6 +125,7 @@ impl Default for VertexShaderDesc {
     }
 }
 
+#[derive(Clone)]
 pub struct FragmentShaderDesc {
     pub shader_path: PathBuf,
     pub entry_point: CString,
@@ -178,18 +156,52 @@ impl Default for FragmentOutputDesc {
     }
 }
 
+// Shared with reload jobs that link against it, destroyed once the last of them is done
+pub(crate) struct PipelineLibrary {
+    pipeline: vk::Pipeline,
+    device: Arc<Device>,
+}
+
+impl PipelineLibrary {
+    pub(crate) fn new(device: &Arc<Device>, pipeline: vk::Pipeline) -> Self {
+        Self {
+            pipeline,
+            device: device.clone(),
+        }
+    }
+}
+
+impl std::ops::Deref for PipelineLibrary {
+    type Target = vk::Pipeline;
+    fn deref(&self) -> &Self::Target {
+        &self.pipeline
+    }
+}
+
+impl Drop for PipelineLibrary {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_pipeline(self.pipeline, None) };
+    }
+}
+
 #[allow(dead_code)]
 pub struct RenderPipeline {
     pub layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
     vertex_input_desc: VertexInputDesc,
-    vertex_input_lib: vk::Pipeline,
+    vertex_input_lib: Arc<PipelineLibrary>,
     vertex_shader_desc: VertexShaderDesc,
-    vertex_shader_lib: vk::Pipeline,
+    vertex_shader_lib: Arc<PipelineLibrary>,
     fragment_shader_desc: FragmentShaderDesc,
-    fragment_shader_lib: vk::Pipeline,
+    fragment_shader_lib: Arc<PipelineLibrary>,
     fragment_output_desc: FragmentOutputDesc,
-    fragment_output_lib: vk::Pipeline,
+    fragment_output_lib: Arc<PipelineLibrary>,
+    #[cfg(feature = "hot-reload")]
+    generation: u64,
+    #[cfg(feature = "hot-reload")]
+    dirty_vertex: Option<PathBuf>,
+    #[cfg(feature = "hot-reload")]
+    dirty_fragment: Option<PathBuf>,
     device: Arc<Device>,
 }
 
@@ -204,21 +216,15 @@ impl RenderPipeline {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Arc<Device>,
-        shader_compiler: &ShaderCompiler,
         vertex_input_desc: VertexInputDesc,
         vertex_shader_desc: VertexShaderDesc,
+        vs_code: &[u32],
         fragment_shader_desc: FragmentShaderDesc,
+        fs_code: &[u32],
         fragment_output_desc: FragmentOutputDesc,
         push_constant_ranges: &[vk::PushConstantRange],
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
     ) -> Result<Self> {
-        let vs_bytes = shader_compiler
-            .compile(&vertex_shader_desc.shader_path, shaderc::ShaderKind::Vertex)?;
-        let fs_bytes = shader_compiler.compile(
-            &fragment_shader_desc.shader_path,
-            shaderc::ShaderKind::Fragment,
-        )?;
-
         let pipeline_layout = unsafe {
             device.create_pipeline_layout(
                 &vk::PipelineLayoutCreateInfo::default()
@@ -240,50 +246,15 @@ impl RenderPipeline {
                     .input_assembly_state(&input_ass)
             })?
         };
+        let vertex_input_lib = PipelineLibrary::new(device, vertex_input_lib);
 
-        let vertex_shader_lib = {
-            let mut shader_module =
-                vk::ShaderModuleCreateInfo::default().code(vs_bytes.as_binary());
-            let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .name(&vertex_shader_desc.entry_point)
-                .push_next(&mut shader_module);
-            let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
-                .dynamic_states(&vertex_shader_desc.dynamic_state);
-            let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
-                .line_width(vertex_shader_desc.line_width)
-                .polygon_mode(vertex_shader_desc.polygon_mode)
-                .cull_mode(vertex_shader_desc.cull_mode)
-                .front_face(vertex_shader_desc.front_face);
-            let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-                .viewport_count(vertex_shader_desc.viewport_count)
-                .scissor_count(vertex_shader_desc.scissor_count);
-
-            create_library(device, GPF::PRE_RASTERIZATION_SHADERS, |desc| {
-                desc.layout(pipeline_layout)
-                    .stages(std::slice::from_ref(&shader_stage))
-                    .dynamic_state(&dynamic_state)
-                    .viewport_state(&viewport_state)
-                    .rasterization_state(&rasterization_state)
-            })?
-        };
+        let vertex_shader_lib =
+            create_vertex_shader_lib(device, pipeline_layout, &vertex_shader_desc, vs_code)?;
+        let vertex_shader_lib = PipelineLibrary::new(device, vertex_shader_lib);
 
-        let fragment_shader_lib = {
-            let mut shader_module =
-                vk::ShaderModuleCreateInfo::default().code(fs_bytes.as_binary());
-            let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::FRAGMENT)
-                .name(&fragment_shader_desc.entry_point)
-                .push_next(&mut shader_module);
-
-            let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default();
-
-            create_library(device, GPF::FRAGMENT_SHADER, |desc| {
-                desc.layout(pipeline_layout)
-                    .stages(std::slice::from_ref(&shader_stage))
-                    .depth_stencil_state(&depth_stencil_state)
-            })?
-        };
+        let fragment_shader_lib =
+            create_fragment_shader_lib(device, pipeline_layout, &fragment_shader_desc, fs_code)?;
+        let fragment_shader_lib = PipelineLibrary::new(device, fragment_shader_lib);
 
         let fragment_output_lib = {
             let color_attachment_formats = [fragment_output_desc.surface_format];
@@ -304,6 +275,7 @@ impl RenderPipeline {
                     .push_next(&mut dyn_render)
             })?
         };
+        let fragment_output_lib = PipelineLibrary::new(device, fragment_output_lib);
 
         let pipeline = Self::link_libraries(
             device,
@@ -314,110 +286,39 @@ impl RenderPipeline {
             &fragment_output_lib,
         )?;
 
-        Ok(Self {
+        let pipeline = Self {
             device: device.clone(),
             layout: pipeline_layout,
             pipeline,
-            vertex_input_lib,
-            vertex_shader_lib,
-            fragment_shader_lib,
-            fragment_output_lib,
+            vertex_input_lib: Arc::new(vertex_input_lib),
+            vertex_shader_lib: Arc::new(vertex_shader_lib),
+            fragment_shader_lib: Arc::new(fragment_shader_lib),
+            fragment_output_lib: Arc::new(fragment_output_lib),
             vertex_input_desc,
             vertex_shader_desc,
             fragment_shader_desc,
             fragment_output_desc,
-        })
-    }
-
-    // TODO: WHERE DESCRIPTORS?!?@?!?!?!
-    pub fn reload_vertex_lib(
-        &mut self,
-        shader_compiler: &ShaderCompiler,
-        shader_path: impl AsRef<Path>,
-    ) -> Result<()> {
-        let vs_bytes = shader_compiler.compile(shader_path, shaderc::ShaderKind::Vertex)?;
-
-        unsafe { self.device.destroy_pipeline(self.vertex_shader_lib, None) };
-
-        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(vs_bytes.as_binary());
-        let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .name(&self.vertex_shader_desc.entry_point)
-            .push_next(&mut shader_module);
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
-            .dynamic_states(&self.vertex_shader_desc.dynamic_state);
-        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
-            .line_width(self.vertex_shader_desc.line_width)
-            .polygon_mode(self.vertex_shader_desc.polygon_mode)
-            .cull_mode(self.vertex_shader_desc.cull_mode)
-            .front_face(self.vertex_shader_desc.front_face);
-        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-            .viewport_count(self.vertex_shader_desc.viewport_count)
-            .scissor_count(self.vertex_shader_desc.scissor_count);
-        let vertex_shader_lib = create_library(
-            &self.device,
-            vk::GraphicsPipelineLibraryFlagsEXT::PRE_RASTERIZATION_SHADERS,
-            |desc| {
-                desc.layout(self.layout)
-                    .stages(std::slice::from_ref(&shader_stage))
-                    .dynamic_state(&dynamic_state)
-                    .viewport_state(&viewport_state)
-                    .rasterization_state(&rasterization_state)
-            },
-        )?;
-
-        self.vertex_shader_lib = vertex_shader_lib;
-
-        Ok(())
-    }
-
-    pub fn reload_fragment_lib(
-        &mut self,
-        shader_compiler: &ShaderCompiler,
-        shader_path: impl AsRef<Path>,
-    ) -> Result<()> {
-        let fs_bytes = shader_compiler.compile(shader_path, shaderc::ShaderKind::Fragment)?;
-
-        unsafe { self.device.destroy_pipeline(self.fragment_shader_lib, None) };
-
-        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(fs_bytes.as_binary());
-        let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .name(&self.fragment_shader_desc.entry_point)
-            .push_next(&mut shader_module);
-
-        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default();
-
-        let fragment_shader_lib = create_library(
-            &self.device,
-            vk::GraphicsPipelineLibraryFlagsEXT::FRAGMENT_SHADER,
-            |desc| {
-                desc.layout(self.layout)
-                    .stages(std::slice::from_ref(&shader_stage))
-                    .depth_stencil_state(&depth_stencil_state)
-            },
-        )?;
-
-        self.fragment_shader_lib = fragment_shader_lib;
-
-        Ok(())
+            #[cfg(feature = "hot-reload")]
+            generation: 0,
+            #[cfg(feature = "hot-reload")]
+            dirty_vertex: None,
+            #[cfg(feature = "hot-reload")]
+            dirty_fragment: None,
+        };
+        pipeline.name_pipeline();
+        Ok(pipeline)
     }
 
-    pub fn link(&mut self) -> Result<()> {
-        unsafe { self.device.destroy_pipeline(self.pipeline, None) };
-        self.pipeline = Self::link_libraries(
-            &self.device,
-            &self.layout,
-            &self.vertex_input_lib,
-            &self.vertex_shader_lib,
-            &self.fragment_shader_lib,
-            &self.fragment_output_lib,
-        )?;
-
-        Ok(())
+    fn name_pipeline(&self) {
+        let name = format!(
+            "{} | {}",
+            shader_name(&self.vertex_shader_desc.shader_path),
+            shader_name(&self.fragment_shader_desc.shader_path)
+        );
+        self.device.name_object(self.pipeline, &name);
     }
 
-    fn link_libraries(
+    pub(super) fn link_libraries(
         device: &ash::Device,
         layout: &vk::PipelineLayout,
         vertex_input_lib: &vk::Pipeline,
@@ -451,16 +352,92 @@ impl RenderPipeline {
 impl Drop for RenderPipeline {
     fn drop(&mut self) {
         unsafe {
-            self.device.destroy_pipeline(self.vertex_input_lib, None);
-            self.device.destroy_pipeline(self.vertex_shader_lib, None);
-            self.device.destroy_pipeline(self.fragment_shader_lib, None);
-            self.device.destroy_pipeline(self.fragment_output_lib, None);
             self.device.destroy_pipeline(self.pipeline, None);
             self.device.destroy_pipeline_layout(self.layout, None);
         }
     }
 }
 
+pub(super) fn create_compute_pipeline(
+    device: &ash::Device,
+    layout: vk::PipelineLayout,
+    code: &[u32],
+) -> VkResult<vk::Pipeline> {
+    let mut shader_module = vk::ShaderModuleCreateInfo::default().code(code);
+    let shader_stage = vk::PipelineShaderStageCreateInfo::default()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .name(c"main")
+        .push_next(&mut shader_module);
+
+    let create_info = vk::ComputePipelineCreateInfo::default()
+        .layout(layout)
+        .stage(shader_stage);
+    let pipeline =
+        unsafe { device.create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None) };
+    Ok(pipeline.map_err(|(_, err)| err)?[0])
+}
+
+// TODO: WHERE DESCRIPTORS?!?@?!?!?!
+pub(super) fn create_vertex_shader_lib(
+    device: &ash::Device,
+    layout: vk::PipelineLayout,
+    vertex_shader_desc: &VertexShaderDesc,
+    code: &[u32],
+) -> VkResult<vk::Pipeline> {
+    let mut shader_module = vk::ShaderModuleCreateInfo::default().code(code);
+    let shader_stage = vk::PipelineShaderStageCreateInfo::default()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .name(&vertex_shader_desc.entry_point)
+        .push_next(&mut shader_module);
+    let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
+        .dynamic_states(&vertex_shader_desc.dynamic_state);
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+        .line_width(vertex_shader_desc.line_width)
+        .polygon_mode(vertex_shader_desc.polygon_mode)
+        .cull_mode(vertex_shader_desc.cull_mode)
+        .front_face(vertex_shader_desc.front_face);
+    let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+        .viewport_count(vertex_shader_desc.viewport_count)
+        .scissor_count(vertex_shader_desc.scissor_count);
+
+    create_library(
+        device,
+        vk::GraphicsPipelineLibraryFlagsEXT::PRE_RASTERIZATION_SHADERS,
+        |desc| {
+            desc.layout(layout)
+                .stages(std::slice::from_ref(&shader_stage))
+                .dynamic_state(&dynamic_state)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterization_state)
+        },
+    )
+}
+
+pub(super) fn create_fragment_shader_lib(
+    device: &ash::Device,
+    layout: vk::PipelineLayout,
+    fragment_shader_desc: &FragmentShaderDesc,
+    code: &[u32],
+) -> VkResult<vk::Pipeline> {
+    let mut shader_module = vk::ShaderModuleCreateInfo::default().code(code);
+    let shader_stage = vk::PipelineShaderStageCreateInfo::default()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .name(&fragment_shader_desc.entry_point)
+        .push_next(&mut shader_module);
+
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default();
+
+    create_library(
+        device,
+        vk::GraphicsPipelineLibraryFlagsEXT::FRAGMENT_SHADER,
+        |desc| {
+            desc.layout(layout)
+                .stages(std::slice::from_ref(&shader_stage))
+                .depth_stencil_state(&depth_stencil_state)
+        },
+    )
+}
+
 fn create_library<'a, F>(
     device: &ash::Device,
     kind: vk::GraphicsPipelineLibraryFlagsEXT,
@@ -489,6 +466,13 @@ where
     Ok(pipeline.map_err(|(_, err)| err)?[0])
 }
 
+fn shader_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
 slotmap::new_key_type! {
     pub struct RenderHandle;
     pub struct ComputeHandle;
@@ -498,54 +482,114 @@ pub struct PipelineArena {
     pub render_arena: SlotMap<RenderHandle, RenderPipeline>,
     pub compute_arena: SlotMap<ComputeHandle, ComputePipeline>,
     pub path_mapping: AHashMap<PathBuf, AHashSet<Either<RenderHandle, ComputeHandle>>>,
+    #[cfg(feature = "hot-reload")]
     pub shader_compiler: ShaderCompiler,
+    #[cfg(feature = "hot-reload")]
     pub file_watcher: Watcher,
+    #[cfg(feature = "hot-reload")]
+    shader_backends: ShaderBackends,
+    precompiled_shaders: Option<ShaderBundle>,
+    #[cfg(feature = "hot-reload")]
+    reloader: PipelineReloader,
+    #[cfg(feature = "hot-reload")]
+    retired_pipelines: Vec<(vk::Pipeline, u64)>,
+    #[cfg(feature = "hot-reload")]
+    retired_libraries: Vec<(Arc<PipelineLibrary>, u64)>,
+    #[cfg(feature = "hot-reload")]
+    frame_counter: u64,
     device: Arc<Device>,
 }
 
 impl PipelineArena {
-    pub fn new(device: &Arc<Device>, file_watcher: Watcher) -> Result<Self> {
+    #[cfg(feature = "hot-reload")]
+    pub fn new(
+        device: &Arc<Device>,
+        file_watcher: Watcher,
+        precompiled_shaders: Option<ShaderBundle>,
+    ) -> Result<Self> {
+        let shader_backends = ShaderBackends::default();
         Ok(Self {
             render_arena: SlotMap::with_key(),
             compute_arena: SlotMap::with_key(),
 
-            shader_compiler: ShaderCompiler::new(&file_watcher)?,
+            shader_compiler: ShaderCompiler::new(&file_watcher, &shader_backends)?,
+            reloader: PipelineReloader::new(device, &file_watcher, &shader_backends),
+            shader_backends,
             file_watcher,
+            precompiled_shaders,
+            path_mapping: AHashMap::new(),
+            retired_pipelines: vec![],
+            retired_libraries: vec![],
+            frame_counter: 0,
+            device: device.clone(),
+        })
+    }
+
+    #[cfg(not(feature = "hot-reload"))]
+    pub fn new(device: &Arc<Device>, precompiled_shaders: Option<ShaderBundle>) -> Result<Self> {
+        Ok(Self {
+            render_arena: SlotMap::with_key(),
+            compute_arena: SlotMap::with_key(),
             path_mapping: AHashMap::new(),
+            precompiled_shaders,
             device: device.clone(),
         })
     }
 
+    #[cfg(feature = "hot-reload")]
+    pub fn register_shader_backend(&mut self, backend: SharedShaderBackend) {
+        self.file_watcher.add_extensions(backend.extensions());
+        self.shader_backends.register(backend);
+    }
+
+    pub fn is_hot_reload_enabled(&self) -> bool {
+        cfg!(feature = "hot-reload") && self.precompiled_shaders.is_none()
+    }
+
+    fn load_shader(&mut self, shader_path: &Path, kind: ShaderKind) -> Result<(PathBuf, Vec<u32>)> {
+        if let Some(bundle) = &self.precompiled_shaders {
+            let code = bundle.get(shader_path, kind)?;
+            return Ok((shader_path.to_path_buf(), code.to_vec()));
+        }
+
+        #[cfg(feature = "hot-reload")]
+        {
+            let path = shader_path.canonicalize()?;
+            self.file_watcher.register_source(ShaderSource {
+                path: path.clone(),
+                kind,
+            })?;
+            let code = self.shader_compiler.compile(&path, kind)?;
+            Ok((path, code))
+        }
+        #[cfg(not(feature = "hot-reload"))]
+        bail!(
+            "Shader {} can't be compiled without the hot-reload feature",
+            shader_path.display()
+        )
+    }
+
     pub fn create_compute_pipeline(
         &mut self,
         shader_path: impl AsRef<Path>,
         push_constant_ranges: &[vk::PushConstantRange],
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
     ) -> Result<ComputeHandle> {
-        let path = shader_path.as_ref().canonicalize()?;
-        {
-            self.file_watcher.watch_file(&path)?;
-            let mut mapping = self.file_watcher.include_mapping.lock();
-            mapping
-                .entry(PathBuf::new().join(path.file_name().unwrap()))
-                .or_default()
-                .insert(ShaderSource {
-                    path: path.clone(),
-                    kind: ShaderKind::Compute,
-                });
-        }
+        let (path, cs_code) = self.load_shader(shader_path.as_ref(), ShaderKind::Compute)?;
         let pipeline = ComputePipeline::new(
             &self.device,
-            &self.shader_compiler,
             &path,
+            &cs_code,
             push_constant_ranges,
             descriptor_set_layouts,
         )?;
         let handle = self.compute_arena.insert(pipeline);
-        self.path_mapping
-            .entry(path)
-            .or_default()
-            .insert(Either::Right(handle));
+        if self.is_hot_reload_enabled() {
+            self.path_mapping
+                .entry(path)
+                .or_default()
+                .insert(Either::Right(handle));
+        }
         Ok(handle)
     }
 
@@ -558,41 +602,168 @@ impl PipelineArena {
         push_constant_ranges: &[vk::PushConstantRange],
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
     ) -> Result<RenderHandle> {
-        let vs_path = vertex_shader_desc.shader_path.canonicalize()?;
-        let fs_path = fragment_shader_desc.shader_path.canonicalize()?;
-        for (path, kind) in [
-            (vs_path.clone(), ShaderKind::Vertex),
-            (fs_path.clone(), ShaderKind::Fragment),
-        ] {
-            self.file_watcher.watch_file(&path)?;
-            let mut mapping = self.file_watcher.include_mapping.lock();
-            mapping
-                .entry(PathBuf::new().join(path.file_name().unwrap()))
-                .or_default()
-                .insert(ShaderSource { path, kind });
-        }
+        let (vs_path, vs_code) =
+            self.load_shader(&vertex_shader_desc.shader_path, ShaderKind::Vertex)?;
+        let (fs_path, fs_code) =
+            self.load_shader(&fragment_shader_desc.shader_path, ShaderKind::Fragment)?;
         let pipeline = RenderPipeline::new(
             &self.device,
-            &self.shader_compiler,
             vertex_input_desc,
             vertex_shader_desc,
+            &vs_code,
             fragment_shader_desc,
+            &fs_code,
             fragment_output_desc,
             push_constant_ranges,
             descriptor_set_layouts,
         )?;
         let handle = self.render_arena.insert(pipeline);
-        self.path_mapping
-            .entry(vs_path)
-            .or_default()
-            .insert(Either::Left(handle));
-        self.path_mapping
-            .entry(fs_path)
-            .or_default()
-            .insert(Either::Left(handle));
+        if self.is_hot_reload_enabled() {
+            for path in [vs_path, fs_path] {
+                self.path_mapping
+                    .entry(path)
+                    .or_default()
+                    .insert(Either::Left(handle));
+            }
+        }
         Ok(handle)
     }
 
+    #[cfg(feature = "hot-reload")]
+    pub fn schedule_reload(&mut self, source: &ShaderSource) -> Result<()> {
+        let Some(handles) = self.path_mapping.get(&source.path) else {
+            return Ok(());
+        };
+        // Nothing is marked dirty or submitted unless every pipeline can take the source
+        if source.kind == ShaderKind::Compute && handles.iter().any(Either::is_left) {
+            bail!("Supplied compute shader into the render pipeline!")
+        }
+        let mut jobs = Vec::with_capacity(handles.len());
+        for handle in handles {
+            match *handle {
+                Either::Left(handle) => {
+                    let pipeline = &mut self.render_arena[handle];
+                    match source.kind {
+                        ShaderKind::Vertex => pipeline.dirty_vertex = Some(source.path.clone()),
+                        ShaderKind::Fragment => pipeline.dirty_fragment = Some(source.path.clone()),
+                        ShaderKind::Compute => unreachable!(),
+                    }
+                    pipeline.generation += 1;
+                    jobs.push(ReloadJob::Render {
+                        handle,
+                        generation: pipeline.generation,
+                        layout: pipeline.layout,
+                        vertex_shader: pipeline
+                            .dirty_vertex
+                            .clone()
+                            .map(|path| (path, pipeline.vertex_shader_desc.clone())),
+                        fragment_shader: pipeline
+                            .dirty_fragment
+                            .clone()
+                            .map(|path| (path, pipeline.fragment_shader_desc.clone())),
+                        libraries: [
+                            pipeline.vertex_input_lib.clone(),
+                            pipeline.vertex_shader_lib.clone(),
+                            pipeline.fragment_shader_lib.clone(),
+                            pipeline.fragment_output_lib.clone(),
+                        ],
+                    });
+                }
+                Either::Right(handle) => {
+                    let pipeline = &mut self.compute_arena[handle];
+                    pipeline.generation += 1;
+                    jobs.push(ReloadJob::Compute {
+                        handle,
+                        generation: pipeline.generation,
+                        layout: pipeline.layout,
+                        shader_path: pipeline.shader_path.clone(),
+                    });
+                }
+            }
+        }
+        for job in jobs {
+            self.reloader.submit(job);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "hot-reload")]
+    pub fn process_reloads(&mut self, frames_in_flight: usize) -> Vec<Result<()>> {
+        self.frame_counter += 1;
+        let frame_counter = self.frame_counter;
+        self.retired_pipelines.retain(|&(pipeline, retired_at)| {
+            let in_flight = frame_counter - retired_at <= frames_in_flight as u64;
+            if !in_flight {
+                unsafe { self.device.destroy_pipeline(pipeline, None) };
+            }
+            in_flight
+        });
+        // Jobs still linking against a retired library keep it alive past this point
+        self.retired_libraries
+            .retain(|(_, retired_at)| frame_counter - retired_at <= frames_in_flight as u64);
+
+        let mut outcomes = vec![];
+        for result in self.reloader.try_iter() {
+            match result {
+                ReloadResult::Compute {
+                    handle,
+                    generation,
+                    pipeline,
+                } => {
+                    let current = self.compute_arena.get_mut(handle);
+                    match (current, pipeline) {
+                        (Some(current), Ok(pipeline)) if current.generation == generation => {
+                            let old = std::mem::replace(&mut current.pipeline, pipeline);
+                            self.retired_pipelines.push((old, frame_counter));
+                            current.name_pipeline();
+                            outcomes.push(Ok(()));
+                        }
+                        (_, Ok(pipeline)) => unsafe {
+                            self.device.destroy_pipeline(pipeline, None)
+                        },
+                        (Some(current), Err(err)) if current.generation == generation => {
+                            outcomes.push(Err(err))
+                        }
+                        (_, Err(_)) => {}
+                    }
+                }
+                ReloadResult::Render {
+                    handle,
+                    generation,
+                    libraries,
+                } => {
+                    let current = self.render_arena.get_mut(handle);
+                    match (current, libraries) {
+                        (Some(current), Ok(libs)) if current.generation == generation => {
+                            if let Some(lib) = libs.vertex_shader_lib {
+                                let lib = Arc::new(lib);
+                                let old = std::mem::replace(&mut current.vertex_shader_lib, lib);
+                                self.retired_libraries.push((old, frame_counter));
+                                current.dirty_vertex = None;
+                            }
+                            if let Some(lib) = libs.fragment_shader_lib {
+                                let lib = Arc::new(lib);
+                                let old = std::mem::replace(&mut current.fragment_shader_lib, lib);
+                                self.retired_libraries.push((old, frame_counter));
+                                current.dirty_fragment = None;
+                            }
+                            let old = std::mem::replace(&mut current.pipeline, libs.pipeline);
+                            self.retired_pipelines.push((old, frame_counter));
+                            current.name_pipeline();
+                            outcomes.push(Ok(()));
+                        }
+                        (_, Ok(libs)) => libs.destroy(&self.device),
+                        (Some(current), Err(err)) if current.generation == generation => {
+                            outcomes.push(Err(err))
+                        }
+                        (_, Err(_)) => {}
+                    }
+                }
+            }
+        }
+        outcomes
+    }
+
     pub fn get_pipeline<H: Handle>(&self, handle: H) -> &H::Pipeline {
         handle.get_pipeline(self)
     }
@@ -602,6 +773,17 @@ impl PipelineArena {
     }
 }
 
+#[cfg(feature = "hot-reload")]
+impl Drop for PipelineArena {
+    fn drop(&mut self) {
+        self.reloader.shutdown();
+        for (pipeline, _) in self.retired_pipelines.drain(..) {
+            unsafe { self.device.destroy_pipeline(pipeline, None) };
+        }
+        self.retired_libraries.clear();
+    }
+}
+
 pub trait Handle {
     type Pipeline;
     fn get_pipeline(self, arena: &PipelineArena) -> &Self::Pipeline;
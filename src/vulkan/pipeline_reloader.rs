@@ -0,0 +1,238 @@
+use std::{path::PathBuf, sync::Arc, thread::JoinHandle};
+
+use anyhow::Result;
+use ash::vk;
+use crossbeam_channel::{Receiver, Sender, TryIter};
+
+use super::{
+    ComputeHandle, Device, FragmentShaderDesc, RenderHandle, RenderPipeline, VertexShaderDesc,
+    pipeline_arena::{
+        PipelineLibrary, create_compute_pipeline, create_fragment_shader_lib,
+        create_vertex_shader_lib,
+    },
+};
+use crate::{ShaderBackends, ShaderCompiler, ShaderKind, Watcher};
+
+const MAX_WORKERS: usize = 4;
+
+pub(crate) enum ReloadJob {
+    Compute {
+        handle: ComputeHandle,
+        generation: u64,
+        layout: vk::PipelineLayout,
+        shader_path: PathBuf,
+    },
+    Render {
+        handle: RenderHandle,
+        generation: u64,
+        layout: vk::PipelineLayout,
+        vertex_shader: Option<(PathBuf, VertexShaderDesc)>,
+        fragment_shader: Option<(PathBuf, FragmentShaderDesc)>,
+        libraries: [Arc<PipelineLibrary>; 4],
+    },
+}
+
+pub(crate) struct ReloadedLibraries {
+    pub vertex_shader_lib: Option<PipelineLibrary>,
+    pub fragment_shader_lib: Option<PipelineLibrary>,
+    pub pipeline: vk::Pipeline,
+}
+
+impl ReloadedLibraries {
+    pub fn destroy(self, device: &Device) {
+        unsafe { device.destroy_pipeline(self.pipeline, None) };
+    }
+}
+
+pub(crate) enum ReloadResult {
+    Compute {
+        handle: ComputeHandle,
+        generation: u64,
+        pipeline: Result<vk::Pipeline>,
+    },
+    Render {
+        handle: RenderHandle,
+        generation: u64,
+        libraries: Result<ReloadedLibraries>,
+    },
+}
+
+pub(crate) struct PipelineReloader {
+    jobs: Option<Sender<ReloadJob>>,
+    results: Receiver<ReloadResult>,
+    workers: Vec<JoinHandle<()>>,
+    device: Arc<Device>,
+}
+
+impl PipelineReloader {
+    pub fn new(device: &Arc<Device>, watcher: &Watcher, backends: &ShaderBackends) -> Self {
+        let (job_tx, job_rx) = crossbeam_channel::unbounded();
+        let (result_tx, result_rx) = crossbeam_channel::unbounded();
+
+        let worker_count = std::thread::available_parallelism()
+            .map_or(1, |n| n.get() / 2)
+            .clamp(1, MAX_WORKERS);
+        let workers = (0..worker_count)
+            .map(|_| {
+                let device = device.clone();
+                let watcher = watcher.clone();
+                let backends = backends.clone();
+                let job_rx = job_rx.clone();
+                let result_tx = result_tx.clone();
+                std::thread::spawn(move || {
+                    reload_thread(device, watcher, backends, job_rx, result_tx)
+                })
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_tx),
+            results: result_rx,
+            workers,
+            device: device.clone(),
+        }
+    }
+
+    pub fn submit(&self, job: ReloadJob) {
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs
+                .send(job)
+                .map_err(|err| tracing::error!("Shader workers are gone: {err}"));
+        }
+    }
+
+    pub fn try_iter(&self) -> TryIter<'_, ReloadResult> {
+        self.results.try_iter()
+    }
+
+    pub fn shutdown(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        for result in self.results.try_iter() {
+            match result {
+                ReloadResult::Compute {
+                    pipeline: Ok(pipeline),
+                    ..
+                } => unsafe { self.device.destroy_pipeline(pipeline, None) },
+                ReloadResult::Render {
+                    libraries: Ok(libraries),
+                    ..
+                } => libraries.destroy(&self.device),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for PipelineReloader {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn reload_thread(
+    device: Arc<Device>,
+    watcher: Watcher,
+    backends: ShaderBackends,
+    jobs: Receiver<ReloadJob>,
+    results: Sender<ReloadResult>,
+) {
+    let compiler = match ShaderCompiler::new(&watcher, &backends) {
+        Ok(compiler) => compiler,
+        Err(err) => {
+            tracing::error!("Failed to create shader compiler for the worker: {err}");
+            return;
+        }
+    };
+
+    while let Ok(job) = jobs.recv() {
+        let result = match job {
+            ReloadJob::Compute {
+                handle,
+                generation,
+                layout,
+                shader_path,
+            } => ReloadResult::Compute {
+                handle,
+                generation,
+                pipeline: compiler
+                    .compile(&shader_path, ShaderKind::Compute)
+                    .and_then(|cs_code| Ok(create_compute_pipeline(&device, layout, &cs_code)?)),
+            },
+            ReloadJob::Render {
+                handle,
+                generation,
+                layout,
+                vertex_shader,
+                fragment_shader,
+                libraries,
+            } => ReloadResult::Render {
+                handle,
+                generation,
+                libraries: rebuild_render_pipeline(
+                    &device,
+                    &compiler,
+                    layout,
+                    vertex_shader,
+                    fragment_shader,
+                    libraries,
+                ),
+            },
+        };
+        if results.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+fn rebuild_render_pipeline(
+    device: &Arc<Device>,
+    compiler: &ShaderCompiler,
+    layout: vk::PipelineLayout,
+    vertex_shader: Option<(PathBuf, VertexShaderDesc)>,
+    fragment_shader: Option<(PathBuf, FragmentShaderDesc)>,
+    libraries: [Arc<PipelineLibrary>; 4],
+) -> Result<ReloadedLibraries> {
+    let [
+        vertex_input_lib,
+        vertex_shader_lib,
+        fragment_shader_lib,
+        fragment_output_lib,
+    ] = libraries;
+
+    // New libraries are destroyed on drop if a later step fails
+    let new_vertex_lib = match vertex_shader {
+        Some((path, desc)) => {
+            let vs_code = compiler.compile(&path, ShaderKind::Vertex)?;
+            let lib = create_vertex_shader_lib(device, layout, &desc, &vs_code)?;
+            Some(PipelineLibrary::new(device, lib))
+        }
+        None => None,
+    };
+
+    let new_fragment_lib = match fragment_shader {
+        Some((path, desc)) => {
+            let fs_code = compiler.compile(&path, ShaderKind::Fragment)?;
+            let lib = create_fragment_shader_lib(device, layout, &desc, &fs_code)?;
+            Some(PipelineLibrary::new(device, lib))
+        }
+        None => None,
+    };
+
+    let pipeline = RenderPipeline::link_libraries(
+        device,
+        &layout,
+        &vertex_input_lib,
+        new_vertex_lib.as_deref().unwrap_or(&vertex_shader_lib),
+        new_fragment_lib.as_deref().unwrap_or(&fragment_shader_lib),
+        &fragment_output_lib,
+    )?;
+
+    Ok(ReloadedLibraries {
+        vertex_shader_lib: new_vertex_lib,
+        fragment_shader_lib: new_fragment_lib,
+        pipeline,
+    })
+}
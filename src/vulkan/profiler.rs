@@ -0,0 +1,472 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use ash::{prelude::VkResult, vk};
+
+const MAX_QUERIES: u32 = 512;
+const AVERAGE_WINDOW: usize = 64;
+const HISTORY_LEN: usize = 256;
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+const FRAME_SCOPE: &str = "Frame";
+
+#[derive(Clone, Debug)]
+pub struct GpuScope {
+    pub name: String,
+    pub start_ms: f64,
+    pub duration_ms: f64,
+    pub children: Vec<GpuScope>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GpuFrameTimings {
+    pub frame: u32,
+    pub scopes: Vec<GpuScope>,
+}
+
+impl GpuFrameTimings {
+    pub fn duration_ms(&self) -> f64 {
+        self.scopes.iter().map(|scope| scope.duration_ms).sum()
+    }
+}
+
+struct ScopeQuery {
+    name: String,
+    depth: usize,
+    begin: u32,
+    end: Option<u32>,
+}
+
+struct QuerySlot {
+    pool: vk::QueryPool,
+    scopes: Vec<ScopeQuery>,
+    next_query: u32,
+    frame: u32,
+    pending: bool,
+}
+
+struct RollingAverage {
+    samples: VecDeque<f64>,
+    sum: f64,
+}
+
+impl RollingAverage {
+    fn push(&mut self, sample: f64) {
+        if self.samples.len() == AVERAGE_WINDOW
+            && let Some(old) = self.samples.pop_front()
+        {
+            self.sum -= old;
+        }
+        self.samples.push_back(sample);
+        self.sum += sample;
+    }
+
+    fn average(&self) -> f64 {
+        self.sum / self.samples.len().max(1) as f64
+    }
+}
+
+pub struct GpuProfiler {
+    supported: bool,
+    enabled: bool,
+    timestamp_period: f64,
+    timestamp_mask: u64,
+    slots: Vec<QuerySlot>,
+    current: Option<(usize, vk::CommandBuffer)>,
+    stack: Vec<Option<usize>>,
+    origin: Option<u64>,
+    averages: Vec<(String, RollingAverage)>,
+    average_lookup: AHashMap<String, usize>,
+    history: VecDeque<GpuFrameTimings>,
+    last_report: Instant,
+}
+
+impl GpuProfiler {
+    pub(super) fn new(timestamp_valid_bits: u32, timestamp_period: f32) -> Self {
+        let timestamp_mask = match timestamp_valid_bits {
+            64.. => u64::MAX,
+            bits => (1 << bits) - 1,
+        };
+        let supported = timestamp_valid_bits > 0 && timestamp_period > 0.;
+        Self {
+            supported,
+            enabled: supported,
+            timestamp_period: timestamp_period as f64,
+            timestamp_mask,
+            slots: vec![],
+            current: None,
+            stack: vec![],
+            origin: None,
+            averages: vec![],
+            average_lookup: AHashMap::new(),
+            history: VecDeque::new(),
+            last_report: Instant::now(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled && self.supported;
+    }
+
+    pub fn last_frame(&self) -> Option<&GpuFrameTimings> {
+        self.history.back()
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = &GpuFrameTimings> {
+        self.history.iter()
+    }
+
+    // Scopes are keyed by their path from the frame root, e.g. "Frame/Bloom/Bloom Pass"
+    pub fn average_ms(&self, path: &str) -> Option<f64> {
+        let &idx = self.average_lookup.get(path)?;
+        Some(self.averages[idx].1.average())
+    }
+
+    pub fn averages(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.averages
+            .iter()
+            .map(|(path, average)| (path.as_str(), average.average()))
+    }
+
+    // Must be called once the slot's fence has signaled, results of the previous use are
+    // read back without waiting
+    pub(super) fn begin_frame(
+        &mut self,
+        device: &ash::Device,
+        cbuff: vk::CommandBuffer,
+        slot_idx: usize,
+        frame: u32,
+    ) -> VkResult<()> {
+        self.current = None;
+        self.stack.clear();
+        if !self.enabled {
+            return Ok(());
+        }
+
+        while self.slots.len() <= slot_idx {
+            let info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(MAX_QUERIES);
+            let pool = unsafe { device.create_query_pool(&info, None)? };
+            self.slots.push(QuerySlot {
+                pool,
+                scopes: vec![],
+                next_query: 0,
+                frame: 0,
+                pending: false,
+            });
+        }
+
+        if self.slots[slot_idx].pending {
+            self.read_back(device, slot_idx);
+        }
+
+        let slot = &mut self.slots[slot_idx];
+        slot.scopes.clear();
+        slot.next_query = 0;
+        slot.frame = frame;
+        slot.pending = true;
+        unsafe { device.cmd_reset_query_pool(cbuff, slot.pool, 0, MAX_QUERIES) };
+
+        self.current = Some((slot_idx, cbuff));
+        self.begin_scope(device, cbuff, FRAME_SCOPE);
+        Ok(())
+    }
+
+    pub(super) fn end_frame(&mut self, device: &ash::Device) {
+        if let Some((_, cbuff)) = self.current {
+            while !self.stack.is_empty() {
+                self.end_scope(device, cbuff);
+            }
+        }
+        self.current = None;
+    }
+
+    // Returns whether the scope has to be ended, markers on other command buffers are ignored
+    pub(super) fn begin_scope(
+        &mut self,
+        device: &ash::Device,
+        cbuff: vk::CommandBuffer,
+        name: &str,
+    ) -> bool {
+        let Some((slot_idx, current)) = self.current else {
+            return false;
+        };
+        if current != cbuff {
+            return false;
+        }
+
+        let depth = self.stack.iter().flatten().count();
+        let slot = &mut self.slots[slot_idx];
+        // Open scopes still need their end queries
+        if slot.next_query + depth as u32 + 2 > MAX_QUERIES {
+            self.stack.push(None);
+            return true;
+        }
+
+        let begin = slot.next_query;
+        slot.next_query += 1;
+        unsafe {
+            device.cmd_write_timestamp2(
+                cbuff,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                slot.pool,
+                begin,
+            )
+        };
+        slot.scopes.push(ScopeQuery {
+            name: name.to_string(),
+            depth,
+            begin,
+            end: None,
+        });
+        self.stack.push(Some(slot.scopes.len() - 1));
+        true
+    }
+
+    pub(super) fn end_scope(&mut self, device: &ash::Device, cbuff: vk::CommandBuffer) {
+        let Some((slot_idx, current)) = self.current else {
+            return;
+        };
+        if current != cbuff {
+            return;
+        }
+        let Some(Some(scope_idx)) = self.stack.pop() else {
+            return;
+        };
+
+        let slot = &mut self.slots[slot_idx];
+        let end = slot.next_query;
+        slot.next_query += 1;
+        unsafe {
+            device.cmd_write_timestamp2(
+                cbuff,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                slot.pool,
+                end,
+            )
+        };
+        slot.scopes[scope_idx].end = Some(end);
+    }
+
+    fn read_back(&mut self, device: &ash::Device, slot_idx: usize) {
+        let slot = &mut self.slots[slot_idx];
+        slot.pending = false;
+        if slot.next_query == 0 {
+            return;
+        }
+
+        let mut timestamps = vec![0u64; slot.next_query as usize];
+        let res = unsafe {
+            device.get_query_pool_results(
+                slot.pool,
+                0,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        // The frame was never submitted or is still running, drop it instead of stalling
+        if res.is_err() {
+            return;
+        }
+
+        let mask = self.timestamp_mask;
+        let origin = *self
+            .origin
+            .get_or_insert(timestamps[slot.scopes[0].begin as usize]);
+        let to_ms = |ticks: u64| {
+            (ticks.wrapping_sub(origin) & mask) as f64 * self.timestamp_period / 1_000_000.
+        };
+
+        let mut roots = vec![];
+        let mut stack: Vec<(GpuScope, usize)> = vec![];
+        let mut frame_totals: Vec<(String, f64)> = vec![];
+        for query in &slot.scopes {
+            let Some(end) = query.end else {
+                continue;
+            };
+            while stack.len() > query.depth {
+                close_scope(&mut stack, &mut roots, &mut frame_totals);
+            }
+            let start_ms = to_ms(timestamps[query.begin as usize]);
+            let end_ms = to_ms(timestamps[end as usize]);
+            let path = match stack.last() {
+                Some(&(_, parent)) => format!("{}/{}", frame_totals[parent].0, query.name),
+                None => query.name.clone(),
+            };
+            frame_totals.push((path, 0.));
+            let scope = GpuScope {
+                name: query.name.clone(),
+                start_ms,
+                duration_ms: (end_ms - start_ms).max(0.),
+                children: vec![],
+            };
+            stack.push((scope, frame_totals.len() - 1));
+        }
+        while !stack.is_empty() {
+            close_scope(&mut stack, &mut roots, &mut frame_totals);
+        }
+
+        // Scopes recorded several times per frame (e.g. fixed updates) are summed up
+        let mut merged: Vec<(String, f64)> = vec![];
+        for (path, duration) in frame_totals {
+            match merged
+                .iter_mut()
+                .find(|(merged_path, _)| *merged_path == path)
+            {
+                Some((_, total)) => *total += duration,
+                None => merged.push((path, duration)),
+            }
+        }
+        for (path, duration) in merged {
+            let idx = *self.average_lookup.entry(path.clone()).or_insert_with(|| {
+                self.averages.push((
+                    path,
+                    RollingAverage {
+                        samples: VecDeque::with_capacity(AVERAGE_WINDOW),
+                        sum: 0.,
+                    },
+                ));
+                self.averages.len() - 1
+            });
+            self.averages[idx].1.push(duration);
+        }
+
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(GpuFrameTimings {
+            frame: slot.frame,
+            scopes: roots,
+        });
+
+        if self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.last_report = Instant::now();
+            self.report();
+        }
+    }
+
+    fn report(&self) {
+        let mut report = String::new();
+        for (path, average) in self.averages() {
+            let depth = path.matches('/').count();
+            let name = path.rsplit('/').next().unwrap_or(path);
+            let _ = write!(
+                report,
+                "\n{:indent$}{name}: {average:.3}ms",
+                "",
+                indent = depth * 2
+            );
+        }
+        // Debug is the default filter, so periodic reports need RUST_LOG=gpu_profiler=trace
+        tracing::trace!(target: "gpu_profiler", "GPU timings (avg over {AVERAGE_WINDOW} frames):{report}");
+    }
+
+    // Chrome trace event format, open with chrome://tracing or ui.perfetto.dev
+    pub fn chrome_trace(&self) -> String {
+        fn push_events(json: &mut String, scope: &GpuScope, frame: u32, depth: usize) {
+            if !json.ends_with('[') {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "\n{{\"name\":\"{}\",\"cat\":\"gpu\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":0,\"tid\":0,\"args\":{{\"frame\":{frame},\"depth\":{depth}}}}}",
+                escape_json(&scope.name),
+                scope.start_ms * 1000.,
+                scope.duration_ms * 1000.,
+            );
+            for child in &scope.children {
+                push_events(json, child, frame, depth + 1);
+            }
+        }
+
+        let mut json = String::from("{\"displayTimeUnit\":\"ms\",\"traceEvents\":[");
+        for timings in &self.history {
+            for scope in &timings.scopes {
+                push_events(&mut json, scope, timings.frame, 0);
+            }
+        }
+        json.push_str("\n]}\n");
+        json
+    }
+
+    pub fn write_chrome_trace(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.chrome_trace())
+    }
+
+    pub(super) fn destroy(&mut self, device: &ash::Device) {
+        for slot in self.slots.drain(..) {
+            unsafe { device.destroy_query_pool(slot.pool, None) };
+        }
+    }
+}
+
+fn close_scope(
+    stack: &mut Vec<(GpuScope, usize)>,
+    roots: &mut Vec<GpuScope>,
+    frame_totals: &mut [(String, f64)],
+) {
+    let Some((scope, total_idx)) = stack.pop() else {
+        return;
+    };
+    frame_totals[total_idx].1 = scope.duration_ms;
+    match stack.last_mut() {
+        Some((parent, _)) => parent.children.push(scope),
+        None => roots.push(scope),
+    }
+}
+
+fn escape_json(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_average_drops_old_samples() {
+        let mut average = RollingAverage {
+            samples: VecDeque::with_capacity(AVERAGE_WINDOW),
+            sum: 0.,
+        };
+        assert_eq!(average.average(), 0.);
+        average.push(2.);
+        average.push(4.);
+        assert_eq!(average.average(), 3.);
+
+        for _ in 0..AVERAGE_WINDOW {
+            average.push(1.);
+        }
+        assert_eq!(average.samples.len(), AVERAGE_WINDOW);
+        assert!((average.average() - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(escape_json("Bloom"), "Bloom");
+        assert_eq!(escape_json(r#"say "hi""#), r#"say \"hi\""#);
+        assert_eq!(escape_json(r"C:\shaders"), r"C:\\shaders");
+        assert_eq!(escape_json("a\nb\t"), r"a\u000ab\u0009");
+        assert_eq!(escape_json("Größe"), "Größe");
+    }
+}
@@ -0,0 +1,307 @@
+use std::sync::Arc;
+
+use ash::{prelude::VkResult, vk};
+
+use super::Device;
+
+const MAX_QUERIES: u32 = 64;
+
+const STATISTICS_FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+    vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+        | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw()
+        | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS.as_raw(),
+);
+const STATISTICS_COUNT: usize = STATISTICS_FLAGS.as_raw().count_ones() as usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryKind {
+    PipelineStatistics,
+    Occlusion,
+}
+
+// Counters come back in the bit order of the enabled flags
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+}
+
+impl PipelineStatistics {
+    fn from_raw(values: &[u64]) -> Self {
+        Self {
+            input_assembly_vertices: values[0],
+            input_assembly_primitives: values[1],
+            vertex_shader_invocations: values[2],
+            clipping_invocations: values[3],
+            clipping_primitives: values[4],
+            fragment_shader_invocations: values[5],
+            compute_shader_invocations: values[6],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct QueryResults {
+    pub frame: u32,
+    pub pipeline_statistics: Vec<(String, PipelineStatistics)>,
+    pub occlusion: Vec<(String, u64)>,
+}
+
+impl QueryResults {
+    pub fn statistics(&self, name: &str) -> Option<&PipelineStatistics> {
+        self.pipeline_statistics
+            .iter()
+            .find_map(|(query, stats)| (query == name).then_some(stats))
+    }
+
+    pub fn samples_passed(&self, name: &str) -> Option<u64> {
+        self.occlusion
+            .iter()
+            .find_map(|(query, samples)| (query == name).then_some(*samples))
+    }
+}
+
+struct QuerySlot {
+    statistics_pool: Option<vk::QueryPool>,
+    occlusion_pool: vk::QueryPool,
+    statistics: Vec<String>,
+    occlusion: Vec<String>,
+    frame: u32,
+    pending: bool,
+}
+
+pub struct GpuQueries {
+    statistics_supported: bool,
+    precise_occlusion: bool,
+    slots: Vec<QuerySlot>,
+    current: Option<(usize, vk::CommandBuffer)>,
+    latest: Option<QueryResults>,
+}
+
+impl GpuQueries {
+    pub(super) fn new(statistics_supported: bool, precise_occlusion: bool) -> Self {
+        Self {
+            statistics_supported,
+            precise_occlusion,
+            slots: vec![],
+            current: None,
+            latest: None,
+        }
+    }
+
+    pub fn statistics_supported(&self) -> bool {
+        self.statistics_supported
+    }
+
+    // Results of the most recent frame that finished on the GPU
+    pub fn last_results(&self) -> Option<&QueryResults> {
+        self.latest.as_ref()
+    }
+
+    pub(super) fn begin_frame(
+        &mut self,
+        device: &ash::Device,
+        cbuff: vk::CommandBuffer,
+        slot_idx: usize,
+        frame: u32,
+    ) -> VkResult<()> {
+        while self.slots.len() <= slot_idx {
+            let statistics_pool = match self.statistics_supported {
+                true => {
+                    let info = vk::QueryPoolCreateInfo::default()
+                        .query_type(vk::QueryType::PIPELINE_STATISTICS)
+                        .pipeline_statistics(STATISTICS_FLAGS)
+                        .query_count(MAX_QUERIES);
+                    Some(unsafe { device.create_query_pool(&info, None)? })
+                }
+                false => None,
+            };
+            let info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::OCCLUSION)
+                .query_count(MAX_QUERIES);
+            let occlusion_pool = unsafe { device.create_query_pool(&info, None)? };
+            self.slots.push(QuerySlot {
+                statistics_pool,
+                occlusion_pool,
+                statistics: vec![],
+                occlusion: vec![],
+                frame: 0,
+                pending: false,
+            });
+        }
+
+        if self.slots[slot_idx].pending {
+            self.read_back(device, slot_idx);
+        }
+
+        let slot = &mut self.slots[slot_idx];
+        slot.statistics.clear();
+        slot.occlusion.clear();
+        slot.frame = frame;
+        slot.pending = true;
+        unsafe {
+            if let Some(pool) = slot.statistics_pool {
+                device.cmd_reset_query_pool(cbuff, pool, 0, MAX_QUERIES);
+            }
+            device.cmd_reset_query_pool(cbuff, slot.occlusion_pool, 0, MAX_QUERIES);
+        }
+        self.current = Some((slot_idx, cbuff));
+        Ok(())
+    }
+
+    pub(super) fn end_frame(&mut self) {
+        self.current = None;
+    }
+
+    pub(super) fn begin_query(
+        &mut self,
+        device: &ash::Device,
+        cbuff: vk::CommandBuffer,
+        kind: QueryKind,
+        name: &str,
+    ) -> Option<u32> {
+        let (slot_idx, current) = self.current?;
+        if current != cbuff {
+            return None;
+        }
+        let slot = &mut self.slots[slot_idx];
+        let (pool, names, flags) = match kind {
+            QueryKind::PipelineStatistics => (
+                slot.statistics_pool?,
+                &mut slot.statistics,
+                vk::QueryControlFlags::empty(),
+            ),
+            QueryKind::Occlusion => (
+                slot.occlusion_pool,
+                &mut slot.occlusion,
+                match self.precise_occlusion {
+                    true => vk::QueryControlFlags::PRECISE,
+                    false => vk::QueryControlFlags::empty(),
+                },
+            ),
+        };
+        let query = names.len() as u32;
+        if query >= MAX_QUERIES {
+            return None;
+        }
+        names.push(name.to_string());
+        unsafe { device.cmd_begin_query(cbuff, pool, query, flags) };
+        Some(query)
+    }
+
+    pub(super) fn end_query(
+        &mut self,
+        device: &ash::Device,
+        cbuff: vk::CommandBuffer,
+        kind: QueryKind,
+        query: u32,
+    ) {
+        let Some((slot_idx, _)) = self.current else {
+            return;
+        };
+        let slot = &self.slots[slot_idx];
+        let pool = match kind {
+            QueryKind::PipelineStatistics => slot.statistics_pool,
+            QueryKind::Occlusion => Some(slot.occlusion_pool),
+        };
+        if let Some(pool) = pool {
+            unsafe { device.cmd_end_query(cbuff, pool, query) };
+        }
+    }
+
+    fn read_back(&mut self, device: &ash::Device, slot_idx: usize) {
+        let slot = &mut self.slots[slot_idx];
+        slot.pending = false;
+        let mut results = self.latest.clone().unwrap_or_default();
+        let mut available = false;
+        let flags = vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY;
+
+        // Queries that aren't available yet keep their previous value instead of stalling
+        if let Some(pool) = slot.statistics_pool
+            && !slot.statistics.is_empty()
+        {
+            let mut values = vec![[0u64; STATISTICS_COUNT + 1]; slot.statistics.len()];
+            let res = unsafe { device.get_query_pool_results(pool, 0, &mut values, flags) };
+            if matches!(res, Ok(()) | Err(vk::Result::NOT_READY)) {
+                for (name, values) in slot.statistics.drain(..).zip(values) {
+                    if values[STATISTICS_COUNT] != 0 {
+                        let stats = PipelineStatistics::from_raw(&values);
+                        update_result(&mut results.pipeline_statistics, name, stats);
+                        available = true;
+                    }
+                }
+            }
+        }
+
+        if !slot.occlusion.is_empty() {
+            let mut values = vec![[0u64; 2]; slot.occlusion.len()];
+            let res = unsafe {
+                device.get_query_pool_results(slot.occlusion_pool, 0, &mut values, flags)
+            };
+            if matches!(res, Ok(()) | Err(vk::Result::NOT_READY)) {
+                for (name, [samples, availability]) in slot.occlusion.drain(..).zip(values) {
+                    if availability != 0 {
+                        update_result(&mut results.occlusion, name, samples);
+                        available = true;
+                    }
+                }
+            }
+        }
+
+        if available {
+            results.frame = slot.frame;
+            self.latest = Some(results);
+        }
+    }
+
+    pub(super) fn destroy(&mut self, device: &ash::Device) {
+        for slot in self.slots.drain(..) {
+            unsafe {
+                if let Some(pool) = slot.statistics_pool {
+                    device.destroy_query_pool(pool, None);
+                }
+                device.destroy_query_pool(slot.occlusion_pool, None);
+            }
+        }
+    }
+}
+
+fn update_result<T>(results: &mut Vec<(String, T)>, name: String, value: T) {
+    match results.iter_mut().find(|(query, _)| *query == name) {
+        Some((_, previous)) => *previous = value,
+        None => results.push((name, value)),
+    }
+}
+
+pub struct ScopedQuery {
+    pub(super) command_buffer: vk::CommandBuffer,
+    pub(super) device: Arc<Device>,
+    pub(super) query: Option<(QueryKind, u32)>,
+}
+
+impl ScopedQuery {
+    // None when the query didn't start, e.g. statistics are unsupported or the pool is full
+    pub fn index(&self) -> Option<u32> {
+        self.query.map(|(_, query)| query)
+    }
+}
+
+impl Drop for ScopedQuery {
+    fn drop(&mut self) {
+        if let Some((kind, query)) = self.query {
+            self.device
+                .queries
+                .lock()
+                .end_query(&self.device, self.command_buffer, kind, query);
+        }
+    }
+}
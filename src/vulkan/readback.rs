@@ -0,0 +1,372 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use ash::vk;
+use gpu_allocator::MemoryLocation;
+
+use super::{Buffer, Device, ImageHandle, ImageUsage, ManagedImage, TextureArena};
+
+const MAX_FREE_BUFFERS: usize = 16;
+
+pub struct ReadbackData<'a> {
+    pub bytes: &'a [u8],
+    // Only set for image readbacks, rows are tightly packed
+    pub format: Option<vk::Format>,
+    pub extent: vk::Extent3D,
+}
+
+type ReadbackCallback = Box<dyn FnOnce(ReadbackData<'_>)>;
+
+struct PendingReadback {
+    buffer: Buffer,
+    size: usize,
+    format: Option<vk::Format>,
+    extent: vk::Extent3D,
+    // Conversion target the copy was made from, kept alive until the copy is done
+    converted: Option<ManagedImage>,
+    callback: ReadbackCallback,
+}
+
+pub struct ReadbackQueue {
+    slots: Vec<Vec<PendingReadback>>,
+    current_slot: usize,
+    free_buffers: Vec<Buffer>,
+    device: Arc<Device>,
+}
+
+impl ReadbackQueue {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            slots: vec![],
+            current_slot: 0,
+            free_buffers: vec![],
+            device: device.clone(),
+        }
+    }
+
+    // Must be called once the frame's fence has signaled, readbacks recorded the last time the
+    // slot was used are complete and their callbacks run here
+    pub fn begin_frame(&mut self, frame_idx: usize) {
+        while self.slots.len() <= frame_idx {
+            self.slots.push(vec![]);
+        }
+        self.current_slot = frame_idx;
+        let pending = std::mem::take(&mut self.slots[frame_idx]);
+        for mut readback in pending {
+            if let Some(mapped) = readback.buffer.map_memory() {
+                (readback.callback)(ReadbackData {
+                    bytes: &mapped[..readback.size],
+                    format: readback.format,
+                    extent: readback.extent,
+                });
+            }
+            self.free_buffers.push(readback.buffer);
+            drop(readback.converted);
+        }
+        if self.free_buffers.len() > MAX_FREE_BUFFERS {
+            let excess = self.free_buffers.len() - MAX_FREE_BUFFERS;
+            self.free_buffers.drain(..excess);
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.slots.iter().map(Vec::len).sum()
+    }
+
+    pub fn read_buffer(
+        &mut self,
+        &cbuff: &vk::CommandBuffer,
+        buffer: vk::Buffer,
+        offset: u64,
+        size: u64,
+        callback: impl FnOnce(ReadbackData<'_>) + 'static,
+    ) -> Result<()> {
+        let readback_buffer = self.acquire_buffer(size)?;
+
+        let before_copy = vk::BufferMemoryBarrier2::default()
+            .buffer(buffer)
+            .offset(offset)
+            .size(size)
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ);
+        self.device.pipeline_barrier(
+            &cbuff,
+            &vk::DependencyInfo::default()
+                .buffer_memory_barriers(std::slice::from_ref(&before_copy)),
+        );
+        let region = vk::BufferCopy2::default()
+            .src_offset(offset)
+            .dst_offset(0)
+            .size(size);
+        let copy_info = vk::CopyBufferInfo2::default()
+            .src_buffer(buffer)
+            .dst_buffer(readback_buffer.buffer)
+            .regions(std::slice::from_ref(&region));
+        unsafe { self.device.cmd_copy_buffer2(cbuff, &copy_info) };
+        self.host_barrier(cbuff);
+
+        self.slots[self.current_slot].push(PendingReadback {
+            buffer: readback_buffer,
+            size: size as usize,
+            format: None,
+            extent: vk::Extent3D::default(),
+            converted: None,
+            callback: Box::new(callback),
+        });
+        Ok(())
+    }
+
+    // Copies the mip in the image's own format, external images have no format to go by
+    pub fn read_image(
+        &mut self,
+        &cbuff: &vk::CommandBuffer,
+        texture_arena: &mut TextureArena,
+        handle: ImageHandle,
+        mip_level: u32,
+        callback: impl FnOnce(ReadbackData<'_>) + 'static,
+    ) -> Result<()> {
+        let image = texture_arena.get_image(handle);
+        let info = image
+            .info
+            .context("Can't read back an image without create info")?;
+        let texel_size = format_texel_size(info.format)
+            .with_context(|| format!("Unsupported readback format: {:?}", info.format))?;
+        let extent = vk::Extent3D {
+            width: (info.extent.width >> mip_level).max(1),
+            height: (info.extent.height >> mip_level).max(1),
+            depth: (info.extent.depth >> mip_level).max(1),
+        };
+        let size = (extent.width * extent.height * extent.depth) as u64 * texel_size;
+        let readback_buffer = self.acquire_buffer(size)?;
+
+        texture_arena.transition_mip(&cbuff, handle, mip_level, ImageUsage::TransferSrc);
+        let image = texture_arena.get_image(handle);
+        let aspect_mask = match info.format {
+            vk::Format::D16_UNORM | vk::Format::D32_SFLOAT => vk::ImageAspectFlags::DEPTH,
+            _ => vk::ImageAspectFlags::COLOR,
+        };
+        let region = vk::BufferImageCopy2::default()
+            .image_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(aspect_mask)
+                    .mip_level(mip_level)
+                    .layer_count(1),
+            )
+            .image_extent(extent);
+        let copy_info = vk::CopyImageToBufferInfo2::default()
+            .src_image(image.inner)
+            .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .dst_buffer(readback_buffer.buffer)
+            .regions(std::slice::from_ref(&region));
+        unsafe { self.device.cmd_copy_image_to_buffer2(cbuff, &copy_info) };
+        self.host_barrier(cbuff);
+
+        self.slots[self.current_slot].push(PendingReadback {
+            buffer: readback_buffer,
+            size: size as usize,
+            format: Some(info.format),
+            extent,
+            converted: None,
+            callback: Box::new(callback),
+        });
+        Ok(())
+    }
+
+    // Blits the first mip into `format` before copying it out, the arena has no create info for
+    // external images like the swapchain's so the caller names the source format and extent
+    #[allow(clippy::too_many_arguments)]
+    pub fn read_image_converted(
+        &mut self,
+        &cbuff: &vk::CommandBuffer,
+        texture_arena: &mut TextureArena,
+        handle: ImageHandle,
+        src_format: vk::Format,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        callback: impl FnOnce(ReadbackData<'_>) + 'static,
+    ) -> Result<()> {
+        if !is_blit_source(src_format) {
+            bail!("Can't convert {src_format:?} for readback");
+        }
+        if !is_blit_destination(format) {
+            bail!("Can't convert to {format:?} for readback");
+        }
+        let texel_size = format_texel_size(format).context("Missing texel size")?;
+        let extent = vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        };
+        let size = (extent.width * extent.height) as u64 * texel_size;
+        let readback_buffer = self.acquire_buffer(size)?;
+        let converted = ManagedImage::new(
+            &self.device,
+            &vk::ImageCreateInfo::default()
+                .extent(extent)
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .mip_levels(1)
+                .array_layers(1),
+            MemoryLocation::GpuOnly,
+        )?;
+
+        texture_arena.transition_mip(&cbuff, handle, 0, ImageUsage::TransferSrc);
+        self.device.image_transition(
+            &cbuff,
+            &converted.image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        let subresource = vk::ImageSubresourceLayers::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(1);
+        let offsets = [
+            vk::Offset3D::default(),
+            vk::Offset3D {
+                x: extent.width as _,
+                y: extent.height as _,
+                z: 1,
+            },
+        ];
+        let region = vk::ImageBlit2::default()
+            .src_subresource(subresource)
+            .src_offsets(offsets)
+            .dst_subresource(subresource)
+            .dst_offsets(offsets);
+        let blit_info = vk::BlitImageInfo2::default()
+            .src_image(texture_arena.get_image(handle).inner)
+            .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .dst_image(converted.image)
+            .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .regions(std::slice::from_ref(&region))
+            .filter(vk::Filter::NEAREST);
+        unsafe { self.device.cmd_blit_image2(cbuff, &blit_info) };
+        self.device.image_transition(
+            &cbuff,
+            &converted.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        );
+
+        let region = vk::BufferImageCopy2::default()
+            .image_subresource(subresource)
+            .image_extent(extent);
+        let copy_info = vk::CopyImageToBufferInfo2::default()
+            .src_image(converted.image)
+            .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .dst_buffer(readback_buffer.buffer)
+            .regions(std::slice::from_ref(&region));
+        unsafe { self.device.cmd_copy_image_to_buffer2(cbuff, &copy_info) };
+        self.host_barrier(cbuff);
+
+        self.slots[self.current_slot].push(PendingReadback {
+            buffer: readback_buffer,
+            size: size as usize,
+            format: Some(format),
+            extent,
+            converted: Some(converted),
+            callback: Box::new(callback),
+        });
+        Ok(())
+    }
+
+    fn host_barrier(&self, cbuff: vk::CommandBuffer) {
+        let after_copy = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ);
+        self.device.pipeline_barrier(
+            &cbuff,
+            &vk::DependencyInfo::default().memory_barriers(std::slice::from_ref(&after_copy)),
+        );
+    }
+
+    // Reuses a finished buffer unless it would waste more than half of its memory
+    fn acquire_buffer(&mut self, size: u64) -> Result<Buffer> {
+        let reusable = self
+            .free_buffers
+            .iter()
+            .position(|buffer| buffer.size >= size && buffer.size / 2 <= size);
+        if let Some(idx) = reusable {
+            return Ok(self.free_buffers.swap_remove(idx));
+        }
+        self.device.create_buffer(
+            size.max(1),
+            vk::BufferUsageFlags::TRANSFER_DST,
+            MemoryLocation::GpuToCpu,
+        )
+    }
+}
+
+pub fn format_texel_size(format: vk::Format) -> Option<u64> {
+    use vk::Format as F;
+    let size = match format {
+        F::R8_UNORM | F::R8_SNORM | F::R8_UINT | F::R8_SINT | F::R8_SRGB => 1,
+        F::R8G8_UNORM | F::R8G8_SNORM | F::R8G8_UINT | F::R8G8_SINT => 2,
+        F::R16_UNORM | F::R16_SNORM | F::R16_UINT | F::R16_SINT | F::R16_SFLOAT => 2,
+        F::D16_UNORM => 2,
+        F::R8G8B8A8_UNORM
+        | F::R8G8B8A8_SNORM
+        | F::R8G8B8A8_UINT
+        | F::R8G8B8A8_SINT
+        | F::R8G8B8A8_SRGB
+        | F::B8G8R8A8_UNORM
+        | F::B8G8R8A8_SRGB => 4,
+        F::R16G16_UNORM | F::R16G16_SNORM | F::R16G16_UINT | F::R16G16_SINT => 4,
+        F::R16G16_SFLOAT => 4,
+        F::R32_UINT | F::R32_SINT | F::R32_SFLOAT | F::D32_SFLOAT => 4,
+        F::B10G11R11_UFLOAT_PACK32
+        | F::E5B9G9R9_UFLOAT_PACK32
+        | F::A2B10G10R10_UNORM_PACK32
+        | F::A2R10G10B10_UNORM_PACK32 => 4,
+        F::R16G16B16A16_UNORM
+        | F::R16G16B16A16_SNORM
+        | F::R16G16B16A16_UINT
+        | F::R16G16B16A16_SINT
+        | F::R16G16B16A16_SFLOAT => 8,
+        F::R32G32_UINT | F::R32G32_SINT | F::R32G32_SFLOAT => 8,
+        F::R32G32B32A32_UINT | F::R32G32B32A32_SINT | F::R32G32B32A32_SFLOAT => 16,
+        _ => return None,
+    };
+    Some(size)
+}
+
+// Formats the spec requires blit support for, integer and depth formats can't be converted
+fn is_blit_source(format: vk::Format) -> bool {
+    use vk::Format as F;
+    matches!(
+        format,
+        F::R8_UNORM
+            | F::R8G8_UNORM
+            | F::R8G8B8A8_UNORM
+            | F::R8G8B8A8_SRGB
+            | F::B8G8R8A8_UNORM
+            | F::B8G8R8A8_SRGB
+            | F::A2B10G10R10_UNORM_PACK32
+            | F::R16_SFLOAT
+            | F::R16G16_SFLOAT
+            | F::R16G16B16A16_SFLOAT
+            | F::R32_SFLOAT
+            | F::R32G32_SFLOAT
+            | F::R32G32B32A32_SFLOAT
+            | F::B10G11R11_UFLOAT_PACK32
+    )
+}
+
+fn is_blit_destination(format: vk::Format) -> bool {
+    use vk::Format as F;
+    matches!(
+        format,
+        F::R8G8B8A8_UNORM
+            | F::R8G8B8A8_SRGB
+            | F::B8G8R8A8_UNORM
+            | F::B8G8R8A8_SRGB
+            | F::R16G16B16A16_SFLOAT
+            | F::R32G32B32A32_SFLOAT
+    )
+}
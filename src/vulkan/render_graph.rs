@@ -0,0 +1,768 @@
+use std::{ops::RangeInclusive, sync::Arc};
+
+use ash::{prelude::VkResult, vk};
+use gpu_allocator::{MemoryLocation, vulkan::Allocation};
+use tracing::{debug, trace};
+
+use crate::{AppState, RenderContext};
+
+use super::{
+    AllocationResource, Device, FrameGuard, ImageHandle, ImageState, ImageTransition, ImageUsage,
+    TextureArena,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GraphImage {
+    Imported(ImageHandle),
+    Transient(usize),
+}
+
+impl From<ImageHandle> for GraphImage {
+    fn from(handle: ImageHandle) -> Self {
+        Self::Imported(handle)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransientImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub mip_levels: u32,
+    pub usage: vk::ImageUsageFlags,
+}
+
+impl TransientImageDesc {
+    pub fn new(format: vk::Format, extent: vk::Extent2D, usage: vk::ImageUsageFlags) -> Self {
+        Self {
+            format,
+            extent,
+            mip_levels: 1,
+            usage,
+        }
+    }
+
+    fn create_info(&self) -> vk::ImageCreateInfo<'static> {
+        vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(self.format)
+            .usage(self.usage)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(self.mip_levels)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferUsage {
+    ComputeRead,
+    ComputeWrite,
+    VertexRead,
+    FragmentRead,
+    IndirectRead,
+    TransferSrc,
+    TransferDst,
+}
+
+impl BufferUsage {
+    fn flags(self) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+        use vk::{AccessFlags2 as A, PipelineStageFlags2 as S};
+        match self {
+            Self::ComputeRead => (S::COMPUTE_SHADER, A::SHADER_READ),
+            Self::ComputeWrite => (S::COMPUTE_SHADER, A::SHADER_READ | A::SHADER_WRITE),
+            Self::VertexRead => (
+                S::VERTEX_INPUT | S::VERTEX_SHADER,
+                A::VERTEX_ATTRIBUTE_READ | A::INDEX_READ | A::SHADER_READ,
+            ),
+            Self::FragmentRead => (S::FRAGMENT_SHADER, A::SHADER_READ),
+            Self::IndirectRead => (S::DRAW_INDIRECT, A::INDIRECT_COMMAND_READ),
+            Self::TransferSrc => (S::TRANSFER, A::TRANSFER_READ),
+            Self::TransferDst => (S::TRANSFER, A::TRANSFER_WRITE),
+        }
+    }
+}
+
+struct ImageAccess {
+    image: GraphImage,
+    mip_level: Option<u32>,
+    usage: ImageUsage,
+    write: bool,
+}
+
+struct BufferAccess {
+    buffer: vk::Buffer,
+    usage: BufferUsage,
+    write: bool,
+}
+
+type RecordFn<'a> = Box<dyn FnOnce(&mut PassContext) + 'a>;
+
+struct PassNode<'a> {
+    name: String,
+    images: Vec<ImageAccess>,
+    buffers: Vec<BufferAccess>,
+    side_effect: bool,
+    record: Option<RecordFn<'a>>,
+}
+
+pub struct PassContext<'f> {
+    pub ctx: &'f RenderContext,
+    pub state: &'f mut AppState,
+    pub frame: &'f FrameGuard,
+    transient_images: &'f [Option<ImageHandle>],
+}
+
+impl PassContext<'_> {
+    pub fn image(&self, image: GraphImage) -> ImageHandle {
+        match image {
+            GraphImage::Imported(handle) => handle,
+            GraphImage::Transient(idx) => {
+                self.transient_images[idx].expect("Transient image is not used by any live pass")
+            }
+        }
+    }
+}
+
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    pass: PassNode<'a>,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    pub fn read(self, image: impl Into<GraphImage>, usage: ImageUsage) -> Self {
+        self.image_access(image.into(), None, usage, false)
+    }
+
+    pub fn write(self, image: impl Into<GraphImage>, usage: ImageUsage) -> Self {
+        self.image_access(image.into(), None, usage, true)
+    }
+
+    pub fn read_mip(self, image: impl Into<GraphImage>, mip_level: u32, usage: ImageUsage) -> Self {
+        self.image_access(image.into(), Some(mip_level), usage, false)
+    }
+
+    pub fn write_mip(
+        self,
+        image: impl Into<GraphImage>,
+        mip_level: u32,
+        usage: ImageUsage,
+    ) -> Self {
+        self.image_access(image.into(), Some(mip_level), usage, true)
+    }
+
+    pub fn read_buffer(mut self, buffer: vk::Buffer, usage: BufferUsage) -> Self {
+        self.pass.buffers.push(BufferAccess {
+            buffer,
+            usage,
+            write: false,
+        });
+        self
+    }
+
+    pub fn write_buffer(mut self, buffer: vk::Buffer, usage: BufferUsage) -> Self {
+        self.pass.buffers.push(BufferAccess {
+            buffer,
+            usage,
+            write: true,
+        });
+        self
+    }
+
+    // Keeps the pass alive even if nothing reads its outputs
+    pub fn side_effect(mut self) -> Self {
+        self.pass.side_effect = true;
+        self
+    }
+
+    pub fn record(mut self, record: impl FnOnce(&mut PassContext) + 'a) {
+        self.pass.record = Some(Box::new(record));
+        self.graph.passes.push(self.pass);
+    }
+
+    fn image_access(
+        mut self,
+        image: GraphImage,
+        mip_level: Option<u32>,
+        usage: ImageUsage,
+        write: bool,
+    ) -> Self {
+        self.pass.images.push(ImageAccess {
+            image,
+            mip_level,
+            usage,
+            write,
+        });
+        self
+    }
+}
+
+// Passes run in declaration order, the graph only culls them and inserts barriers
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    passes: Vec<PassNode<'a>>,
+    transients: Vec<(String, TransientImageDesc)>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn create_image(&mut self, name: &str, desc: TransientImageDesc) -> GraphImage {
+        self.transients.push((name.to_owned(), desc));
+        GraphImage::Transient(self.transients.len() - 1)
+    }
+
+    pub fn add_pass(&mut self, name: &str) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            graph: self,
+            pass: PassNode {
+                name: name.to_owned(),
+                images: vec![],
+                buffers: vec![],
+                side_effect: false,
+                record: None,
+            },
+        }
+    }
+
+    pub fn execute(
+        self,
+        ctx: &RenderContext,
+        state: &mut AppState,
+        frame: &FrameGuard,
+    ) -> VkResult<()> {
+        let RenderGraph {
+            mut passes,
+            transients,
+        } = self;
+
+        let alive = cull_passes(&passes, transients.len());
+        for (pass, _) in passes.iter().zip(&alive).filter(|(_, alive)| !**alive) {
+            trace!("Render graph culled pass: {}", pass.name);
+        }
+
+        let mut lifetimes: Vec<Option<RangeInclusive<usize>>> = vec![None; transients.len()];
+        for (i, pass) in passes.iter().enumerate().filter(|(i, _)| alive[*i]) {
+            for access in &pass.images {
+                if let GraphImage::Transient(idx) = access.image {
+                    let start = lifetimes[idx].as_ref().map_or(i, |range| *range.start());
+                    lifetimes[idx] = Some(start..=i);
+                }
+            }
+        }
+        let layout: Vec<_> = transients
+            .into_iter()
+            .zip(lifetimes)
+            .map(|((name, desc), lifetime)| TransientLayout {
+                name,
+                desc,
+                lifetime,
+            })
+            .collect();
+
+        state.transient_images.collect_garbage(frame.frame_idx);
+        if state.transient_images.layout != layout {
+            state
+                .transient_images
+                .rebuild(&mut state.texture_arena, layout, frame.frame_idx)?;
+        }
+        let transient_images = state.transient_images.images.clone();
+
+        let device = &ctx.device;
+        let cbuff = frame.command_buffer();
+        let mut buffer_states: Vec<(vk::Buffer, BufferState)> = vec![];
+        for (i, pass) in passes.iter_mut().enumerate() {
+            if !alive[i] {
+                continue;
+            }
+            let _marker = device.create_scoped_marker(cbuff, &pass.name);
+
+            for (idx, handle) in transient_images.iter().enumerate() {
+                let Some(handle) = *handle else { continue };
+                if state.transient_images.first_use(idx) != Some(i) {
+                    continue;
+                }
+                let previous = state.transient_images.previous_occupant(idx);
+                let after = combined_state(&state.texture_arena, previous);
+                state.texture_arena.discard(handle, after);
+            }
+
+            let transitions: Vec<_> = pass
+                .images
+                .iter()
+                .map(|access| ImageTransition {
+                    handle: match access.image {
+                        GraphImage::Imported(handle) => handle,
+                        GraphImage::Transient(idx) => transient_images[idx].unwrap(),
+                    },
+                    mip_level: access.mip_level,
+                    usage: access.usage,
+                })
+                .collect();
+            state.texture_arena.transitions(cbuff, &transitions);
+
+            let barriers: Vec<_> = pass
+                .buffers
+                .iter()
+                .filter_map(|access| {
+                    let idx = match buffer_states.iter().position(|(b, _)| *b == access.buffer) {
+                        Some(idx) => idx,
+                        None => {
+                            buffer_states.push((access.buffer, BufferState::UNKNOWN));
+                            buffer_states.len() - 1
+                        }
+                    };
+                    buffer_states[idx].1.transition_to(access).map(|prev| {
+                        let (stages, access_flags) = access.usage.flags();
+                        vk::BufferMemoryBarrier2::default()
+                            .buffer(access.buffer)
+                            .size(vk::WHOLE_SIZE)
+                            .src_stage_mask(prev.stages)
+                            .src_access_mask(prev.access)
+                            .dst_stage_mask(stages)
+                            .dst_access_mask(access_flags)
+                    })
+                })
+                .collect();
+            if !barriers.is_empty() {
+                device.pipeline_barrier(
+                    cbuff,
+                    &vk::DependencyInfo::default().buffer_memory_barriers(&barriers),
+                );
+            }
+
+            if let Some(record) = pass.record.take() {
+                let mut pass_ctx = PassContext {
+                    ctx,
+                    state,
+                    frame,
+                    transient_images: &transient_images,
+                };
+                record(&mut pass_ctx);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Walks passes backwards keeping the ones whose outputs are observable
+fn cull_passes(passes: &[PassNode], transient_count: usize) -> Vec<bool> {
+    let mut needed = vec![false; transient_count];
+    let mut alive = vec![false; passes.len()];
+    for (i, pass) in passes.iter().enumerate().rev() {
+        let observable = pass.side_effect
+            || pass.buffers.iter().any(|access| access.write)
+            || pass
+                .images
+                .iter()
+                .filter(|access| access.write)
+                .any(|access| match access.image {
+                    GraphImage::Imported(_) => true,
+                    GraphImage::Transient(idx) => needed[idx],
+                });
+        if !observable {
+            continue;
+        }
+        alive[i] = true;
+        for access in pass.images.iter().filter(|access| !access.write) {
+            if let GraphImage::Transient(idx) = access.image {
+                needed[idx] = true;
+            }
+        }
+    }
+    alive
+}
+
+fn combined_state(texture_arena: &TextureArena, handle: ImageHandle) -> ImageState {
+    let image = texture_arena.get_image(handle);
+    (0..image.mip_levels())
+        .map(|mip_level| image.state(mip_level))
+        .fold(ImageState::UNDEFINED, |acc, state| ImageState {
+            layout: vk::ImageLayout::UNDEFINED,
+            stages: acc.stages | state.stages,
+            access: acc.access | state.access,
+        })
+}
+
+#[derive(Clone, Copy)]
+struct BufferState {
+    stages: vk::PipelineStageFlags2,
+    access: vk::AccessFlags2,
+    written: bool,
+}
+
+impl BufferState {
+    // Buffers may have been written by anything recorded before the graph
+    const UNKNOWN: Self = Self {
+        stages: vk::PipelineStageFlags2::ALL_COMMANDS,
+        access: vk::AccessFlags2::MEMORY_WRITE,
+        written: true,
+    };
+
+    fn transition_to(&mut self, access: &BufferAccess) -> Option<BufferState> {
+        let (stages, access_flags) = access.usage.flags();
+        if !self.written && !access.write {
+            self.stages |= stages;
+            self.access |= access_flags;
+            return None;
+        }
+        let prev = *self;
+        *self = BufferState {
+            stages,
+            access: access_flags,
+            written: access.write,
+        };
+        Some(BufferState {
+            access: if prev.written {
+                prev.access
+            } else {
+                vk::AccessFlags2::NONE
+            },
+            ..prev
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+struct TransientLayout {
+    name: String,
+    desc: TransientImageDesc,
+    lifetime: Option<RangeInclusive<usize>>,
+}
+
+struct AliasBlock {
+    memory: Allocation,
+    // Sorted by the start of their lifetimes
+    occupants: Vec<usize>,
+}
+
+pub struct TransientImagePool {
+    layout: Vec<TransientLayout>,
+    images: Vec<Option<ImageHandle>>,
+    blocks: Vec<AliasBlock>,
+    block_of: Vec<Option<usize>>,
+    // Images are retired through the texture arena, their memory follows once the same frame
+    // slot comes around again and the arena has removed them
+    retired: Vec<Vec<AliasBlock>>,
+    device: Arc<Device>,
+}
+
+impl TransientImagePool {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            layout: vec![],
+            images: vec![],
+            blocks: vec![],
+            block_of: vec![],
+            retired: vec![],
+            device: device.clone(),
+        }
+    }
+
+    fn first_use(&self, idx: usize) -> Option<usize> {
+        self.layout[idx]
+            .lifetime
+            .as_ref()
+            .map(|lifetime| *lifetime.start())
+    }
+
+    // Wraps around to the last occupant, whose writes happened in the previous frame
+    fn previous_occupant(&self, idx: usize) -> ImageHandle {
+        let block = &self.blocks[self.block_of[idx].unwrap()];
+        let position = block.occupants.iter().position(|&i| i == idx).unwrap();
+        let previous = match position {
+            0 => *block.occupants.last().unwrap(),
+            _ => block.occupants[position - 1],
+        };
+        self.images[previous].unwrap()
+    }
+
+    fn collect_garbage(&mut self, frame_idx: usize) {
+        while self.retired.len() <= frame_idx {
+            self.retired.push(vec![]);
+        }
+        for block in std::mem::take(&mut self.retired[frame_idx]) {
+            self.device.dealloc_memory(block.memory);
+        }
+    }
+
+    fn rebuild(
+        &mut self,
+        texture_arena: &mut TextureArena,
+        layout: Vec<TransientLayout>,
+        frame_idx: usize,
+    ) -> VkResult<()> {
+        let mut created = vec![];
+        for entry in layout.iter() {
+            if entry.lifetime.is_none() {
+                created.push(None);
+                continue;
+            }
+            let info = entry.desc.create_info();
+            let image = unsafe { ash::Device::create_image(&self.device, &info, None)? };
+            let requirements = unsafe { self.device.get_image_memory_requirements(image) };
+            created.push(Some((image, info, requirements)));
+        }
+
+        let requirements: Vec<_> = created
+            .iter()
+            .map(|created| created.map(|(_, _, requirements)| requirements))
+            .collect();
+        let lifetimes: Vec<_> = layout.iter().map(|entry| entry.lifetime.clone()).collect();
+        let (groups, block_of) = alias_groups(&requirements, &lifetimes);
+
+        let mut blocks = vec![];
+        for (requirements, mut occupants) in groups {
+            let memory = self
+                .device
+                .alloc_memory(
+                    requirements,
+                    MemoryLocation::GpuOnly,
+                    false,
+                    AllocationResource::None,
+                )
+                .map_err(|_| vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)?;
+            for &idx in &occupants {
+                let (image, ..) = created[idx].unwrap();
+                unsafe {
+                    self.device
+                        .bind_image_memory(image, memory.memory(), memory.offset())?
+                };
+            }
+            occupants.sort_by_key(|&idx| *layout[idx].lifetime.as_ref().unwrap().start());
+            blocks.push(AliasBlock { memory, occupants });
+        }
+
+        // Images that only changed extent keep their handle, the previous image is retired instead
+        let mut retired: Vec<_> = self.images.iter().copied().flatten().collect();
+        let mut images = vec![];
+        for (idx, (entry, created)) in layout.iter().zip(created).enumerate() {
+            let Some((image, info, _)) = created else {
+                images.push(None);
+                continue;
+            };
+            let reused = self
+                .layout
+                .get(idx)
+                .zip(self.images.get(idx).copied().flatten());
+            let handle = match reused {
+                Some((old, handle))
+                    if old.name == entry.name
+                        && TransientImageDesc {
+                            extent: entry.desc.extent,
+                            ..old.desc
+                        } == entry.desc =>
+                {
+                    let previous =
+                        texture_arena.replace_aliased_image(handle, image, info, &entry.name)?;
+                    if let Some(slot) = retired.iter_mut().find(|retired| **retired == handle) {
+                        *slot = previous;
+                    }
+                    handle
+                }
+                _ => texture_arena.push_aliased_image(image, info, &entry.name)?,
+            };
+            images.push(Some(handle));
+        }
+        debug!(
+            "Render graph: {} transient images aliased into {} memory blocks",
+            images.iter().flatten().count(),
+            blocks.len()
+        );
+
+        for handle in retired {
+            texture_arena.retire_image(handle);
+        }
+        let old_blocks = std::mem::replace(&mut self.blocks, blocks);
+        self.retired[frame_idx].extend(old_blocks);
+        self.layout = layout;
+        self.images = images;
+        self.block_of = block_of;
+        Ok(())
+    }
+}
+
+type AliasGroup = (vk::MemoryRequirements, Vec<usize>);
+
+// Largest images first, each one goes into the first group it does not overlap in time
+fn alias_groups(
+    requirements: &[Option<vk::MemoryRequirements>],
+    lifetimes: &[Option<RangeInclusive<usize>>],
+) -> (Vec<AliasGroup>, Vec<Option<usize>>) {
+    let mut order: Vec<_> = (0..requirements.len())
+        .filter(|&idx| requirements[idx].is_some())
+        .collect();
+    order.sort_by_key(|&idx| std::cmp::Reverse(requirements[idx].unwrap().size));
+
+    let mut groups: Vec<AliasGroup> = vec![];
+    let mut block_of = vec![None; requirements.len()];
+    for idx in order {
+        let requirements = requirements[idx].unwrap();
+        let lifetime = lifetimes[idx].clone().unwrap();
+        let overlaps = |other: &usize| {
+            let other = lifetimes[*other].as_ref().unwrap();
+            lifetime.start() <= other.end() && other.start() <= lifetime.end()
+        };
+        let group = groups.iter().position(|(group, occupants)| {
+            group.memory_type_bits & requirements.memory_type_bits != 0
+                && group.size >= requirements.size
+                && !occupants.iter().any(overlaps)
+        });
+        let group = match group {
+            Some(group) => {
+                let (group_requirements, occupants) = &mut groups[group];
+                group_requirements.memory_type_bits &= requirements.memory_type_bits;
+                group_requirements.alignment =
+                    group_requirements.alignment.max(requirements.alignment);
+                occupants.push(idx);
+                group
+            }
+            None => {
+                groups.push((requirements, vec![idx]));
+                groups.len() - 1
+            }
+        };
+        block_of[idx] = Some(group);
+    }
+    (groups, block_of)
+}
+
+impl Drop for TransientImagePool {
+    fn drop(&mut self) {
+        let retired = self.retired.drain(..).flatten();
+        for block in self.blocks.drain(..).chain(retired) {
+            self.device.dealloc_memory(block.memory);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc() -> TransientImageDesc {
+        TransientImageDesc::new(
+            vk::Format::R16G16B16A16_SFLOAT,
+            vk::Extent2D {
+                width: 64,
+                height: 64,
+            },
+            vk::ImageUsageFlags::STORAGE,
+        )
+    }
+
+    fn requirements(size: u64, memory_type_bits: u32) -> Option<vk::MemoryRequirements> {
+        Some(vk::MemoryRequirements {
+            size,
+            alignment: 256,
+            memory_type_bits,
+        })
+    }
+
+    #[test]
+    fn culls_passes_nobody_reads() {
+        let output = ImageHandle::default();
+        let mut graph = RenderGraph::new();
+        let unread = graph.create_image("Unread", desc());
+        let used = graph.create_image("Used", desc());
+        graph
+            .add_pass("Dead")
+            .write(unread, ImageUsage::ComputeStorage)
+            .record(|_| {});
+        graph
+            .add_pass("Producer")
+            .write(used, ImageUsage::ComputeStorage)
+            .record(|_| {});
+        graph
+            .add_pass("Consumer")
+            .read(used, ImageUsage::ComputeSampled)
+            .write(output, ImageUsage::ComputeStorage)
+            .record(|_| {});
+        graph
+            .add_pass("Reads only")
+            .read(output, ImageUsage::ComputeSampled)
+            .record(|_| {});
+        graph.add_pass("Side effect").side_effect().record(|_| {});
+
+        let alive = cull_passes(&graph.passes, graph.transients.len());
+        assert_eq!(alive, [false, true, true, false, true]);
+    }
+
+    #[test]
+    fn culling_follows_transient_chains() {
+        let mut graph = RenderGraph::new();
+        let a = graph.create_image("A", desc());
+        let b = graph.create_image("B", desc());
+        graph
+            .add_pass("Write A")
+            .write(a, ImageUsage::ComputeStorage)
+            .record(|_| {});
+        graph
+            .add_pass("A to B")
+            .read(a, ImageUsage::ComputeSampled)
+            .write(b, ImageUsage::ComputeStorage)
+            .record(|_| {});
+        let alive = cull_passes(&graph.passes, graph.transients.len());
+        assert_eq!(alive, [false, false]);
+
+        graph
+            .add_pass("Write buffer")
+            .read(b, ImageUsage::ComputeSampled)
+            .write_buffer(vk::Buffer::null(), BufferUsage::ComputeWrite)
+            .record(|_| {});
+        let alive = cull_passes(&graph.passes, graph.transients.len());
+        assert_eq!(alive, [true, true, true]);
+    }
+
+    #[test]
+    fn disjoint_lifetimes_share_memory() {
+        let (groups, block_of) = alias_groups(
+            &[
+                requirements(1024, 0b11),
+                requirements(512, 0b01),
+                requirements(2048, 0b100),
+                None,
+            ],
+            &[Some(0..=1), Some(2..=3), Some(4..=4), None],
+        );
+        // The largest image comes first, the others fit behind it in time
+        assert_eq!(groups.len(), 2);
+        assert_eq!(block_of, [Some(1), Some(1), Some(0), None]);
+        assert_eq!(groups[1].1, [0, 1]);
+        assert_eq!(groups[1].0.memory_type_bits, 0b01);
+    }
+
+    #[test]
+    fn overlapping_lifetimes_get_their_own_memory() {
+        let (groups, block_of) = alias_groups(
+            &[
+                requirements(1024, !0),
+                requirements(1024, !0),
+                requirements(4096, !0),
+            ],
+            &[Some(0..=2), Some(2..=3), Some(3..=5)],
+        );
+        assert_eq!(groups.len(), 2);
+        assert_eq!(block_of, [Some(0), Some(1), Some(0)]);
+        assert_eq!(groups[0].0.size, 4096);
+    }
+
+    #[test]
+    fn groups_are_sized_by_their_largest_image() {
+        let (groups, block_of) = alias_groups(
+            &[requirements(1024, !0), requirements(4096, !0)],
+            &[Some(0..=0), Some(1..=1)],
+        );
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].0.size, 4096);
+        assert_eq!(block_of, [Some(0), Some(0)]);
+    }
+}
@@ -13,8 +13,15 @@ struct PendingWrite {
     buffer: vk::Buffer,
 }
 
-pub struct StagingWrite {
+struct StagingSlot {
     buffer: Buffer,
+    offset: usize,
+    retired: Vec<Buffer>,
+}
+
+pub struct StagingWrite {
+    slots: Vec<StagingSlot>,
+    current_slot: usize,
     intermediate_data: Vec<u8>,
     pending_writes: Vec<PendingWrite>,
     device: Arc<Device>,
@@ -22,17 +29,32 @@ pub struct StagingWrite {
 
 impl StagingWrite {
     pub fn new(device: &Arc<Device>) -> Result<Self> {
-        let buffer = device.create_buffer(
-            INITIAL_BUFFER_SIZE,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            MemoryLocation::CpuToGpu,
-        )?;
-        Ok(Self {
-            buffer,
+        let mut staging = Self {
+            slots: vec![],
+            current_slot: 0,
             intermediate_data: vec![],
             pending_writes: vec![],
             device: device.clone(),
-        })
+        };
+        staging.begin_frame(0)?;
+        Ok(staging)
+    }
+
+    // Must be called once the frame's fence has signaled, the slot's memory is reused afterwards
+    pub fn begin_frame(&mut self, frame_idx: usize) -> Result<()> {
+        while self.slots.len() <= frame_idx {
+            let buffer = self.create_staging_buffer(INITIAL_BUFFER_SIZE)?;
+            self.slots.push(StagingSlot {
+                buffer,
+                offset: 0,
+                retired: vec![],
+            });
+        }
+        let slot = &mut self.slots[frame_idx];
+        slot.offset = 0;
+        slot.retired.clear();
+        self.current_slot = frame_idx;
+        Ok(())
     }
 
     pub fn write_buffer(&mut self, buffer: vk::Buffer, data: &[u8]) {
@@ -48,8 +70,9 @@ impl StagingWrite {
     }
 
     pub fn reserve_storage(&mut self) -> Result<bool> {
-        let offset = self.intermediate_data.len();
-        if offset < self.buffer.size as usize {
+        let slot = &self.slots[self.current_slot];
+        let required = slot.offset + self.intermediate_data.len();
+        if required <= slot.buffer.size as usize {
             return Ok(false);
         }
 
@@ -58,15 +81,17 @@ impl StagingWrite {
             .device_properties
             .limits
             .max_storage_buffer_range;
-        let new_size = offset
+        let new_size = required
             .checked_next_power_of_two()
-            .unwrap_or(offset)
+            .unwrap_or(required)
             .min(max_buffer_size as usize);
-        self.buffer = self.device.create_buffer(
-            new_size as u64,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            MemoryLocation::CpuToGpu,
-        )?;
+        let buffer = self.create_staging_buffer(new_size as u64)?;
+
+        // Copies recorded earlier this frame still read from the old buffer
+        let slot = &mut self.slots[self.current_slot];
+        let old = std::mem::replace(&mut slot.buffer, buffer);
+        slot.retired.push(old);
+        slot.offset = 0;
 
         Ok(true)
     }
@@ -76,16 +101,52 @@ impl StagingWrite {
             return Ok(());
         }
         self.reserve_storage()?;
-        let mapped = self.buffer.map_memory().context("Failed to map memory")?;
-        mapped[..self.intermediate_data.len()].copy_from_slice(&self.intermediate_data);
+
+        let slot = &mut self.slots[self.current_slot];
+        let base = slot.offset;
+        let len = self.intermediate_data.len();
+        let mapped = slot.buffer.map_memory().context("Failed to map memory")?;
+        mapped[base..base + len].copy_from_slice(&self.intermediate_data);
+        slot.offset += len;
+
+        let before_copy = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE);
+        self.device.pipeline_barrier(
+            &cbuff,
+            &vk::DependencyInfo::default().memory_barriers(std::slice::from_ref(&before_copy)),
+        );
         for write in self.pending_writes.drain(..) {
+            let region = write
+                .region
+                .src_offset(write.region.src_offset + base as u64);
             let copy_info = vk::CopyBufferInfo2::default()
-                .src_buffer(self.buffer.buffer)
+                .src_buffer(slot.buffer.buffer)
                 .dst_buffer(write.buffer)
-                .regions(std::slice::from_ref(&write.region));
+                .regions(std::slice::from_ref(&region));
             unsafe { self.device.cmd_copy_buffer2(cbuff, &copy_info) };
         }
+        let after_copy = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE);
+        self.device.pipeline_barrier(
+            &cbuff,
+            &vk::DependencyInfo::default().memory_barriers(std::slice::from_ref(&after_copy)),
+        );
+
         self.intermediate_data.clear();
         Ok(())
     }
+
+    fn create_staging_buffer(&self, size: u64) -> Result<Buffer> {
+        self.device.create_buffer(
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        )
+    }
 }
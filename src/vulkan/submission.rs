@@ -0,0 +1,148 @@
+use ash::{prelude::VkResult, vk};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueType {
+    Graphics,
+    // Falls back to the graphics queue when there is no dedicated compute family
+    AsyncCompute,
+}
+
+// Point on a queue's timeline, reached once everything submitted up to it has finished
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncPoint {
+    pub semaphore: vk::Semaphore,
+    pub value: u64,
+}
+
+impl SyncPoint {
+    pub fn is_reached(&self, device: &ash::Device) -> VkResult<bool> {
+        let value = unsafe { device.get_semaphore_counter_value(self.semaphore)? };
+        Ok(value >= self.value)
+    }
+
+    pub fn wait(&self, device: &ash::Device, timeout: u64) -> VkResult<()> {
+        let semaphores = [self.semaphore];
+        let values = [self.value];
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(&semaphores)
+            .values(&values);
+        unsafe { device.wait_semaphores(&wait_info, timeout) }
+    }
+}
+
+#[derive(Default)]
+pub struct Submission {
+    command_buffers: Vec<vk::CommandBufferSubmitInfo<'static>>,
+    waits: Vec<vk::SemaphoreSubmitInfo<'static>>,
+    signals: Vec<vk::SemaphoreSubmitInfo<'static>>,
+    fence: vk::Fence,
+}
+
+impl Submission {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn command_buffer(mut self, command_buffer: vk::CommandBuffer) -> Self {
+        self.command_buffers
+            .push(vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer));
+        self
+    }
+
+    pub fn wait(mut self, point: SyncPoint, stages: vk::PipelineStageFlags2) -> Self {
+        self.waits.push(
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(point.semaphore)
+                .value(point.value)
+                .stage_mask(stages),
+        );
+        self
+    }
+
+    pub fn wait_binary(
+        mut self,
+        semaphore: vk::Semaphore,
+        stages: vk::PipelineStageFlags2,
+    ) -> Self {
+        self.waits.push(
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(semaphore)
+                .stage_mask(stages),
+        );
+        self
+    }
+
+    pub fn signal_binary(
+        mut self,
+        semaphore: vk::Semaphore,
+        stages: vk::PipelineStageFlags2,
+    ) -> Self {
+        self.signals.push(
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(semaphore)
+                .stage_mask(stages),
+        );
+        self
+    }
+
+    pub fn fence(mut self, fence: vk::Fence) -> Self {
+        self.fence = fence;
+        self
+    }
+}
+
+pub struct GpuQueue {
+    pub queue: vk::Queue,
+    pub family_idx: u32,
+    timeline: vk::Semaphore,
+    last_value: u64,
+}
+
+impl GpuQueue {
+    pub(super) fn new(device: &ash::Device, queue: vk::Queue, family_idx: u32) -> VkResult<Self> {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let info = vk::SemaphoreCreateInfo::default().push_next(&mut type_info);
+        let timeline = unsafe { device.create_semaphore(&info, None)? };
+        Ok(Self {
+            queue,
+            family_idx,
+            timeline,
+            last_value: 0,
+        })
+    }
+
+    pub fn last_submitted(&self) -> SyncPoint {
+        SyncPoint {
+            semaphore: self.timeline,
+            value: self.last_value,
+        }
+    }
+
+    // Every submission signals the queue timeline once all of its commands are done
+    pub fn submit(&mut self, device: &ash::Device, submission: Submission) -> VkResult<SyncPoint> {
+        let point = SyncPoint {
+            semaphore: self.timeline,
+            value: self.last_value + 1,
+        };
+        let mut signals = submission.signals;
+        signals.push(
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(point.semaphore)
+                .value(point.value)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS),
+        );
+        let submit_info = vk::SubmitInfo2::default()
+            .command_buffer_infos(&submission.command_buffers)
+            .wait_semaphore_infos(&submission.waits)
+            .signal_semaphore_infos(&signals);
+        unsafe { device.queue_submit2(self.queue, &[submit_info], submission.fence)? };
+        self.last_value = point.value;
+        Ok(point)
+    }
+
+    pub(super) fn destroy(&mut self, device: &ash::Device) {
+        unsafe { device.destroy_semaphore(self.timeline, None) };
+    }
+}
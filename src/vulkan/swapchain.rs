@@ -5,12 +5,75 @@ use ash::{
     prelude::VkResult,
     vk::{self, Extent2D},
 };
-use tracing::debug;
+use tracing::{debug, warn};
+
+use super::{Device, Frame, FrameGuard, ImageDimensions, QueueType, Submission, Surface};
+
+// Values match the COLOR_SPACE_* constants in shaders/color_space.glsl
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SwapchainColorSpace {
+    #[default]
+    Srgb,
+    // Linear Rec.709 primaries in R16G16B16A16_SFLOAT, 1.0 is 80 nits
+    ScRgb,
+    // PQ encoded Rec.2020 primaries in A2B10G10R10
+    Hdr10,
+}
+
+impl SwapchainColorSpace {
+    pub fn is_hdr(self) -> bool {
+        self != Self::Srgb
+    }
+
+    pub fn as_raw(self) -> u32 {
+        self as u32
+    }
+
+    fn matches(self, format: &vk::SurfaceFormatKHR) -> bool {
+        match self {
+            Self::Srgb => {
+                format.format == vk::Format::B8G8R8A8_UNORM
+                    && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+            }
+            Self::ScRgb => {
+                format.format == vk::Format::R16G16B16A16_SFLOAT
+                    && format.color_space == vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+            }
+            Self::Hdr10 => {
+                matches!(
+                    format.format,
+                    vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32
+                ) && format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SwapchainConfig {
+    // Falls back to FIFO, the only mode every surface has to support
+    pub present_mode: vk::PresentModeKHR,
+    pub frames_in_flight: usize,
+    // Defaults to one image more than the surface minimum
+    pub image_count: Option<u32>,
+    // HDR color spaces are used only when the surface offers them, falling back to sRGB
+    pub color_space: SwapchainColorSpace,
+}
 
-use super::{BASE_IMAGE_RANGE, Device, Frame, FrameGuard, ImageDimensions, Surface};
+impl Default for SwapchainConfig {
+    fn default() -> Self {
+        Self {
+            present_mode: vk::PresentModeKHR::FIFO,
+            frames_in_flight: 2,
+            image_count: None,
+            color_space: SwapchainColorSpace::Srgb,
+        }
+    }
+}
 
 pub struct Swapchain {
     pub format: vk::SurfaceFormatKHR,
+    pub color_space: SwapchainColorSpace,
     pub present_mode: vk::PresentModeKHR,
     pub extent: vk::Extent2D,
     pub frames: Vec<Option<Frame>>,
@@ -18,8 +81,12 @@ pub struct Swapchain {
     pub images: Vec<vk::Image>,
     pub loader: khr::swapchain::Device,
     pub inner: vk::SwapchainKHR,
+    config: SwapchainConfig,
+    requested_present_mode: vk::PresentModeKHR,
+    present_semaphores: Vec<vk::Semaphore>,
     reclaimed_semaphores: Vec<vk::Semaphore>,
     current_frame: usize,
+    current_image: usize,
     device: Arc<Device>,
 }
 
@@ -32,6 +99,34 @@ impl Swapchain {
         self.extent
     }
 
+    pub fn color_space(&self) -> SwapchainColorSpace {
+        self.color_space
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn config(&self) -> &SwapchainConfig {
+        &self.config
+    }
+
+    // Reflects the mode in use, which can differ from the request when it's unsupported
+    pub fn vsync(&self) -> bool {
+        is_vsync(self.present_mode)
+    }
+
+    // Takes effect on the next recreation
+    pub fn set_vsync(&mut self, enabled: bool) {
+        use vk::PresentModeKHR as PM;
+        let preferred = self.config.present_mode;
+        self.requested_present_mode = match (enabled, is_vsync(preferred)) {
+            (true, true) | (false, false) => preferred,
+            (true, false) => PM::FIFO,
+            (false, true) => PM::IMMEDIATE,
+        };
+    }
+
     pub fn image_dimensions(&self) -> ImageDimensions {
         let Extent2D { width, height } = self.extent();
         let memory_reqs = unsafe { self.device.get_image_memory_requirements(self.images[0]) };
@@ -44,106 +139,72 @@ impl Swapchain {
         swapchain_loader: khr::swapchain::Device,
         width: u32,
         height: u32,
+        config: SwapchainConfig,
     ) -> VkResult<Self> {
         let surface_info = surface.info(device);
 
-        let format = surface_info
-            .formats
+        let candidates: &[SwapchainColorSpace] = match config.color_space {
+            SwapchainColorSpace::Srgb => &[],
+            SwapchainColorSpace::ScRgb => &[SwapchainColorSpace::ScRgb, SwapchainColorSpace::Hdr10],
+            SwapchainColorSpace::Hdr10 => &[SwapchainColorSpace::Hdr10, SwapchainColorSpace::ScRgb],
+        };
+        let (color_space, format) = candidates
             .iter()
-            .find(|format| format.format == vk::Format::B8G8R8A8_UNORM)
-            .unwrap_or(&surface_info.formats[0]);
+            .find_map(|&color_space| {
+                let format = surface_info
+                    .formats
+                    .iter()
+                    .find(|format| color_space.matches(format))?;
+                Some((color_space, format))
+            })
+            .unwrap_or_else(|| {
+                let format = surface_info
+                    .formats
+                    .iter()
+                    .find(|format| SwapchainColorSpace::Srgb.matches(format))
+                    .unwrap_or(&surface_info.formats[0]);
+                (SwapchainColorSpace::Srgb, format)
+            });
+        if color_space != config.color_space {
+            warn!(
+                "Surface doesn't support {:?} output, falling back to {color_space:?}",
+                config.color_space
+            );
+        }
         debug!("Swapchain format: {:?}", format);
 
-        // Swapchain present mode
-        let present_mode = surface_info
-            .present_modes
-            .iter()
-            .cloned()
-            .find(|&present_mode| present_mode == vk::PresentModeKHR::FIFO)
-            .unwrap_or(surface_info.present_modes[0]);
-        debug!("Swapchain present mode: {:?}", present_mode);
-
-        let capabilities = surface_info.capabilities;
-
-        // Swapchain extent
-        let extent = {
-            let max = capabilities.max_image_extent;
-            let min = capabilities.min_image_extent;
-            let width = width.min(max.width).max(min.width);
-            let height = height.min(max.height).max(min.height);
-            vk::Extent2D { width, height }
-        };
-        debug!("Swapchain extent: {:?}", extent);
-
-        // Swapchain image count
-        let image_count = capabilities
-            .max_image_count
-            .min(3)
-            .max(capabilities.min_image_count);
-        debug!("Swapchain image count: {:?}", image_count);
-
-        let queue_family_index = [device.main_queue_family_idx];
-
-        // Swapchain
         assert!(
-            capabilities
+            surface_info
+                .capabilities
                 .supported_composite_alpha
                 .contains(vk::CompositeAlphaFlagsKHR::OPAQUE)
         );
-        let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
-            .surface(surface.inner)
-            .image_format(format.format)
-            .image_usage(
-                vk::ImageUsageFlags::COLOR_ATTACHMENT
-                    | vk::ImageUsageFlags::SAMPLED
-                    | vk::ImageUsageFlags::STORAGE
-                    | vk::ImageUsageFlags::TRANSFER_SRC,
-            )
-            .image_extent(extent)
-            .image_color_space(format.color_space)
-            .min_image_count(image_count)
-            .image_array_layers(1)
-            .queue_family_indices(&queue_family_index)
-            .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-            .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
-            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(present_mode)
-            .clipped(true);
-        let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None)? };
-
-        // Swapchain images and image views
-        let images = unsafe { swapchain_loader.get_swapchain_images(swapchain)? };
-        images
-            .iter()
-            .enumerate()
-            .for_each(|(i, &image)| device.name_object(image, &format!("Swapchain Image {i}")));
-
-        let views = images
-            .iter()
-            .map(|img| device.create_2d_view(img, format.format, 0))
-            .collect::<VkResult<Vec<_>>>()?;
-        views
-            .iter()
-            .enumerate()
-            .for_each(|(i, &view)| device.name_object(view, &format!("Swapchain View {i}")));
 
-        let frames = (0..images.len())
+        let frames = (0..config.frames_in_flight.max(1))
             .map(|_| Some(Frame::new(device)).transpose())
             .collect::<VkResult<Vec<Option<Frame>>>>()?;
+        debug!("Frames in flight: {}", frames.len());
 
-        Ok(Self {
+        let mut swapchain = Self {
             device: device.clone(),
             loader: swapchain_loader,
-            inner: swapchain,
-            present_mode,
-            extent,
+            inner: vk::SwapchainKHR::null(),
+            present_mode: config.present_mode,
+            requested_present_mode: config.present_mode,
+            extent: vk::Extent2D::default(),
             format: *format,
+            color_space,
             frames,
-            images,
-            views,
+            images: vec![],
+            views: vec![],
+            present_semaphores: vec![],
+            config,
             current_frame: 0,
+            current_image: 0,
             reclaimed_semaphores: vec![],
-        })
+        };
+        swapchain.recreate(surface, width, height)?;
+        Ok(swapchain)
     }
 
     pub fn recreate(&mut self, surface: &Surface, width: u32, height: u32) -> VkResult<()> {
@@ -167,6 +228,21 @@ impl Swapchain {
             vk::Extent2D { width, height }
         };
 
+        self.present_mode = select_present_mode(&info.present_modes, self.requested_present_mode);
+        debug!("Swapchain present mode: {:?}", self.present_mode);
+
+        // Zero max image count means there is no upper limit
+        let image_count = self
+            .config
+            .image_count
+            .unwrap_or(capabilities.min_image_count + 1)
+            .max(capabilities.min_image_count);
+        let image_count = match capabilities.max_image_count {
+            0 => image_count,
+            max => image_count.min(max),
+        };
+        debug!("Swapchain image count: {:?}", image_count);
+
         let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
             .surface(**surface)
             .old_swapchain(old_swapchain)
@@ -179,7 +255,7 @@ impl Swapchain {
             )
             .image_extent(self.extent)
             .image_color_space(self.format.color_space)
-            .min_image_count(self.images.len() as u32)
+            .min_image_count(image_count)
             .image_array_layers(1)
             .queue_family_indices(&queue_family_index)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
@@ -207,6 +283,13 @@ impl Swapchain {
                 .name_object(view, &format!("Swapchain View {i}"))
         });
 
+        // Presentation waits on these, so they belong to images rather than frames
+        while self.present_semaphores.len() < self.images.len() {
+            self.present_semaphores
+                .push(self.device.create_semaphore()?);
+        }
+        self.current_image = 0;
+
         Ok(())
     }
 
@@ -219,18 +302,25 @@ impl Swapchain {
             .and_then(Option::as_mut)
     }
     pub fn get_current_image(&self) -> &vk::Image {
-        &self.images[self.current_frame]
+        &self.images[self.current_image]
     }
     pub fn get_current_view(&self) -> &vk::ImageView {
-        &self.views[self.current_frame]
+        &self.views[self.current_image]
     }
 
     pub fn acquire_next_image(&mut self) -> VkResult<FrameGuard> {
+        let one_second = Duration::from_secs(1).as_nanos() as u64;
+        let frame_idx = self.current_frame;
+        let Some(frame) = &self.frames[frame_idx] else {
+            return Err(vk::Result::ERROR_UNKNOWN);
+        };
+        self.device
+            .wait_for_fences(&[frame.present_finished], true, one_second)?;
+
         let acquire_semaphore = self
             .reclaimed_semaphores
             .pop()
             .map_or_else(|| self.device.create_semaphore(), Ok)?;
-        let one_second = Duration::from_secs(1).as_nanos() as u64;
 
         let image_idx = match unsafe {
             self.loader.acquire_next_image(
@@ -247,8 +337,8 @@ impl Swapchain {
             }
             Err(e) => return Err(e),
         };
-        self.current_frame = image_idx;
-        let Some(mut frame) = self.frames[image_idx].take() else {
+        self.current_image = image_idx;
+        let Some(mut frame) = self.frames[frame_idx].take() else {
             return Err(vk::Result::ERROR_UNKNOWN);
         };
         self.reclaimed_semaphores.push(std::mem::replace(
@@ -256,25 +346,18 @@ impl Swapchain {
             acquire_semaphore,
         ));
 
-        self.device
-            .wait_for_fences(&[frame.present_finished], true, one_second)?;
         unsafe { self.device.reset_fences(&[frame.present_finished])? };
 
-        let command_buffer = self.device.start_command_buffer()?;
-
-        self.device.image_transition(
-            &command_buffer,
-            &self.images[image_idx],
-            vk::ImageLayout::UNDEFINED,
-            vk::ImageLayout::GENERAL,
-        );
+        let command_buffer = frame.begin_command_buffer(&self.device)?;
 
         Ok(FrameGuard {
             frame,
             command_buffer,
             extent: self.extent,
+            frame_idx,
             image_idx,
             device: self.device.clone(),
+            waits: vec![],
         })
     }
 
@@ -282,44 +365,38 @@ impl Swapchain {
         let frame = frame_guard.frame;
         let command_buffer = &frame_guard.command_buffer;
 
-        let image_barrier = vk::ImageMemoryBarrier2::default()
-            .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
-            .dst_stage_mask(vk::PipelineStageFlags2::BOTTOM_OF_PIPE)
-            .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
-            .dst_access_mask(vk::AccessFlags2::empty())
-            .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .new_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-            .image(self.images[frame_guard.image_idx])
-            .subresource_range(BASE_IMAGE_RANGE);
-        let dependency_info =
-            vk::DependencyInfo::default().image_memory_barriers(slice::from_ref(&image_barrier));
-        self.device
-            .pipeline_barrier(command_buffer, &dependency_info);
-
         self.device.end_command_buffer(command_buffer)?;
 
-        let wait_semaphores = [frame.image_available_semaphore];
-        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let signal_semaphores = [frame.render_finished_semaphore];
-        let submit_info = vk::SubmitInfo::default()
-            .wait_semaphores(&wait_semaphores)
-            .wait_dst_stage_mask(&wait_stages)
-            .command_buffers(slice::from_ref(command_buffer))
-            .signal_semaphores(&signal_semaphores);
-        unsafe {
-            self.device
-                .queue_submit(self.device.queue, &[submit_info], frame.present_finished)?
-        };
+        let present_semaphore = self.present_semaphores[frame_guard.image_idx];
+        let submission = frame_guard
+            .waits
+            .iter()
+            .fold(Submission::new(), |submission, &(point, stages)| {
+                submission.wait(point, stages)
+            })
+            .wait_binary(
+                frame.image_available_semaphore,
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            )
+            .command_buffer(*command_buffer)
+            .signal_binary(present_semaphore, vk::PipelineStageFlags2::ALL_COMMANDS)
+            .fence(frame.present_finished);
+        self.device.submit(QueueType::Graphics, submission)?;
 
         let image_indices = [frame_guard.image_idx as u32];
         let present_info = vk::PresentInfoKHR::default()
-            .wait_semaphores(&signal_semaphores)
+            .wait_semaphores(slice::from_ref(&present_semaphore))
             .swapchains(slice::from_ref(&self.inner))
             .image_indices(&image_indices);
 
-        self.frames[self.current_frame] = Some(frame);
+        self.frames[frame_guard.frame_idx] = Some(frame);
+        self.current_frame = (frame_guard.frame_idx + 1) % self.frames.len();
 
-        match unsafe { self.loader.queue_present(self.device.queue, &present_info) } {
+        let present = {
+            let queue = self.device.graphics_queue.lock();
+            unsafe { self.loader.queue_present(queue.queue, &present_info) }
+        };
+        match present {
             Ok(false) => Ok(()),
             Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
                 VkResult::Err(vk::Result::ERROR_OUT_OF_DATE_KHR)
@@ -329,6 +406,31 @@ impl Swapchain {
     }
 }
 
+fn is_vsync(present_mode: vk::PresentModeKHR) -> bool {
+    matches!(
+        present_mode,
+        vk::PresentModeKHR::FIFO | vk::PresentModeKHR::FIFO_RELAXED
+    )
+}
+
+fn select_present_mode(
+    supported: &[vk::PresentModeKHR],
+    preferred: vk::PresentModeKHR,
+) -> vk::PresentModeKHR {
+    use vk::PresentModeKHR as PM;
+    let candidates: &[PM] = match preferred {
+        PM::MAILBOX => &[PM::MAILBOX, PM::IMMEDIATE],
+        PM::IMMEDIATE => &[PM::IMMEDIATE, PM::MAILBOX],
+        PM::FIFO_RELAXED => &[PM::FIFO_RELAXED],
+        _ => &[],
+    };
+    candidates
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PM::FIFO)
+}
+
 impl Drop for Swapchain {
     fn drop(&mut self) {
         unsafe {
@@ -339,10 +441,50 @@ impl Drop for Swapchain {
             self.views.iter().for_each(|&view| {
                 self.device.destroy_image_view(view, None);
             });
-            self.reclaimed_semaphores.iter().for_each(|&sema| {
-                self.device.destroy_semaphore(sema, None);
-            });
+            self.reclaimed_semaphores
+                .iter()
+                .chain(&self.present_semaphores)
+                .for_each(|&sema| {
+                    self.device.destroy_semaphore(sema, None);
+                });
             self.loader.destroy_swapchain(self.inner, None);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vk::PresentModeKHR as PM;
+
+    #[test]
+    fn preferred_mode_when_supported() {
+        let all = [PM::FIFO, PM::FIFO_RELAXED, PM::MAILBOX, PM::IMMEDIATE];
+        for mode in all {
+            assert_eq!(select_present_mode(&all, mode), mode);
+        }
+    }
+
+    #[test]
+    fn low_latency_modes_fall_back_to_each_other() {
+        let supported = [PM::FIFO, PM::IMMEDIATE];
+        assert_eq!(select_present_mode(&supported, PM::MAILBOX), PM::IMMEDIATE);
+        let supported = [PM::FIFO, PM::MAILBOX];
+        assert_eq!(select_present_mode(&supported, PM::IMMEDIATE), PM::MAILBOX);
+    }
+
+    #[test]
+    fn fifo_is_the_last_resort() {
+        let supported = [PM::FIFO];
+        for mode in [PM::MAILBOX, PM::IMMEDIATE, PM::FIFO_RELAXED, PM::FIFO] {
+            assert_eq!(select_present_mode(&supported, mode), PM::FIFO);
+        }
+        // Relaxed fifo is only picked when asked for
+        let supported = [PM::FIFO, PM::FIFO_RELAXED];
+        assert_eq!(select_present_mode(&supported, PM::MAILBOX), PM::FIFO);
+        assert_eq!(
+            select_present_mode(&supported, PM::SHARED_DEMAND_REFRESH),
+            PM::FIFO
+        );
+    }
+}
@@ -10,7 +10,7 @@ use slotmap::{SecondaryMap, SlotMap};
 
 use crate::utils::align_to;
 
-use super::Device;
+use super::{Device, ImageState, ImageTransition, ImageUsage, QueueType};
 
 const SAMPLER_SET: u32 = 0;
 const IMAGE_SET: u32 = 1;
@@ -97,30 +97,42 @@ impl Drop for ManagedImage {
     }
 }
 
-const MAX_MIPCOUNT: usize = 8;
+pub const MAX_MIPCOUNT: usize = 8;
 
 slotmap::new_key_type! {
     pub struct ImageHandle;
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ScreenRelation {
     Identity,
     Half,
     Quarter,
+    // Arbitrary fraction of the swapchain size
+    Scale(f32),
+    // Follows the arena's render scale, for images the scene is rendered into
+    Render,
     None,
 }
 
 impl ScreenRelation {
-    pub fn as_f32(&self) -> Option<f32> {
+    pub fn as_f32(&self, render_scale: f32) -> Option<f32> {
         match self {
             Self::Identity => Some(1.),
             Self::Half => Some(0.5),
             Self::Quarter => Some(0.25),
+            Self::Scale(factor) => Some(*factor),
+            Self::Render => Some(render_scale),
             Self::None => None,
         }
     }
 }
 
+fn scale_extent(factor: f32, width: u32, height: u32) -> (u32, u32) {
+    let scale = |size: u32| ((factor * size as f32) as u32).max(1);
+    (scale(width), scale(height))
+}
+
 // TODO: Name Images
 #[derive(Debug)]
 pub struct Image {
@@ -129,6 +141,8 @@ pub struct Image {
     pub views: [Option<vk::ImageView>; MAX_MIPCOUNT],
     pub info: Option<vk::ImageCreateInfo<'static>>,
     memory: Option<Allocation>,
+    aliased: bool,
+    states: [ImageState; MAX_MIPCOUNT],
 }
 
 impl Image {
@@ -139,18 +153,101 @@ impl Image {
         }
     }
 
-    fn destroy(&mut self, device: &Device) {
-        if let Some(memory) = self.memory.take() {
-            device.destroy_image(self.inner, memory);
-            for view in self
-                .views
-                .iter()
-                .filter_map(|view| view.as_ref())
-                .filter(|view| !view.is_null())
-            {
-                unsafe { device.destroy_image_view(*view, None) };
+    pub fn mip_levels(&self) -> u32 {
+        self.info.map_or(1, |info| info.mip_levels)
+    }
+
+    pub fn state(&self, mip_level: u32) -> ImageState {
+        self.states[mip_level as usize]
+    }
+
+    fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        match self.info.map(|info| info.format) {
+            Some(
+                vk::Format::D16_UNORM | vk::Format::D32_SFLOAT | vk::Format::X8_D24_UNORM_PACK32,
+            ) => vk::ImageAspectFlags::DEPTH,
+            Some(
+                vk::Format::D16_UNORM_S8_UINT
+                | vk::Format::D24_UNORM_S8_UINT
+                | vk::Format::D32_SFLOAT_S8_UINT,
+            ) => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
+
+    fn push_barriers(
+        &mut self,
+        mip_levels: std::ops::Range<u32>,
+        usage: ImageUsage,
+        barriers: &mut Vec<vk::ImageMemoryBarrier2<'static>>,
+    ) {
+        let next = usage.state();
+        let aspect_mask = self.aspect_mask();
+        let mut pending: Option<(ImageState, u32, u32)> = None;
+        for mip_level in mip_levels {
+            let Some(prev) = self.states[mip_level as usize].transition_to(next) else {
+                continue;
+            };
+            // Neighbouring mips coming from the same state share a single barrier
+            match pending.as_mut() {
+                Some((state, base, count)) if *state == prev && *base + *count == mip_level => {
+                    *count += 1
+                }
+                _ => {
+                    if let Some((state, base, count)) = pending.take() {
+                        barriers.push(self.barrier(state, next, aspect_mask, base, count));
+                    }
+                    pending = Some((prev, mip_level, 1));
+                }
             }
         }
+        if let Some((state, base, count)) = pending {
+            barriers.push(self.barrier(state, next, aspect_mask, base, count));
+        }
+    }
+
+    fn barrier(
+        &self,
+        prev: ImageState,
+        next: ImageState,
+        aspect_mask: vk::ImageAspectFlags,
+        base_mip_level: u32,
+        level_count: u32,
+    ) -> vk::ImageMemoryBarrier2<'static> {
+        vk::ImageMemoryBarrier2::default()
+            .src_stage_mask(prev.stages)
+            .src_access_mask(prev.access)
+            .dst_stage_mask(next.stages)
+            .dst_access_mask(next.access)
+            .old_layout(prev.layout)
+            .new_layout(next.layout)
+            .image(self.inner)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level,
+                level_count,
+                base_array_layer: 0,
+                layer_count: vk::REMAINING_ARRAY_LAYERS,
+            })
+    }
+
+    fn destroy(&mut self, device: &Device) {
+        let memory = self.memory.take();
+        if memory.is_none() && !self.aliased {
+            return;
+        }
+        for view in self
+            .views
+            .iter()
+            .filter_map(|view| view.as_ref())
+            .filter(|view| !view.is_null())
+        {
+            unsafe { device.destroy_image_view(*view, None) };
+        }
+        match memory {
+            Some(memory) => device.destroy_image(self.inner, memory),
+            None => unsafe { ash::Device::destroy_image(device, self.inner, None) },
+        }
     }
 }
 
@@ -158,11 +255,15 @@ pub struct TextureArena {
     pub images: SlotMap<ImageHandle, Image>,
     pub sampled_indices: SecondaryMap<ImageHandle, [Option<u32>; MAX_MIPCOUNT]>,
     pub storage_indices: SecondaryMap<ImageHandle, [Option<u32>; MAX_MIPCOUNT]>,
-    last_sampled_idx: u32,
-    last_storage_idx: u32,
+    sampled_slots: DescriptorSlots,
+    storage_slots: DescriptorSlots,
 
-    screen_sized_images: SecondaryMap<ImageHandle, f32>,
+    screen_sized_images: SecondaryMap<ImageHandle, ScreenRelation>,
+    render_scale: f32,
     default_images: Vec<ImageHandle>,
+    // Removed once the frame slot they were retired in comes around again
+    retired: Vec<Vec<ImageHandle>>,
+    current_slot: usize,
 
     pub samplers: [vk::Sampler; SAMPLER_COUNT as usize],
 
@@ -331,11 +432,14 @@ impl TextureArena {
             sampled_indices: SecondaryMap::new(),
             storage_indices: SecondaryMap::new(),
 
-            last_sampled_idx: 0,
-            last_storage_idx: 0,
+            sampled_slots: DescriptorSlots::new(IMAGES_COUNT),
+            storage_slots: DescriptorSlots::new(STORAGE_COUNT),
 
             screen_sized_images: SecondaryMap::new(),
+            render_scale: 1.,
             default_images: vec![],
+            retired: vec![vec![]],
+            current_slot: 0,
 
             samplers,
 
@@ -412,6 +516,11 @@ impl TextureArena {
         Ok(texture_arena)
     }
 
+    // One of DUMMY_IMAGE_IDX, DITHER_IMAGE_IDX, NOISE_IMAGE_IDX or BLUE_IMAGE_IDX
+    pub fn default_image(&self, idx: usize) -> ImageHandle {
+        self.default_images[idx]
+    }
+
     pub fn get_image(&self, handle: ImageHandle) -> &Image {
         &self.images[handle]
     }
@@ -420,6 +529,123 @@ impl TextureArena {
         &mut self.images[handle]
     }
 
+    pub fn transition(
+        &mut self,
+        cbuff: &vk::CommandBuffer,
+        handle: ImageHandle,
+        usage: ImageUsage,
+    ) {
+        self.transitions(cbuff, &[ImageTransition::new(handle, usage)]);
+    }
+
+    pub fn transition_mip(
+        &mut self,
+        cbuff: &vk::CommandBuffer,
+        handle: ImageHandle,
+        mip_level: u32,
+        usage: ImageUsage,
+    ) {
+        self.transitions(cbuff, &[ImageTransition::mip(handle, mip_level, usage)]);
+    }
+
+    pub fn transitions(&mut self, cbuff: &vk::CommandBuffer, transitions: &[ImageTransition]) {
+        let mut barriers = vec![];
+        for transition in transitions {
+            let image = &mut self.images[transition.handle];
+            let mip_levels = match transition.mip_level {
+                Some(mip_level) => {
+                    assert!(mip_level < image.mip_levels());
+                    mip_level..mip_level + 1
+                }
+                None => 0..image.mip_levels(),
+            };
+            image.push_barriers(mip_levels, transition.usage, &mut barriers);
+        }
+        if !barriers.is_empty() {
+            self.device.pipeline_barrier(
+                cbuff,
+                &vk::DependencyInfo::default().image_memory_barriers(&barriers),
+            );
+        }
+    }
+
+    // Exclusive images keep their contents across queue families only through a release on the
+    // source queue followed by a matching acquire on the destination queue. `usage` is how the
+    // destination uses the image and has to be the same for both halves
+    pub fn release_ownership(
+        &mut self,
+        cbuff: &vk::CommandBuffer,
+        handle: ImageHandle,
+        usage: ImageUsage,
+        from: QueueType,
+        to: QueueType,
+    ) {
+        self.ownership_transfer(cbuff, handle, usage, from, to, true);
+    }
+
+    pub fn acquire_ownership(
+        &mut self,
+        cbuff: &vk::CommandBuffer,
+        handle: ImageHandle,
+        usage: ImageUsage,
+        from: QueueType,
+        to: QueueType,
+    ) {
+        self.ownership_transfer(cbuff, handle, usage, from, to, false);
+    }
+
+    fn ownership_transfer(
+        &mut self,
+        cbuff: &vk::CommandBuffer,
+        handle: ImageHandle,
+        usage: ImageUsage,
+        from: QueueType,
+        to: QueueType,
+        release: bool,
+    ) {
+        let src_family = self.device.queue_family_idx(from);
+        let dst_family = self.device.queue_family_idx(to);
+        // Within one family the acquire is an ordinary transition
+        if src_family == dst_family {
+            if !release {
+                self.transition(cbuff, handle, usage);
+            }
+            return;
+        }
+
+        let image = &mut self.images[handle];
+        let next = usage.state();
+        let aspect_mask = image.aspect_mask();
+        let mut barriers = vec![];
+        for mip_level in 0..image.mip_levels() {
+            // The release leaves the tracked state alone, so the acquire sees the same layout
+            let mut state = image.states[mip_level as usize];
+            let prev = state.transition_to(next).unwrap_or(ImageState {
+                access: vk::AccessFlags2::NONE,
+                ..state
+            });
+            let barrier = image
+                .barrier(prev, next, aspect_mask, mip_level, 1)
+                .src_queue_family_index(src_family)
+                .dst_queue_family_index(dst_family);
+            barriers.push(match release {
+                true => barrier
+                    .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+                    .dst_access_mask(vk::AccessFlags2::NONE),
+                false => {
+                    image.states[mip_level as usize] = next;
+                    barrier
+                        .src_stage_mask(vk::PipelineStageFlags2::NONE)
+                        .src_access_mask(vk::AccessFlags2::NONE)
+                }
+            });
+        }
+        self.device.pipeline_barrier(
+            cbuff,
+            &vk::DependencyInfo::default().image_memory_barriers(&barriers),
+        );
+    }
+
     pub fn get_sampled_idx(&mut self, handle: ImageHandle, mip_level: u32) -> u32 {
         if let Some(info) = self.images[handle].info {
             assert!(mip_level < info.mip_levels);
@@ -434,7 +660,7 @@ impl TextureArena {
                         let view = make_image_view(
                             &self.device,
                             &image.inner,
-                            image.info.unwrap().format,
+                            &image.info.unwrap(),
                             mip_level,
                         )
                         .unwrap();
@@ -443,10 +669,9 @@ impl TextureArena {
                     }
                 };
 
-                let sampled_idx = self.last_sampled_idx;
+                let sampled_idx = self.sampled_slots.alloc();
                 update_sampled_set(&self.device, &self.sampled_set, sampled_idx, &view);
                 self.sampled_indices[handle][mip_level as usize] = Some(sampled_idx);
-                self.last_sampled_idx += 1;
 
                 sampled_idx
             }
@@ -468,7 +693,7 @@ impl TextureArena {
                         let view = make_image_view(
                             &self.device,
                             &image.inner,
-                            image.info.unwrap().format,
+                            &image.info.unwrap(),
                             mip_level,
                         )
                         .unwrap();
@@ -477,31 +702,64 @@ impl TextureArena {
                     }
                 };
 
-                let storage_idx = self.last_storage_idx;
+                let storage_idx = self.storage_slots.alloc();
                 update_storage_set(&self.device, &self.storage_set, storage_idx, &view);
                 self.storage_indices[handle][mip_level as usize] = Some(storage_idx);
-                self.last_storage_idx += 1;
 
                 storage_idx
             }
         }
     }
 
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    // Size of ScreenRelation::Render images for the given swapchain extent
+    pub fn render_extent(&self, extent: vk::Extent2D) -> vk::Extent2D {
+        let (width, height) = scale_extent(self.render_scale, extent.width, extent.height);
+        vk::Extent2D { width, height }
+    }
+
+    // Recreates the ScreenRelation::Render images, they must not be in use by the device
+    pub fn set_render_scale(&mut self, scale: f32, width: u32, height: u32) -> Result<()> {
+        if scale == self.render_scale {
+            return Ok(());
+        }
+        self.render_scale = scale;
+        self.recreate_screen_images(width, height, |relation| relation == ScreenRelation::Render)
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) -> Result<()> {
-        for (handle, &factor) in self.screen_sized_images.iter() {
+        self.recreate_screen_images(width, height, |_| true)
+    }
+
+    fn recreate_screen_images(
+        &mut self,
+        width: u32,
+        height: u32,
+        filter: impl Fn(ScreenRelation) -> bool,
+    ) -> Result<()> {
+        for (handle, &relation) in self.screen_sized_images.iter() {
+            if !filter(relation) {
+                continue;
+            }
+            let Some(factor) = relation.as_f32(self.render_scale) else {
+                continue;
+            };
             let image = &mut self.images[handle];
             let Some(info) = image.info.as_mut() else {
                 continue;
             };
             let usage = info.usage;
-            info.extent.width = (factor * width as f32) as u32;
-            info.extent.height = (factor * height as f32) as u32;
+            (info.extent.width, info.extent.height) = scale_extent(factor, width, height);
 
             let (new_image, new_memory) =
                 self.device.create_image(info, MemoryLocation::GpuOnly)?;
             image.destroy(&self.device);
             image.inner = new_image;
             image.memory = Some(new_memory);
+            image.states = [ImageState::UNDEFINED; MAX_MIPCOUNT];
             if let Some(name) = image.name.as_ref() {
                 self.device.name_object(image.inner, name);
             }
@@ -515,7 +773,7 @@ impl TextureArena {
                 *view = make_image_view(
                     &self.device,
                     &image.inner,
-                    image.info.unwrap().format,
+                    &image.info.unwrap(),
                     mip_level as u32,
                 )?;
                 if let Some(name) = image.name.as_ref() {
@@ -526,10 +784,10 @@ impl TextureArena {
                 if let Some(idx) = self.sampled_indices[handle][mip_level] {
                     update_sampled_set(&self.device, &self.sampled_set, idx, view);
                 }
-                if usage.contains(vk::ImageUsageFlags::STORAGE) {
-                    if let Some(idx) = self.storage_indices[handle][mip_level] {
-                        update_storage_set(&self.device, &self.storage_set, idx, view);
-                    }
+                if usage.contains(vk::ImageUsageFlags::STORAGE)
+                    && let Some(idx) = self.storage_indices[handle][mip_level]
+                {
+                    update_storage_set(&self.device, &self.storage_set, idx, view);
                 }
             }
         }
@@ -546,9 +804,9 @@ impl TextureArena {
         if !data.is_empty() {
             info.usage |= vk::ImageUsageFlags::TRANSFER_DST;
         }
-        if let Some(factor) = screen_relation.as_f32() {
-            info.extent.width = (factor * info.extent.width as f32) as u32;
-            info.extent.height = (factor * info.extent.height as f32) as u32;
+        if let Some(factor) = screen_relation.as_f32(self.render_scale) {
+            (info.extent.width, info.extent.height) =
+                scale_extent(factor, info.extent.width, info.extent.height);
         }
         let (image, memory) = self.device.create_image(&info, MemoryLocation::GpuOnly)?;
         if let Some(name) = name {
@@ -596,11 +854,49 @@ impl TextureArena {
             })?;
         }
 
+        // The upload is fenced on the host, nothing is left to synchronize with
+        let states = if data.is_empty() {
+            ImageState::UNDEFINED
+        } else {
+            ImageState {
+                layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                stages: vk::PipelineStageFlags2::NONE,
+                access: vk::AccessFlags2::NONE,
+            }
+        };
+
+        let handle = self.insert_image(image, info, Some(memory), name, states)?;
+        if screen_relation != ScreenRelation::None {
+            self.screen_sized_images.insert(handle, screen_relation);
+        }
+
+        Ok(handle)
+    }
+
+    // The image has to be bound to memory owned by the caller, which must outlive it
+    pub fn push_aliased_image(
+        &mut self,
+        image: vk::Image,
+        info: vk::ImageCreateInfo<'static>,
+        name: &str,
+    ) -> VkResult<ImageHandle> {
+        self.device.name_object(image, name);
+        self.insert_image(image, info, None, Some(name), ImageState::UNDEFINED)
+    }
+
+    fn insert_image(
+        &mut self,
+        image: vk::Image,
+        info: vk::ImageCreateInfo<'static>,
+        memory: Option<Allocation>,
+        name: Option<&str>,
+        states: ImageState,
+    ) -> VkResult<ImageHandle> {
         let mut views = [None; MAX_MIPCOUNT];
         let mut sampled_indices = [None; MAX_MIPCOUNT];
         let mut storage_indices = [None; MAX_MIPCOUNT];
         for (i, view) in views.iter_mut().enumerate().take(info.mip_levels as usize) {
-            let new_view = make_image_view(&self.device, &image, info.format, i as u32)?;
+            let new_view = make_image_view(&self.device, &image, &info, i as u32)?;
             *view = Some(new_view);
             if let Some(name) = name {
                 self.device
@@ -608,16 +904,14 @@ impl TextureArena {
             }
 
             {
-                let sampled_idx = self.last_sampled_idx;
+                let sampled_idx = self.sampled_slots.alloc();
                 update_sampled_set(&self.device, &self.sampled_set, sampled_idx, &new_view);
                 sampled_indices[i] = Some(sampled_idx);
-                self.last_sampled_idx += 1;
             }
             if info.usage.contains(vk::ImageUsageFlags::STORAGE) {
-                let storage_idx = self.last_storage_idx;
+                let storage_idx = self.storage_slots.alloc();
                 update_storage_set(&self.device, &self.storage_set, storage_idx, &new_view);
                 storage_indices[i] = Some(storage_idx);
-                self.last_storage_idx += 1;
             }
         }
 
@@ -625,17 +919,82 @@ impl TextureArena {
             inner: image,
             views,
             info: Some(info),
-            memory: Some(memory),
+            aliased: memory.is_none(),
+            memory,
             name: name.map(|name| name.to_owned()),
+            states: [states; MAX_MIPCOUNT],
         });
         self.sampled_indices.insert(handle, sampled_indices);
         self.storage_indices.insert(handle, storage_indices);
 
-        if let Some(factor) = screen_relation.as_f32() {
-            self.screen_sized_images.insert(handle, factor);
+        Ok(handle)
+    }
+
+    // Must be called once the frame's fence has signaled, images retired the last time the slot
+    // was used are no longer referenced by any frame in flight
+    pub fn begin_frame(&mut self, frame_idx: usize) {
+        while self.retired.len() <= frame_idx {
+            self.retired.push(vec![]);
+        }
+        self.current_slot = frame_idx;
+        for handle in std::mem::take(&mut self.retired[frame_idx]) {
+            self.remove_image(handle);
         }
+    }
 
-        Ok(handle)
+    // Removes the image once the frames in flight are done with it
+    pub fn retire_image(&mut self, handle: ImageHandle) {
+        self.screen_sized_images.remove(handle);
+        self.retired[self.current_slot].push(handle);
+    }
+
+    // Descriptor slots are reused right away, only remove images the GPU is done with
+    pub fn remove_image(&mut self, handle: ImageHandle) {
+        if let Some(mut image) = self.images.remove(handle) {
+            image.destroy(&self.device);
+        }
+        if let Some(indices) = self.sampled_indices.remove(handle) {
+            self.sampled_slots.release(indices);
+        }
+        if let Some(indices) = self.storage_indices.remove(handle) {
+            self.storage_slots.release(indices);
+        }
+        self.screen_sized_images.remove(handle);
+    }
+
+    // Puts the image under an existing handle, which gets new descriptor slots. The previous
+    // image and its slots move to the returned handle, to be removed once frames using it are done
+    pub fn replace_aliased_image(
+        &mut self,
+        handle: ImageHandle,
+        image: vk::Image,
+        info: vk::ImageCreateInfo<'static>,
+        name: &str,
+    ) -> VkResult<ImageHandle> {
+        let previous = self.push_aliased_image(image, info, name)?;
+        let [a, b] = self.images.get_disjoint_mut([handle, previous]).unwrap();
+        std::mem::swap(a, b);
+        let [a, b] = self
+            .sampled_indices
+            .get_disjoint_mut([handle, previous])
+            .unwrap();
+        std::mem::swap(a, b);
+        let [a, b] = self
+            .storage_indices
+            .get_disjoint_mut([handle, previous])
+            .unwrap();
+        std::mem::swap(a, b);
+        Ok(previous)
+    }
+
+    // Forgets the contents of the image, later transitions still wait on `after`
+    pub fn discard(&mut self, handle: ImageHandle, after: ImageState) {
+        let image = &mut self.images[handle];
+        let state = ImageState {
+            layout: vk::ImageLayout::UNDEFINED,
+            ..after
+        };
+        image.states = [state; MAX_MIPCOUNT];
     }
 
     pub fn push_external_image(
@@ -650,25 +1009,25 @@ impl TextureArena {
             views,
             info: None,
             memory: None,
+            aliased: false,
             name: None,
+            states: [ImageState::ACQUIRED; MAX_MIPCOUNT],
         });
 
         {
-            let sampled_idx = self.last_sampled_idx;
+            let sampled_idx = self.sampled_slots.alloc();
             update_sampled_set(&self.device, &self.sampled_set, sampled_idx, &view);
             let mut indices = [None; MAX_MIPCOUNT];
             indices[0] = Some(sampled_idx);
             self.sampled_indices.insert(handle, indices);
-            self.last_sampled_idx += 1;
         }
 
         {
-            let storage_idx = self.last_storage_idx;
+            let storage_idx = self.storage_slots.alloc();
             update_storage_set(&self.device, &self.storage_set, storage_idx, &view);
             let mut indices = [None; MAX_MIPCOUNT];
             indices[0] = Some(storage_idx);
             self.storage_indices.insert(handle, indices);
-            self.last_storage_idx += 1;
         }
 
         Ok(handle)
@@ -683,6 +1042,7 @@ impl TextureArena {
         let image = &mut self.images[handle];
         image.inner = new_image;
         image.views[0] = Some(new_view);
+        image.states = [ImageState::ACQUIRED; MAX_MIPCOUNT];
 
         let sampled_idx = self.get_sampled_idx(handle, 0);
         update_sampled_set(&self.device, &self.sampled_set, sampled_idx, &new_view);
@@ -691,6 +1051,40 @@ impl TextureArena {
     }
 }
 
+// Indices into one bindless array, released ones are handed out again before growing
+struct DescriptorSlots {
+    next: u32,
+    capacity: u32,
+    free: Vec<u32>,
+}
+
+impl DescriptorSlots {
+    fn new(capacity: u32) -> Self {
+        Self {
+            next: 0,
+            capacity,
+            free: vec![],
+        }
+    }
+
+    fn alloc(&mut self) -> u32 {
+        if let Some(idx) = self.free.pop() {
+            return idx;
+        }
+        assert!(
+            self.next < self.capacity,
+            "Texture arena ran out of its {} descriptor slots",
+            self.capacity
+        );
+        self.next += 1;
+        self.next - 1
+    }
+
+    fn release(&mut self, indices: [Option<u32>; MAX_MIPCOUNT]) {
+        self.free.extend(indices.into_iter().flatten());
+    }
+}
+
 fn update_sampled_set(device: &Device, set: &vk::DescriptorSet, idx: u32, view: &vk::ImageView) {
     let image_info = vk::DescriptorImageInfo::default()
         .image_view(*view)
@@ -720,15 +1114,20 @@ fn update_storage_set(device: &Device, set: &vk::DescriptorSet, idx: u32, view:
 fn make_image_view(
     device: &Device,
     image: &vk::Image,
-    format: vk::Format,
+    info: &vk::ImageCreateInfo,
     base_mip_level: u32,
 ) -> VkResult<vk::ImageView> {
+    let view_type = match info.image_type {
+        vk::ImageType::TYPE_3D => vk::ImageViewType::TYPE_3D,
+        vk::ImageType::TYPE_1D => vk::ImageViewType::TYPE_1D,
+        _ => vk::ImageViewType::TYPE_2D,
+    };
     unsafe {
         device.create_image_view(
             &vk::ImageViewCreateInfo::default()
-                .view_type(vk::ImageViewType::TYPE_2D)
+                .view_type(view_type)
                 .image(*image)
-                .format(format)
+                .format(info.format)
                 .subresource_range(
                     vk::ImageSubresourceRange::default()
                         .aspect_mask(vk::ImageAspectFlags::COLOR)
@@ -768,3 +1167,43 @@ impl Drop for TextureArena {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_slots_are_reused_first() {
+        let mut slots = DescriptorSlots::new(8);
+        let allocated: Vec<_> = (0..4).map(|_| slots.alloc()).collect();
+        assert_eq!(allocated, [0, 1, 2, 3]);
+
+        let mut released = [None; MAX_MIPCOUNT];
+        released[0] = Some(1);
+        released[1] = Some(3);
+        slots.release(released);
+        let mut reused = [slots.alloc(), slots.alloc()];
+        reused.sort();
+        assert_eq!(reused, [1, 3]);
+        assert_eq!(slots.alloc(), 4);
+    }
+
+    #[test]
+    fn full_capacity_is_usable_after_releases() {
+        let mut slots = DescriptorSlots::new(2);
+        let first = slots.alloc();
+        slots.alloc();
+        let mut released = [None; MAX_MIPCOUNT];
+        released[0] = Some(first);
+        slots.release(released);
+        assert_eq!(slots.alloc(), first);
+    }
+
+    #[test]
+    #[should_panic(expected = "ran out")]
+    fn running_out_of_slots_panics() {
+        let mut slots = DescriptorSlots::new(1);
+        slots.alloc();
+        slots.alloc();
+    }
+}
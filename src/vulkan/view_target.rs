@@ -19,6 +19,16 @@ pub struct ViewTarget {
 
 impl ViewTarget {
     pub fn new(ctx: &RenderContext, state: &mut AppState, format: vk::Format) -> Result<Self> {
+        Self::with_relation(ctx, state, format, ScreenRelation::Identity)
+    }
+
+    // ScreenRelation::Render for a target the scene is rendered into before upscaling
+    pub fn with_relation(
+        ctx: &RenderContext,
+        state: &mut AppState,
+        format: vk::Format,
+        relation: ScreenRelation,
+    ) -> Result<Self> {
         let Extent2D { width, height } = ctx.swapchain.extent();
         let image_info = vk::ImageCreateInfo::default()
             .format(format)
@@ -33,18 +43,12 @@ impl ViewTarget {
             .mip_levels(1)
             .array_layers(1)
             .tiling(vk::ImageTiling::OPTIMAL);
-        let a = state.texture_arena.push_image(
-            image_info,
-            ScreenRelation::Identity,
-            &[],
-            Some("View Target A"),
-        )?;
-        let b = state.texture_arena.push_image(
-            image_info,
-            ScreenRelation::Identity,
-            &[],
-            Some("View Target B"),
-        )?;
+        let a = state
+            .texture_arena
+            .push_image(image_info, relation, &[], Some("View Target A"))?;
+        let b = state
+            .texture_arena
+            .push_image(image_info, relation, &[], Some("View Target B"))?;
 
         Ok(Self {
             images: [a, b],
@@ -57,7 +61,21 @@ impl ViewTarget {
         &self.images[idx as usize]
     }
 
-    pub fn post_process_write(&self) -> PostProcessWrite {
+    // Both images of the pair, the main one is either of them
+    pub fn images(&self) -> [ImageHandle; 2] {
+        self.images
+    }
+
+    // The pair the next `post_process_write` will hand out, without swapping
+    pub fn next_post_process_write(&self) -> PostProcessWrite<'_> {
+        let idx = self.main_image.load(Ordering::Relaxed) as usize;
+        PostProcessWrite {
+            source: &self.images[idx],
+            destination: &self.images[idx ^ 1],
+        }
+    }
+
+    pub fn post_process_write(&self) -> PostProcessWrite<'_> {
         let old_target = self.main_image.fetch_xor(1, Ordering::Relaxed);
         if old_target == 0 {
             PostProcessWrite {
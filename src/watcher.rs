@@ -11,30 +11,102 @@ use std::{
     time::Duration,
 };
 
-use crate::{ShaderSource, UserEvent};
+use crate::{SHADERC_EXTENSIONS, ShaderSource, UserEvent};
 
 use parking_lot::Mutex;
 
+#[derive(Default)]
+struct IncludeGraph {
+    sources: AHashMap<PathBuf, AHashSet<ShaderSource>>,
+    includes: AHashMap<PathBuf, AHashSet<PathBuf>>,
+    dependents: AHashMap<PathBuf, AHashSet<PathBuf>>,
+}
+
+impl IncludeGraph {
+    fn dependent_sources(&self, path: &Path) -> Vec<ShaderSource> {
+        let roots = self.dependents.get(path).into_iter().flatten();
+        std::iter::once(path)
+            .chain(roots.map(PathBuf::as_path))
+            .filter_map(|root| self.sources.get(root))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    // Returns the includes that gained their first dependent and the ones no longer watched
+    fn update_includes(
+        &mut self,
+        source_path: &Path,
+        includes: AHashSet<PathBuf>,
+        replace: bool,
+    ) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let old_includes = self.includes.remove(source_path).unwrap_or_default();
+        let (new_includes, stale_includes): (AHashSet<_>, Vec<_>) = if replace {
+            let stale = old_includes.difference(&includes).cloned().collect();
+            (includes, stale)
+        } else {
+            (old_includes.union(&includes).cloned().collect(), vec![])
+        };
+
+        let mut watch = vec![];
+        let mut unwatch = vec![];
+        for include in &new_includes {
+            let dependents = self.dependents.entry(include.clone()).or_default();
+            if dependents.is_empty() {
+                watch.push(include.clone());
+            }
+            dependents.insert(source_path.to_path_buf());
+        }
+        for include in stale_includes {
+            let Some(dependents) = self.dependents.get_mut(&include) else {
+                continue;
+            };
+            dependents.remove(source_path);
+            if dependents.is_empty() {
+                self.dependents.remove(&include);
+                if !self.sources.contains_key(&include) {
+                    unwatch.push(include);
+                }
+            }
+        }
+        self.includes
+            .insert(source_path.to_path_buf(), new_includes);
+        (watch, unwatch)
+    }
+}
+
 #[derive(Clone)]
 pub struct Watcher {
     pub watcher: Arc<Mutex<notify_debouncer_full::Debouncer<RecommendedWatcher, RecommendedCache>>>,
-    pub include_mapping: Arc<Mutex<AHashMap<PathBuf, AHashSet<ShaderSource>>>>,
+    include_graph: Arc<Mutex<IncludeGraph>>,
+    extensions: Arc<Mutex<AHashSet<String>>>,
 }
 
 impl Watcher {
     pub fn new(proxy: EventLoopProxy<UserEvent>) -> Result<Self> {
+        let extensions: AHashSet<_> = SHADERC_EXTENSIONS
+            .iter()
+            .map(|ext| ext.to_string())
+            .collect();
+        let extensions = Arc::new(Mutex::new(extensions));
         let watcher = notify_debouncer_full::new_debouncer(
             Duration::from_millis(350),
             None,
-            watch_callback(proxy),
+            watch_callback(proxy, extensions.clone()),
         )?;
 
         Ok(Self {
             watcher: Arc::new(Mutex::new(watcher)),
-            include_mapping: Arc::new(Mutex::new(AHashMap::new())),
+            include_graph: Arc::new(Mutex::new(IncludeGraph::default())),
+            extensions,
         })
     }
 
+    pub fn add_extensions(&self, extensions: &[&str]) {
+        let mut watched = self.extensions.lock();
+        watched.extend(extensions.iter().map(|ext| ext.to_string()));
+    }
+
     pub fn unwatch_file(&mut self, path: impl AsRef<Path>) -> Result<()> {
         let mut watcher = self.watcher.lock();
         watcher.unwatch(path.as_ref())?;
@@ -46,9 +118,41 @@ impl Watcher {
         watcher.watch(path.as_ref(), notify::RecursiveMode::NonRecursive)?;
         Ok(())
     }
+
+    pub fn register_source(&mut self, source: ShaderSource) -> Result<()> {
+        self.watch_file(&source.path)?;
+        let mut graph = self.include_graph.lock();
+        graph
+            .sources
+            .entry(source.path.clone())
+            .or_default()
+            .insert(source);
+        Ok(())
+    }
+
+    pub fn dependent_sources(&self, path: impl AsRef<Path>) -> Vec<ShaderSource> {
+        self.include_graph.lock().dependent_sources(path.as_ref())
+    }
+
+    pub fn update_includes(&self, source_path: &Path, includes: AHashSet<PathBuf>, replace: bool) {
+        let (watch, unwatch) =
+            self.include_graph
+                .lock()
+                .update_includes(source_path, includes, replace);
+        let mut watcher = self.watcher.lock();
+        for include in watch {
+            let _ = watcher.watch(&include, notify::RecursiveMode::NonRecursive);
+        }
+        for include in unwatch {
+            let _ = watcher.unwatch(&include);
+        }
+    }
 }
 
-fn watch_callback(proxy: EventLoopProxy<UserEvent>) -> impl FnMut(DebounceEventResult) {
+fn watch_callback(
+    proxy: EventLoopProxy<UserEvent>,
+    extensions: Arc<Mutex<AHashSet<String>>>,
+) -> impl FnMut(DebounceEventResult) {
     move |event| match event {
         Ok(events) => {
             if let Some(path) = events
@@ -56,20 +160,105 @@ fn watch_callback(proxy: EventLoopProxy<UserEvent>) -> impl FnMut(DebounceEventR
                 .filter(|e| matches!(e.event.kind, EventKind::Modify(_)))
                 .filter_map(|event| event.event.paths.into_iter().next())
                 .next()
+                && path
+                    .extension()
+                    .and_then(OsStr::to_str)
+                    .is_some_and(|ext| extensions.lock().contains(ext))
             {
-                if path.extension() == Some(OsStr::new("glsl"))
-                    || path.extension() == Some(OsStr::new("frag"))
-                    || path.extension() == Some(OsStr::new("vert"))
-                    || path.extension() == Some(OsStr::new("comp"))
-                {
-                    let _ = proxy
-                        .send_event(UserEvent::Glsl {
-                            path: path.canonicalize().unwrap(),
-                        })
-                        .map_err(|err| tracing::error!("Event Loop has been dropped: {err}"));
-                }
+                let _ = proxy
+                    .send_event(UserEvent::Shader {
+                        path: path.canonicalize().unwrap(),
+                    })
+                    .map_err(|err| tracing::error!("Event Loop has been dropped: {err}"));
             }
         }
         Err(errors) => tracing::error!("File watcher error: {errors:?}"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShaderKind;
+
+    fn paths(names: &[&str]) -> AHashSet<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    fn graph_with_source(path: &str) -> IncludeGraph {
+        let mut graph = IncludeGraph::default();
+        let source = ShaderSource {
+            path: PathBuf::from(path),
+            kind: ShaderKind::Compute,
+        };
+        graph
+            .sources
+            .entry(source.path.clone())
+            .or_default()
+            .insert(source);
+        graph
+    }
+
+    #[test]
+    fn replace_drops_stale_includes() {
+        let mut graph = graph_with_source("main.comp");
+        let source = Path::new("main.comp");
+
+        let (watch, unwatch) = graph.update_includes(source, paths(&["a.glsl", "b.glsl"]), true);
+        assert_eq!(watch.len(), 2);
+        assert!(unwatch.is_empty());
+
+        let (watch, unwatch) = graph.update_includes(source, paths(&["b.glsl", "c.glsl"]), true);
+        assert_eq!(watch, [PathBuf::from("c.glsl")]);
+        assert_eq!(unwatch, [PathBuf::from("a.glsl")]);
+        assert!(graph.dependent_sources(Path::new("a.glsl")).is_empty());
+        assert_eq!(graph.dependent_sources(Path::new("c.glsl")).len(), 1);
+    }
+
+    #[test]
+    fn failed_compile_keeps_known_includes() {
+        let mut graph = graph_with_source("main.comp");
+        let source = Path::new("main.comp");
+
+        graph.update_includes(source, paths(&["a.glsl", "b.glsl"]), true);
+        // A failed compile only sees the includes up to the error
+        let (watch, unwatch) = graph.update_includes(source, paths(&["c.glsl"]), false);
+        assert_eq!(watch, [PathBuf::from("c.glsl")]);
+        assert!(unwatch.is_empty());
+        assert_eq!(
+            graph.includes[source],
+            paths(&["a.glsl", "b.glsl", "c.glsl"])
+        );
+        for include in ["a.glsl", "b.glsl", "c.glsl"] {
+            let dependents = graph.dependent_sources(Path::new(include));
+            assert_eq!(dependents.len(), 1, "{include}");
+            assert_eq!(dependents[0].path, source);
+        }
+    }
+
+    #[test]
+    fn shared_include_stays_watched() {
+        let mut graph = graph_with_source("a.comp");
+        let a = Path::new("a.comp");
+        let b = Path::new("b.comp");
+
+        graph.update_includes(a, paths(&["common.glsl"]), true);
+        let (watch, _) = graph.update_includes(b, paths(&["common.glsl"]), true);
+        assert!(watch.is_empty());
+
+        let (_, unwatch) = graph.update_includes(a, AHashSet::new(), true);
+        assert!(unwatch.is_empty());
+        assert_eq!(
+            graph.dependents[Path::new("common.glsl")],
+            paths(&["b.comp"])
+        );
+    }
+
+    #[test]
+    fn dependent_sources_include_the_path_itself() {
+        let graph = graph_with_source("main.comp");
+        let sources = graph.dependent_sources(Path::new("main.comp"));
+        assert_eq!(sources.len(), 1);
+        assert!(graph.dependent_sources(Path::new("other.comp")).is_empty());
+    }
+}